@@ -0,0 +1,152 @@
+//! Command path benchmark: commands run end-to-end
+//!
+//! Run with `cargo bench --bench command_path`. Plain timing loop, without a
+//! benchmark framework, printing the mean time per command. Each workload
+//! goes through `Dispatcher::dispatch` (a single store, as a shard runs it)
+//! and through `ClusterManager::execute` (routing and the shard channels),
+//! and each reply is encoded with `RespEncoder` as the server would. GET and
+//! SET also run on a cluster in the striped mode, whose shards are locked
+//! directly instead of reached through a channel.
+
+use bytes::BytesMut;
+use ferrumdb::cluster::{ClusterManager, ShardMode};
+use ferrumdb::dispatch::Dispatcher;
+use ferrumdb::protocol::{RespEncoder, RespParser, RespValue};
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::runtime::Runtime;
+use tokio::task::JoinSet;
+
+/// Fields of the hash read back by HGETALL
+const HASH_FIELDS: usize = 1000;
+
+/// Commands in the pipelined batch
+const PIPELINE_DEPTH: usize = 100;
+
+/// Concurrent clients incrementing the hot key
+const INCR_CLIENTS: usize = 16;
+
+/// Build a command array from its parts
+fn cmd(parts: &[&str]) -> RespValue {
+    RespValue::array(parts.iter().map(|p| RespValue::bulk_string(p.to_string())).collect())
+}
+
+/// Print the mean time per command of `commands` commands run in `elapsed`
+fn report(name: &str, start: Instant, commands: usize) {
+    let elapsed = start.elapsed();
+    println!("{:<40} {:>10.3} µs/command", name, elapsed.as_secs_f64() * 1e6 / commands as f64);
+}
+
+/// Run `commands` one after the other through a dispatcher, `iterations` times
+fn bench_dispatch(name: &str, dispatcher: &mut Dispatcher, commands: &[RespValue], iterations: usize) {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        for command in commands {
+            black_box(RespEncoder::encode(&dispatcher.dispatch(command.clone())));
+        }
+    }
+    report(name, start, iterations * commands.len());
+}
+
+/// Run `commands` one after the other through a cluster, `iterations` times
+fn bench_cluster(name: &str, runtime: &Runtime, cluster: &ClusterManager, commands: &[RespValue], iterations: usize) {
+    let start = Instant::now();
+    runtime.block_on(async {
+        for _ in 0..iterations {
+            for command in commands {
+                black_box(RespEncoder::encode(&cluster.execute(command.clone()).await));
+            }
+        }
+    });
+    report(name, start, iterations * commands.len());
+}
+
+/// SET/GET mix: one SET for three GETs over a small set of keys
+fn set_get_mix() -> Vec<RespValue> {
+    let mut commands = Vec::new();
+    for i in 0..100 {
+        let key = format!("key:{}", i);
+        commands.push(cmd(&["SET", &key, "value"]));
+        for _ in 0..3 {
+            commands.push(cmd(&["GET", &key]));
+        }
+    }
+    commands
+}
+
+/// HSET of a large hash, one field per command
+fn hash_fill() -> Vec<RespValue> {
+    (0..HASH_FIELDS)
+        .map(|i| cmd(&["HSET", "hash", &format!("field:{}", i), "value"]))
+        .collect()
+}
+
+/// A pipelined batch as received from a client: parsed from one buffer,
+/// dispatched in order, replies encoded into one output buffer
+fn bench_pipeline(dispatcher: &mut Dispatcher, iterations: usize) {
+    let mut input = BytesMut::new();
+    for i in 0..PIPELINE_DEPTH {
+        let key = format!("pipe:{}", i % 10);
+        let command = if i % 2 == 0 { cmd(&["SET", &key, "value"]) } else { cmd(&["GET", &key]) };
+        RespEncoder::encode_to(&mut input, &command);
+    }
+    let input = input.freeze();
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let mut buf = BytesMut::from(&input[..]);
+        let mut output = BytesMut::new();
+        while let Some(command) = RespParser::parse(&mut buf).unwrap() {
+            RespEncoder::encode_to(&mut output, &dispatcher.dispatch(command));
+        }
+        black_box(output);
+    }
+    report("dispatch: pipeline of 100 SET/GET", start, iterations * PIPELINE_DEPTH);
+}
+
+/// INCR on a single hot key from concurrent clients
+fn bench_cluster_incr_contention(name: &str, runtime: &Runtime, cluster: &Arc<ClusterManager>, per_client: usize) {
+    let start = Instant::now();
+    runtime.block_on(async {
+        let mut clients = JoinSet::new();
+        for _ in 0..INCR_CLIENTS {
+            let cluster = cluster.clone();
+            clients.spawn(async move {
+                for _ in 0..per_client {
+                    black_box(cluster.execute(cmd(&["INCR", "hot"])).await);
+                }
+            });
+        }
+        while let Some(result) = clients.join_next().await {
+            result.unwrap();
+        }
+    });
+    report(name, start, INCR_CLIENTS * per_client);
+}
+
+fn main() {
+    let mut dispatcher = Dispatcher::new();
+    bench_dispatch("dispatch: SET", &mut dispatcher, &[cmd(&["SET", "key", "value"])], 1_000_000);
+    bench_dispatch("dispatch: GET", &mut dispatcher, &[cmd(&["GET", "key"])], 1_000_000);
+    bench_dispatch("dispatch: INCR", &mut dispatcher, &[cmd(&["INCR", "counter"])], 1_000_000);
+    bench_dispatch("dispatch: SET/GET mix", &mut dispatcher, &set_get_mix(), 2_500);
+    bench_dispatch("dispatch: HSET, 1000 fields", &mut dispatcher, &hash_fill(), 100);
+    bench_dispatch("dispatch: HGETALL, 1000 fields", &mut dispatcher, &[cmd(&["HGETALL", "hash"])], 2_000);
+    bench_pipeline(&mut dispatcher, 10_000);
+
+    let runtime = Runtime::new().unwrap();
+    let cluster = Arc::new(ClusterManager::new(4, false).unwrap());
+    bench_cluster("cluster: SET", &runtime, &cluster, &[cmd(&["SET", "key", "value"])], 100_000);
+    bench_cluster("cluster: GET", &runtime, &cluster, &[cmd(&["GET", "key"])], 100_000);
+    bench_cluster("cluster: INCR", &runtime, &cluster, &[cmd(&["INCR", "counter"])], 100_000);
+    bench_cluster("cluster: SET/GET mix", &runtime, &cluster, &set_get_mix(), 250);
+    bench_cluster("cluster: HSET, 1000 fields", &runtime, &cluster, &hash_fill(), 10);
+    bench_cluster("cluster: HGETALL, 1000 fields", &runtime, &cluster, &[cmd(&["HGETALL", "hash"])], 1_000);
+    bench_cluster_incr_contention("cluster: INCR hot key, 16 clients", &runtime, &cluster, 10_000);
+
+    let striped = Arc::new(ClusterManager::with_mode(4, false, ShardMode::Striped).unwrap());
+    bench_cluster("striped: SET", &runtime, &striped, &[cmd(&["SET", "key", "value"])], 100_000);
+    bench_cluster("striped: GET", &runtime, &striped, &[cmd(&["GET", "key"])], 100_000);
+    bench_cluster_incr_contention("striped: INCR hot key, 16 clients", &runtime, &striped, 10_000);
+}
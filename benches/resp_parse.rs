@@ -0,0 +1,30 @@
+//! RESP parsing benchmark: long lines and bulk strings
+//!
+//! Run with `cargo bench --bench resp_parse`. Plain timing loop, without a
+//! benchmark framework, printing the mean time per parse.
+
+use bytes::BytesMut;
+use ferrumdb::protocol::RespParser;
+use std::hint::black_box;
+use std::time::Instant;
+
+/// Parse `input` `iterations` times and print the mean time per parse
+fn bench(name: &str, input: &[u8], iterations: u32) {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let mut buf = BytesMut::from(input);
+        black_box(RespParser::parse(&mut buf).unwrap());
+    }
+    let elapsed = start.elapsed();
+    println!("{:<32} {:>10.2} µs/parse", name, elapsed.as_secs_f64() * 1e6 / iterations as f64);
+}
+
+fn main() {
+    let long_line = format!("+{}\r\n", "x".repeat(1024 * 1024));
+    bench("simple string, 1 MB line", long_line.as_bytes(), 200);
+
+    let bulk = format!("*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n${}\r\n{}\r\n", 1024 * 1024, "v".repeat(1024 * 1024));
+    bench("SET with a 1 MB bulk string", bulk.as_bytes(), 200);
+
+    bench("SET key value", b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n", 1_000_000);
+}
@@ -29,6 +29,24 @@ pub enum AofOperation {
     Incr = 9,
     /// INCRBY operation
     IncrBy = 10,
+    /// ZADD operation (score/member pairs)
+    ZAdd = 11,
+    /// ZREM operation (members removed, also used by ZPOPMIN/ZPOPMAX)
+    ZRem = 12,
+    /// XADD operation (explicit entry ID followed by field/value pairs)
+    XAdd = 13,
+    /// PEXPIREAT operation (absolute Unix time in milliseconds)
+    PExpireAt = 14,
+    /// JSON.SET operation (the whole serialized document)
+    JsonSet = 15,
+    /// SREM operation (members removed)
+    SRem = 16,
+    /// LPOP operation (number of elements popped from the head)
+    LPop = 17,
+    /// RPOP operation (number of elements popped from the tail)
+    RPop = 18,
+    /// APPEND operation (the suffix appended)
+    Append = 19,
 }
 
 impl AofOperation {
@@ -45,6 +63,15 @@ impl AofOperation {
             8 => Some(AofOperation::SAdd),
             9 => Some(AofOperation::Incr),
             10 => Some(AofOperation::IncrBy),
+            11 => Some(AofOperation::ZAdd),
+            12 => Some(AofOperation::ZRem),
+            13 => Some(AofOperation::XAdd),
+            14 => Some(AofOperation::PExpireAt),
+            15 => Some(AofOperation::JsonSet),
+            16 => Some(AofOperation::SRem),
+            17 => Some(AofOperation::LPop),
+            18 => Some(AofOperation::RPop),
+            19 => Some(AofOperation::Append),
             _ => None,
         }
     }
@@ -0,0 +1,76 @@
+//! AOF loading status
+//!
+//! Every shard replaying an AOF at startup keeps a `LoadStatus`: whether it
+//! is still loading, and once done the number of entries it replayed. The
+//! cluster reads the statuses of its shards for `/ready`, and, like the
+//! writer statuses, those of the whole process are registered here for
+//! INFO `# Persistence`.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Statuses of the loads of the process, dropped ones pruned on read
+static LOADS: Mutex<Vec<Weak<LoadStatus>>> = Mutex::new(Vec::new());
+
+/// Loading state of one shard
+#[derive(Debug)]
+pub struct LoadStatus {
+    /// Set until the replay is complete
+    loading: AtomicBool,
+
+    /// Entries replayed so far, updated after each replayed batch
+    entries_loaded: AtomicU64,
+}
+
+impl LoadStatus {
+    /// Create the status of a shard, loading or not yet, and register it
+    pub fn register(loading: bool) -> Arc<Self> {
+        let status = Arc::new(LoadStatus {
+            loading: AtomicBool::new(loading),
+            entries_loaded: AtomicU64::new(0),
+        });
+        let mut loads = LOADS.lock().unwrap();
+        loads.retain(|status| status.strong_count() > 0);
+        loads.push(Arc::downgrade(&status));
+        status
+    }
+
+    /// Record the number of entries replayed so far
+    pub fn progress(&self, entries: u64) {
+        self.entries_loaded.store(entries, Ordering::Release);
+    }
+
+    /// Record the end of the replay and the number of entries replayed
+    pub fn complete(&self, entries: u64) {
+        self.entries_loaded.store(entries, Ordering::Release);
+        self.loading.store(false, Ordering::Release);
+    }
+
+    /// Whether the replay is still running
+    pub fn is_loading(&self) -> bool {
+        self.loading.load(Ordering::Acquire)
+    }
+
+    /// Entries replayed so far, all of them once the replay completes
+    pub fn entries_loaded(&self) -> u64 {
+        self.entries_loaded.load(Ordering::Acquire)
+    }
+}
+
+/// Statuses of the loads currently tracked in the process
+fn loads() -> Vec<Arc<LoadStatus>> {
+    LOADS.lock().unwrap().iter().filter_map(Weak::upgrade).collect()
+}
+
+/// The loading fields of INFO `# Persistence`
+///
+/// `loading` is 1 while any shard replays its AOF, and
+/// `rdb_last_load_keys_loaded` the entries replayed by all of them.
+pub(super) fn info_fields() -> String {
+    let loads = loads();
+    format!(
+        "loading:{}\r\nrdb_last_load_keys_loaded:{}\r\n",
+        loads.iter().any(|load| load.is_loading()) as u8,
+        loads.iter().map(|load| load.entries_loaded()).sum::<u64>(),
+    )
+}
@@ -4,34 +4,73 @@
 //! Each operation is written in a compact binary format with checksums.
 
 mod entry;
+mod load;
 mod writer;
 mod reader;
 mod replay;
+mod rewrite;
+mod status;
 
 pub use entry::{AofEntry, AofOperation};
+pub use load::LoadStatus;
 pub use writer::AofWriter;
 pub use reader::AofReader;
 pub use replay::replay_entries;
+pub use rewrite::{push_key_entries, rewrite_aof, rewrite_aof_in_background, snapshot_entries};
+pub use status::{info_section, AofStatus};
 
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Default growth over the last rewrite that triggers an automatic one
+/// (`auto-aof-rewrite-percentage`)
+pub const DEFAULT_AUTO_REWRITE_PERCENTAGE: u64 = 100;
+
+/// Default minimum AOF size for an automatic rewrite
+/// (`auto-aof-rewrite-min-size`)
+pub const DEFAULT_AUTO_REWRITE_MIN_SIZE: u64 = 64 * 1024 * 1024;
+
+/// `auto-aof-rewrite-percentage`, process-wide, read when the server
+/// builds its cluster
+static AUTO_REWRITE_PERCENTAGE: AtomicU64 = AtomicU64::new(DEFAULT_AUTO_REWRITE_PERCENTAGE);
+
+/// `auto-aof-rewrite-min-size`, process-wide, read when the server builds
+/// its cluster
+static AUTO_REWRITE_MIN_SIZE: AtomicU64 = AtomicU64::new(DEFAULT_AUTO_REWRITE_MIN_SIZE);
+
+/// Set `auto-aof-rewrite-percentage` (0 = no automatic rewrite, effective
+/// at the next start)
+pub fn set_auto_rewrite_percentage(percentage: u64) {
+    AUTO_REWRITE_PERCENTAGE.store(percentage, Ordering::Relaxed);
+}
+
+/// Current `auto-aof-rewrite-percentage`
+pub fn auto_rewrite_percentage() -> u64 {
+    AUTO_REWRITE_PERCENTAGE.load(Ordering::Relaxed)
+}
+
+/// Set `auto-aof-rewrite-min-size` in bytes (effective at the next start)
+pub fn set_auto_rewrite_min_size(bytes: u64) {
+    AUTO_REWRITE_MIN_SIZE.store(bytes, Ordering::Relaxed);
+}
+
+/// Current `auto-aof-rewrite-min-size` in bytes
+pub fn auto_rewrite_min_size() -> u64 {
+    AUTO_REWRITE_MIN_SIZE.load(Ordering::Relaxed)
+}
 
 /// AOF sync policy
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum SyncPolicy {
     /// Sync after every write (safest, slowest)
     Always,
     /// Sync every second (balanced)
+    #[default]
     EverySecond,
     /// Let the OS decide when to sync (fastest, least safe)
     No,
 }
 
-impl Default for SyncPolicy {
-    fn default() -> Self {
-        SyncPolicy::EverySecond
-    }
-}
-
 /// AOF configuration
 #[derive(Debug, Clone)]
 pub struct AofConfig {
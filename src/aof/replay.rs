@@ -3,10 +3,8 @@
 //! Handles replaying AOF entries to reconstruct the database state.
 
 use super::{AofEntry, AofOperation};
-use crate::store::{MemoryStore, Value};
-//use bytes::Bytes;
-//use std::collections::HashMap;
-use tracing::{info, warn};
+use crate::store::{unix_ms_to_instant, ListItem, MemoryStore, StreamId, Value};
+use tracing::{debug, warn};
 
 /// Replay AOF entries into a memory store
 ///
@@ -25,7 +23,7 @@ pub fn replay_entries(store: &mut MemoryStore, entries: Vec<AofEntry>) -> Result
         }
     }
 
-    info!("Successfully replayed {} AOF entries", replayed);
+    debug!("Successfully replayed {} AOF entries", replayed);
     Ok(replayed)
 }
 
@@ -37,7 +35,7 @@ fn replay_entry(store: &mut MemoryStore, entry: &AofEntry) -> Result<(), String>
                 return Err("SET operation requires value payload".to_string());
             }
             let value = &entry.payload[0];
-            store.set(entry.key.clone(), Value::String(value.clone()));
+            store.set(entry.key.clone(), Value::from_bytes(value.clone()));
             Ok(())
         }
 
@@ -46,6 +44,30 @@ fn replay_entry(store: &mut MemoryStore, entry: &AofEntry) -> Result<(), String>
             Ok(())
         }
 
+        AofOperation::Append => {
+            if entry.payload.is_empty() {
+                return Err("APPEND operation requires suffix payload".to_string());
+            }
+            let suffix = &entry.payload[0];
+
+            // Appended in place, keeping any TTL
+            match store.get_mut(&entry.key) {
+                Some(value) => {
+                    let mut bytes = value
+                        .as_string_bytes()
+                        .ok_or("Key exists but is not a string")?
+                        .to_vec();
+                    bytes.extend_from_slice(suffix);
+                    *value = Value::String(bytes.into());
+                    store.notify_modified(&entry.key);
+                }
+                None => {
+                    store.set(entry.key.clone(), Value::from_bytes(suffix.clone()));
+                }
+            }
+            Ok(())
+        }
+
         AofOperation::Expire => {
             if entry.payload.is_empty() {
                 return Err("EXPIRE operation requires TTL payload".to_string());
@@ -54,7 +76,27 @@ fn replay_entry(store: &mut MemoryStore, entry: &AofEntry) -> Result<(), String>
                 .map_err(|_| "Invalid TTL encoding")?;
             let ttl: i64 = ttl_str.parse()
                 .map_err(|_| "Invalid TTL value")?;
-            store.expire(&entry.key, ttl);
+            // Replayed exactly: the logged TTL was already jittered
+            store.expire_with_jitter(&entry.key, ttl, 0);
+            Ok(())
+        }
+
+        AofOperation::PExpireAt => {
+            if entry.payload.is_empty() {
+                return Err("PEXPIREAT operation requires a timestamp payload".to_string());
+            }
+            let unix_ms: i64 = std::str::from_utf8(&entry.payload[0])
+                .map_err(|_| "Invalid timestamp encoding")?
+                .parse()
+                .map_err(|_| "Invalid timestamp value")?;
+            let expire_at = unix_ms_to_instant(unix_ms).ok_or("Invalid timestamp value")?;
+
+            // A key whose time has passed while the server was down is gone
+            if expire_at <= std::time::Instant::now() {
+                store.delete(&entry.key);
+            } else {
+                store.set_expire_at(&entry.key, expire_at);
+            }
             Ok(())
         }
 
@@ -80,6 +122,7 @@ fn replay_entry(store: &mut MemoryStore, entry: &AofEntry) -> Result<(), String>
             };
 
             hash_value.insert(field.clone(), value.clone());
+            store.notify_modified(&entry.key);
             Ok(())
         }
 
@@ -93,25 +136,98 @@ fn replay_entry(store: &mut MemoryStore, entry: &AofEntry) -> Result<(), String>
                 if let Some(hash) = value.as_hash_mut() {
                     hash.remove(field);
                 }
+                store.notify_modified(&entry.key);
             }
+            store.remove_if_empty(&entry.key);
             Ok(())
         }
 
-        AofOperation::LPush => {
-            // TODO: Implement list operations
-            warn!("LPUSH replay not yet implemented");
+        AofOperation::LPush | AofOperation::RPush => {
+            // Get or create list
+            let list = match store.get_mut(&entry.key) {
+                Some(v) => {
+                    match v.as_list_mut() {
+                        Some(l) => l,
+                        None => return Err("Key exists but is not a list".to_string()),
+                    }
+                }
+                None => {
+                    store.set(entry.key.clone(), Value::empty_list());
+                    store.get_mut(&entry.key).unwrap().as_list_mut().unwrap()
+                }
+            };
+
+            for value in &entry.payload {
+                if entry.op == AofOperation::LPush {
+                    list.push_front(ListItem::new(value.clone()));
+                } else {
+                    list.push_back(ListItem::new(value.clone()));
+                }
+            }
+            store.notify_modified(&entry.key);
+            Ok(())
+        }
+
+        AofOperation::SAdd => {
+            // Get or create set
+            let set = match store.get_mut(&entry.key) {
+                Some(v) => {
+                    match v.as_set_mut() {
+                        Some(s) => s,
+                        None => return Err("Key exists but is not a set".to_string()),
+                    }
+                }
+                None => {
+                    store.set(entry.key.clone(), Value::empty_set());
+                    store.get_mut(&entry.key).unwrap().as_set_mut().unwrap()
+                }
+            };
+
+            set.extend(entry.payload.iter().cloned());
+            store.notify_modified(&entry.key);
             Ok(())
         }
 
-        AofOperation::RPush => {
-            // TODO: Implement list operations
-            warn!("RPUSH replay not yet implemented");
+        AofOperation::SRem => {
+            if let Some(value) = store.get_mut(&entry.key) {
+                match value.as_set_mut() {
+                    Some(set) => {
+                        for member in &entry.payload {
+                            set.remove(member);
+                        }
+                    }
+                    None => return Err("Key exists but is not a set".to_string()),
+                }
+                store.notify_modified(&entry.key);
+            }
+            store.remove_if_empty(&entry.key);
             Ok(())
         }
 
-        AofOperation::SAdd => {
-            // TODO: Implement set operations
-            warn!("SADD replay not yet implemented");
+        AofOperation::LPop | AofOperation::RPop => {
+            if entry.payload.is_empty() {
+                return Err("POP operation requires a count payload".to_string());
+            }
+            let count: usize = std::str::from_utf8(&entry.payload[0])
+                .map_err(|_| "Invalid count encoding")?
+                .parse()
+                .map_err(|_| "Invalid count value")?;
+
+            if let Some(value) = store.get_mut(&entry.key) {
+                match value.as_list_mut() {
+                    Some(list) => {
+                        let n = count.min(list.len());
+                        if entry.op == AofOperation::RPop {
+                            list.truncate(list.len() - n);
+                        } else {
+                            list.drain(..n);
+                        }
+                    }
+                    None => return Err("Key exists but is not a list".to_string()),
+                }
+                store.notify_modified(&entry.key);
+            }
+            store.remove_if_empty(&entry.key);
             Ok(())
         }
 
@@ -140,12 +256,114 @@ fn replay_entry(store: &mut MemoryStore, entry: &AofEntry) -> Result<(), String>
             store.set(entry.key.clone(), Value::Integer(value));
             Ok(())
         }
+
+        AofOperation::ZAdd => {
+            if entry.payload.is_empty() || !entry.payload.len().is_multiple_of(2) {
+                return Err("ZADD operation requires score/member pairs".to_string());
+            }
+
+            // Get or create sorted set
+            let zset = match store.get_mut(&entry.key) {
+                Some(v) => {
+                    match v.as_zset_mut() {
+                        Some(z) => z,
+                        None => return Err("Key exists but is not a sorted set".to_string()),
+                    }
+                }
+                None => {
+                    store.set(entry.key.clone(), Value::empty_zset());
+                    store.get_mut(&entry.key).unwrap().as_zset_mut().unwrap()
+                }
+            };
+
+            for pair in entry.payload.chunks(2) {
+                let score_str = std::str::from_utf8(&pair[0])
+                    .map_err(|_| "Invalid score encoding")?;
+                let score: f64 = score_str.parse()
+                    .map_err(|_| "Invalid score value")?;
+                zset.insert(pair[1].clone(), score);
+            }
+            store.notify_modified(&entry.key);
+            Ok(())
+        }
+
+        AofOperation::ZRem => {
+            let now_empty = match store.get_mut(&entry.key) {
+                Some(value) => match value.as_zset_mut() {
+                    Some(zset) => {
+                        for member in &entry.payload {
+                            zset.remove(member);
+                        }
+                        zset.is_empty()
+                    }
+                    None => return Err("Key exists but is not a sorted set".to_string()),
+                },
+                None => return Ok(()),
+            };
+            store.notify_modified(&entry.key);
+
+            if now_empty {
+                store.delete(&entry.key);
+            }
+            Ok(())
+        }
+
+        AofOperation::XAdd => {
+            if entry.payload.len() < 3 || entry.payload.len() % 2 != 1 {
+                return Err("XADD operation requires an ID and field/value pairs".to_string());
+            }
+            let id = StreamId::parse(&entry.payload[0], 0)
+                .ok_or("Invalid stream ID")?;
+
+            // Get or create stream
+            let stream = match store.get_mut(&entry.key) {
+                Some(v) => {
+                    match v.as_stream_mut() {
+                        Some(s) => s,
+                        None => return Err("Key exists but is not a stream".to_string()),
+                    }
+                }
+                None => {
+                    store.set(entry.key.clone(), Value::empty_stream());
+                    store.get_mut(&entry.key).unwrap().as_stream_mut().unwrap()
+                }
+            };
+
+            let fields = entry.payload[1..]
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect();
+            stream.add(id, fields).map_err(|e| e.to_string())?;
+            store.notify_modified(&entry.key);
+            Ok(())
+        }
+
+        AofOperation::JsonSet => {
+            if entry.payload.is_empty() {
+                return Err("JSON.SET operation requires document payload".to_string());
+            }
+            let doc = serde_json::from_slice(&entry.payload[0])
+                .map_err(|e| format!("Invalid JSON document: {}", e))?;
+
+            // An update keeps the TTL of the key, like JSON.SET itself
+            match store.get_mut(&entry.key) {
+                Some(value) => {
+                    *value = Value::Json(doc);
+                    store.notify_modified(&entry.key);
+                }
+                None => {
+                    store.set(entry.key.clone(), Value::Json(doc));
+                }
+            }
+            Ok(())
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bytes::Bytes;
 
     #[test]
     fn test_replay_set() {
@@ -164,6 +382,49 @@ mod tests {
         assert_eq!(value.as_string().unwrap(), &Bytes::from("value1"));
     }
 
+    #[test]
+    fn test_replay_append() {
+        let mut store = MemoryStore::new();
+        let append = |key: &str, suffix: &str| AofEntry::new(AofOperation::Append, Bytes::from(key.to_string()), vec![Bytes::from(suffix.to_string())]);
+
+        let entries = vec![
+            append("new", "hello"),
+            append("new", " world"),
+            AofEntry::new(AofOperation::Incr, Bytes::from("counter"), vec![Bytes::from("12")]),
+            append("counter", "3"),
+        ];
+        assert_eq!(replay_entries(&mut store, entries).unwrap(), 4);
+
+        assert_eq!(store.get(&Bytes::from("new")).unwrap().as_string().unwrap(), &Bytes::from("hello world"));
+        assert_eq!(store.get(&Bytes::from("counter")).unwrap().as_string().unwrap(), &Bytes::from("123"));
+
+        // A key of another type is left alone
+        store.set(Bytes::from("myhash"), Value::empty_hash());
+        assert!(replay_entry(&mut store, &append("myhash", "x")).is_err());
+    }
+
+    #[test]
+    fn test_replay_pexpireat() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let mut store = MemoryStore::new();
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
+        for key in ["future", "past"] {
+            let entry = AofEntry::new(AofOperation::Set, Bytes::from(key), vec![Bytes::from("v")]);
+            replay_entry(&mut store, &entry).unwrap();
+        }
+
+        let future = Bytes::from((now_ms + 100_000).to_string());
+        let entry = AofEntry::new(AofOperation::PExpireAt, Bytes::from("future"), vec![future]);
+        replay_entry(&mut store, &entry).unwrap();
+        assert!((99..=100).contains(&store.ttl(&Bytes::from("future"))));
+
+        let past = Bytes::from((now_ms - 1000).to_string());
+        let entry = AofEntry::new(AofOperation::PExpireAt, Bytes::from("past"), vec![past]);
+        replay_entry(&mut store, &entry).unwrap();
+        assert_eq!(store.len(), 1);
+    }
+
     #[test]
     fn test_replay_hset() {
         let mut store = MemoryStore::new();
@@ -182,6 +443,34 @@ mod tests {
         assert_eq!(hash.get(&Bytes::from("field1")).unwrap(), &Bytes::from("value1"));
     }
 
+    #[test]
+    fn test_replay_zadd_zrem() {
+        let mut store = MemoryStore::new();
+
+        let entries = vec![
+            AofEntry::new(
+                AofOperation::ZAdd,
+                Bytes::from("myzset"),
+                vec![Bytes::from("1"), Bytes::from("a"), Bytes::from("2"), Bytes::from("b")],
+            ),
+            AofEntry::new(
+                AofOperation::ZRem,
+                Bytes::from("myzset"),
+                vec![Bytes::from("a")],
+            ),
+        ];
+        replay_entries(&mut store, entries).unwrap();
+
+        let zset = store.get(&Bytes::from("myzset")).unwrap().as_zset().unwrap();
+        assert_eq!(zset.len(), 1);
+        assert_eq!(zset.score(&Bytes::from("b")), Some(2.0));
+
+        // Removing the last member deletes the key
+        let entry = AofEntry::new(AofOperation::ZRem, Bytes::from("myzset"), vec![Bytes::from("b")]);
+        replay_entry(&mut store, &entry).unwrap();
+        assert!(!store.exists(&Bytes::from("myzset")));
+    }
+
     #[test]
     fn test_replay_multiple_entries() {
         let mut store = MemoryStore::new();
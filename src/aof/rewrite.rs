@@ -0,0 +1,155 @@
+//! AOF rewrite
+//!
+//! Rebuilds a minimal AOF from the current state of a store, so the file
+//! stops growing with every overwritten or deleted key.
+
+use super::{AofEntry, AofOperation, AofWriter};
+use crate::store::{Entry, ListItem, MemoryStore, StreamId, Value};
+use bytes::Bytes;
+use std::io;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Build the entries recreating the store's current state
+///
+/// Each key becomes the entries that rebuild its value (a SET for
+/// strings, an RPUSH for lists, one HSET per field, ...), followed by an
+/// EXPIRE if it has a TTL. Expired keys are left out.
+pub fn snapshot_entries(store: &MemoryStore) -> Vec<AofEntry> {
+    let mut entries = Vec::with_capacity(store.len());
+    for entry in store.entries() {
+        push_key_entries(&mut entries, entry);
+    }
+    entries
+}
+
+/// Push the entries recreating a single key, TTL included
+///
+/// Also used by commands that write a whole value at once (RESTORE).
+pub fn push_key_entries(entries: &mut Vec<AofEntry>, entry: &Entry) {
+    push_value_entries(entries, entry);
+
+    if let Some(expire_at) = entry.expire_at {
+        // EXPIRE has a one second resolution: round up so a key about
+        // to expire doesn't lose its TTL
+        let remaining = expire_at.saturating_duration_since(Instant::now());
+        let seconds = remaining.as_millis().div_ceil(1000).max(1);
+        entries.push(AofEntry::new(
+            AofOperation::Expire,
+            entry.key.clone(),
+            vec![Bytes::from(seconds.to_string())],
+        ));
+    }
+}
+
+/// Push the entries rebuilding the value of a single key
+fn push_value_entries(entries: &mut Vec<AofEntry>, entry: &Entry) {
+    let key = entry.key.clone();
+    match &entry.value {
+        Value::String(bytes) => {
+            entries.push(AofEntry::new(AofOperation::Set, key, vec![bytes.clone()]));
+        }
+        Value::Integer(i) => {
+            entries.push(AofEntry::new(AofOperation::Set, key, vec![Bytes::from(i.to_string())]));
+        }
+        Value::List(list) => {
+            entries.push(AofEntry::new(AofOperation::RPush, key, list.iter().map(ListItem::to_bytes).collect()));
+        }
+        Value::Set(set) => {
+            entries.push(AofEntry::new(AofOperation::SAdd, key, set.iter().cloned().collect()));
+        }
+        Value::Hash(hash) => {
+            for (field, value) in hash {
+                entries.push(AofEntry::new(AofOperation::HSet, key.clone(), vec![field.clone(), value.clone()]));
+            }
+        }
+        Value::ZSet(zset) => {
+            let payload = zset
+                .iter()
+                .flat_map(|(member, score)| [Bytes::from(score.to_string()), member.clone()])
+                .collect();
+            entries.push(AofEntry::new(AofOperation::ZAdd, key, payload));
+        }
+        Value::Stream(stream) => {
+            for (id, fields) in stream.range(StreamId::MIN, StreamId::MAX, None) {
+                let mut payload = vec![Bytes::from(id.to_string())];
+                for (field, value) in fields {
+                    payload.push(field.clone());
+                    payload.push(value.clone());
+                }
+                entries.push(AofEntry::new(AofOperation::XAdd, key.clone(), payload));
+            }
+        }
+        Value::Json(doc) => {
+            entries.push(AofEntry::new(AofOperation::JsonSet, key, vec![Bytes::from(doc.to_string())]));
+        }
+    }
+}
+
+/// Rewrite a writer's AOF from the store's current state
+///
+/// Returns the size of the new file.
+pub fn rewrite_aof(writer: &AofWriter, store: &MemoryStore) -> io::Result<u64> {
+    writer.rewrite(&snapshot_entries(store))
+}
+
+/// Rewrite a writer's AOF from the store's current state, on a thread of
+/// its own
+///
+/// Only the snapshot is taken by the caller; the file is written and
+/// swapped in by the thread, which then calls `done` with the result. The
+/// store may change meanwhile: its writes go to the new file as well.
+/// Returns false, doing nothing, if a rewrite is already running.
+pub fn rewrite_aof_in_background(
+    writer: &Arc<AofWriter>,
+    store: &MemoryStore,
+    done: impl FnOnce(io::Result<u64>) + Send + 'static,
+) -> io::Result<bool> {
+    let Some(ticket) = writer.start_rewrite() else {
+        return Ok(false);
+    };
+    let entries = snapshot_entries(store);
+    let writer = writer.clone();
+    std::thread::Builder::new()
+        .name("ferrumdb-aof-rewrite".to_string())
+        .spawn(move || done(writer.finish_rewrite(ticket, &entries)))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aof::replay_entries;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut store = MemoryStore::new();
+        store.set("string", Value::string("value"));
+        store.set("counter", Value::integer(42));
+        store.set("list", Value::list([Bytes::from("a"), Bytes::from("b")]));
+        store.set("set", Value::Set([Bytes::from("x")].into_iter().collect()));
+        store.set("hash", Value::Hash([(Bytes::from("f"), Bytes::from("v"))].into_iter().collect()));
+        store.set("volatile", Value::string("soon gone"));
+        store.expire(&Bytes::from("volatile"), 100);
+
+        let mut zset = Value::empty_zset();
+        zset.as_zset_mut().unwrap().insert(Bytes::from("m"), 1.5);
+        store.set("zset", zset);
+
+        let mut stream = Value::empty_stream();
+        stream.as_stream_mut().unwrap().add(StreamId::new(1, 2), vec![(Bytes::from("k"), Bytes::from("v"))]).unwrap();
+        store.set("stream", stream);
+        store.set("json", Value::Json(serde_json::json!({"a": {"b": 1}})));
+
+        let mut replayed = MemoryStore::new();
+        replay_entries(&mut replayed, snapshot_entries(&store)).unwrap();
+
+        assert_eq!(replayed.len(), store.len());
+        for key in store.keys() {
+            assert_eq!(replayed.get(&key), store.get(&key), "{:?}", key);
+        }
+        let ttl = replayed.ttl(&Bytes::from("volatile"));
+        assert!(ttl > 0 && ttl <= 100);
+        assert_eq!(replayed.ttl(&Bytes::from("string")), -1);
+    }
+}
@@ -0,0 +1,158 @@
+//! AOF durability status
+//!
+//! Every `AofWriter` keeps an `AofStatus`: bytes written but not yet
+//! fsynced, when the oldest of them was written, when the file was last
+//! synced and the last write and rewrite errors. The statuses of all the
+//! writers of the process (one per shard) are registered here, so INFO
+//! `# Persistence` reports them together whichever shard answers.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+
+/// Statuses of the writers of the process, dropped writers pruned on read
+static STATUSES: Mutex<Vec<Weak<AofStatus>>> = Mutex::new(Vec::new());
+
+/// Durability state of one AOF writer
+#[derive(Debug)]
+pub struct AofStatus {
+    /// Bytes written since the last fsync
+    pending_bytes: AtomicU64,
+
+    /// When the oldest write not yet fsynced was made
+    oldest_pending: Mutex<Option<Instant>>,
+
+    /// When the file was last fsynced (or opened)
+    last_sync: Mutex<Instant>,
+
+    /// Error of the last write, None once a write succeeds again
+    last_write_error: Mutex<Option<String>>,
+
+    /// Error of the last rewrite, None once a rewrite succeeds again
+    last_rewrite_error: Mutex<Option<String>>,
+}
+
+impl AofStatus {
+    /// Create the status of a new writer and register it
+    pub(super) fn register() -> Arc<Self> {
+        let status = Arc::new(AofStatus {
+            pending_bytes: AtomicU64::new(0),
+            oldest_pending: Mutex::new(None),
+            last_sync: Mutex::new(Instant::now()),
+            last_write_error: Mutex::new(None),
+            last_rewrite_error: Mutex::new(None),
+        });
+        let mut statuses = STATUSES.lock().unwrap();
+        statuses.retain(|status| status.strong_count() > 0);
+        statuses.push(Arc::downgrade(&status));
+        status
+    }
+
+    /// Record bytes written to the file but not yet synced
+    pub(super) fn written(&self, bytes: u64) {
+        self.pending_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.oldest_pending.lock().unwrap().get_or_insert_with(Instant::now);
+    }
+
+    /// Record an fsync covering everything written so far
+    pub(super) fn synced(&self) {
+        self.pending_bytes.store(0, Ordering::Relaxed);
+        *self.oldest_pending.lock().unwrap() = None;
+        *self.last_sync.lock().unwrap() = Instant::now();
+    }
+
+    /// Record the outcome of a write
+    pub(super) fn record_write<T>(&self, result: &std::io::Result<T>) {
+        *self.last_write_error.lock().unwrap() = result.as_ref().err().map(|e| e.to_string());
+    }
+
+    /// Record the outcome of a rewrite
+    pub(super) fn record_rewrite<T>(&self, result: &std::io::Result<T>) {
+        *self.last_rewrite_error.lock().unwrap() = result.as_ref().err().map(|e| e.to_string());
+    }
+
+    /// Bytes written since the last fsync
+    pub fn pending_bytes(&self) -> u64 {
+        self.pending_bytes.load(Ordering::Relaxed)
+    }
+
+    /// When the oldest write not yet fsynced was made, None if all are
+    pub fn oldest_pending(&self) -> Option<Instant> {
+        *self.oldest_pending.lock().unwrap()
+    }
+
+    /// When the file was last fsynced
+    pub fn last_sync(&self) -> Instant {
+        *self.last_sync.lock().unwrap()
+    }
+
+    /// Error of the last write, if it failed
+    pub fn last_write_error(&self) -> Option<String> {
+        self.last_write_error.lock().unwrap().clone()
+    }
+
+    /// Error of the last rewrite, if it failed
+    pub fn last_rewrite_error(&self) -> Option<String> {
+        self.last_rewrite_error.lock().unwrap().clone()
+    }
+}
+
+/// Statuses of the writers currently open in the process
+fn statuses() -> Vec<Arc<AofStatus>> {
+    STATUSES.lock().unwrap().iter().filter_map(Weak::upgrade).collect()
+}
+
+/// `ok`, or `err` if any of the errors is set
+fn status_word(mut errors: impl Iterator<Item = Option<String>>) -> &'static str {
+    if errors.any(|error| error.is_some()) {
+        "err"
+    } else {
+        "ok"
+    }
+}
+
+/// The INFO `# Persistence` section, without its header
+///
+/// Starts with the loading state of the shards (`load::info_fields`).
+/// Then aggregated over the writers of every shard: the pending bytes are
+/// summed, the ages are those of the oldest pending write and of the
+/// stalest fsync, and a status is `err` as soon as one writer failed.
+/// Rewrites run in the foreground, so none is ever in progress.
+pub fn info_section(enabled: bool) -> String {
+    let statuses = statuses();
+    let now = Instant::now();
+    let pending: u64 = statuses.iter().map(|status| status.pending_bytes()).sum();
+    let oldest_pending_ms = statuses
+        .iter()
+        .filter_map(|status| status.oldest_pending())
+        .map(|at| now.saturating_duration_since(at).as_millis())
+        .max()
+        .unwrap_or(0);
+    let last_fsync_secs = statuses
+        .iter()
+        .map(|status| now.saturating_duration_since(status.last_sync()).as_secs())
+        .max()
+        .unwrap_or(0);
+    let last_write_error = statuses.iter().find_map(|status| status.last_write_error());
+
+    let mut section = super::load::info_fields();
+    section.push_str(&format!(
+        "aof_enabled:{}\r\n\
+        aof_rewrite_in_progress:0\r\n\
+        aof_last_bgrewrite_status:{}\r\n\
+        aof_last_write_status:{}\r\n\
+        aof_pending_fsync:{}\r\n\
+        aof_oldest_pending_fsync_ms:{}\r\n\
+        aof_last_fsync_ago_sec:{}\r\n",
+        enabled as u8,
+        status_word(statuses.iter().map(|status| status.last_rewrite_error())),
+        status_word(std::iter::once(last_write_error.clone())),
+        pending,
+        oldest_pending_ms,
+        last_fsync_secs,
+    ));
+    if let Some(error) = last_write_error {
+        section.push_str(&format!("aof_last_write_error:{}\r\n", error));
+    }
+    section
+}
@@ -2,52 +2,94 @@
 //!
 //! Handles writing operations to the AOF file.
 
-use super::{AofEntry, SyncPolicy};
-use std::fs::{File, OpenOptions};
-use std::io::{self, Write};
-use std::path::Path;
-use std::sync::Mutex;
-use std::time::{Duration, Instant};
+use super::{AofEntry, AofStatus, SyncPolicy};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 /// AOF writer
 pub struct AofWriter {
+    path: PathBuf,
     file: Mutex<File>,
     sync_policy: SyncPolicy,
-    last_sync: Mutex<Instant>,
+
+    /// Durability state, reported by INFO `# Persistence`
+    status: Arc<AofStatus>,
+
+    /// Current size of the file in bytes
+    size: AtomicU64,
+
+    /// Size of the file after the last rewrite (or when it was opened)
+    base_size: AtomicU64,
+
+    /// Entries written since a background rewrite took its snapshot, to
+    /// append to its file before it is swapped in (None when none runs)
+    rewrite_buffer: Mutex<Option<Vec<u8>>>,
+
+    /// Incremented by every rewrite, so a background rewrite started before
+    /// another one completed isn't swapped in over it
+    rewrite_generation: AtomicU64,
 }
 
 impl AofWriter {
     /// Create a new AOF writer
     pub fn new<P: AsRef<Path>>(path: P, sync_policy: SyncPolicy) -> io::Result<Self> {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)?;
+        let path = path.as_ref().to_path_buf();
+        let file = Self::open(&path)?;
+        let size = file.metadata()?.len();
 
         Ok(AofWriter {
+            path,
             file: Mutex::new(file),
             sync_policy,
-            last_sync: Mutex::new(Instant::now()),
+            status: AofStatus::register(),
+            size: AtomicU64::new(size),
+            base_size: AtomicU64::new(size),
+            rewrite_buffer: Mutex::new(None),
+            rewrite_generation: AtomicU64::new(0),
         })
     }
 
+    /// Open the AOF file for appending
+    fn open(path: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+    }
+
     /// Write an entry to the AOF
     pub fn write(&self, entry: &AofEntry) -> io::Result<()> {
+        let result = self.write_entry(entry);
+        self.status.record_write(&result);
+        result
+    }
+
+    /// Append an entry and sync as the policy says
+    fn write_entry(&self, entry: &AofEntry) -> io::Result<()> {
         let bytes = entry.to_bytes();
 
         let mut file = self.file.lock().unwrap();
         file.write_all(&bytes)?;
+        if let Some(buffer) = self.rewrite_buffer.lock().unwrap().as_mut() {
+            buffer.extend_from_slice(&bytes);
+        }
+        self.size.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        self.status.written(bytes.len() as u64);
 
         // Apply sync policy
         match self.sync_policy {
             SyncPolicy::Always => {
                 file.sync_all()?;
+                self.status.synced();
             }
             SyncPolicy::EverySecond => {
-                let mut last_sync = self.last_sync.lock().unwrap();
-                if last_sync.elapsed() >= Duration::from_secs(1) {
+                if self.status.last_sync().elapsed() >= Duration::from_secs(1) {
                     file.sync_all()?;
-                    *last_sync = Instant::now();
+                    self.status.synced();
                 }
             }
             SyncPolicy::No => {
@@ -62,15 +104,141 @@ impl AofWriter {
     pub fn sync(&self) -> io::Result<()> {
         let file = self.file.lock().unwrap();
         file.sync_all()?;
-        *self.last_sync.lock().unwrap() = Instant::now();
+        self.status.synced();
         Ok(())
     }
 
+    /// Durability state of this writer
+    pub fn status(&self) -> &AofStatus {
+        &self.status
+    }
+
     /// Flush buffered data
     pub fn flush(&self) -> io::Result<()> {
         let mut file = self.file.lock().unwrap();
         file.flush()
     }
+
+    /// Current size of the AOF in bytes
+    pub fn size(&self) -> u64 {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    /// Size of the AOF after the last rewrite (or when it was opened)
+    pub fn base_size(&self) -> u64 {
+        self.base_size.load(Ordering::Relaxed)
+    }
+
+    /// Whether the AOF has grown enough to be rewritten automatically
+    ///
+    /// Like Redis' `auto-aof-rewrite-percentage` and
+    /// `auto-aof-rewrite-min-size`: the file must be at least `min_size`
+    /// bytes and have grown by `percentage` percent over its base size.
+    /// A percentage of 0 disables automatic rewrites.
+    pub fn needs_rewrite(&self, percentage: u64, min_size: u64) -> bool {
+        let size = self.size();
+        if percentage == 0 || size < min_size {
+            return false;
+        }
+        let base = self.base_size().max(1);
+        size.saturating_sub(base) * 100 / base >= percentage
+    }
+
+    /// Replace the AOF with the given entries
+    ///
+    /// The entries are written to a temporary file next to the AOF, synced,
+    /// then renamed over it, so a crash at any point leaves either the old
+    /// or the new file. Returns the size of the new file. A background
+    /// rewrite still running is abandoned, as its snapshot is older.
+    pub fn rewrite(&self, entries: &[AofEntry]) -> io::Result<u64> {
+        let result = self.rewrite_file(entries, ".rewrite", None);
+        self.status.record_rewrite(&result);
+        result
+    }
+
+    /// Whether a background rewrite is running
+    pub fn is_rewriting(&self) -> bool {
+        self.rewrite_buffer.lock().unwrap().is_some()
+    }
+
+    /// Start a background rewrite, returning its ticket for
+    /// `finish_rewrite`, or None if one is already running
+    ///
+    /// Every entry written from now on is also kept for the new file, so
+    /// the snapshot it is written from must be taken right after, before
+    /// anything else is written.
+    pub fn start_rewrite(&self) -> Option<u64> {
+        let _file = self.file.lock().unwrap();
+        let mut buffer = self.rewrite_buffer.lock().unwrap();
+        if buffer.is_some() {
+            return None;
+        }
+        *buffer = Some(Vec::new());
+        Some(self.rewrite_generation.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    /// Replace the AOF with the snapshot of a background rewrite, followed
+    /// by the entries written since it started
+    ///
+    /// Meant to run off the shard loop: only the final append and swap hold
+    /// up the writes. Fails without touching the AOF if another rewrite
+    /// completed in the meantime.
+    pub fn finish_rewrite(&self, ticket: u64, entries: &[AofEntry]) -> io::Result<u64> {
+        let result = self.rewrite_file(entries, ".bgrewrite", Some(ticket));
+        match &result {
+            // Not a failure of the AOF: the newer rewrite was recorded
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return result,
+            Err(_) => {
+                let _file = self.file.lock().unwrap();
+                if self.rewrite_generation.load(Ordering::Relaxed) == ticket {
+                    *self.rewrite_buffer.lock().unwrap() = None;
+                }
+            }
+            Ok(_) => {}
+        }
+        self.status.record_rewrite(&result);
+        result
+    }
+
+    /// Write the entries to a temporary file and swap it in
+    ///
+    /// For a background rewrite (`ticket`), the entries written since it
+    /// started are appended first.
+    fn rewrite_file(&self, entries: &[AofEntry], suffix: &str, ticket: Option<u64>) -> io::Result<u64> {
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(suffix);
+        let temp_path = PathBuf::from(temp_path);
+
+        let mut temp = BufWriter::new(File::create(&temp_path)?);
+        for entry in entries {
+            temp.write_all(&entry.to_bytes())?;
+        }
+        let mut temp = temp.into_inner().map_err(|e| e.into_error())?;
+        temp.sync_all()?;
+
+        // Writes wait for the swap, so none lands in the replaced file
+        let mut file = self.file.lock().unwrap();
+        let mut buffer = self.rewrite_buffer.lock().unwrap();
+        if let Some(ticket) = ticket {
+            if self.rewrite_generation.load(Ordering::Relaxed) != ticket {
+                let _ = fs::remove_file(&temp_path);
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "superseded by another AOF rewrite"));
+            }
+            temp.write_all(&buffer.take().unwrap_or_default())?;
+            temp.sync_all()?;
+        } else {
+            *buffer = None;
+            self.rewrite_generation.fetch_add(1, Ordering::Relaxed);
+        }
+        fs::rename(&temp_path, &self.path)?;
+        *file = Self::open(&self.path)?;
+
+        let size = file.metadata()?.len();
+        self.size.store(size, Ordering::Relaxed);
+        self.base_size.store(size, Ordering::Relaxed);
+        self.status.synced();
+        Ok(size)
+    }
 }
 
 #[cfg(test)]
@@ -105,4 +273,94 @@ mod tests {
         // Clean up
         fs::remove_file(temp_file).unwrap();
     }
+
+    #[test]
+    fn test_rewrite_resets_growth() {
+        let temp_file = std::env::temp_dir().join(format!("ferrumdb_rewrite_{}.aof", std::process::id()));
+        let _ = fs::remove_file(&temp_file);
+
+        let writer = AofWriter::new(&temp_file, SyncPolicy::No).unwrap();
+        let entry = AofEntry::new(AofOperation::Set, Bytes::from("key"), vec![Bytes::from("value")]);
+        for _ in 0..10 {
+            writer.write(&entry).unwrap();
+        }
+        assert_eq!(writer.size(), fs::metadata(&temp_file).unwrap().len());
+        assert!(writer.needs_rewrite(100, 0));
+        assert!(!writer.needs_rewrite(100, writer.size() + 1));
+        assert!(!writer.needs_rewrite(0, 0));
+
+        // Once rewritten, the file is the new base and keeps appending
+        let size = writer.rewrite(std::slice::from_ref(&entry)).unwrap();
+        assert_eq!(size, entry.to_bytes().len() as u64);
+        assert_eq!(writer.base_size(), size);
+        assert!(!writer.needs_rewrite(100, 0));
+        writer.write(&entry).unwrap();
+        assert_eq!(fs::metadata(&temp_file).unwrap().len(), 2 * size);
+        assert!(writer.needs_rewrite(100, 0));
+
+        fs::remove_file(&temp_file).unwrap();
+    }
+
+    #[test]
+    fn test_background_rewrite_keeps_later_writes() {
+        use crate::aof::AofReader;
+
+        let temp_file = std::env::temp_dir().join(format!("ferrumdb_bgrewrite_{}.aof", std::process::id()));
+        let _ = fs::remove_file(&temp_file);
+        let entry = |key: &str| AofEntry::new(AofOperation::Set, Bytes::from(key.to_string()), vec![Bytes::from("v")]);
+        let keys = |path: &Path| -> Vec<Bytes> {
+            AofReader::load(path).unwrap().parse_entries().into_iter().map(|entry| entry.key).collect()
+        };
+
+        let writer = AofWriter::new(&temp_file, SyncPolicy::No).unwrap();
+        for _ in 0..10 {
+            writer.write(&entry("old")).unwrap();
+        }
+
+        // Written while the snapshot is turned into the new file
+        let ticket = writer.start_rewrite().unwrap();
+        assert_eq!(writer.start_rewrite(), None);
+        writer.write(&entry("during")).unwrap();
+        let size = writer.finish_rewrite(ticket, &[entry("snapshot")]).unwrap();
+        assert_eq!(keys(&temp_file), [Bytes::from("snapshot"), Bytes::from("during")]);
+        assert_eq!(writer.size(), size);
+
+        // Appends go on as usual, and another rewrite may start
+        writer.write(&entry("after")).unwrap();
+        assert_eq!(keys(&temp_file).len(), 3);
+        let ticket = writer.start_rewrite().unwrap();
+
+        // A rewrite completed meanwhile wins over an older snapshot
+        writer.rewrite(&[entry("flushed")]).unwrap();
+        assert!(writer.finish_rewrite(ticket, &[entry("stale")]).is_err());
+        assert_eq!(keys(&temp_file), [Bytes::from("flushed")]);
+        assert!(writer.status().last_rewrite_error().is_none());
+        assert!(writer.start_rewrite().is_some());
+
+        fs::remove_file(&temp_file).unwrap();
+    }
+
+    #[test]
+    fn test_status_tracks_pending_fsync() {
+        let temp_file = std::env::temp_dir().join(format!("ferrumdb_status_{}.aof", std::process::id()));
+        let _ = fs::remove_file(&temp_file);
+
+        let writer = AofWriter::new(&temp_file, SyncPolicy::No).unwrap();
+        let entry = AofEntry::new(AofOperation::Set, Bytes::from("key"), vec![Bytes::from("value")]);
+        assert_eq!(writer.status().pending_bytes(), 0);
+        assert!(writer.status().oldest_pending().is_none());
+
+        writer.write(&entry).unwrap();
+        writer.write(&entry).unwrap();
+        assert_eq!(writer.status().pending_bytes(), 2 * entry.to_bytes().len() as u64);
+        assert!(writer.status().oldest_pending().is_some());
+
+        writer.sync().unwrap();
+        assert_eq!(writer.status().pending_bytes(), 0);
+        assert!(writer.status().oldest_pending().is_none());
+        assert!(writer.status().last_write_error().is_none());
+        assert!(writer.status().last_rewrite_error().is_none());
+
+        fs::remove_file(&temp_file).unwrap();
+    }
 }
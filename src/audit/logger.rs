@@ -0,0 +1,125 @@
+//! Audit logger
+//!
+//! Appends one line per write command to the audit log file:
+//!
+//! ```text
+//! 2026-01-31T14:03:07.412Z 127.0.0.1:52814 SET user:1
+//! ```
+//!
+//! Keys are printed as is, except for non-printable bytes, spaces and
+//! backslashes which are escaped as `\xNN`. Commands run without a client
+//! (e.g. from the web API) are logged with `-` as address.
+
+use bytes::Bytes;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Audit logger
+#[derive(Debug)]
+pub struct AuditLogger {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLogger {
+    /// Open (or create) the audit log for appending
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(AuditLogger {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append the line of a write command
+    ///
+    /// Each line is written with a single call, so lines from several
+    /// shards sharing the logger never interleave.
+    pub fn log(&self, client: Option<SocketAddr>, command: &str, keys: &[Bytes]) -> io::Result<()> {
+        let line = format_line(SystemTime::now(), client, command, keys);
+        self.file.lock().unwrap().write_all(line.as_bytes())
+    }
+
+    /// Path of the audit log
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Format the audit line of a command, newline included
+pub fn format_line(time: SystemTime, client: Option<SocketAddr>, command: &str, keys: &[Bytes]) -> String {
+    let mut line = format_timestamp(time);
+    line.push(' ');
+    match client {
+        Some(addr) => line.push_str(&addr.to_string()),
+        None => line.push('-'),
+    }
+    line.push(' ');
+    line.push_str(&command.to_uppercase());
+    for key in keys {
+        line.push(' ');
+        for &byte in key.iter() {
+            if byte.is_ascii_graphic() && byte != b'\\' {
+                line.push(byte as char);
+            } else {
+                line.push_str(&format!("\\x{:02x}", byte));
+            }
+        }
+    }
+    line.push('\n');
+    line
+}
+
+/// Format a time as RFC 3339 in UTC, with milliseconds
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_line() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_769_868_187_412);
+        let client: SocketAddr = "127.0.0.1:52814".parse().unwrap();
+        assert_eq!(
+            format_line(time, Some(client), "set", &[Bytes::from("user:1")]),
+            "2026-01-31T14:03:07.412Z 127.0.0.1:52814 SET user:1\n"
+        );
+        assert_eq!(
+            format_line(UNIX_EPOCH, None, "DEL", &[Bytes::from("a b"), Bytes::from("c\\\n")]),
+            "1970-01-01T00:00:00.000Z - DEL a\\x20b c\\x5c\\x0a\n"
+        );
+    }
+}
@@ -0,0 +1,65 @@
+//! Audit log module
+//!
+//! Records every write command to a human-readable, append-only text file,
+//! one line per command with the time, the client address, the command
+//! name and the keys it touched. Unlike the AOF it is never replayed, and
+//! leaves values out of the file.
+
+mod logger;
+
+pub use logger::{format_line, AuditLogger};
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Default path of the audit log file
+pub const DEFAULT_AUDIT_LOG_FILE: &str = "ferrumdb_audit.log";
+
+/// Audit log configuration
+#[derive(Debug, Clone)]
+pub struct AuditConfig {
+    /// Path to the audit log file
+    pub path: PathBuf,
+    /// Whether to enable the audit log
+    pub enabled: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            path: PathBuf::from(DEFAULT_AUDIT_LOG_FILE),
+            enabled: false,
+        }
+    }
+}
+
+/// `audit-log`, process-wide, read when the server builds its cluster
+static AUDIT_LOG: AtomicBool = AtomicBool::new(false);
+
+/// `audit-log-file`, process-wide (empty = `DEFAULT_AUDIT_LOG_FILE`)
+static AUDIT_LOG_FILE: Mutex<String> = Mutex::new(String::new());
+
+/// Set `audit-log` (effective at the next start)
+pub fn set_audit_log(enabled: bool) {
+    AUDIT_LOG.store(enabled, Ordering::Relaxed);
+}
+
+/// Set `audit-log-file` (effective at the next start)
+pub fn set_audit_log_file(path: &str) {
+    *AUDIT_LOG_FILE.lock().unwrap() = path.to_string();
+}
+
+/// Current `audit-log-file`
+pub fn audit_log_file() -> String {
+    let path = AUDIT_LOG_FILE.lock().unwrap();
+    if path.is_empty() { DEFAULT_AUDIT_LOG_FILE.to_string() } else { path.clone() }
+}
+
+/// The audit log configuration set by `audit-log` and `audit-log-file`
+pub fn audit_config() -> AuditConfig {
+    AuditConfig {
+        path: PathBuf::from(audit_log_file()),
+        enabled: AUDIT_LOG.load(Ordering::Relaxed),
+    }
+}
@@ -2,34 +2,231 @@
 //!
 //! This module implements the sharding logic for distributing keys across multiple
 //! shards, each running in its own thread. This architecture is designed to support
-//! future multi-node clustering.
+//! future multi-node clustering. In the striped mode, the shards are instead
+//! lock-guarded stores the connection tasks use directly (see `StripedStore`).
+//! The shard threads can also run as tasks of the server's runtime
+//! (`ShardRuntime::Shared`).
 
 mod shard;
 mod router;
+mod striped;
 
-pub use shard::{Shard, ShardCommand, ShardConfig};
+pub use shard::{ExportBatch, Shard, ShardCommand, ShardConfig};
 pub use router::ShardRouter;
+pub use striped::StripedStore;
 
+use crate::aof::{auto_rewrite_min_size, auto_rewrite_percentage};
+use crate::audit::{AuditConfig, AuditLogger};
+use crate::commands::{
+    async_sleep_duration, bitop, debug_subcommand_key, disable_keys_command, keys_scan_aborted, keys_scan_limit,
+    parse_copy_options, BitOp, ClientOptions, CommandRegistry, SetOp, OOM_ERROR, WRONGTYPE,
+};
+use crate::store::{self, StoreStats, Value};
 use crate::protocol::RespValue;
+use bytes::Bytes;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::sync::oneshot;
 use tracing::{info, error};
 
+/// How the shards run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShardMode {
+    /// Each shard in its own thread, receiving the commands on a channel
+    #[default]
+    Actor,
+
+    /// Each shard's store behind its own lock, used by the connection tasks
+    /// directly (see `StripedStore`)
+    Striped,
+}
+
+impl ShardMode {
+    /// Every mode, by its `shard-mode` name
+    pub const NAMES: &'static [(&'static str, ShardMode)] = &[("actor", ShardMode::Actor), ("striped", ShardMode::Striped)];
+
+    /// Name of the mode in the configuration
+    pub fn name(self) -> &'static str {
+        Self::NAMES.iter().find(|(_, mode)| *mode == self).map_or("actor", |(name, _)| name)
+    }
+
+    /// Mode of a configuration name, in any case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, mode)| *mode)
+    }
+}
+
+/// `shard-mode`, process-wide, read when the server builds its cluster
+static SHARD_MODE: AtomicU8 = AtomicU8::new(ShardMode::Actor as u8);
+
+/// Set `shard-mode` (effective at the next start)
+pub fn set_shard_mode(mode: ShardMode) {
+    SHARD_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Current `shard-mode`
+pub fn shard_mode() -> ShardMode {
+    match SHARD_MODE.load(Ordering::Relaxed) {
+        mode if mode == ShardMode::Striped as u8 => ShardMode::Striped,
+        _ => ShardMode::Actor,
+    }
+}
+
+/// Where the loops of the shard threads (`ShardMode::Actor`) run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShardRuntime {
+    /// Each shard in a thread of its own, with its own single-threaded
+    /// runtime
+    #[default]
+    Dedicated,
+
+    /// Every shard as a task of the server's multi-threaded runtime, which
+    /// saves one thread and one runtime per shard
+    Shared,
+}
+
+impl ShardRuntime {
+    /// Every runtime, by its `shard-runtime` name
+    pub const NAMES: &'static [(&'static str, ShardRuntime)] =
+        &[("dedicated", ShardRuntime::Dedicated), ("shared", ShardRuntime::Shared)];
+
+    /// Name of the runtime in the configuration
+    pub fn name(self) -> &'static str {
+        Self::NAMES.iter().find(|(_, runtime)| *runtime == self).map_or("dedicated", |(name, _)| name)
+    }
+
+    /// Runtime of a configuration name, in any case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, runtime)| *runtime)
+    }
+}
+
+/// `shard-runtime`, process-wide, read when the server builds its cluster
+static SHARD_RUNTIME: AtomicU8 = AtomicU8::new(ShardRuntime::Dedicated as u8);
+
+/// Set `shard-runtime` (effective at the next start)
+pub fn set_shard_runtime(runtime: ShardRuntime) {
+    SHARD_RUNTIME.store(runtime as u8, Ordering::Relaxed);
+}
+
+/// Current `shard-runtime`
+pub fn shard_runtime() -> ShardRuntime {
+    match SHARD_RUNTIME.load(Ordering::Relaxed) {
+        runtime if runtime == ShardRuntime::Shared as u8 => ShardRuntime::Shared,
+        _ => ShardRuntime::Dedicated,
+    }
+}
+
+/// `hash-tags`, process-wide, read when the server builds its cluster
+static HASH_TAGS: AtomicBool = AtomicBool::new(false);
+
+/// Set `hash-tags` (effective at the next start)
+pub fn set_hash_tags(enabled: bool) {
+    HASH_TAGS.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the keys with a hash tag are routed by their tag (`hash-tags`)
+pub fn hash_tags() -> bool {
+    HASH_TAGS.load(Ordering::Relaxed)
+}
+
+/// `expected-keys`, process-wide, read when the server builds its cluster
+static EXPECTED_KEYS: AtomicUsize = AtomicUsize::new(0);
+
+/// Set `expected-keys` (0 = default capacity, effective at the next start)
+pub fn set_expected_keys(keys: usize) {
+    EXPECTED_KEYS.store(keys, Ordering::Relaxed);
+}
+
+/// Number of keys the shards are pre-sized for, in total (`expected-keys`)
+pub fn expected_keys() -> usize {
+    EXPECTED_KEYS.load(Ordering::Relaxed)
+}
+
+/// The shards of a cluster, in either mode
+enum Shards {
+    /// Shard threads
+    Actors(Vec<Arc<Shard>>),
+
+    /// Lock-guarded stores
+    Striped(StripedStore),
+}
+
 /// Cluster manager that owns all shards and routes commands
 pub struct ClusterManager {
-    shards: Vec<Arc<Shard>>,
+    shards: Shards,
     router: ShardRouter,
+
+    /// Key specs of the commands, to find the key a command is routed by
+    registry: CommandRegistry,
 }
 
 impl ClusterManager {
     /// Create a new cluster manager with the specified number of shards
     pub fn new(num_shards: usize, aof_enabled: bool) -> anyhow::Result<Self> {
-        info!("Initializing cluster with {} shards", num_shards);
+        Self::with_expected_keys(num_shards, aof_enabled, 0)
+    }
+
+    /// Create a cluster manager whose shards run in the given mode
+    pub fn with_mode(num_shards: usize, aof_enabled: bool, mode: ShardMode) -> anyhow::Result<Self> {
+        Self::with_runtime(num_shards, aof_enabled, mode, ShardRuntime::Dedicated)
+    }
+
+    /// Create a cluster manager whose shards run in the given mode, the
+    /// shard threads on the given runtime
+    ///
+    /// The shared runtime is the one this is called from, which must be a
+    /// multi-threaded one: the shards execute their commands synchronously,
+    /// each one holding a worker thread while it does.
+    pub fn with_runtime(
+        num_shards: usize,
+        aof_enabled: bool,
+        mode: ShardMode,
+        runtime: ShardRuntime,
+    ) -> anyhow::Result<Self> {
+        Self::build(num_shards, aof_enabled, 0, None, mode, runtime)
+    }
+
+    /// Create a cluster manager pre-sizing each shard for `expected_keys`
+    /// keys in total (split evenly across shards, 0 = default capacity)
+    pub fn with_expected_keys(num_shards: usize, aof_enabled: bool, expected_keys: usize) -> anyhow::Result<Self> {
+        Self::build(num_shards, aof_enabled, expected_keys, None, ShardMode::Actor, ShardRuntime::Dedicated)
+    }
+
+    /// Create a cluster manager like `with_runtime`, recording write
+    /// commands in an audit log (a single file shared by all shards) when
+    /// `audit.enabled` is set, and pre-sizing the shards for
+    /// `expected-keys` keys
+    pub fn with_audit_log(
+        num_shards: usize,
+        aof_enabled: bool,
+        mode: ShardMode,
+        runtime: ShardRuntime,
+        audit: &AuditConfig,
+    ) -> anyhow::Result<Self> {
+        let audit_logger = if audit.enabled {
+            info!("Audit log enabled at {:?}", audit.path);
+            Some(Arc::new(AuditLogger::new(&audit.path)?))
+        } else {
+            None
+        };
+        Self::build(num_shards, aof_enabled, expected_keys(), audit_logger, mode, runtime)
+    }
+
+    fn build(
+        num_shards: usize,
+        aof_enabled: bool,
+        expected_keys: usize,
+        audit_logger: Option<Arc<AuditLogger>>,
+        mode: ShardMode,
+        runtime: ShardRuntime,
+    ) -> anyhow::Result<Self> {
+        info!("Initializing cluster with {} shards ({} mode)", num_shards, mode.name());
 
-        let mut shards = Vec::with_capacity(num_shards);
+        let mut configs = Vec::with_capacity(num_shards);
 
         for shard_id in 0..num_shards {
-            let config = ShardConfig {
+            configs.push(ShardConfig {
                 shard_id,
                 aof_enabled,
                 aof_path: if aof_enabled {
@@ -37,32 +234,101 @@ impl ClusterManager {
                 } else {
                     None
                 },
-            };
-
-            let shard = Shard::new(config)?;
-            shards.push(Arc::new(shard));
+                expected_keys: expected_keys / num_shards.max(1),
+                replay_batch_size: 0,
+                aof_rewrite_percentage: auto_rewrite_percentage(),
+                aof_rewrite_min_size: auto_rewrite_min_size(),
+                audit_logger: audit_logger.clone(),
+            });
         }
 
-        let router = ShardRouter::new(num_shards);
+        Self::with_shard_configs(configs, mode, runtime)
+    }
+
+    /// Create a cluster manager with one shard per configuration
+    fn with_shard_configs(configs: Vec<ShardConfig>, mode: ShardMode, runtime: ShardRuntime) -> anyhow::Result<Self> {
+        let num_shards = configs.len();
+        let shards = match mode {
+            ShardMode::Actor => Shards::Actors(
+                configs
+                    .into_iter()
+                    .map(|config| Shard::with_runtime(config, runtime).map(Arc::new))
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            ShardMode::Striped => Shards::Striped(StripedStore::new(configs)?),
+        };
+
+        let router = ShardRouter::with_hash_tags(num_shards, hash_tags());
 
         info!("Cluster initialized with {} shards", num_shards);
 
-        Ok(ClusterManager { shards, router })
+        Ok(ClusterManager { shards, router, registry: CommandRegistry::new() })
     }
 
     /// Execute a command on the appropriate shard
     pub async fn execute(&self, command: RespValue) -> RespValue {
+        self.execute_for_client(command, ClientOptions::default()).await
+    }
+
+    /// Execute a command on behalf of a client with the given options
+    pub async fn execute_for_client(&self, command: RespValue, client: ClientOptions) -> RespValue {
+        // BITOP reads keys that may live on several shards
+        if command_name_is(&command, "BITOP") {
+            return self.execute_bitop(command, client).await;
+        }
+
+        // SCAN iterates over the shards one after the other
+        if command_name_is(&command, "SCAN") {
+            return self.execute_scan(command, client).await;
+        }
+
+        // KEYS goes through the keys of every shard
+        if command_name_is(&command, "KEYS") {
+            return self.execute_keys(command, client).await;
+        }
+
+        // Set algebra sources may live on several shards
+        let set_op = command.as_array().and_then(|parts| SetOp::from_store_command(parts.first()?.as_bulk_string()?));
+        if let Some(op) = set_op {
+            return self.execute_set_store(op, command, client).await;
+        }
+
+        // COPY source and destination may live on different shards
+        if command_name_is(&command, "COPY") {
+            return self.execute_copy(command, client).await;
+        }
+
+        // MSET keys may live on several shards
+        if command_name_is(&command, "MSET") {
+            return self.execute_mset(command, client).await;
+        }
+
+        // Each shard flushes or rewrites its own store and AOF
+        if ["FLUSHDB", "FLUSHALL", "BGREWRITEAOF"].iter().any(|name| command_name_is(&command, name)) {
+            return self.execute_on_all_shards(command, client).await;
+        }
+
         // Extract the key from the command to determine the shard
-        let shard_id = match self.extract_key_and_route(&command) {
-            Some(id) => id,
-            None => {
-                // Commands without keys (like INFO, FLUSHDB) go to shard 0
-                0
-            }
-        };
+        // Commands without keys (like INFO) go to shard 0
+        let shard_id = self.extract_key_and_route(&command).unwrap_or_default();
 
+        self.execute_on_shard(shard_id, command, client).await
+    }
+
+    /// Send a command to a specific shard and wait for its response
+    async fn execute_on_shard(&self, shard_id: usize, command: RespValue, client: ClientOptions) -> RespValue {
         // Get the shard
-        let shard = &self.shards[shard_id];
+        let shard = match &self.shards {
+            Shards::Actors(shards) => &shards[shard_id],
+            Shards::Striped(striped) => {
+                // DEBUG SLEEP ... ASYNC must not hold the stripe's lock
+                if let Some(duration) = async_sleep_duration(&command) {
+                    tokio::time::sleep(duration).await;
+                    return RespValue::simple_string("OK");
+                }
+                return striped.execute_async(shard_id, command, client).await;
+            }
+        };
 
         // Create a oneshot channel for the response
         let (tx, rx) = oneshot::channel();
@@ -71,11 +337,14 @@ impl ClusterManager {
         let shard_command = ShardCommand {
             command,
             response_tx: tx,
+            client,
         };
 
+        // A closed channel means the shard's thread is gone: its keys stay
+        // unreachable, so say so rather than hang or fail vaguely
         if let Err(e) = shard.send_command(shard_command).await {
             error!("Failed to send command to shard {}: {}", shard_id, e);
-            return RespValue::error("ERR internal error");
+            return RespValue::error(format!("ERR shard {} is down", shard_id));
         }
 
         // Wait for response
@@ -83,55 +352,405 @@ impl ClusterManager {
             Ok(response) => response,
             Err(_) => {
                 error!("Shard {} did not respond", shard_id);
-                RespValue::error("ERR shard did not respond")
+                RespValue::error(format!("ERR shard {} is down", shard_id))
             }
         }
     }
 
-    /// Extract the key from a command and route to shard
-    fn extract_key_and_route(&self, command: &RespValue) -> Option<usize> {
-        if let RespValue::Array(parts) = command {
-            if parts.len() < 2 {
-                return None;
+    /// Execute single-key commands, each shard receiving all of its own
+    /// before any reply is awaited
+    ///
+    /// Saves a round-trip per command when many are sent at once (e.g. the
+    /// RESTOREs of an import); the stripes run theirs in turn. The replies
+    /// are returned in the order of the commands. The commands are routed
+    /// by their key only: none of the cross-shard handling of `execute`.
+    pub async fn execute_batch(&self, commands: Vec<RespValue>) -> Vec<RespValue> {
+        let client = ClientOptions::default();
+        let shards = match &self.shards {
+            Shards::Actors(shards) => shards,
+            Shards::Striped(striped) => {
+                let mut replies = Vec::with_capacity(commands.len());
+                for command in commands {
+                    let shard_id = self.extract_key_and_route(&command).unwrap_or_default();
+                    replies.push(striped.execute_async(shard_id, command, client).await);
+                }
+                return replies;
             }
+        };
+
+        let mut pending = Vec::with_capacity(commands.len());
+        for command in commands {
+            let shard_id = self.extract_key_and_route(&command).unwrap_or_default();
+            let (tx, rx) = oneshot::channel();
+            let sent = shards[shard_id].send_command(ShardCommand { command, response_tx: tx, client }).await;
+            pending.push((shard_id, sent.ok().map(|_| rx)));
+        }
 
-            // Get the command name
-            let cmd_name = match &parts[0] {
-                RespValue::BulkString(b) => std::str::from_utf8(b).ok()?,
-                _ => return None,
+        let mut replies = Vec::with_capacity(pending.len());
+        for (shard_id, rx) in pending {
+            let reply = match rx {
+                Some(rx) => rx.await.ok(),
+                None => None,
             };
+            replies.push(reply.unwrap_or_else(|| RespValue::error(format!("ERR shard {} is down", shard_id))));
+        }
+        replies
+    }
 
-            // Commands without keys
-            let no_key_commands = ["INFO", "FLUSHDB", "PING"];
-            if no_key_commands.contains(&cmd_name.to_uppercase().as_str()) {
-                return None;
+    /// Send a command to every shard, returning the first error if any,
+    /// otherwise the reply of the last shard
+    async fn execute_on_all_shards(&self, command: RespValue, client: ClientOptions) -> RespValue {
+        let mut reply = RespValue::simple_string("OK");
+        for shard_id in 0..self.num_shards() {
+            reply = self.execute_on_shard(shard_id, command.clone(), client).await;
+            if matches!(reply, RespValue::Error(_)) {
+                return reply;
             }
+        }
+        reply
+    }
 
-            // Extract the key (second element for most commands)
-            let key = match &parts[1] {
-                RespValue::BulkString(b) => b,
-                _ => return None,
+    /// Execute MSET by splitting its pairs by shard
+    ///
+    /// Each shard receives an MSET of its own keys, so each write lands in
+    /// (and is logged by) the shard the key routes to. The whole command is
+    /// not atomic across shards. Invalid commands are forwarded as-is for the
+    /// shard to produce the error.
+    async fn execute_mset(&self, command: RespValue, client: ClientOptions) -> RespValue {
+        let parts = match &command {
+            RespValue::Array(parts) if parts.len() >= 3 && parts.len() % 2 == 1 => parts,
+            _ => return self.execute_on_shard(0, command, client).await,
+        };
+
+        let mut per_shard: Vec<Vec<RespValue>> = vec![Vec::new(); self.num_shards()];
+        for pair in parts[1..].chunks(2) {
+            let Some(key) = pair[0].as_bulk_string() else {
+                return self.execute_on_shard(0, command, client).await;
             };
+            per_shard[self.router.route_key(key)].extend_from_slice(pair);
+        }
+
+        for (shard_id, pairs) in per_shard.into_iter().enumerate() {
+            if pairs.is_empty() {
+                continue;
+            }
+            let mut mset = Vec::with_capacity(pairs.len() + 1);
+            mset.push(parts[0].clone());
+            mset.extend(pairs);
+            if let error @ RespValue::Error(_) = self.execute_on_shard(shard_id, RespValue::Array(mset), client).await {
+                return error;
+            }
+        }
+        RespValue::simple_string("OK")
+    }
+
+    /// Execute COPY when the source and destination live on different shards
+    ///
+    /// The source is read with DUMP and PTTL on its shard and written with
+    /// RESTORE on the destination's shard, so it is logged to that shard's
+    /// AOF. When both keys live on the same shard, or the command is
+    /// invalid, it is forwarded as-is and the shard produces the reply.
+    async fn execute_copy(&self, command: RespValue, client: ClientOptions) -> RespValue {
+        let parts = match &command {
+            RespValue::Array(parts) if parts.len() >= 3 => parts,
+            _ => return self.execute_on_shard(0, command, client).await,
+        };
+        let (source, destination) = match (parts[1].as_bulk_string(), parts[2].as_bulk_string()) {
+            (Some(source), Some(destination)) => (source.clone(), destination.clone()),
+            _ => return self.execute_on_shard(0, command, client).await,
+        };
+
+        let source_shard = self.router.route_key(&source);
+        let dest_shard = self.router.route_key(&destination);
+        if source_shard == dest_shard {
+            return self.execute_on_shard(source_shard, command, client).await;
+        }
+        let replace = match parse_copy_options(&parts[3..]) {
+            Ok(replace) => replace,
+            Err(error) => return error,
+        };
+
+        let dump = RespValue::array(vec![
+            RespValue::bulk_string("DUMP"),
+            RespValue::bulk_string(source),
+            RespValue::bulk_string("WITHTTL"),
+        ]);
+        let (payload, ttl_ms) = match self.execute_on_shard(source_shard, dump, client).await {
+            RespValue::Array(reply) => match reply.as_slice() {
+                [RespValue::BulkString(payload), RespValue::Integer(-1)] => (payload.clone(), 0),
+                [RespValue::BulkString(payload), RespValue::Integer(ms)] => (payload.clone(), (*ms).max(1)),
+                _ => {
+                    error!("Unexpected DUMP reply while copying across shards: {:?}", reply);
+                    return RespValue::error("ERR internal error");
+                }
+            },
+            RespValue::Null => return RespValue::integer(0),
+            error @ RespValue::Error(_) => return error,
+            other => {
+                error!("Unexpected DUMP reply while copying across shards: {:?}", other);
+                return RespValue::error("ERR internal error");
+            }
+        };
+
+        let mut restore = vec![
+            RespValue::bulk_string("RESTORE"),
+            RespValue::bulk_string(destination),
+            RespValue::bulk_string(ttl_ms.to_string()),
+            RespValue::bulk_string(payload),
+        ];
+        if replace {
+            restore.push(RespValue::bulk_string("REPLACE"));
+        }
+        match self.execute_on_shard(dest_shard, RespValue::array(restore), client).await {
+            RespValue::Error(error) if error.starts_with("BUSYKEY") => RespValue::integer(0),
+            error @ RespValue::Error(_) => error,
+            _ => RespValue::integer(1),
+        }
+    }
+
+    /// Execute BITOP by gathering the sources across shards
+    ///
+    /// Sources are read with GET on their own shard, the result is computed
+    /// here and written with SET (or DEL when empty) on the destination's
+    /// shard, so it is logged to that shard's AOF. When every key lives on
+    /// the destination's shard, or the command is invalid, it is forwarded
+    /// as-is and the shard produces the reply.
+    async fn execute_bitop(&self, command: RespValue, client: ClientOptions) -> RespValue {
+        let parts = match &command {
+            RespValue::Array(parts) if parts.len() >= 4 => parts,
+            _ => return self.execute_on_shard(0, command, client).await,
+        };
+
+        let keys: Option<Vec<Bytes>> = parts[2..].iter().map(|p| p.as_bulk_string().cloned()).collect();
+        let op = parts[1].as_bulk_string().and_then(|name| BitOp::parse(name));
+        let (op, keys) = match (op, keys) {
+            (Some(op), Some(keys)) if op != BitOp::Not || keys.len() == 2 => (op, keys),
+            _ => return self.execute_on_shard(self.extract_key_and_route(&command).unwrap_or_default(), command, client).await,
+        };
 
-            Some(self.router.route_key(key))
+        let dest = keys[0].clone();
+        let dest_shard = self.router.route_key(&dest);
+        if keys[1..].iter().all(|key| self.router.route_key(key) == dest_shard) {
+            return self.execute_on_shard(dest_shard, command, client).await;
+        }
+
+        let mut sources = Vec::with_capacity(keys.len() - 1);
+        for key in &keys[1..] {
+            let get = RespValue::array(vec![RespValue::bulk_string("GET"), RespValue::bulk_string(key.clone())]);
+            match self.execute_on_shard(self.router.route_key(key), get, client).await {
+                RespValue::BulkString(bytes) => sources.push(bytes),
+                RespValue::Null => sources.push(Bytes::new()),
+                error @ RespValue::Error(_) => return error,
+                other => {
+                    error!("Unexpected GET reply while gathering BITOP sources: {:?}", other);
+                    return RespValue::error("ERR internal error");
+                }
+            }
+        }
+
+        let result = bitop(op, &sources);
+        let len = result.len() as i64;
+        let write = if result.is_empty() {
+            RespValue::array(vec![RespValue::bulk_string("DEL"), RespValue::bulk_string(dest)])
         } else {
-            None
+            RespValue::array(vec![
+                RespValue::bulk_string("SET"),
+                RespValue::bulk_string(dest),
+                RespValue::bulk_string(result),
+            ])
+        };
+
+        match self.execute_on_shard(dest_shard, write, client).await {
+            error @ RespValue::Error(_) => error,
+            _ => RespValue::integer(len),
         }
     }
 
+    /// Execute SUNIONSTORE, SINTERSTORE or SDIFFSTORE by gathering the
+    /// sources across shards
+    ///
+    /// Like BITOP: sources are read with DUMP on their own shard, and the
+    /// result is computed here once its estimated size passes the
+    /// `maxmemory` check. It replaces the destination with a single RESTORE
+    /// ... REPLACE on the destination's shard (a DEL when it is empty), so
+    /// the destination is never seen half written, and the write is logged
+    /// to that shard's AOF. When every key lives on the destination's shard,
+    /// or the command is invalid, it is forwarded as-is.
+    async fn execute_set_store(&self, op: SetOp, command: RespValue, client: ClientOptions) -> RespValue {
+        let parts = match &command {
+            RespValue::Array(parts) if parts.len() >= 3 => parts,
+            _ => return self.execute_on_shard(0, command, client).await,
+        };
+        let keys = match parts[1..].iter().map(|p| p.as_bulk_string().cloned()).collect::<Option<Vec<Bytes>>>() {
+            Some(keys) => keys,
+            None => return self.execute_on_shard(0, command, client).await,
+        };
+
+        let dest = keys[0].clone();
+        let dest_shard = self.router.route_key(&dest);
+        if keys[1..].iter().all(|key| self.router.route_key(key) == dest_shard) {
+            return self.execute_on_shard(dest_shard, command, client).await;
+        }
+
+        let mut sources = Vec::with_capacity(keys.len() - 1);
+        for key in &keys[1..] {
+            let dump = RespValue::array(vec![RespValue::bulk_string("DUMP"), RespValue::bulk_string(key.clone())]);
+            let source = match self.execute_on_shard(self.router.route_key(key), dump, client).await {
+                RespValue::BulkString(payload) => store::restore_value(&payload),
+                RespValue::Null => Ok(Value::empty_set()),
+                error @ RespValue::Error(_) => return error,
+                other => {
+                    error!("Unexpected DUMP reply while gathering set sources: {:?}", other);
+                    return RespValue::error("ERR internal error");
+                }
+            };
+            match source {
+                Ok(Value::Set(set)) => sources.push(set),
+                Ok(_) => return RespValue::error(WRONGTYPE),
+                Err(e) => {
+                    error!("Unreadable DUMP payload while gathering set sources: {}", e);
+                    return RespValue::error("ERR internal error");
+                }
+            }
+        }
+
+        let sets: Vec<_> = sources.iter().collect();
+        if store::exceeds_maxmemory(op.estimated_size(&sets)) {
+            return RespValue::error(OOM_ERROR);
+        }
+        let result = op.apply(&sets);
+        let len = result.len() as i64;
+
+        let write = if result.is_empty() {
+            vec![RespValue::bulk_string("DEL"), RespValue::bulk_string(dest)]
+        } else {
+            vec![
+                RespValue::bulk_string("RESTORE"),
+                RespValue::bulk_string(dest),
+                RespValue::bulk_string("0"),
+                RespValue::bulk_string(store::dump_value(&Value::Set(result))),
+                RespValue::bulk_string("REPLACE"),
+            ]
+        };
+        match self.execute_on_shard(dest_shard, RespValue::array(write), client).await {
+            error @ RespValue::Error(_) => error,
+            _ => RespValue::integer(len),
+        }
+    }
+
+    /// Execute SCAN across all shards
+    ///
+    /// The cluster cursor encodes the shard being scanned in its low part
+    /// (`shard_cursor * num_shards + shard_id`). When a shard's iteration
+    /// completes, the returned cursor points at the start of the next shard,
+    /// and 0 is only returned once the last shard is done.
+    async fn execute_scan(&self, command: RespValue, client: ClientOptions) -> RespValue {
+        let mut parts = match command {
+            RespValue::Array(parts) if parts.len() >= 2 => parts,
+            other => return self.execute_on_shard(0, other, client).await,
+        };
+
+        let cursor = match parts[1]
+            .as_bulk_string()
+            .and_then(|c| std::str::from_utf8(c).ok())
+            .and_then(|c| c.parse::<u64>().ok())
+        {
+            Some(c) => c,
+            None => return RespValue::error("ERR invalid cursor"),
+        };
+
+        let num_shards = self.num_shards() as u64;
+        let shard_id = (cursor % num_shards) as usize;
+        parts[1] = RespValue::bulk_string((cursor / num_shards).to_string());
+
+        let reply = self.execute_on_shard(shard_id, RespValue::Array(parts), client).await;
+        let mut reply_parts = match reply {
+            RespValue::Array(reply_parts) if reply_parts.len() == 2 => reply_parts,
+            other => return other,
+        };
+
+        let shard_cursor = match reply_parts[0]
+            .as_bulk_string()
+            .and_then(|c| std::str::from_utf8(c).ok())
+            .and_then(|c| c.parse::<u64>().ok())
+        {
+            Some(c) => c,
+            None => return RespValue::error("ERR internal error"),
+        };
+
+        let next_cursor = if shard_cursor != 0 {
+            shard_cursor * num_shards + shard_id as u64
+        } else if shard_id + 1 < self.num_shards() {
+            shard_id as u64 + 1
+        } else {
+            0
+        };
+
+        reply_parts[0] = RespValue::bulk_string(next_cursor.to_string());
+        RespValue::Array(reply_parts)
+    }
+
+    /// Execute KEYS on every shard and concatenate the keys they match
+    ///
+    /// `keys-scan-limit` bounds the keys of all the shards together: the
+    /// call is aborted before any shard is scanned when they hold more keys
+    /// than that. A disabled or invalid KEYS is forwarded to shard 0 for it
+    /// to produce the error.
+    async fn execute_keys(&self, command: RespValue, client: ClientOptions) -> RespValue {
+        if disable_keys_command() || command.as_array().is_none_or(|parts| parts.len() != 2) {
+            return self.execute_on_shard(0, command, client).await;
+        }
+
+        let limit = keys_scan_limit();
+        if limit > 0 {
+            let mut total_keys = 0;
+            for shard_id in 0..self.num_shards() {
+                total_keys += self.shard_stats(shard_id).await.active_keys;
+            }
+            if total_keys > limit {
+                return keys_scan_aborted(limit);
+            }
+        }
+
+        let mut keys = Vec::new();
+        for shard_id in 0..self.num_shards() {
+            match self.execute_on_shard(shard_id, command.clone(), client).await {
+                RespValue::Array(shard_keys) => keys.extend(shard_keys),
+                other => return other,
+            }
+        }
+        RespValue::Array(keys)
+    }
+
+    /// Extract the key from a command and route to shard
+    ///
+    /// The key is the first one of the command's key spec
+    /// (`CommandRegistry::get_keys`), such as the key of OBJECT ENCODING or
+    /// of the command wrapped by IDEMPOTENT. DEBUG is routed by the key of
+    /// its subcommand. Commands without keys, unknown ones and those with a
+    /// wrong number of arguments route nowhere.
+    fn extract_key_and_route(&self, command: &RespValue) -> Option<usize> {
+        let parts = command.as_array()?;
+        let key = match self.registry.get_keys(parts) {
+            Ok(keys) => keys.into_iter().next()?,
+            Err(_) => debug_subcommand_key(parts)?.clone(),
+        };
+        Some(self.router.route_key(&key))
+    }
+
     /// Get statistics from all shards
     pub async fn get_cluster_stats(&self) -> ClusterStats {
         let mut total_keys = 0;
         let mut total_memory = 0;
 
-        for shard in &self.shards {
-            let stats = shard.get_stats().await;
+        for shard_id in 0..self.num_shards() {
+            let stats = self.shard_stats(shard_id).await;
             total_keys += stats.active_keys;
             total_memory += stats.used_memory_bytes;
         }
 
         ClusterStats {
-            num_shards: self.shards.len(),
+            num_shards: self.num_shards(),
             total_keys,
             total_memory_bytes: total_memory,
         }
@@ -141,13 +760,14 @@ impl ClusterManager {
     pub async fn get_shard_details(&self) -> Vec<ShardStats> {
         let mut shard_stats = Vec::new();
 
-        for shard in &self.shards {
-            let store_stats = shard.get_stats().await;
+        for shard_id in 0..self.num_shards() {
+            let store_stats = self.shard_stats(shard_id).await;
             shard_stats.push(ShardStats {
-                shard_id: shard.id(),
+                shard_id,
                 active_keys: store_stats.active_keys,
                 total_keys: store_stats.total_keys,
                 expired_keys: store_stats.expired_keys,
+                volatile_keys: store_stats.volatile_keys,
                 memory_bytes: store_stats.used_memory_bytes,
             });
         }
@@ -155,9 +775,57 @@ impl ClusterManager {
         shard_stats
     }
 
+    /// Statistics of a shard's store
+    async fn shard_stats(&self, shard_id: usize) -> StoreStats {
+        match &self.shards {
+            Shards::Actors(shards) => shards[shard_id].get_stats().await,
+            Shards::Striped(striped) => striped.stats(shard_id),
+        }
+    }
+
+    /// Next batch of the NDJSON export of a shard (see `Shard::export_batch`)
+    ///
+    /// Start with cursor 0 and go on with the returned one until it is 0.
+    /// Returns None when the shard is down.
+    pub async fn export_batch(&self, shard_id: usize, cursor: u64, count: usize) -> Option<ExportBatch> {
+        match &self.shards {
+            Shards::Actors(shards) => shards[shard_id].export_batch(cursor, count).await,
+            Shards::Striped(striped) => Some(striped.export_batch(shard_id, cursor, count)),
+        }
+    }
+
+    /// Whether any shard is still replaying its AOF (striped stores replay
+    /// theirs before the cluster is built)
+    pub fn is_loading(&self) -> bool {
+        match &self.shards {
+            Shards::Actors(shards) => shards.iter().any(|shard| shard.is_loading()),
+            Shards::Striped(_) => false,
+        }
+    }
+
+    /// Number of AOF entries replayed by all the shards, final once none
+    /// of them is loading any more
+    pub fn replayed_entries(&self) -> u64 {
+        match &self.shards {
+            Shards::Actors(shards) => shards.iter().map(|shard| shard.replayed_entries()).sum(),
+            Shards::Striped(striped) => (0..self.num_shards()).map(|stripe_id| striped.replayed_entries(stripe_id)).sum(),
+        }
+    }
+
     /// Get number of shards
     pub fn num_shards(&self) -> usize {
-        self.shards.len()
+        self.router.num_shards()
+    }
+}
+
+/// Check whether a command array starts with the given command name
+fn command_name_is(command: &RespValue, name: &str) -> bool {
+    match command {
+        RespValue::Array(parts) => parts
+            .first()
+            .and_then(|p| p.as_bulk_string())
+            .is_some_and(|b| b.eq_ignore_ascii_case(name.as_bytes())),
+        _ => false,
     }
 }
 
@@ -175,6 +843,584 @@ pub struct ShardStats {
     pub shard_id: usize,
     pub active_keys: usize,
     pub total_keys: usize,
+    /// Keys expired but not reaped yet
     pub expired_keys: usize,
+    /// Keys with a TTL
+    pub volatile_keys: usize,
     pub memory_bytes: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{command, set_disable_keys_command, set_keys_scan_limit};
+
+    fn cmd(parts: &[&[u8]]) -> RespValue {
+        RespValue::array(parts.iter().map(|p| RespValue::bulk_string(p.to_vec())).collect())
+    }
+
+    #[tokio::test]
+    async fn test_malformed_commands_match_dispatcher() {
+        let cluster = ClusterManager::new(2, false).unwrap();
+        let mut dispatcher = crate::dispatch::Dispatcher::new();
+
+        let cases = [
+            (RespValue::array(vec![]), "ERR empty command array"),
+            (RespValue::Null, "ERR expected array"),
+            (RespValue::bulk_string("GET"), "ERR expected array"),
+            (RespValue::integer(1), "ERR expected array"),
+            (cmd(&[b"FOO", b"bar", b"baz"]), "ERR unknown command 'FOO', with args beginning with: 'bar' 'baz' "),
+        ];
+        for (command, expected) in cases {
+            assert_eq!(dispatcher.dispatch(command.clone()), RespValue::error(expected));
+            assert_eq!(cluster.execute(command).await, RespValue::error(expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replayed_entries_over_populated_aof() {
+        use crate::aof::{AofEntry, AofOperation, AofWriter, SyncPolicy};
+
+        for mode in [ShardMode::Actor, ShardMode::Striped] {
+            // 300 entries in the AOF of shard 0, 200 in that of shard 1
+            let configs: Vec<ShardConfig> = (0..2)
+                .map(|shard_id| {
+                    let name = format!("ferrumdb_replayed_{}_{}_{}.aof", mode.name(), shard_id, std::process::id());
+                    let path = std::env::temp_dir().join(name);
+                    let _ = std::fs::remove_file(&path);
+                    let writer = AofWriter::new(&path, SyncPolicy::No).unwrap();
+                    for i in 0..300 - 100 * shard_id {
+                        let key = Bytes::from(format!("key:{}", i));
+                        writer.write(&AofEntry::new(AofOperation::Set, key, vec![Bytes::from("v")])).unwrap();
+                    }
+                    writer.flush().unwrap();
+                    ShardConfig {
+                        shard_id,
+                        aof_enabled: true,
+                        aof_path: Some(path.to_string_lossy().into_owned()),
+                        expected_keys: 0,
+                        replay_batch_size: 7,
+                        aof_rewrite_percentage: 0,
+                        aof_rewrite_min_size: 0,
+                        audit_logger: None,
+                    }
+                })
+                .collect();
+            let paths: Vec<_> = configs.iter().filter_map(|config| config.aof_path.clone()).collect();
+
+            let cluster = ClusterManager::with_shard_configs(configs, mode, ShardRuntime::Dedicated).unwrap();
+            while cluster.is_loading() {
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+            assert_eq!(cluster.replayed_entries(), 500, "{} mode", mode.name());
+
+            drop(cluster);
+            for path in paths {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_in_every_mode() {
+        for mode in [ShardMode::Actor, ShardMode::Striped] {
+            let name = format!("ferrumdb_cluster_audit_{}_{}.log", mode.name(), std::process::id());
+            let path = std::env::temp_dir().join(name);
+            let _ = std::fs::remove_file(&path);
+            let audit = AuditConfig { path: path.clone(), enabled: true };
+            let cluster = ClusterManager::with_audit_log(2, false, mode, ShardRuntime::Dedicated, &audit).unwrap();
+
+            cluster.execute(command(&["SET", "audited", "v"])).await;
+            cluster.execute(command(&["GET", "audited"])).await;
+            cluster.execute(command(&["LPUSH", "audited", "v"])).await;
+
+            let log = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(log.lines().count(), 1, "{} mode: {:?}", mode.name(), log);
+            assert!(log.trim_end().ends_with(" SET audited"), "{} mode: {:?}", mode.name(), log);
+
+            drop(cluster);
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_tagged_key_of_old_aof() {
+        use crate::aof::{AofEntry, AofOperation, AofWriter, SyncPolicy};
+
+        // A tagged key that older versions, hashing whole keys, put on
+        // another shard than its tag would
+        let whole = ShardRouter::new(4);
+        let tagged = ShardRouter::with_hash_tags(4, true);
+        let key = (0..100)
+            .map(|id| Bytes::from(format!("user:{{{}}}:name", id)))
+            .find(|key| whole.route_key(key) != tagged.route_key(key))
+            .unwrap();
+
+        let configs: Vec<ShardConfig> = (0..4)
+            .map(|shard_id| {
+                let name = format!("ferrumdb_tagged_{}_{}.aof", shard_id, std::process::id());
+                let path = std::env::temp_dir().join(name);
+                let _ = std::fs::remove_file(&path);
+                let writer = AofWriter::new(&path, SyncPolicy::No).unwrap();
+                if shard_id == whole.route_key(&key) {
+                    writer.write(&AofEntry::new(AofOperation::Set, key.clone(), vec![Bytes::from("alice")])).unwrap();
+                }
+                writer.flush().unwrap();
+                ShardConfig {
+                    shard_id,
+                    aof_enabled: true,
+                    aof_path: Some(path.to_string_lossy().into_owned()),
+                    expected_keys: 0,
+                    replay_batch_size: 0,
+                    aof_rewrite_percentage: 0,
+                    aof_rewrite_min_size: 0,
+                    audit_logger: None,
+                }
+            })
+            .collect();
+        let paths: Vec<_> = configs.iter().filter_map(|config| config.aof_path.clone()).collect();
+
+        // With hash tags off (the default), the key is still found
+        let cluster = ClusterManager::with_shard_configs(configs, ShardMode::Actor, ShardRuntime::Dedicated).unwrap();
+        while cluster.is_loading() {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        let get = RespValue::Array(vec![RespValue::bulk_string("GET"), RespValue::BulkString(key)]);
+        assert_eq!(cluster.execute(get).await, RespValue::bulk_string("alice"));
+
+        drop(cluster);
+        for path in paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_time_served_by_shard_zero() {
+        let cluster = ClusterManager::new(4, false).unwrap();
+        let reply = cluster.execute(cmd(&[b"TIME"])).await;
+        let parts = reply.as_array().expect("Expected an array");
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|p| p.as_bulk_string().is_some_and(|b| b.iter().all(u8::is_ascii_digit))));
+    }
+
+    #[tokio::test]
+    async fn test_mset_across_shards() {
+        let cluster = ClusterManager::new(4, false).unwrap();
+        let mut mset: Vec<Vec<u8>> = vec![b"MSET".to_vec()];
+        for i in 0..20 {
+            mset.push(format!("key:{}", i).into_bytes());
+            mset.push(format!("value:{}", i).into_bytes());
+        }
+        let mset: Vec<&[u8]> = mset.iter().map(|p| p.as_slice()).collect();
+        assert_eq!(cluster.execute(cmd(&mset)).await, RespValue::simple_string("OK"));
+
+        // Every key is found on the shard GET routes it to
+        for i in 0..20 {
+            let key = format!("key:{}", i);
+            let value = format!("value:{}", i);
+            assert_eq!(cluster.execute(cmd(&[b"GET", key.as_bytes()])).await, RespValue::bulk_string(value));
+        }
+
+        assert_eq!(
+            cluster.execute(cmd(&[b"MSET", b"a", b"1", b"b"])).await,
+            RespValue::error("ERR wrong number of arguments for 'MSET' command")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_subcommand_keys_routed_to_key_shard() {
+        let cluster = ClusterManager::new(4, false).unwrap();
+
+        // The key is the third element of OBJECT and DEBUG SET-EXPIRE
+        for i in 0..20 {
+            let key = format!("key:{}", i);
+            cluster.execute(cmd(&[b"SET", key.as_bytes(), b"42"])).await;
+            let encoding = cluster.execute(cmd(&[b"OBJECT", b"ENCODING", key.as_bytes()])).await;
+            assert_eq!(encoding, RespValue::bulk_string("int"));
+            let reply = cluster.execute(cmd(&[b"DEBUG", b"SET-EXPIRE", key.as_bytes(), b"0"])).await;
+            assert_eq!(reply, RespValue::simple_string("OK"));
+            assert_eq!(cluster.execute(cmd(&[b"GET", key.as_bytes()])).await, RespValue::null());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routed_by_key_spec() {
+        let cluster = ClusterManager::new(4, false).unwrap();
+        let route = |parts: &[&[u8]]| cluster.extract_key_and_route(&cmd(parts));
+        let shard_of = |key: &[u8]| Some(cluster.router.route_key(&Bytes::copy_from_slice(key)));
+
+        assert_eq!(route(&[b"GET", b"key:1"]), shard_of(b"key:1"));
+        assert_eq!(route(&[b"OBJECT", b"ENCODING", b"key:2"]), shard_of(b"key:2"));
+        assert_eq!(route(&[b"IDEMPOTENT", b"token", b"INCR", b"key:3"]), shard_of(b"key:3"));
+        assert_eq!(route(&[b"MIGRATE", b"host", b"6379", b"key:4", b"0", b"1000"]), shard_of(b"key:4"));
+        assert_eq!(route(&[b"DEBUG", b"SET-LRU", b"key:5", b"10"]), shard_of(b"key:5"));
+
+        // No key to route by
+        assert_eq!(route(&[b"PING"]), None);
+        assert_eq!(route(&[b"ECHO", b"key:1"]), None);
+        assert_eq!(route(&[b"CONFIG", b"GET", b"hz"]), None);
+        assert_eq!(route(&[b"DEBUG", b"SLEEP", b"0"]), None);
+        assert_eq!(route(&[b"NOSUCHCOMMAND", b"key:1"]), None);
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_routed_to_key_shard() {
+        let cluster = ClusterManager::new(4, false).unwrap();
+
+        // Each retry reaches the shard that remembers its token
+        for i in 0..20 {
+            let (token, key) = (format!("token:{}", i), format!("counter:{}", i));
+            let incr = cmd(&[b"IDEMPOTENT", token.as_bytes(), b"INCR", key.as_bytes()]);
+            assert_eq!(cluster.execute(incr.clone()).await, RespValue::integer(1));
+            assert_eq!(cluster.execute(incr).await, RespValue::integer(1));
+            assert_eq!(cluster.execute(cmd(&[b"GET", key.as_bytes()])).await, RespValue::bulk_string("1"));
+        }
+
+        // Keys of a multi-key command could live on several shards
+        let mset = cmd(&[b"IDEMPOTENT", b"tok", b"MSET", b"k0", b"v", b"k1", b"v", b"k2", b"v"]);
+        assert_eq!(
+            cluster.execute(mset).await,
+            RespValue::error("ERR IDEMPOTENT can only wrap single-key commands, not 'MSET'")
+        );
+        assert_eq!(cluster.execute(cmd(&[b"EXISTS", b"k0", b"k1", b"k2"])).await, RespValue::integer(0));
+    }
+
+    #[tokio::test]
+    async fn test_bitop_across_shards() {
+        let cluster = ClusterManager::new(4, false).unwrap();
+
+        // Enough keys to spread the sources over several shards
+        let keys: Vec<String> = (0..8).map(|i| format!("src{}", i)).collect();
+        for (i, key) in keys.iter().enumerate() {
+            let value = vec![1u8 << i; i + 1];
+            cluster.execute(cmd(&[b"SET", key.as_bytes(), &value])).await;
+        }
+
+        let mut args: Vec<&[u8]> = vec![b"BITOP", b"OR", b"dest"];
+        args.extend(keys.iter().map(|k| k.as_bytes()));
+        assert_eq!(cluster.execute(cmd(&args)).await, RespValue::integer(8));
+
+        let expected: Vec<u8> = (0..8).map(|i| (0xFFu16 << i) as u8).collect();
+        assert_eq!(cluster.execute(cmd(&[b"GET", b"dest"])).await, RespValue::bulk_string(expected));
+
+        // An empty result deletes the destination wherever it lives
+        assert_eq!(cluster.execute(cmd(&[b"BITOP", b"AND", b"dest", b"missing1", b"missing2"])).await, RespValue::integer(0));
+        assert_eq!(cluster.execute(cmd(&[b"EXISTS", b"dest"])).await, RespValue::integer(0));
+
+        // Validation errors come from the shard
+        let result = cluster.execute(cmd(&[b"BITOP", b"NOT", b"dest", b"src0", b"src1"])).await;
+        assert_eq!(result, RespValue::error("ERR BITOP NOT must be called with a single source key."));
+    }
+
+    #[tokio::test]
+    async fn test_set_store_across_shards() {
+        let cluster = ClusterManager::new(4, false).unwrap();
+        let keys: Vec<String> = (0..8).map(|i| format!("set{}", i)).collect();
+        for (i, key) in keys.iter().enumerate() {
+            cluster.execute(cmd(&[b"SADD", key.as_bytes(), b"common", format!("only{}", i).as_bytes()])).await;
+        }
+        let sources = |name: &'static [u8]| {
+            let mut args: Vec<&[u8]> = vec![name, b"dest"];
+            args.extend(keys.iter().map(|k| k.as_bytes()));
+            cmd(&args)
+        };
+
+        assert_eq!(cluster.execute(sources(b"SUNIONSTORE")).await, RespValue::integer(9));
+        assert_eq!(cluster.execute(cmd(&[b"SCARD", b"dest"])).await, RespValue::integer(9));
+        assert_eq!(cluster.execute(sources(b"SINTERSTORE")).await, RespValue::integer(1));
+        assert_eq!(cluster.execute(cmd(&[b"SMEMBERS", b"dest"])).await, RespValue::array(vec![RespValue::bulk_string("common")]));
+
+        // An empty result deletes the destination wherever it lives
+        assert_eq!(cluster.execute(sources(b"SDIFFSTORE")).await, RespValue::integer(1));
+        assert_eq!(cluster.execute(cmd(&[b"SDIFFSTORE", b"dest", b"missing", b"set0"])).await, RespValue::integer(0));
+        assert_eq!(cluster.execute(cmd(&[b"EXISTS", b"dest"])).await, RespValue::integer(0));
+
+        // The destination is replaced whole, TTL and type included
+        cluster.execute(cmd(&[b"SET", b"dest", b"string", b"EX", b"100"])).await;
+        assert_eq!(cluster.execute(sources(b"SINTERSTORE")).await, RespValue::integer(1));
+        assert_eq!(cluster.execute(cmd(&[b"TTL", b"dest"])).await, RespValue::integer(-1));
+        assert_eq!(cluster.execute(cmd(&[b"SMEMBERS", b"dest"])).await, RespValue::array(vec![RespValue::bulk_string("common")]));
+
+        // A source holding another type is an error, wherever it lives
+        let router = ShardRouter::new(4);
+        let string_source = (0..)
+            .map(|i| Bytes::from(format!("str{}", i)))
+            .find(|key| router.route_key(key) != router.route_key(&Bytes::from("dest")))
+            .unwrap();
+        cluster.execute(cmd(&[b"SET", &string_source, b"v"])).await;
+        let reply = cluster.execute(cmd(&[b"SUNIONSTORE", b"dest", b"set0", &string_source])).await;
+        assert_eq!(reply, RespValue::error(WRONGTYPE));
+        assert_eq!(cluster.execute(cmd(&[b"SCARD", b"dest"])).await, RespValue::integer(1));
+    }
+
+    #[tokio::test]
+    async fn test_copy_across_shards() {
+        let cluster = ClusterManager::new(4, false).unwrap();
+        let router = ShardRouter::new(4);
+        let source = Bytes::from("src");
+        let destination = (0..)
+            .map(|i| Bytes::from(format!("dst{}", i)))
+            .find(|key| router.route_key(key) != router.route_key(&source))
+            .unwrap();
+
+        cluster.execute(cmd(&[b"SET", &source, b"value", b"EX", b"100"])).await;
+        let copy = cmd(&[b"COPY", &source, &destination, b"DB", b"0"]);
+        assert_eq!(cluster.execute(copy.clone()).await, RespValue::integer(1));
+        assert_eq!(cluster.execute(cmd(&[b"GET", &destination])).await, RespValue::bulk_string("value"));
+        assert_eq!(cluster.execute(cmd(&[b"GET", &source])).await, RespValue::bulk_string("value"));
+        match cluster.execute(cmd(&[b"PTTL", &destination])).await {
+            RespValue::Integer(ms) => assert!((99_000..=100_000).contains(&ms), "{}", ms),
+            other => panic!("Expected integer response, got {:?}", other),
+        }
+
+        // The destination now exists, only REPLACE overwrites it
+        cluster.execute(cmd(&[b"SET", &source, b"new"])).await;
+        assert_eq!(cluster.execute(copy).await, RespValue::integer(0));
+        assert_eq!(cluster.execute(cmd(&[b"COPY", &source, &destination, b"REPLACE"])).await, RespValue::integer(1));
+        assert_eq!(cluster.execute(cmd(&[b"GET", &destination])).await, RespValue::bulk_string("new"));
+        assert_eq!(cluster.execute(cmd(&[b"TTL", &destination])).await, RespValue::integer(-1));
+
+        assert_eq!(cluster.execute(cmd(&[b"COPY", b"missing", &destination])).await, RespValue::integer(0));
+    }
+
+    #[tokio::test]
+    async fn test_copy_to_another_db_is_rejected() {
+        let cluster = ClusterManager::new(4, false).unwrap();
+        let router = ShardRouter::new(4);
+        let source = Bytes::from("src");
+        let same_shard = (0..)
+            .map(|i| Bytes::from(format!("dst{}", i)))
+            .find(|key| router.route_key(key) == router.route_key(&source))
+            .unwrap();
+        let other_shard = (0..)
+            .map(|i| Bytes::from(format!("dst{}", i)))
+            .find(|key| router.route_key(key) != router.route_key(&source))
+            .unwrap();
+        cluster.execute(cmd(&[b"SET", &source, b"value"])).await;
+
+        // FerrumDB has a single database: COPY ... DB 1 copies nothing and
+        // keeps the source, whichever shard the destination is on
+        for destination in [&same_shard, &other_shard] {
+            assert_eq!(
+                cluster.execute(cmd(&[b"COPY", &source, destination, b"DB", b"1"])).await,
+                RespValue::error("ERR DB index is out of range")
+            );
+            assert_eq!(cluster.execute(cmd(&[b"EXISTS", destination])).await, RespValue::integer(0));
+        }
+        assert_eq!(cluster.execute(cmd(&[b"GET", &source])).await, RespValue::bulk_string("value"));
+    }
+
+    #[tokio::test]
+    async fn test_debug_sleep_async_does_not_block_shard() {
+        // A single shard, so both commands go through the same loop
+        let cluster = Arc::new(ClusterManager::new(1, false).unwrap());
+
+        let sleeping = cluster.clone();
+        let sleeper = tokio::spawn(async move {
+            sleeping.execute(cmd(&[b"DEBUG", b"SLEEP", b"0.5", b"ASYNC"])).await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        let start = std::time::Instant::now();
+        assert_eq!(cluster.execute(cmd(&[b"SET", b"other", b"v"])).await, RespValue::simple_string("OK"));
+        assert!(start.elapsed() < std::time::Duration::from_millis(250));
+        assert!(!sleeper.is_finished());
+
+        assert_eq!(sleeper.await.unwrap(), RespValue::simple_string("OK"));
+    }
+
+    #[tokio::test]
+    async fn test_scan_across_shards() {
+        let cluster = ClusterManager::new(4, false).unwrap();
+        for i in 0..200 {
+            let key = format!("key:{}", i);
+            cluster.execute(cmd(&[b"SET", key.as_bytes(), b"v"])).await;
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        let mut cursor = b"0".to_vec();
+        loop {
+            let reply = cluster.execute(cmd(&[b"SCAN", &cursor, b"COUNT", b"7"])).await;
+            let parts = reply.as_array().unwrap().clone();
+            cursor = parts[0].as_bulk_string().unwrap().to_vec();
+            for key in parts[1].as_array().unwrap() {
+                seen.insert(key.as_bulk_string().unwrap().clone());
+            }
+            if cursor == b"0" {
+                break;
+            }
+        }
+
+        assert_eq!(seen.len(), 200);
+    }
+
+    // The parameters lock keeps the other tests off keys-scan-limit while
+    // the shards run KEYS
+    #[tokio::test]
+    #[allow(clippy::await_holding_lock)]
+    async fn test_keys_across_shards() {
+        let cluster = ClusterManager::new(4, false).unwrap();
+        for i in 0..40 {
+            let key = format!("key:{}", i);
+            cluster.execute(cmd(&[b"SET", key.as_bytes(), b"v"])).await;
+        }
+        cluster.execute(cmd(&[b"SET", b"other", b"v"])).await;
+
+        let keys_on = |reply: RespValue| -> std::collections::HashSet<Bytes> {
+            reply.as_array().unwrap().iter().map(|key| key.as_bulk_string().unwrap().clone()).collect()
+        };
+        let matched = keys_on(cluster.execute(cmd(&[b"KEYS", b"key:*"])).await);
+        assert_eq!(matched.len(), 40);
+        assert!((0..40).all(|i| matched.contains(format!("key:{}", i).as_bytes())));
+
+        // The limit applies to the keys of all the shards, none of which
+        // holds 30 keys on its own
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
+        set_keys_scan_limit(30);
+        let over = cluster.execute(cmd(&[b"KEYS", b"nomatch*"])).await;
+        set_keys_scan_limit(41);
+        let at_limit = cluster.execute(cmd(&[b"KEYS", b"*"])).await;
+        set_keys_scan_limit(0);
+        assert_eq!(over, keys_scan_aborted(30));
+        assert_eq!(at_limit.as_array().unwrap().len(), 41);
+
+        set_disable_keys_command(true);
+        let disabled = cluster.execute(cmd(&[b"KEYS", b"*"])).await;
+        set_disable_keys_command(false);
+        assert_eq!(disabled, RespValue::error("ERR KEYS is disabled by disable-keys-command, use SCAN instead"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_striped_mode_under_concurrent_clients() {
+        let cluster = Arc::new(ClusterManager::with_mode(4, false, ShardMode::Striped).unwrap());
+
+        let mut clients = tokio::task::JoinSet::new();
+        for client in 0..16 {
+            let cluster = cluster.clone();
+            clients.spawn(async move {
+                for i in 0..200 {
+                    let key = format!("client:{}:{}", client, i);
+                    cluster.execute(cmd(&[b"SET", key.as_bytes(), b"value"])).await;
+                    assert_eq!(cluster.execute(cmd(&[b"GET", key.as_bytes()])).await, RespValue::bulk_string("value"));
+                    cluster.execute(cmd(&[b"INCR", b"hot"])).await;
+                }
+            });
+        }
+        while let Some(result) = clients.join_next().await {
+            result.unwrap();
+        }
+
+        assert_eq!(cluster.execute(cmd(&[b"GET", b"hot"])).await, RespValue::bulk_string("3200"));
+        assert_eq!(cluster.get_cluster_stats().await.total_keys, 16 * 200 + 1);
+        assert!(!cluster.is_loading());
+
+        // Commands spanning several stripes go through the same paths
+        assert_eq!(cluster.execute(cmd(&[b"MSET", b"a", b"1", b"b", b"2", b"c", b"3"])).await, RespValue::simple_string("OK"));
+        assert_eq!(cluster.execute(cmd(&[b"COPY", b"a", b"d"])).await, RespValue::integer(1));
+        assert_eq!(cluster.execute(cmd(&[b"GET", b"d"])).await, RespValue::bulk_string("1"));
+        assert_eq!(cluster.execute(cmd(&[b"FLUSHALL"])).await, RespValue::simple_string("OK"));
+        assert_eq!(cluster.get_cluster_stats().await.total_keys, 0);
+    }
+
+    #[tokio::test]
+    async fn test_striped_debug_sleep_async_releases_stripe() {
+        let cluster = Arc::new(ClusterManager::with_mode(1, false, ShardMode::Striped).unwrap());
+
+        let sleeping = cluster.clone();
+        let sleeper = tokio::spawn(async move {
+            sleeping.execute(cmd(&[b"DEBUG", b"SLEEP", b"0.5", b"ASYNC"])).await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let start = std::time::Instant::now();
+        assert_eq!(cluster.execute(cmd(&[b"SET", b"key", b"value"])).await, RespValue::simple_string("OK"));
+        assert!(start.elapsed() < std::time::Duration::from_millis(250));
+        assert_eq!(sleeper.await.unwrap(), RespValue::simple_string("OK"));
+    }
+
+    #[tokio::test]
+    async fn test_striped_blocking_command_leaves_worker_free() {
+        let cluster = Arc::new(ClusterManager::with_mode(2, false, ShardMode::Striped).unwrap());
+        let router = ShardRouter::new(2);
+        let other = (0..100)
+            .map(|i| Bytes::from(format!("key:{}", i)))
+            .find(|key| router.route_key(key) != 0)
+            .unwrap();
+
+        // A blocking DEBUG SLEEP on stripe 0 holds neither its lock nor the
+        // only worker thread of this runtime
+        let start = std::time::Instant::now();
+        let sleeping = cluster.clone();
+        let sleeper = tokio::spawn(async move { sleeping.execute_on_shard(0, cmd(&[b"DEBUG", b"SLEEP", b"0.5"]), ClientOptions::default()).await });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        assert_eq!(cluster.execute(cmd(&[b"SET", &other, b"value"])).await, RespValue::simple_string("OK"));
+        assert!(start.elapsed() < std::time::Duration::from_millis(250));
+        assert_eq!(sleeper.await.unwrap(), RespValue::simple_string("OK"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_shared_runtime_blocking_command_leaves_worker_free() {
+        let cluster = Arc::new(ClusterManager::with_runtime(2, false, ShardMode::Actor, ShardRuntime::Shared).unwrap());
+        let router = ShardRouter::new(2);
+        let other = (0..100)
+            .map(|i| Bytes::from(format!("key:{}", i)))
+            .find(|key| router.route_key(key) != 0)
+            .unwrap();
+
+        // A blocking DEBUG SLEEP in the loop of shard 0 doesn't hold the
+        // only worker thread, which keeps running the loop of shard 1
+        let start = std::time::Instant::now();
+        let sleeping = cluster.clone();
+        let sleeper = tokio::spawn(async move { sleeping.execute_on_shard(0, cmd(&[b"DEBUG", b"SLEEP", b"0.5"]), ClientOptions::default()).await });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        assert_eq!(cluster.execute(cmd(&[b"SET", &other, b"value"])).await, RespValue::simple_string("OK"));
+        assert!(start.elapsed() < std::time::Duration::from_millis(250));
+        assert_eq!(sleeper.await.unwrap(), RespValue::simple_string("OK"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_shard_runtimes_process_commands() {
+        for runtime in [ShardRuntime::Dedicated, ShardRuntime::Shared] {
+            let cluster = Arc::new(ClusterManager::with_runtime(4, false, ShardMode::Actor, runtime).unwrap());
+
+            let mut clients = tokio::task::JoinSet::new();
+            for client in 0..8 {
+                let cluster = cluster.clone();
+                clients.spawn(async move {
+                    for i in 0..100 {
+                        let key = format!("client:{}:{}", client, i);
+                        cluster.execute(cmd(&[b"SET", key.as_bytes(), b"value"])).await;
+                        assert_eq!(cluster.execute(cmd(&[b"GET", key.as_bytes()])).await, RespValue::bulk_string("value"));
+                        cluster.execute(cmd(&[b"INCR", b"hot"])).await;
+                    }
+                });
+            }
+            while let Some(result) = clients.join_next().await {
+                result.unwrap();
+            }
+
+            assert_eq!(cluster.execute(cmd(&[b"GET", b"hot"])).await, RespValue::bulk_string("800"), "{:?}", runtime);
+            assert_eq!(cluster.get_cluster_stats().await.total_keys, 8 * 100 + 1, "{:?}", runtime);
+            assert_eq!(cluster.execute(cmd(&[b"MSET", b"a", b"1", b"b", b"2"])).await, RespValue::simple_string("OK"));
+            assert_eq!(cluster.execute(cmd(&[b"DEBUG", b"SLEEP", b"0.01", b"ASYNC"])).await, RespValue::simple_string("OK"));
+        }
+    }
+
+    #[test]
+    fn test_shared_runtime_needs_a_runtime() {
+        assert!(ClusterManager::with_runtime(1, false, ShardMode::Actor, ShardRuntime::Shared).is_err());
+        assert_eq!(ShardRuntime::from_name("SHARED"), Some(ShardRuntime::Shared));
+        assert_eq!(ShardRuntime::Dedicated.name(), "dedicated");
+        assert_eq!(ShardRuntime::from_name("pool"), None);
+    }
+
+    #[test]
+    fn test_shard_mode_names() {
+        assert_eq!(ShardMode::from_name("STRIPED"), Some(ShardMode::Striped));
+        assert_eq!(ShardMode::from_name("actor"), Some(ShardMode::Actor));
+        assert_eq!(ShardMode::from_name("threads"), None);
+        assert_eq!(ShardMode::Striped.name(), "striped");
+    }
+}
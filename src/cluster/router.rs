@@ -1,6 +1,12 @@
 //! Key routing logic for sharding
 //!
 //! Routes keys to shards using consistent hashing based on SipHash.
+//!
+//! With `hash-tags` enabled, as in Redis Cluster, a key with a hash tag
+//! (`user:{42}:name`) is routed by its tag alone, so the keys sharing a tag
+//! live on the same shard and multi-key commands on them run on that shard
+//! only. It is off by default: each shard replays its own AOF, so turning it
+//! on strands the tagged keys already written on another shard.
 
 use bytes::Bytes;
 use std::hash::{Hash, Hasher};
@@ -9,28 +15,37 @@ use siphasher::sip::SipHasher13;
 /// Routes keys to shards using consistent hashing
 pub struct ShardRouter {
     num_shards: usize,
+    hash_tags: bool,
 }
 
 impl ShardRouter {
-    /// Create a new shard router
+    /// Create a new shard router, hashing whole keys
     pub fn new(num_shards: usize) -> Self {
+        Self::with_hash_tags(num_shards, false)
+    }
+
+    /// Create a new shard router, routing the keys with a hash tag by their
+    /// tag if `hash_tags` is set
+    pub fn with_hash_tags(num_shards: usize, hash_tags: bool) -> Self {
         assert!(num_shards > 0, "Number of shards must be > 0");
-        ShardRouter { num_shards }
+        ShardRouter { num_shards, hash_tags }
     }
 
     /// Route a key to a shard ID
     ///
     /// Uses SipHash13 for fast, secure hashing with good distribution.
-    /// This ensures keys are evenly distributed across shards.
+    /// This ensures keys are evenly distributed across shards. Only the
+    /// hash tag of the key is hashed, if it has one and hash tags are on.
     pub fn route_key(&self, key: &Bytes) -> usize {
         let hash = self.hash_key(key);
         (hash as usize) % self.num_shards
     }
 
-    /// Hash a key using SipHash13
+    /// Hash a key's hash tag, or the whole key, using SipHash13
     fn hash_key(&self, key: &Bytes) -> u64 {
         let mut hasher = SipHasher13::new();
-        key.hash(&mut hasher);
+        let routed: &[u8] = if self.hash_tags { hash_tag(key) } else { key };
+        routed.hash(&mut hasher);
         hasher.finish()
     }
 
@@ -40,6 +55,18 @@ impl ShardRouter {
     }
 }
 
+/// The part of a key that routes it: what is between its first `{` and the
+/// first `}` after it, or the whole key when there is no such non-empty tag
+fn hash_tag(key: &[u8]) -> &[u8] {
+    let Some(open) = key.iter().position(|&b| b == b'{') else {
+        return key;
+    };
+    match key[open + 1..].iter().position(|&b| b == b'}') {
+        Some(len) if len > 0 => &key[open + 1..open + 1 + len],
+        _ => key,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -73,6 +100,54 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_hash_tag() {
+        assert_eq!(hash_tag(b"user:{42}:name"), b"42");
+        assert_eq!(hash_tag(b"{a}{b}"), b"a");
+        assert_eq!(hash_tag(b"a{b{c}d}"), b"b{c");
+        assert_eq!(hash_tag(b"plain"), b"plain");
+        assert_eq!(hash_tag(b"empty{}tag"), b"empty{}tag");
+        assert_eq!(hash_tag(b"open{only"), b"open{only");
+    }
+
+    #[test]
+    fn test_hash_tag_colocates_keys() {
+        let router = ShardRouter::with_hash_tags(16, true);
+        for id in 0..100 {
+            let name = Bytes::from(format!("user:{{{}}}:name", id));
+            let email = Bytes::from(format!("user:{{{}}}:email", id));
+            assert_eq!(router.route_key(&name), router.route_key(&email), "{:?}", name);
+            assert_eq!(router.route_key(&name), router.route_key(&Bytes::from(id.to_string())));
+        }
+
+        // Tagged keys still spread over the shards by their tag
+        let shards: std::collections::HashSet<_> =
+            (0..100).map(|id| router.route_key(&Bytes::from(format!("{{{}}}:key", id)))).collect();
+        assert!(shards.len() > 8, "{:?}", shards);
+    }
+
+    #[test]
+    fn test_hash_tags_off_by_default() {
+        let router = ShardRouter::new(16);
+        let tagged = ShardRouter::with_hash_tags(16, true);
+        let key = |text: &str| Bytes::from(text.to_string());
+
+        // Off, a tagged key routes as a whole, where older versions put it
+        let moved = (0..100)
+            .map(|id| key(&format!("user:{{{}}}:name", id)))
+            .find(|name| router.route_key(name) != tagged.route_key(name))
+            .unwrap();
+        let mut hasher = SipHasher13::new();
+        moved[..].hash(&mut hasher);
+        assert_eq!(router.route_key(&moved), hasher.finish() as usize % 16);
+
+        // Keys without a tag route the same either way
+        for id in 0..100 {
+            let plain = key(&format!("key_{}", id));
+            assert_eq!(router.route_key(&plain), tagged.route_key(&plain));
+        }
+    }
+
     #[test]
     fn test_single_shard() {
         let router = ShardRouter::new(1);
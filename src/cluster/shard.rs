@@ -2,15 +2,22 @@
 //!
 //! Each shard runs in its own thread with a dedicated MemoryStore.
 //! This provides true parallelism while maintaining single-threaded consistency
-//! within each shard.
+//! within each shard. With `ShardRuntime::Shared`, the shard loops are tasks
+//! of the server's runtime instead, one at a time on its worker threads.
 
-use crate::aof::{AofWriter, AofReader, SyncPolicy};
-use crate::commands::{CommandContext, CommandRegistry};
+use crate::aof::{replay_entries, rewrite_aof_in_background, AofEntry, AofWriter, AofReader, LoadStatus, SyncPolicy};
+use crate::audit::AuditLogger;
+use crate::commands::{async_sleep_duration, check_maxmemory, command_flags, execute_traced, is_cached_retry, log_to_audit, may_block, unknown_command, ClientOptions, CommandContext, CommandRegistry, IdempotencyCache};
 use crate::protocol::RespValue;
-use crate::store::{MemoryStore, StoreStats};
+use crate::replication::ReplicationLog;
+use crate::store::{self, MemoryStore, StoreStats};
+use super::ShardRuntime;
 use tokio::sync::{mpsc, oneshot};
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 use std::path::PathBuf;
+use std::time::Duration;
 use tracing::{debug, error, info, warn};
 
 /// Configuration for a shard
@@ -24,8 +31,51 @@ pub struct ShardConfig {
 
     /// Path to AOF file (if enabled)
     pub aof_path: Option<String>,
+
+    /// Expected number of keys held by this shard, used to pre-size the
+    /// store and avoid repeated rehashing while loading (0 = default capacity)
+    pub expected_keys: usize,
+
+    /// AOF entries replayed between two polls of the command queue while
+    /// loading (0 = `DEFAULT_REPLAY_BATCH_SIZE`)
+    pub replay_batch_size: usize,
+
+    /// Growth of the AOF over its size after the last rewrite, in percent,
+    /// that triggers an automatic rewrite (0 = disabled)
+    pub aof_rewrite_percentage: u64,
+
+    /// Minimum AOF size in bytes for an automatic rewrite
+    pub aof_rewrite_min_size: u64,
+
+    /// Audit log shared by all shards (None = disabled)
+    pub audit_logger: Option<Arc<AuditLogger>>,
 }
 
+/// Run blocking work, with `offload` through `block_in_place` so the other
+/// tasks of a shared multi-threaded runtime move to another worker meanwhile
+fn run_blocking<R>(offload: bool, work: impl FnOnce() -> R) -> R {
+    if offload {
+        tokio::task::block_in_place(work)
+    } else {
+        work()
+    }
+}
+
+/// Default number of AOF entries replayed per batch while loading
+pub const DEFAULT_REPLAY_BATCH_SIZE: usize = 1000;
+
+/// Period of the shard's maintenance tick (AOF auto-rewrite check)
+pub(super) const MAINTENANCE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Most durable writes acknowledged by a single fsync
+const MAX_GROUP_COMMIT: usize = 256;
+
+/// A reply held back until the AOF is fsynced (CLIENT DURABLE)
+type DurableAck = (oneshot::Sender<RespValue>, RespValue);
+
+/// Error returned while the AOF is being replayed, like Redis
+const LOADING_ERROR: &str = "LOADING Redis is loading the dataset in memory";
+
 /// A command sent to a shard
 pub struct ShardCommand {
     /// The RESP command to execute
@@ -33,8 +83,24 @@ pub struct ShardCommand {
 
     /// Channel to send the response back
     pub response_tx: oneshot::Sender<RespValue>,
+
+    /// Options of the client that issued the command
+    pub client: ClientOptions,
+}
+
+/// A batch of the NDJSON export of a shard
+#[derive(Debug, Clone)]
+pub struct ExportBatch {
+    /// SCAN cursor to resume from, 0 once the shard is done
+    pub cursor: u64,
+
+    /// One `store::export_line` per key
+    pub lines: Vec<String>,
 }
 
+/// A request for the next export batch: cursor, SCAN count, reply channel
+type ExportRequest = (u64, usize, oneshot::Sender<ExportBatch>);
+
 /// A shard that processes commands in its own thread
 pub struct Shard {
     /// Shard ID
@@ -45,31 +111,64 @@ pub struct Shard {
 
     /// Channel to request stats
     stats_tx: mpsc::UnboundedSender<oneshot::Sender<StoreStats>>,
+
+    /// Channel to request export batches
+    export_tx: mpsc::UnboundedSender<ExportRequest>,
+
+    /// Whether the AOF is being replayed, and how many entries were
+    loading: Arc<LoadStatus>,
 }
 
 impl Shard {
     /// Create a new shard and start its thread
     pub fn new(config: ShardConfig) -> anyhow::Result<Self> {
+        Self::with_runtime(config, ShardRuntime::Dedicated)
+    }
+
+    /// Create a new shard, its loop on a thread and runtime of its own or
+    /// as a task of the current runtime
+    pub fn with_runtime(config: ShardConfig, runtime: ShardRuntime) -> anyhow::Result<Self> {
         let shard_id = config.shard_id;
         info!("Initializing shard {}", shard_id);
 
         // Create channels
         let (command_tx, command_rx) = mpsc::unbounded_channel();
         let (stats_tx, stats_rx) = mpsc::unbounded_channel();
-
-        // Spawn the shard thread
-        std::thread::spawn(move || {
-            let runtime = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .expect("Failed to create shard runtime");
-
-            runtime.block_on(async move {
-                if let Err(e) = Self::run_shard_loop(config, command_rx, stats_rx).await {
-                    error!("Shard {} failed: {}", shard_id, e);
-                }
-            });
-        });
+        let (export_tx, export_rx) = mpsc::unbounded_channel();
+
+        // Loading until the shard has replayed its AOF (if any)
+        let loading = LoadStatus::register(config.aof_enabled);
+        let shard_loading = loading.clone();
+
+        // On a multi-threaded runtime, blocking work moves off the worker
+        // threads (see `run_blocking`)
+        let offload = match runtime {
+            ShardRuntime::Dedicated => false,
+            ShardRuntime::Shared => tokio::runtime::Handle::try_current()
+                .is_ok_and(|handle| handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread),
+        };
+        let shard_loop = async move {
+            if let Err(e) = Self::run_shard_loop(config, command_rx, stats_rx, export_rx, shard_loading, offload).await {
+                error!("Shard {} failed: {}", shard_id, e);
+            }
+        };
+        match runtime {
+            // Spawn the shard thread
+            ShardRuntime::Dedicated => {
+                std::thread::spawn(move || {
+                    let runtime = tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .expect("Failed to create shard runtime");
+                    runtime.block_on(shard_loop);
+                });
+            }
+            ShardRuntime::Shared => {
+                let handle = tokio::runtime::Handle::try_current()
+                    .map_err(|e| anyhow::anyhow!("A shared shard runtime needs a running runtime: {}", e))?;
+                handle.spawn(shard_loop);
+            }
+        }
 
         info!("Shard {} started", shard_id);
 
@@ -77,9 +176,22 @@ impl Shard {
             id: shard_id,
             command_tx,
             stats_tx,
+            export_tx,
+            loading,
         })
     }
 
+    /// Whether the shard is still replaying its AOF
+    pub fn is_loading(&self) -> bool {
+        self.loading.is_loading()
+    }
+
+    /// Number of AOF entries the shard replayed, 0 until its replay is
+    /// complete
+    pub fn replayed_entries(&self) -> u64 {
+        self.loading.entries_loaded()
+    }
+
     /// Send a command to this shard
     pub async fn send_command(&self, command: ShardCommand) -> anyhow::Result<()> {
         self.command_tx
@@ -96,30 +208,51 @@ impl Shard {
             return StoreStats {
                 total_keys: 0,
                 expired_keys: 0,
+                volatile_keys: 0,
                 active_keys: 0,
                 used_memory_bytes: 0,
             };
         }
 
-        rx.await.unwrap_or_else(|_| StoreStats {
+        rx.await.unwrap_or(StoreStats {
             total_keys: 0,
             expired_keys: 0,
+            volatile_keys: 0,
             active_keys: 0,
             used_memory_bytes: 0,
         })
     }
 
+    /// Export the keys of a SCAN step as NDJSON lines
+    ///
+    /// Returns None when the shard is down.
+    pub async fn export_batch(&self, cursor: u64, count: usize) -> Option<ExportBatch> {
+        let (tx, rx) = oneshot::channel();
+        self.export_tx.send((cursor, count, tx)).ok()?;
+        rx.await.ok()
+    }
+
     /// The main loop that runs in the shard's thread
     async fn run_shard_loop(
         config: ShardConfig,
         mut command_rx: mpsc::UnboundedReceiver<ShardCommand>,
         mut stats_rx: mpsc::UnboundedReceiver<oneshot::Sender<StoreStats>>,
+        mut export_rx: mpsc::UnboundedReceiver<ExportRequest>,
+        loading: Arc<LoadStatus>,
+        offload: bool,
     ) -> anyhow::Result<()> {
         let shard_id = config.shard_id;
+        let (rewrite_percentage, rewrite_min_size) = (config.aof_rewrite_percentage, config.aof_rewrite_min_size);
+        let mut pending_entries: VecDeque<AofEntry> = VecDeque::new();
         info!("Shard {} loop starting", shard_id);
 
-        // Initialize the store
-        let store = MemoryStore::new();
+        // Initialize the store, pre-sized when a key count hint is given
+        let store = if config.expected_keys > 0 {
+            info!("Shard {}: Pre-allocating store for {} keys", shard_id, config.expected_keys);
+            MemoryStore::with_capacity(config.expected_keys)
+        } else {
+            MemoryStore::new()
+        };
 
         // Initialize AOF writer if enabled
         let aof_writer = if config.aof_enabled {
@@ -129,9 +262,9 @@ impl Shard {
                 let path = PathBuf::from(&aof_path);
 
                 // Load existing AOF if present
-                let entries = match AofReader::load(&path) {
+                let entries = match run_blocking(offload, || AofReader::load(&path)) {
                     Ok(reader) => {
-                        let entries = reader.parse_entries();
+                        let entries = run_blocking(offload, || reader.parse_entries());
                         info!("Shard {}: Loaded {} AOF entries", shard_id, entries.len());
                         entries
                     }
@@ -150,12 +283,9 @@ impl Shard {
                     }
                 };
 
-                // Replay entries if any
-                if !entries.is_empty() {
-                    // We'll need to replay these entries
-                    // For now, skip replay in shard (will implement later)
-                    warn!("Shard {}: AOF replay not yet implemented in sharded mode ({} entries skipped)", shard_id, entries.len());
-                }
+                // Replayed in batches by the main loop, which keeps answering
+                // (with LOADING) meanwhile
+                pending_entries = entries.into();
 
                 Some(Arc::new(writer))
             } else {
@@ -165,28 +295,79 @@ impl Shard {
             None
         };
 
+        // Create command registry
+        let registry = Arc::new(CommandRegistry::new());
+
         // Create command context
         let mut context = CommandContext {
             store,
             aof_writer,
+            audit_logger: config.audit_logger.clone(),
+            registry: registry.clone(),
+            client: ClientOptions::default(),
+            idempotency: IdempotencyCache::default(),
         };
 
-        // Create command registry
-        let registry = CommandRegistry::new();
+        // Replay the AOF, serving the queued commands between batches
+        let batch_size = match config.replay_batch_size {
+            0 => DEFAULT_REPLAY_BATCH_SIZE,
+            n => n,
+        };
+        let mut durable_acks: Vec<DurableAck> = Vec::new();
+        let mut replication = ReplicationLog::default();
+        let to_replay = pending_entries.len();
+        let mut replayed = 0;
+        while !pending_entries.is_empty() {
+            while let Ok(shard_command) = command_rx.try_recv() {
+                Self::handle_command(&registry, &mut context, shard_command, true, &mut durable_acks, &mut replication, offload);
+            }
+            Self::commit_durable(&context, &mut durable_acks, offload);
+            while let Ok(stats_tx) = stats_rx.try_recv() {
+                let _ = stats_tx.send(context.store.stats());
+            }
+
+            let batch: Vec<AofEntry> = pending_entries.drain(..batch_size.min(pending_entries.len())).collect();
+            match run_blocking(offload, || replay_entries(&mut context.store, batch)) {
+                Ok(count) => replayed += count,
+                Err(e) => warn!("Shard {}: Error replaying AOF: {}", shard_id, e),
+            }
+            loading.progress(replayed as u64);
+            tokio::task::yield_now().await;
+        }
+        if to_replay > 0 {
+            info!("Shard {}: Replayed {} of {} AOF entries", shard_id, replayed, to_replay);
+        }
+        loading.complete(replayed as u64);
 
         // Main event loop
+        let mut maintenance = tokio::time::interval(MAINTENANCE_INTERVAL);
+        maintenance.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut hz = store::hz();
+        let mut expire_sweep = Self::expire_sweep_interval(hz);
         loop {
             tokio::select! {
-                // Process commands
-                Some(shard_command) = command_rx.recv() => {
-                    debug!("Shard {} received command: {:?}", shard_id, shard_command.command);
-
-                    // Dispatch the command
-                    let response = Self::dispatch_command(&registry, &mut context, shard_command.command);
-
-                    // Send response back
-                    let _ = shard_command.response_tx.send(response);
-                }
+                // Process commands, until the channel is closed
+                command = command_rx.recv() => match command {
+                    Some(shard_command) => {
+                        Self::handle_command(&registry, &mut context, shard_command, false, &mut durable_acks, &mut replication, offload);
+
+                        // Group commit: durable writes already queued share
+                        // the same fsync
+                        while !durable_acks.is_empty() && durable_acks.len() < MAX_GROUP_COMMIT {
+                            match command_rx.try_recv() {
+                                Ok(shard_command) => {
+                                    Self::handle_command(&registry, &mut context, shard_command, false, &mut durable_acks, &mut replication, offload);
+                                }
+                                Err(_) => break,
+                            }
+                        }
+                        Self::commit_durable(&context, &mut durable_acks, offload);
+                    }
+                    None => {
+                        info!("Shard {} shutting down", shard_id);
+                        break;
+                    }
+                },
 
                 // Handle stats requests
                 Some(stats_tx) = stats_rx.recv() => {
@@ -194,10 +375,26 @@ impl Shard {
                     let _ = stats_tx.send(stats);
                 }
 
-                // Channel closed, exit
-                else => {
-                    info!("Shard {} shutting down", shard_id);
-                    break;
+                // Handle export requests, one SCAN step at a time
+                Some((cursor, count, export_tx)) = export_rx.recv() => {
+                    let (cursor, keys) = context.store.scan_cursor(cursor, count);
+                    let lines = keys.iter().filter_map(|key| context.store.get_entry(key)).map(store::export_line).collect();
+                    let _ = export_tx.send(ExportBatch { cursor, lines });
+                }
+
+                // Periodic maintenance
+                _ = maintenance.tick() => {
+                    Self::run_maintenance(shard_id, &mut context, rewrite_percentage, rewrite_min_size);
+                }
+
+                // Active expiration, `hz` times per second
+                _ = expire_sweep.tick() => {
+                    let effort = store::active_expire_effort();
+                    context.store.active_expire_cycle(effort, store::sweep_time_limit(hz, effort));
+                    if store::hz() != hz {
+                        hz = store::hz();
+                        expire_sweep = Self::expire_sweep_interval(hz);
+                    }
                 }
             }
         }
@@ -205,23 +402,161 @@ impl Shard {
         Ok(())
     }
 
+    /// Timer of the active expiration sweep at a given `hz`
+    fn expire_sweep_interval(hz: u32) -> tokio::time::Interval {
+        let period = store::sweep_period(hz);
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval
+    }
+
+    /// Periodic maintenance: shrink the store after mass deletions, and
+    /// rewrite the AOF once it has grown enough
+    ///
+    /// The rewrite only takes a snapshot here; the new file is written on a
+    /// thread of its own while the shard keeps serving commands.
+    pub(super) fn run_maintenance(shard_id: usize, context: &mut CommandContext, rewrite_percentage: u64, rewrite_min_size: u64) {
+        if context.store.maybe_shrink() {
+            debug!("Shard {}: Store shrunk to a capacity of {} keys", shard_id, context.store.capacity());
+        }
+
+        let Some(writer) = &context.aof_writer else {
+            return;
+        };
+        if !writer.needs_rewrite(rewrite_percentage, rewrite_min_size) {
+            return;
+        }
+
+        let old_size = writer.size();
+        let started = rewrite_aof_in_background(writer, &context.store, move |result| match result {
+            Ok(new_size) => info!("Shard {}: AOF rewritten automatically ({} -> {} bytes)", shard_id, old_size, new_size),
+            Err(e) => error!("Shard {}: Automatic AOF rewrite failed: {}", shard_id, e),
+        });
+        if let Err(e) = started {
+            error!("Shard {}: Automatic AOF rewrite failed to start: {}", shard_id, e);
+        }
+    }
+
+    /// Fsync the AOF once for all held back replies, then send them
+    fn commit_durable(context: &CommandContext, durable_acks: &mut Vec<DurableAck>, offload: bool) {
+        if durable_acks.is_empty() {
+            return;
+        }
+
+        let synced = context.aof_writer.as_ref().map_or(Ok(()), |writer| run_blocking(offload, || writer.sync()));
+        for (response_tx, response) in durable_acks.drain(..) {
+            let response = match &synced {
+                Ok(()) => response,
+                Err(e) => RespValue::error(format!("ERR AOF fsync failed: {}", e)),
+            };
+            let _ = response_tx.send(response);
+        }
+    }
+
+    /// Whether a command is a write to record: flagged `write` in the
+    /// registry (through the command it wraps for IDEMPOTENT), and not an
+    /// IDEMPOTENT retry answered from the cache
+    pub(super) fn is_write(context: &CommandContext, command: &RespValue) -> bool {
+        command.as_array().is_some_and(|parts| {
+            command_flags(&context.registry, parts).contains(&"write") && !is_cached_retry(context, parts)
+        })
+    }
+
+    /// Execute a command received by the shard and send back its response
+    ///
+    /// Writes from a CLIENT DURABLE client are queued in `durable_acks`
+    /// instead, and only answered once the AOF has been fsynced. Writes that
+    /// didn't fail are recorded in the shard's replication log. With
+    /// `offload`, commands that may block (DEBUG SLEEP, MIGRATE, ...) run
+    /// off the runtime's worker threads.
+    fn handle_command(
+        registry: &CommandRegistry,
+        context: &mut CommandContext,
+        shard_command: ShardCommand,
+        loading: bool,
+        durable_acks: &mut Vec<DurableAck>,
+        replication: &mut ReplicationLog,
+        offload: bool,
+    ) {
+        debug!("Shard received command: {:?}", shard_command.command);
+
+        // DEBUG SLEEP ... ASYNC sleeps on the shard runtime so the loop
+        // keeps processing other commands meanwhile
+        if let Some(duration) = async_sleep_duration(&shard_command.command) {
+            tokio::spawn(async move {
+                tokio::time::sleep(duration).await;
+                let _ = shard_command.response_tx.send(RespValue::simple_string("OK"));
+            });
+            return;
+        }
+
+        let write = Self::is_write(context, &shard_command.command).then(|| shard_command.command.clone());
+        let durable = shard_command.client.durable && context.aof_writer.is_some() && write.is_some();
+
+        context.apply_client_options(shard_command.client);
+        let offload = offload && may_block(&shard_command.command);
+        let response = run_blocking(offload, || Self::execute_caught(registry, context, shard_command.command, loading));
+        if let Some(write) = write.filter(|_| !matches!(response, RespValue::Error(_))) {
+            replication.record(write);
+        }
+
+        // Send response back, once on disk for durable writes
+        if durable {
+            durable_acks.push((shard_command.response_tx, response));
+        } else {
+            let _ = shard_command.response_tx.send(response);
+        }
+    }
+
+    /// Dispatch a command with the client options already applied to the
+    /// context. A panic only fails this command: the store keeps serving
+    /// the others
+    pub(super) fn execute_caught(
+        registry: &CommandRegistry,
+        context: &mut CommandContext,
+        command: RespValue,
+        loading: bool,
+    ) -> RespValue {
+        let name = command
+            .as_array()
+            .and_then(|parts| parts.first())
+            .and_then(|name| name.as_bulk_string())
+            .map(|name| String::from_utf8_lossy(name).to_uppercase())
+            .unwrap_or_default();
+        let dispatched = panic::catch_unwind(AssertUnwindSafe(|| Self::dispatch_command(registry, context, command, loading)));
+        dispatched.unwrap_or_else(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+            error!("Command {} panicked: {}", name, message);
+            RespValue::error(format!("ERR internal error while executing '{}'", name))
+        })
+    }
+
     /// Dispatch a command using the registry
+    ///
+    /// While loading, only commands flagged `loading` (INFO, COMMAND, ...)
+    /// are executed; the others get the LOADING error.
     fn dispatch_command(
         registry: &CommandRegistry,
         context: &mut CommandContext,
         command: RespValue,
+        loading: bool,
     ) -> RespValue {
         use base64::{Engine as _, engine::general_purpose};
 
-        // Parse command array
+        // Parse command array (same errors as Dispatcher::dispatch)
         let parts = match command {
             RespValue::Array(ref parts) if !parts.is_empty() => parts,
-            ref invalid => {
-                // Encode the invalid command in Base64 for debugging
-                let debug_msg = format!("{:?}", invalid);
-                let b64 = general_purpose::STANDARD.encode(debug_msg.as_bytes());
-                error!("Invalid command format - not an array or empty. Command (B64): {}", b64);
-                return RespValue::error("ERR invalid command format");
+            RespValue::Array(_) => {
+                debug!("Rejected empty command array");
+                return RespValue::error("ERR empty command array");
+            }
+            _ => {
+                debug!("Rejected command that is not an array");
+                return RespValue::error("ERR expected array");
             }
         };
 
@@ -248,15 +583,25 @@ impl Shard {
             Some(c) => c,
             None => {
                 warn!("Unknown command: '{}'", cmd_name);
-                return RespValue::error(format!("ERR unknown command '{}'", cmd_name));
+                return unknown_command(cmd_name.as_bytes(), &parts[1..]);
             }
         };
 
+        if loading && !cmd.flags().contains(&"loading") {
+            return RespValue::error(LOADING_ERROR);
+        }
+
         // Get arguments (everything after command name)
         let args = &parts[1..];
 
+        if let Some(oom) = check_maxmemory(context, cmd.as_ref(), args) {
+            return oom;
+        }
+
         // Execute command
-        cmd.execute(context, args)
+        let response = execute_traced(context, cmd.as_ref(), parts);
+        log_to_audit(context, cmd.as_ref(), parts, &response);
+        response
     }
 
     /// Get shard ID
@@ -270,3 +615,292 @@ impl Drop for Shard {
         info!("Shard {} dropped", self.id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::command;
+
+    #[tokio::test]
+    async fn test_expected_keys_presized_store() {
+        let shard = Shard::new(ShardConfig {
+            shard_id: 0,
+            aof_enabled: false,
+            aof_path: None,
+            expected_keys: 100_000,
+            replay_batch_size: 0,
+            aof_rewrite_percentage: 0,
+            aof_rewrite_min_size: 0,
+            audit_logger: None,
+        })
+        .unwrap();
+
+        let mut responses = Vec::new();
+        for i in 0..20_000 {
+            let (tx, rx) = oneshot::channel();
+            let command = RespValue::array(vec![
+                RespValue::bulk_string("SET"),
+                RespValue::bulk_string(format!("key:{}", i)),
+                RespValue::bulk_string("value"),
+            ]);
+            shard.send_command(ShardCommand { command, response_tx: tx, client: ClientOptions::default() }).await.unwrap();
+            responses.push(rx);
+        }
+        for rx in responses {
+            assert_eq!(rx.await.unwrap(), RespValue::simple_string("OK"));
+        }
+
+        let stats = shard.get_stats().await;
+        assert_eq!(stats.total_keys, 20_000);
+        assert_eq!(stats.active_keys, 20_000);
+        assert_eq!(stats.volatile_keys, 0);
+    }
+
+    async fn send(shard: &Shard, parts: &[&str]) -> RespValue {
+        let (tx, rx) = oneshot::channel();
+        shard.send_command(ShardCommand { command: command(parts), response_tx: tx, client: ClientOptions::default() }).await.unwrap();
+        rx.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_loading_error_during_replay() {
+        use crate::aof::AofOperation;
+        use bytes::Bytes;
+
+        // An AOF big enough for the replay, one entry per batch, to be slow
+        let path = std::env::temp_dir().join(format!("ferrumdb_loading_{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let writer = AofWriter::new(&path, SyncPolicy::No).unwrap();
+        for i in 0..20_000 {
+            let entry = AofEntry::new(AofOperation::Set, Bytes::from(format!("key:{}", i)), vec![Bytes::from("v")]);
+            writer.write(&entry).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let shard = Shard::new(ShardConfig {
+            shard_id: 0,
+            aof_enabled: true,
+            aof_path: Some(path.to_string_lossy().into_owned()),
+            expected_keys: 0,
+            replay_batch_size: 1,
+            aof_rewrite_percentage: 0,
+            aof_rewrite_min_size: 0,
+            audit_logger: None,
+        })
+        .unwrap();
+        assert!(shard.is_loading());
+
+        // Queued before the replay ends: data commands are refused, commands
+        // flagged `loading` still run
+        assert_eq!(send(&shard, &["GET", "key:0"]).await, RespValue::error(LOADING_ERROR));
+        assert!(matches!(send(&shard, &["COMMAND", "COUNT"]).await, RespValue::Integer(_)));
+
+        // The count of replayed entries grows as the batches are replayed
+        let mut counts = Vec::new();
+        while shard.is_loading() {
+            counts.push(shard.replayed_entries());
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+        assert!(counts.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(counts.iter().any(|&count| count > 0 && count < 20_000), "{:?}", counts);
+        assert_eq!(shard.replayed_entries(), 20_000);
+        assert_eq!(send(&shard, &["GET", "key:19999"]).await, RespValue::bulk_string("v"));
+
+        drop(shard);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_auto_rewrite_shrinks_aof() {
+        let path = std::env::temp_dir().join(format!("ferrumdb_auto_rewrite_{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let shard = Shard::new(ShardConfig {
+            shard_id: 0,
+            aof_enabled: true,
+            aof_path: Some(path.to_string_lossy().into_owned()),
+            expected_keys: 0,
+            replay_batch_size: 0,
+            aof_rewrite_percentage: 100,
+            aof_rewrite_min_size: 16 * 1024,
+            audit_logger: None,
+        })
+        .unwrap();
+
+        // Overwriting the same key grows the file well past the threshold
+        let mut written = 0;
+        for i in 0..2000 {
+            assert_eq!(send(&shard, &["SET", "key", &format!("value:{}", i)]).await, RespValue::simple_string("OK"));
+            written = written.max(std::fs::metadata(&path).unwrap().len());
+        }
+        assert!(written >= 16 * 1024);
+
+        // The maintenance tick rewrites it down to the single live key
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while std::fs::metadata(&path).unwrap().len() >= 1024 {
+            assert!(std::time::Instant::now() < deadline, "AOF was never rewritten");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        drop(shard);
+
+        let entries = AofReader::load(&path).unwrap().parse_entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].payload[0], bytes::Bytes::from("value:1999"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_flushall_survives_restart() {
+        let path = std::env::temp_dir().join(format!("ferrumdb_flushall_{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = ShardConfig {
+            shard_id: 0,
+            aof_enabled: true,
+            aof_path: Some(path.to_string_lossy().into_owned()),
+            expected_keys: 0,
+            replay_batch_size: 0,
+            aof_rewrite_percentage: 0,
+            aof_rewrite_min_size: 0,
+            audit_logger: None,
+        };
+
+        let shard = Shard::new(config.clone()).unwrap();
+        assert_eq!(send(&shard, &["SET", "gone", "v"]).await, RespValue::simple_string("OK"));
+        assert_eq!(send(&shard, &["FLUSHALL"]).await, RespValue::simple_string("OK"));
+        assert_eq!(send(&shard, &["SET", "kept", "v"]).await, RespValue::simple_string("OK"));
+        drop(shard);
+
+        // Restarting replays the AOF: only the write after the flush is left
+        let shard = Shard::new(config).unwrap();
+        while shard.is_loading() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(send(&shard, &["GET", "gone"]).await, RespValue::null());
+        assert_eq!(send(&shard, &["GET", "kept"]).await, RespValue::bulk_string("v"));
+
+        drop(shard);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_durable_writes_on_disk_when_acknowledged() {
+        use crate::store::MemoryStore;
+        use bytes::Bytes;
+
+        let path = std::env::temp_dir().join(format!("ferrumdb_durable_{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let shard = Shard::new(ShardConfig {
+            shard_id: 0,
+            aof_enabled: true,
+            aof_path: Some(path.to_string_lossy().into_owned()),
+            expected_keys: 0,
+            replay_batch_size: 0,
+            aof_rewrite_percentage: 0,
+            aof_rewrite_min_size: 0,
+            audit_logger: None,
+        })
+        .unwrap();
+
+        // A burst of durable writes, acknowledged by group commits
+        let client = ClientOptions { durable: true, ..ClientOptions::default() };
+        let mut replies = Vec::new();
+        for i in 0..100 {
+            let (tx, rx) = oneshot::channel();
+            let command = RespValue::array(vec![
+                RespValue::bulk_string("SET"),
+                RespValue::bulk_string(format!("key:{}", i)),
+                RespValue::bulk_string("v"),
+            ]);
+            shard.send_command(ShardCommand { command, response_tx: tx, client }).await.unwrap();
+            replies.push(rx);
+        }
+        for rx in replies {
+            assert_eq!(rx.await.unwrap(), RespValue::simple_string("OK"));
+        }
+
+        // Simulated crash: the shard is never shut down, the file is
+        // reloaded as a restart would
+        let mut store = MemoryStore::new();
+        replay_entries(&mut store, AofReader::load(&path).unwrap().parse_entries()).unwrap();
+        assert_eq!(store.len(), 100);
+        assert!(store.exists(&Bytes::from("key:99")));
+
+        drop(shard);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_panicking_command_fails_alone() {
+        use crate::commands::Command;
+
+        /// A command whose handler panics, like an unwrap gone wrong
+        struct PanicCommand;
+
+        impl Command for PanicCommand {
+            fn execute(&self, _ctx: &mut CommandContext, _args: &[RespValue]) -> RespValue {
+                panic!("handler bug");
+            }
+
+            fn name(&self) -> &'static str {
+                "PANIC"
+            }
+        }
+
+        let mut registry = CommandRegistry::new();
+        registry.register(Arc::new(PanicCommand));
+        let mut context = CommandContext::new();
+        let mut durable_acks = Vec::new();
+        let mut replication = ReplicationLog::default();
+        let mut run = |parts: &[&str]| {
+            let (tx, mut rx) = oneshot::channel();
+            let shard_command = ShardCommand { command: command(parts), response_tx: tx, client: ClientOptions::default() };
+            Shard::handle_command(&registry, &mut context, shard_command, false, &mut durable_acks, &mut replication, false);
+            rx.try_recv().expect("no reply sent")
+        };
+
+        assert_eq!(run(&["SET", "k", "v"]), RespValue::simple_string("OK"));
+        assert_eq!(run(&["panic"]), RespValue::error("ERR internal error while executing 'PANIC'"));
+
+        // The shard's store is still there and serving
+        assert_eq!(run(&["GET", "k"]), RespValue::bulk_string("v"));
+        assert_eq!(run(&["SET", "k2", "v2"]), RespValue::simple_string("OK"));
+    }
+
+    #[test]
+    fn test_writes_advance_replication_offset() {
+        let registry = CommandRegistry::new();
+        let mut context = CommandContext::new();
+        let mut durable_acks = Vec::new();
+        let mut replication = ReplicationLog::new(2);
+        let mut run = |replication: &mut ReplicationLog, parts: &[&str]| {
+            let (tx, mut rx) = oneshot::channel();
+            let shard_command = ShardCommand { command: command(parts), response_tx: tx, client: ClientOptions::default() };
+            Shard::handle_command(&registry, &mut context, shard_command, false, &mut durable_acks, replication, false);
+            rx.try_recv().expect("no reply sent")
+        };
+
+        // One per applied write, none for reads and failed writes
+        run(&mut replication, &["SET", "k", "v"]);
+        assert_eq!(replication.offset(), 1);
+        run(&mut replication, &["GET", "k"]);
+        assert_eq!(replication.offset(), 1);
+        assert!(matches!(run(&mut replication, &["INCR", "k"]), RespValue::Error(_)));
+        assert_eq!(replication.offset(), 1);
+        run(&mut replication, &["SET", "k", "w"]);
+        run(&mut replication, &["DEL", "k"]);
+        assert_eq!(replication.offset(), 3);
+
+        // The backlog keeps the most recent writes
+        let recent: Vec<RespValue> = replication.since(1).unwrap().cloned().collect();
+        assert_eq!(recent, vec![command(&["SET", "k", "w"]), command(&["DEL", "k"])]);
+        assert!(replication.since(0).is_none());
+
+        // IDEMPOTENT is a write when it runs a write, not when it replays
+        run(&mut replication, &["IDEMPOTENT", "tok", "INCR", "n"]);
+        assert_eq!(replication.offset(), 4);
+        run(&mut replication, &["IDEMPOTENT", "tok", "INCR", "n"]);
+        run(&mut replication, &["IDEMPOTENT", "tok-2", "GET", "n"]);
+        assert_eq!(replication.offset(), 4);
+    }
+}
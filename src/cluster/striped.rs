@@ -0,0 +1,286 @@
+//! Striped store: the shards as lock-guarded stores
+//!
+//! An alternative to the shard threads (`ShardMode::Striped`). Each shard's
+//! store is a stripe guarded by an `RwLock`, and the connection tasks run
+//! their commands under the lock of the stripe the key routes to, instead
+//! of sending them to a shard thread and awaiting a oneshot reply. Keys are
+//! routed by the same `ShardRouter`, so the cluster-wide commands (MSET,
+//! COPY, SCAN...) work unchanged on top of it.
+//!
+//! Commands take the write lock, since even reads update the store (lazy
+//! expiration, access times); exports take the read lock. The AOF of each
+//! stripe is replayed when the store is created, so there is no loading
+//! phase, and a background thread runs the active expiration and the
+//! maintenance (store shrinking, AOF auto-rewrite checks).
+
+use super::shard::{Shard, ShardConfig, MAINTENANCE_INTERVAL};
+use crate::aof::{replay_entries, AofReader, AofWriter, LoadStatus, SyncPolicy};
+use crate::commands::{may_block, ClientOptions, CommandContext, CommandRegistry, IdempotencyCache};
+use crate::protocol::RespValue;
+use crate::replication::ReplicationLog;
+use crate::store::{self, MemoryStore, StoreStats};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// A stripe: the store of one shard and its AOF
+struct Stripe {
+    /// Execution context of the stripe's commands
+    context: CommandContext,
+
+    /// Growth of the AOF that triggers an automatic rewrite, in percent
+    aof_rewrite_percentage: u64,
+
+    /// Minimum AOF size in bytes for an automatic rewrite
+    aof_rewrite_min_size: u64,
+
+    /// Replication offset and backlog of the stripe's writes
+    replication: ReplicationLog,
+}
+
+/// The shards' stores, each guarded by its own lock
+///
+/// Cheap to clone: the clones share the stripes.
+#[derive(Clone)]
+pub struct StripedStore {
+    /// One stripe per shard, shared with the maintenance thread
+    stripes: Arc<Vec<RwLock<Stripe>>>,
+
+    /// Registry the commands are dispatched from
+    registry: Arc<CommandRegistry>,
+
+    /// Loading status of each stripe, complete once the store is created
+    loads: Arc<Vec<Arc<LoadStatus>>>,
+}
+
+impl StripedStore {
+    /// Create the stripes, replaying their AOF, and start the maintenance
+    /// thread
+    pub fn new(configs: Vec<ShardConfig>) -> anyhow::Result<Self> {
+        let registry = Arc::new(CommandRegistry::new());
+        let mut stripes = Vec::with_capacity(configs.len());
+        let mut loads = Vec::with_capacity(configs.len());
+        for config in configs {
+            let load = LoadStatus::register(config.aof_enabled);
+            stripes.push(RwLock::new(Self::open_stripe(config, &registry, &load)?));
+            loads.push(load);
+        }
+
+        let stripes = Arc::new(stripes);
+        let maintained = Arc::downgrade(&stripes);
+        std::thread::Builder::new()
+            .name("ferrumdb-stripes".to_string())
+            .spawn(move || Self::run_maintenance_loop(maintained))?;
+
+        Ok(StripedStore { stripes, registry, loads: Arc::new(loads) })
+    }
+
+    /// Create the store of a stripe, with its AOF replayed
+    fn open_stripe(config: ShardConfig, registry: &Arc<CommandRegistry>, load: &LoadStatus) -> anyhow::Result<Stripe> {
+        let shard_id = config.shard_id;
+        let mut store = if config.expected_keys > 0 {
+            MemoryStore::with_capacity(config.expected_keys)
+        } else {
+            MemoryStore::new()
+        };
+
+        let aof_writer = match config.aof_path.filter(|_| config.aof_enabled) {
+            Some(aof_path) => {
+                info!("Stripe {}: Initializing AOF at {}", shard_id, aof_path);
+                let mut replayed = 0;
+                if let Ok(reader) = AofReader::load(&aof_path) {
+                    match replay_entries(&mut store, reader.parse_entries()) {
+                        Ok(count) => {
+                            info!("Stripe {}: Replayed {} AOF entries", shard_id, count);
+                            replayed = count;
+                        }
+                        Err(e) => warn!("Stripe {}: Error replaying AOF: {}", shard_id, e),
+                    }
+                }
+                load.complete(replayed as u64);
+                let writer = AofWriter::new(&aof_path, SyncPolicy::EverySecond)
+                    .map_err(|e| anyhow::anyhow!("Failed to create AOF writer: {}", e))?;
+                Some(Arc::new(writer))
+            }
+            None => None,
+        };
+
+        Ok(Stripe {
+            context: CommandContext {
+                store,
+                aof_writer,
+                audit_logger: config.audit_logger,
+                registry: registry.clone(),
+                client: ClientOptions::default(),
+                idempotency: IdempotencyCache::default(),
+            },
+            aof_rewrite_percentage: config.aof_rewrite_percentage,
+            aof_rewrite_min_size: config.aof_rewrite_min_size,
+            replication: ReplicationLog::default(),
+        })
+    }
+
+    /// Execute a command on a stripe, from an async task
+    ///
+    /// Like `execute`, but the commands that may block for long (see
+    /// `commands::may_block`) and the CLIENT DURABLE ones, which wait for
+    /// an fsync, run on the runtime's blocking threads: neither the stripe's
+    /// lock nor the wait is held on a worker thread.
+    pub async fn execute_async(&self, stripe_id: usize, command: RespValue, client: ClientOptions) -> RespValue {
+        if !may_block(&command) && !client.durable {
+            return self.execute(stripe_id, command, client);
+        }
+
+        let striped = self.clone();
+        tokio::task::spawn_blocking(move || striped.execute(stripe_id, command, client))
+            .await
+            .unwrap_or_else(|e| RespValue::error(format!("ERR command failed: {}", e)))
+    }
+
+    /// Execute a command on a stripe, under its write lock
+    ///
+    /// Writes that didn't fail are recorded in the stripe's replication
+    /// log. A CLIENT DURABLE write is fsynced once the lock is released,
+    /// before its reply is returned.
+    pub fn execute(&self, stripe_id: usize, command: RespValue, client: ClientOptions) -> RespValue {
+        let mut stripe = self.write(stripe_id);
+        let write = Shard::is_write(&stripe.context, &command).then(|| command.clone());
+        let durable = client.durable && stripe.context.aof_writer.is_some() && write.is_some();
+
+        stripe.context.apply_client_options(client);
+        let response = Shard::execute_caught(&self.registry, &mut stripe.context, command, false);
+        if let Some(write) = write.filter(|_| !matches!(response, RespValue::Error(_))) {
+            stripe.replication.record(write);
+        }
+        let writer = stripe.context.aof_writer.clone().filter(|_| durable);
+        drop(stripe);
+
+        match writer.map(|writer| writer.sync()) {
+            Some(Err(e)) => RespValue::error(format!("ERR AOF fsync failed: {}", e)),
+            _ => response,
+        }
+    }
+
+    /// Number of AOF entries a stripe replayed when the store was created
+    pub fn replayed_entries(&self, stripe_id: usize) -> u64 {
+        self.loads[stripe_id].entries_loaded()
+    }
+
+    /// Statistics of a stripe's store
+    pub fn stats(&self, stripe_id: usize) -> StoreStats {
+        self.write(stripe_id).context.store.stats()
+    }
+
+    /// Export the keys of a SCAN step of a stripe as NDJSON lines
+    pub fn export_batch(&self, stripe_id: usize, cursor: u64, count: usize) -> super::ExportBatch {
+        let stripe = self.read(stripe_id);
+        let store = &stripe.context.store;
+        let (cursor, keys) = store.scan_cursor(cursor, count);
+        let lines = keys.iter().filter_map(|key| store.get_entry(key)).map(store::export_line).collect();
+        super::ExportBatch { cursor, lines }
+    }
+
+    /// Lock a stripe for reading
+    ///
+    /// The commands run under `catch_unwind`, so a poisoned lock still
+    /// guards a usable store.
+    fn read(&self, stripe_id: usize) -> RwLockReadGuard<'_, Stripe> {
+        self.stripes[stripe_id].read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Lock a stripe for writing
+    fn write(&self, stripe_id: usize) -> RwLockWriteGuard<'_, Stripe> {
+        Self::lock(&self.stripes[stripe_id])
+    }
+
+    /// Lock a stripe for writing, from the stripes themselves
+    fn lock(stripe: &RwLock<Stripe>) -> RwLockWriteGuard<'_, Stripe> {
+        stripe.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Active expiration `hz` times per second and AOF auto-rewrite checks,
+    /// one stripe at a time, until the store is dropped
+    fn run_maintenance_loop(stripes: Weak<Vec<RwLock<Stripe>>>) {
+        let mut last_maintenance = Instant::now();
+        loop {
+            let hz = store::hz();
+            std::thread::sleep(store::sweep_period(hz));
+            let Some(stripes) = stripes.upgrade() else {
+                return;
+            };
+
+            let effort = store::active_expire_effort();
+            for stripe in stripes.iter() {
+                Self::lock(stripe).context.store.active_expire_cycle(effort, store::sweep_time_limit(hz, effort));
+            }
+
+            if last_maintenance.elapsed() >= MAINTENANCE_INTERVAL {
+                last_maintenance = Instant::now();
+                for (stripe_id, stripe) in stripes.iter().enumerate() {
+                    let mut stripe = Self::lock(stripe);
+                    let (percentage, min_size) = (stripe.aof_rewrite_percentage, stripe.aof_rewrite_min_size);
+                    Shard::run_maintenance(stripe_id, &mut stripe.context, percentage, min_size);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::command;
+    use std::panic::AssertUnwindSafe;
+
+    fn config(shard_id: usize) -> ShardConfig {
+        ShardConfig {
+            shard_id,
+            aof_enabled: false,
+            aof_path: None,
+            expected_keys: 0,
+            replay_batch_size: 0,
+            aof_rewrite_percentage: 0,
+            aof_rewrite_min_size: 0,
+            audit_logger: None,
+        }
+    }
+
+    #[test]
+    fn test_concurrent_incr_on_one_stripe() {
+        let striped = Arc::new(StripedStore::new(vec![config(0), config(1)]).unwrap());
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let striped = striped.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        striped.execute(0, command(&["INCR", "counter"]), ClientOptions::default());
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let reply = striped.execute(0, command(&["GET", "counter"]), ClientOptions::default());
+        assert_eq!(reply, RespValue::bulk_string("8000"));
+        assert_eq!(striped.stats(0).active_keys, 1);
+        assert_eq!(striped.stats(1).active_keys, 0);
+        assert_eq!(striped.read(0).replication.offset(), 8000);
+        assert_eq!(striped.read(1).replication.offset(), 0);
+    }
+
+    #[test]
+    fn test_panic_keeps_stripe_usable() {
+        let striped = StripedStore::new(vec![config(0)]).unwrap();
+        striped.execute(0, command(&["SET", "key", "value"]), ClientOptions::default());
+        std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = striped.write(0);
+            panic!("poison the lock");
+        }))
+        .unwrap_err();
+
+        assert_eq!(striped.execute(0, command(&["GET", "key"]), ClientOptions::default()), RespValue::bulk_string("value"));
+        assert_eq!(striped.export_batch(0, 0, 10).lines.len(), 1);
+    }
+}
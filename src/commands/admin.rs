@@ -1,7 +1,39 @@
-//! Admin commands (INFO, FLUSHDB)
+//! Admin commands (INFO, FLUSHDB, FLUSHALL, CLIENT, HELLO, COMMAND, MEMORY, BGREWRITEAOF, TIME, ROLE, CONFIG)
 
-use super::{Command, CommandContext};
-use crate::protocol::RespValue;
+use super::{Command, CommandContext, CommandInfo, extract_bulk_string};
+use crate::aof::{self, rewrite_aof};
+use crate::config::SERVER_CONFIG;
+use crate::protocol::{ProtocolVersion, RespValue};
+use crate::replication;
+use crate::store::{maxmemory, maxmemory_policy, DetachedKeys, KEYSPACE_STATS};
+use bytes::Bytes;
+use std::time::{SystemTime, UNIX_EPOCH};
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System};
+
+/// Resident set size of the process in bytes (0 if unavailable)
+fn process_rss_bytes() -> u64 {
+    let Ok(pid) = sysinfo::get_current_pid() else {
+        return 0;
+    };
+    let mut sys = System::new();
+    sys.refresh_processes_specifics(
+        ProcessesToUpdate::Some(&[pid]),
+        false,
+        ProcessRefreshKind::new().with_memory(),
+    );
+    sys.process(pid).map(|p| p.memory()).unwrap_or(0)
+}
+
+/// Fragmentation ratio as reported by INFO: RSS over dataset bytes
+///
+/// FerrumDB uses the system allocator, so this only approximates Redis'
+/// allocator-based figure. An empty dataset reports 0.
+fn fragmentation_ratio(rss_bytes: u64, dataset_bytes: usize) -> f64 {
+    if dataset_bytes == 0 {
+        return 0.0;
+    }
+    rss_bytes as f64 / dataset_bytes as f64
+}
 
 /// INFO command - Get information and statistics about the server
 ///
@@ -11,6 +43,7 @@ pub struct InfoCommand;
 impl Command for InfoCommand {
     fn execute(&self, ctx: &mut CommandContext, _args: &[RespValue]) -> RespValue {
         let stats = ctx.store.stats();
+        let rss = process_rss_bytes();
 
         let info = format!(
             "# Server\r\n\
@@ -19,12 +52,44 @@ impl Command for InfoCommand {
             os:{}\r\n\
             arch:{}\r\n\
             \r\n\
+            # Memory\r\n\
+            used_memory:{}\r\n\
+            used_memory_rss:{}\r\n\
+            mem_fragmentation_ratio:{:.2}\r\n\
+            maxmemory:{}\r\n\
+            maxmemory_policy:{}\r\n\
+            mem_allocator:libc\r\n\
+            active_defrag_running:0\r\n\
+            \r\n\
+            # Persistence\r\n\
+            {}\
+            \r\n\
+            # Stats\r\n\
+            expired_keys:{}\r\n\
+            evicted_keys:{}\r\n\
+            keyspace_hits:{}\r\n\
+            keyspace_misses:{}\r\n\
+            \r\n\
+            # Replication\r\n\
+            {}\
+            \r\n\
             # Keyspace\r\n\
             db0:keys={},expires={}\r\n",
             std::env::consts::OS,
             std::env::consts::ARCH,
+            stats.used_memory_bytes,
+            rss,
+            fragmentation_ratio(rss, stats.used_memory_bytes),
+            maxmemory(),
+            maxmemory_policy().name(),
+            aof::info_section(ctx.aof_writer.is_some()),
+            KEYSPACE_STATS.expired_keys(),
+            KEYSPACE_STATS.evicted_keys(),
+            KEYSPACE_STATS.keyspace_hits(),
+            KEYSPACE_STATS.keyspace_misses(),
+            replication::info_section(),
             stats.active_keys,
-            stats.expired_keys
+            stats.volatile_keys
         );
 
         RespValue::bulk_string(info)
@@ -34,6 +99,14 @@ impl Command for InfoCommand {
         "INFO"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["loading", "stale"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
     fn min_args(&self) -> usize {
         0
     }
@@ -43,27 +116,113 @@ impl Command for InfoCommand {
     }
 }
 
+/// Parse the `[ASYNC | SYNC]` option of FLUSHDB / FLUSHALL, true for ASYNC
+fn parse_flush_mode(args: &[RespValue]) -> Result<bool, RespValue> {
+    match args.first().map(extract_bulk_string) {
+        None => Ok(false),
+        Some(Ok(mode)) if mode.eq_ignore_ascii_case(b"ASYNC") => Ok(true),
+        Some(Ok(mode)) if mode.eq_ignore_ascii_case(b"SYNC") => Ok(false),
+        Some(_) => Err(RespValue::error("ERR syntax error")),
+    }
+}
+
+/// Free flushed keys off the calling thread
+///
+/// On a blocking task of the current runtime (the shard's), or on a thread
+/// of its own outside of one.
+fn free_in_background(keys: DetachedKeys) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => {
+            handle.spawn_blocking(move || drop(keys));
+        }
+        Err(_) => {
+            std::thread::spawn(move || drop(keys));
+        }
+    }
+}
+
+/// Remove all keys, and truncate the AOF so a restart doesn't bring them back
+///
+/// With ASYNC the keys are swapped out for an empty map and freed in the
+/// background, so the shard isn't stalled while a large keyspace is
+/// dropped. The AOF is rewritten from the now empty store, through the same
+/// temporary file and rename as BGREWRITEAOF.
+fn flush(ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+    let lazy = match parse_flush_mode(args) {
+        Ok(lazy) => lazy,
+        Err(e) => return e,
+    };
+    if lazy {
+        free_in_background(ctx.store.detach_all());
+    } else {
+        ctx.store.clear();
+    }
+
+    if let Some(writer) = &ctx.aof_writer {
+        if let Err(e) = rewrite_aof(writer, &ctx.store) {
+            return RespValue::error(format!("ERR failed to truncate the AOF: {}", e));
+        }
+    }
+    RespValue::simple_string("OK")
+}
+
 /// FLUSHDB command - Remove all keys from the current database
 ///
-/// Syntax: FLUSHDB
+/// Syntax: FLUSHDB [ASYNC | SYNC]
 pub struct FlushDbCommand;
 
 impl Command for FlushDbCommand {
-    fn execute(&self, ctx: &mut CommandContext, _args: &[RespValue]) -> RespValue {
-        ctx.store.clear();
-        RespValue::simple_string("OK")
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        flush(ctx, args)
     }
 
     fn name(&self) -> &'static str {
         "FLUSHDB"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
     fn min_args(&self) -> usize {
         0
     }
 
     fn max_args(&self) -> Option<usize> {
-        Some(0)
+        Some(1)
+    }
+}
+
+/// FLUSHALL command - Remove all keys from all databases
+///
+/// Syntax: FLUSHALL [ASYNC | SYNC]
+///
+/// FerrumDB has a single database, so this is the same as FLUSHDB.
+pub struct FlushAllCommand;
+
+impl Command for FlushAllCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        flush(ctx, args)
+    }
+
+    fn name(&self) -> &'static str {
+        "FLUSHALL"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(1)
     }
 }
 
@@ -75,6 +234,10 @@ impl Command for FlushDbCommand {
 /// - GETNAME: Get client name
 /// - LIST: List client connections
 /// - SETINFO: Set client info (stub)
+///
+/// Over RESP, the connection answers ID, INFO, LIST, SETNAME and GETNAME
+/// itself from the client registry (`server::CLIENTS`); the replies here
+/// are for callers without a connection, such as the Web API.
 pub struct ClientCommand;
 
 impl Command for ClientCommand {
@@ -140,6 +303,14 @@ impl Command for ClientCommand {
         "CLIENT"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["loading", "stale"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
     fn min_args(&self) -> usize {
         1
     }
@@ -149,10 +320,476 @@ impl Command for ClientCommand {
     }
 }
 
+/// Options of a HELLO command
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct HelloOptions {
+    /// Protocol version to switch to, None to keep the current one
+    pub protocol: Option<ProtocolVersion>,
+
+    /// Username and password of AUTH
+    pub auth: Option<(Bytes, Bytes)>,
+
+    /// Client name of SETNAME
+    pub name: Option<Bytes>,
+}
+
+/// Parse `[protover [AUTH username password] [SETNAME clientname]]`, with
+/// the errors of Redis
+pub(crate) fn parse_hello(args: &[RespValue]) -> Result<HelloOptions, RespValue> {
+    let mut options = HelloOptions::default();
+    let Some((version, rest)) = args.split_first() else {
+        return Ok(options);
+    };
+
+    options.protocol = match version.as_bulk_string().map(|v| super::parse_redis_int(v)) {
+        Some(Ok(2)) => Some(ProtocolVersion::Resp2),
+        Some(Ok(3)) => Some(ProtocolVersion::Resp3),
+        Some(Ok(_)) => return Err(RespValue::error("NOPROTO unsupported protocol version")),
+        _ => return Err(RespValue::error("ERR Protocol version is not an integer or out of range")),
+    };
+
+    let mut rest = rest.iter();
+    while let Some(option) = rest.next() {
+        let name = option.as_bulk_string().map(|o| String::from_utf8_lossy(o).into_owned()).unwrap_or_default();
+        let syntax_error = || RespValue::error(format!("ERR Syntax error in HELLO option '{}'", name));
+        let mut value = || rest.next().and_then(|v| v.as_bulk_string()).cloned().ok_or_else(syntax_error);
+        if name.eq_ignore_ascii_case("AUTH") {
+            options.auth = Some((value()?, value()?));
+        } else if name.eq_ignore_ascii_case("SETNAME") {
+            options.name = Some(value()?);
+        } else {
+            return Err(syntax_error());
+        }
+    }
+    Ok(options)
+}
+
+/// Check a username and password (AUTH, HELLO AUTH)
+///
+/// There are no passwords yet: like Redis without `requirepass`, the
+/// `default` user accepts any password, and there is no other user.
+pub(crate) fn authenticate(username: &[u8], _password: &[u8]) -> Result<(), RespValue> {
+    if username == b"default" {
+        Ok(())
+    } else {
+        Err(RespValue::error("WRONGPASS invalid username-password pair or user is disabled."))
+    }
+}
+
+/// The server metadata replied to HELLO, as a map (the flat array of the
+/// pairs in RESP2)
+pub(crate) fn hello_reply(protocol: ProtocolVersion, client_id: u64) -> RespValue {
+    let proto = match protocol {
+        ProtocolVersion::Resp2 => 2,
+        ProtocolVersion::Resp3 => 3,
+    };
+    let field = |name: &str, value| (RespValue::bulk_string(name.to_string()), value);
+    RespValue::map(vec![
+        field("server", RespValue::bulk_string("ferrumdb")),
+        field("version", RespValue::bulk_string(env!("CARGO_PKG_VERSION"))),
+        field("proto", RespValue::integer(proto)),
+        field("id", RespValue::integer(client_id as i64)),
+        field("mode", RespValue::bulk_string("standalone")),
+        field("role", RespValue::bulk_string(replication::role())),
+        field("modules", RespValue::array(vec![])),
+    ])
+}
+
+/// HELLO command - Handshake, switching the protocol version
+///
+/// Syntax: HELLO [protover [AUTH username password] [SETNAME clientname]]
+///
+/// Over RESP, the connection answers HELLO itself, switching its protocol
+/// and naming itself in the client registry; the reply here is for callers
+/// without a connection, such as the Web API, which stay in RESP2.
+pub struct HelloCommand;
+
+impl Command for HelloCommand {
+    fn execute(&self, _ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        let options = match parse_hello(args) {
+            Ok(options) => options,
+            Err(error) => return error,
+        };
+        if let Some((username, password)) = &options.auth {
+            if let Err(error) = authenticate(username, password) {
+                return error;
+            }
+        }
+        hello_reply(ProtocolVersion::Resp2, 0)
+    }
+
+    fn name(&self) -> &'static str {
+        "HELLO"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["noscript", "loading", "stale", "fast"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+}
+
+/// MEMORY command - Memory introspection
+///
+/// Syntax: MEMORY PURGE
+///
+/// FerrumDB runs on the system allocator, which has no purge hook, so
+/// PURGE is accepted as a no-op for tooling that expects it.
+pub struct MemoryCommand;
+
+impl Command for MemoryCommand {
+    fn execute(&self, _ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        let subcommand = match args[0].as_bulk_string() {
+            Some(s) => String::from_utf8_lossy(s).to_uppercase(),
+            None => return RespValue::error("ERR Expected bulk string"),
+        };
+
+        match subcommand.as_str() {
+            "PURGE" if args.len() == 1 => RespValue::simple_string("OK"),
+            "PURGE" => RespValue::error("ERR wrong number of arguments for 'memory|purge' command"),
+            _ => RespValue::error(format!("ERR unknown subcommand '{}'. Try MEMORY HELP.", subcommand)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "MEMORY"
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// BGREWRITEAOF command - Rewrite the AOF from the current dataset
+///
+/// Syntax: BGREWRITEAOF
+///
+/// Unlike Redis there is no fork: the shard writes the new file before
+/// replying, and the rewrite is done by the time the reply is received.
+pub struct BgRewriteAofCommand;
+
+impl Command for BgRewriteAofCommand {
+    fn execute(&self, ctx: &mut CommandContext, _args: &[RespValue]) -> RespValue {
+        let Some(writer) = &ctx.aof_writer else {
+            return RespValue::error("ERR AOF is disabled");
+        };
+
+        match rewrite_aof(writer, &ctx.store) {
+            Ok(_) => RespValue::simple_string("Background append only file rewriting started"),
+            Err(e) => RespValue::error(format!("ERR AOF rewrite failed: {}", e)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "BGREWRITEAOF"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["admin", "noscript"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(0)
+    }
+}
+
+/// TIME command - Get the server time
+///
+/// Syntax: TIME
+///
+/// Replies with the Unix time as [seconds, microseconds], both as bulk
+/// strings like Redis.
+pub struct TimeCommand;
+
+impl Command for TimeCommand {
+    fn execute(&self, _ctx: &mut CommandContext, _args: &[RespValue]) -> RespValue {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        RespValue::array(vec![
+            RespValue::bulk_string(now.as_secs().to_string()),
+            RespValue::bulk_string(now.subsec_micros().to_string()),
+        ])
+    }
+
+    fn name(&self) -> &'static str {
+        "TIME"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["loading", "stale", "fast"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(0)
+    }
+}
+
+/// ROLE command - Get the replication role of the server
+///
+/// Syntax: ROLE
+///
+/// FerrumDB has no replication (and no REPLICAOF) yet, so it always
+/// replies with the master form: ["master", replication offset, [replicas]],
+/// with no replica. Enough for clients that check the role before sending
+/// commands.
+pub struct RoleCommand;
+
+impl Command for RoleCommand {
+    fn execute(&self, _ctx: &mut CommandContext, _args: &[RespValue]) -> RespValue {
+        RespValue::array(vec![
+            RespValue::bulk_string(replication::role()),
+            RespValue::integer(replication::master_repl_offset() as i64),
+            RespValue::array(vec![]),
+        ])
+    }
+
+    fn name(&self) -> &'static str {
+        "ROLE"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["noscript", "loading", "stale", "fast"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(0)
+    }
+}
+
+/// Handle `CONFIG SET parameter value [parameter value ...]`
+///
+/// All the parameters are set or none: on an invalid value, those already
+/// set get their previous value back.
+fn config_set(args: &[RespValue]) -> RespValue {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return RespValue::error("ERR wrong number of arguments for 'config|set' command");
+    }
+
+    let mut pairs = Vec::with_capacity(args.len() / 2);
+    for pair in args.chunks(2) {
+        match (extract_bulk_string(&pair[0]), extract_bulk_string(&pair[1])) {
+            (Ok(name), Ok(value)) => pairs.push((
+                String::from_utf8_lossy(name).into_owned(),
+                String::from_utf8_lossy(value).into_owned(),
+            )),
+            (Err(e), _) | (_, Err(e)) => return RespValue::error(format!("ERR {}", e)),
+        }
+    }
+
+    let mut previous: Vec<(&str, String)> = Vec::with_capacity(pairs.len());
+    for (name, value) in &pairs {
+        let Some(old) = SERVER_CONFIG.get(name) else {
+            return RespValue::error(format!("ERR {}", crate::config::ConfigError::UnknownOption(name.clone())));
+        };
+        if let Err(e) = SERVER_CONFIG.set(name, value) {
+            for (name, old) in previous.iter().rev() {
+                let _ = SERVER_CONFIG.set(name, old);
+            }
+            return RespValue::error(format!("ERR {}", e));
+        }
+        previous.push((name, old));
+    }
+
+    RespValue::simple_string("OK")
+}
+
+/// CONFIG command - Read and change the runtime configuration
+///
+/// Syntax:
+/// - CONFIG GET pattern [pattern ...]
+/// - CONFIG SET parameter value [parameter value ...]
+/// - CONFIG REWRITE
+///
+/// GET replies with a map of the parameters matching the glob patterns to
+/// their values (a flat [name, value, ...] array in RESP2). REWRITE writes the current values to the
+/// config file the server was started with (see `config::ServerConfig`).
+/// The configuration is process-wide, whichever shard runs the command.
+pub struct ConfigCommand;
+
+impl Command for ConfigCommand {
+    fn execute(&self, _ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.is_empty() {
+            return RespValue::error("ERR wrong number of arguments for 'CONFIG' command");
+        }
+
+        let subcommand = match extract_bulk_string(&args[0]) {
+            Ok(s) => String::from_utf8_lossy(s).to_uppercase(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        match subcommand.as_str() {
+            "GET" => {
+                if args.len() < 2 {
+                    return RespValue::error("ERR wrong number of arguments for 'config|get' command");
+                }
+                let mut reply: Vec<(&'static str, String)> = Vec::new();
+                for pattern in &args[1..] {
+                    let pattern = match extract_bulk_string(pattern) {
+                        Ok(p) => p,
+                        Err(e) => return RespValue::error(format!("ERR {}", e)),
+                    };
+                    for (name, value) in SERVER_CONFIG.matching(pattern) {
+                        if !reply.iter().any(|(seen, _)| *seen == name) {
+                            reply.push((name, value));
+                        }
+                    }
+                }
+                RespValue::map(
+                    reply
+                        .into_iter()
+                        .map(|(name, value)| (RespValue::bulk_string(name), RespValue::bulk_string(value)))
+                        .collect(),
+                )
+            }
+            "SET" => config_set(&args[1..]),
+            "REWRITE" => {
+                if args.len() != 1 {
+                    return RespValue::error("ERR wrong number of arguments for 'config|rewrite' command");
+                }
+                match SERVER_CONFIG.rewrite() {
+                    Ok(()) => RespValue::simple_string("OK"),
+                    Err(e) => RespValue::error(format!("ERR {}", e)),
+                }
+            }
+            _ => RespValue::error(format!("ERR unknown subcommand '{}'. Try CONFIG HELP.", subcommand)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "CONFIG"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["admin", "noscript", "loading", "stale"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+}
+
+/// Build the COMMAND INFO reply for a single command
+///
+/// Format: [name, arity, [flags...], first key, last key, step]
+fn command_info_reply(info: CommandInfo) -> RespValue {
+    RespValue::array(vec![
+        RespValue::bulk_string(info.name),
+        RespValue::integer(info.arity),
+        RespValue::array(info.flags.iter().map(|f| RespValue::simple_string(*f)).collect()),
+        RespValue::integer(info.first_key),
+        RespValue::integer(info.last_key),
+        RespValue::integer(info.step),
+    ])
+}
+
+/// COMMAND command - Introspect the registered commands
+///
+/// Syntax: COMMAND [COUNT | LIST | INFO [name ...] | GETKEYS command [arg ...]]
+///
+/// Without subcommand (or INFO without names), returns the info of every
+/// command. COMMAND INFO replies with a nil element for unknown names.
+/// GETKEYS returns the keys the given command would access.
+pub struct CommandCommand;
+
+impl Command for CommandCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        let registry = &ctx.registry;
+
+        // Sorted so the output is stable
+        let mut names = registry.command_names();
+        names.sort();
+
+        let all_infos = || {
+            RespValue::array(
+                names
+                    .iter()
+                    .filter_map(|name| registry.info(name))
+                    .map(command_info_reply)
+                    .collect(),
+            )
+        };
+
+        let subcommand = match args.first() {
+            None => return all_infos(),
+            Some(RespValue::BulkString(bytes)) => String::from_utf8_lossy(bytes).to_uppercase(),
+            Some(_) => return RespValue::error("ERR invalid subcommand"),
+        };
+
+        match subcommand.as_str() {
+            "COUNT" => RespValue::integer(names.len() as i64),
+            "LIST" => RespValue::array(
+                names
+                    .iter()
+                    .map(|name| RespValue::bulk_string(name.to_lowercase()))
+                    .collect(),
+            ),
+            "INFO" if args.len() == 1 => all_infos(),
+            "INFO" => {
+                let mut infos = Vec::with_capacity(args.len() - 1);
+                for arg in &args[1..] {
+                    let info = arg
+                        .as_bulk_string()
+                        .and_then(|name| std::str::from_utf8(name).ok())
+                        .and_then(|name| registry.info(name));
+                    infos.push(info.map(command_info_reply).unwrap_or(RespValue::Null));
+                }
+                RespValue::array(infos)
+            }
+            "GETKEYS" if args.len() == 1 => {
+                RespValue::error("ERR wrong number of arguments for 'command|getkeys' command")
+            }
+            "GETKEYS" => match registry.get_keys(&args[1..]) {
+                Ok(keys) => RespValue::array(keys.into_iter().map(RespValue::bulk_string).collect()),
+                Err(e) => RespValue::error(format!("ERR {}", e)),
+            },
+            _ => RespValue::error(format!("ERR unknown subcommand '{}'", subcommand)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "COMMAND"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["loading", "stale"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::commands::args;
     use crate::store::Value;
+    use bytes::Bytes;
 
     #[test]
     fn test_info() {
@@ -172,6 +809,128 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_info_replication() {
+        let mut ctx = CommandContext::new();
+        let RespValue::BulkString(bytes) = InfoCommand.execute(&mut ctx, &[]) else {
+            panic!("Expected bulk string response");
+        };
+        let info = String::from_utf8(bytes.to_vec()).unwrap();
+        let section: Vec<&str> = info
+            .split("\r\n")
+            .skip_while(|line| *line != "# Replication")
+            .take_while(|line| !line.is_empty())
+            .collect();
+
+        assert!(section.contains(&"role:master"));
+        assert!(section.contains(&"connected_slaves:0"));
+        // Advanced by the writes of the other tests' shards meanwhile
+        let offset = section.iter().find_map(|line| line.strip_prefix("master_repl_offset:")).unwrap();
+        assert!(offset.parse::<u64>().is_ok());
+        let replid = section.iter().find_map(|line| line.strip_prefix("master_replid:")).unwrap();
+        assert_eq!(replid.len(), 40);
+        assert!(replid.bytes().all(|b| b.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn test_info_persistence() {
+        use crate::aof::{AofWriter, SyncPolicy};
+        use std::sync::Arc;
+
+        let section = |ctx: &mut CommandContext| -> Vec<String> {
+            let RespValue::BulkString(bytes) = InfoCommand.execute(ctx, &[]) else {
+                panic!("Expected bulk string response");
+            };
+            String::from_utf8(bytes.to_vec())
+                .unwrap()
+                .split("\r\n")
+                .skip_while(|line| *line != "# Persistence")
+                .take_while(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+
+        let mut ctx = CommandContext::new();
+        assert!(section(&mut ctx).contains(&"aof_enabled:0".to_string()));
+
+        let path = std::env::temp_dir().join(format!("ferrumdb_info_persistence_{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        ctx.set_aof_writer(Arc::new(AofWriter::new(&path, SyncPolicy::No).unwrap()));
+        ctx.store.set("k", Value::string("v"));
+        crate::commands::log_key_to_aof(&ctx, &bytes::Bytes::from("k"));
+
+        let persistence = section(&mut ctx);
+        for line in ["aof_enabled:1", "aof_rewrite_in_progress:0", "aof_last_write_status:ok"] {
+            assert!(persistence.contains(&line.to_string()), "{:?}", persistence);
+        }
+        for field in ["loading:", "rdb_last_load_keys_loaded:"] {
+            assert!(persistence.iter().any(|line| line.starts_with(field)), "{:?}", persistence);
+        }
+        // Other tests may have writers open too, but this one has pending bytes
+        let pending: u64 = persistence
+            .iter()
+            .find_map(|line| line.strip_prefix("aof_pending_fsync:"))
+            .and_then(|n| n.parse().ok())
+            .unwrap();
+        assert!(pending > 0);
+        assert!(persistence.iter().any(|line| line.starts_with("aof_last_bgrewrite_status:")));
+
+        drop(ctx);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Read a numeric field of the INFO reply
+    fn info_field(ctx: &mut CommandContext, field: &str) -> u64 {
+        let RespValue::BulkString(bytes) = InfoCommand.execute(ctx, &[]) else {
+            panic!("Expected bulk string response");
+        };
+        let info = String::from_utf8(bytes.to_vec()).unwrap();
+        info.lines()
+            .find_map(|line| line.strip_prefix(&format!("{}:", field)))
+            .and_then(|value| value.parse().ok())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_info_keyspace_stats() {
+        use crate::commands::string::GetCommand;
+
+        let mut ctx = CommandContext::new();
+        ctx.store.set("present", Value::string("value"));
+
+        // The counters are process-wide, other tests may move them too
+        let hits = info_field(&mut ctx, "keyspace_hits");
+        let misses = info_field(&mut ctx, "keyspace_misses");
+        GetCommand.execute(&mut ctx, &[RespValue::bulk_string("present")]);
+        GetCommand.execute(&mut ctx, &[RespValue::bulk_string("missing")]);
+        assert!(info_field(&mut ctx, "keyspace_hits") > hits);
+        assert!(info_field(&mut ctx, "keyspace_misses") > misses);
+
+        // An expired key is reaped on access
+        let expired = info_field(&mut ctx, "expired_keys");
+        ctx.store.get_entry_mut(&Bytes::from("present")).unwrap().expire_at = Some(std::time::Instant::now());
+        assert_eq!(GetCommand.execute(&mut ctx, &[RespValue::bulk_string("present")]), RespValue::null());
+        assert!(info_field(&mut ctx, "expired_keys") > expired);
+    }
+
+    #[test]
+    fn test_memory_purge_and_fragmentation() {
+        let mut ctx = CommandContext::new();
+        ctx.store.set("key", Value::string("value"));
+
+        let args = vec![RespValue::bulk_string("purge")];
+        assert_eq!(MemoryCommand.execute(&mut ctx, &args), RespValue::simple_string("OK"));
+
+        let RespValue::BulkString(bytes) = InfoCommand.execute(&mut ctx, &[]) else {
+            panic!("Expected bulk string response");
+        };
+        let info = String::from_utf8(bytes.to_vec()).unwrap();
+        let ratio = info.lines()
+            .find_map(|line| line.strip_prefix("mem_fragmentation_ratio:"))
+            .unwrap();
+        assert!(ratio.parse::<f64>().unwrap() > 0.0);
+    }
+
     #[test]
     fn test_flushdb() {
         let mut ctx = CommandContext::new();
@@ -185,5 +944,215 @@ mod tests {
         assert_eq!(result, RespValue::simple_string("OK"));
 
         assert_eq!(ctx.store.len(), 0);
+
+        let result = cmd.execute(&mut ctx, &[RespValue::bulk_string("LATER")]);
+        assert_eq!(result, RespValue::error("ERR syntax error"));
+    }
+
+    #[tokio::test]
+    async fn test_flushdb_async_frees_in_background() {
+        use bytes::Bytes;
+        use std::sync::mpsc;
+        use std::time::{Duration, Instant};
+
+        /// Buffer reporting the thread it is dropped on
+        struct Tracked(Vec<u8>, mpsc::Sender<std::thread::ThreadId>);
+
+        impl AsRef<[u8]> for Tracked {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                let _ = self.1.send(std::thread::current().id());
+            }
+        }
+
+        let mut ctx = CommandContext::new();
+        for i in 0..100_000 {
+            ctx.store.set(format!("key:{}", i), Value::string("v"));
+        }
+        let (tx, rx) = mpsc::channel();
+        ctx.store.set("tracked", Value::String(Bytes::from_owner(Tracked(b"v".to_vec(), tx))));
+
+        let start = Instant::now();
+        let result = FlushAllCommand.execute(&mut ctx, &[RespValue::bulk_string("async")]);
+        let elapsed = start.elapsed();
+        assert_eq!(result, RespValue::simple_string("OK"));
+        assert_eq!(ctx.store.len(), 0);
+        assert!(ctx.store.get(&Bytes::from("key:1")).is_none());
+        assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+
+        // The old keys are dropped off the calling thread
+        let dropped_on = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_ne!(dropped_on, std::thread::current().id());
+    }
+
+    #[test]
+    fn test_command_info() {
+        let mut ctx = CommandContext::new();
+
+        let args = vec![
+            RespValue::bulk_string("INFO"),
+            RespValue::bulk_string("get"),
+            RespValue::bulk_string("NOSUCHCMD"),
+        ];
+        let result = CommandCommand.execute(&mut ctx, &args);
+        assert_eq!(result, RespValue::array(vec![
+            RespValue::array(vec![
+                RespValue::bulk_string("get"),
+                RespValue::integer(2),
+                RespValue::array(vec![RespValue::simple_string("readonly"), RespValue::simple_string("fast")]),
+                RespValue::integer(1),
+                RespValue::integer(1),
+                RespValue::integer(1),
+            ]),
+            RespValue::Null,
+        ]));
+
+        // Variadic commands report a negative arity
+        let info = ctx.registry.info("DEL").unwrap();
+        assert_eq!((info.arity, info.last_key), (-2, -1));
+    }
+
+    #[test]
+    fn test_hello() {
+        let options = parse_hello(&args(&["3", "SETNAME", "worker", "auth", "default", "secret"])).unwrap();
+        assert_eq!(options.protocol, Some(ProtocolVersion::Resp3));
+        assert_eq!(options.name, Some(Bytes::from("worker")));
+        assert_eq!(options.auth, Some((Bytes::from("default"), Bytes::from("secret"))));
+        assert_eq!(parse_hello(&[]).unwrap(), HelloOptions::default());
+
+        assert_eq!(parse_hello(&args(&["4"])).unwrap_err(), RespValue::error("NOPROTO unsupported protocol version"));
+        assert_eq!(
+            parse_hello(&args(&["three"])).unwrap_err(),
+            RespValue::error("ERR Protocol version is not an integer or out of range")
+        );
+        assert_eq!(
+            parse_hello(&args(&["3", "AUTH", "default"])).unwrap_err(),
+            RespValue::error("ERR Syntax error in HELLO option 'AUTH'")
+        );
+        assert_eq!(
+            parse_hello(&args(&["3", "FAST"])).unwrap_err(),
+            RespValue::error("ERR Syntax error in HELLO option 'FAST'")
+        );
+        assert!(authenticate(b"default", b"anything").is_ok());
+        assert_eq!(
+            authenticate(b"admin", b"secret").unwrap_err(),
+            RespValue::error("WRONGPASS invalid username-password pair or user is disabled.")
+        );
+
+        let RespValue::Map(fields) = hello_reply(ProtocolVersion::Resp3, 42) else {
+            panic!("HELLO should reply with a map");
+        };
+        let keys: Vec<_> = fields.iter().filter_map(|(key, _)| key.as_bulk_string().cloned()).collect();
+        assert_eq!(keys, ["server", "version", "proto", "id", "mode", "role", "modules"]);
+        assert_eq!(fields[2].1, RespValue::integer(3));
+        assert_eq!(fields[3].1, RespValue::integer(42));
+    }
+
+    #[test]
+    fn test_command_list_and_count() {
+        let mut ctx = CommandContext::new();
+
+        let result = CommandCommand.execute(&mut ctx, &[RespValue::bulk_string("LIST")]);
+        let names = match result {
+            RespValue::Array(names) => names,
+            other => panic!("Unexpected reply: {:?}", other),
+        };
+        assert!(names.contains(&RespValue::bulk_string("command")));
+        assert!(names.contains(&RespValue::bulk_string("zpopmin")));
+
+        let result = CommandCommand.execute(&mut ctx, &[RespValue::bulk_string("COUNT")]);
+        assert_eq!(result, RespValue::integer(names.len() as i64));
+    }
+
+    #[test]
+    fn test_command_getkeys() {
+        let mut ctx = CommandContext::new();
+        let getkeys = |ctx: &mut CommandContext, parts: &[&str]| {
+            let mut args = args(parts);
+            args.insert(0, RespValue::bulk_string("GETKEYS"));
+            CommandCommand.execute(ctx, &args)
+        };
+        let keys = |names: &[&str]| RespValue::array(names.iter().map(|n| RespValue::bulk_string(n.to_string())).collect());
+
+        assert_eq!(getkeys(&mut ctx, &["MSET", "a", "1", "b", "2"]), keys(&["a", "b"]));
+        assert_eq!(getkeys(&mut ctx, &["GET", "foo"]), keys(&["foo"]));
+        assert_eq!(getkeys(&mut ctx, &["get", "foo"]), keys(&["foo"]));
+        assert_eq!(getkeys(&mut ctx, &["DEL", "a", "b", "c"]), keys(&["a", "b", "c"]));
+
+        assert_eq!(getkeys(&mut ctx, &["INFO"]), RespValue::error("ERR The command has no key arguments"));
+        assert_eq!(getkeys(&mut ctx, &["NOPE", "a"]), RespValue::error("ERR Invalid command specified"));
+        assert_eq!(
+            getkeys(&mut ctx, &["GET"]),
+            RespValue::error("ERR Invalid number of arguments specified for command")
+        );
+        assert_eq!(
+            getkeys(&mut ctx, &[]),
+            RespValue::error("ERR wrong number of arguments for 'command|getkeys' command")
+        );
+    }
+
+    #[test]
+    fn test_time() {
+        let mut ctx = CommandContext::new();
+        let reply = TimeCommand.execute(&mut ctx, &[]);
+
+        // [seconds, microseconds] as numeric bulk strings
+        let parts = reply.as_array().expect("Expected an array");
+        assert_eq!(parts.len(), 2);
+        let field = |i: usize| -> u64 {
+            std::str::from_utf8(parts[i].as_bulk_string().expect("Expected a bulk string"))
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        assert!(field(0).abs_diff(now) <= 5, "implausible timestamp {}", field(0));
+        assert!(field(1) < 1_000_000);
+    }
+
+    #[test]
+    fn test_config_get_set() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
+        let mut ctx = CommandContext::new();
+        let config = |ctx: &mut CommandContext, parts: &[&str]| {
+            ConfigCommand.execute(ctx, &args(parts))
+        };
+
+        assert_eq!(config(&mut ctx, &["SET", "list-max-listpack-size", "64"]), RespValue::simple_string("OK"));
+        let reply = config(&mut ctx, &["GET", "list-max-*"]);
+        crate::store::set_list_max_listpack_size(crate::store::DEFAULT_LIST_MAX_LISTPACK_SIZE);
+        assert_eq!(
+            reply,
+            RespValue::map(vec![(RespValue::bulk_string("list-max-listpack-size"), RespValue::bulk_string("64"))])
+        );
+
+        // An invalid value leaves everything unchanged
+        let reply = config(&mut ctx, &["SET", "list-max-listpack-size", "64", "list-max-listpack-size", "x"]);
+        assert!(matches!(reply, RespValue::Error(_)));
+        assert_eq!(crate::store::list_max_listpack_size(), crate::store::DEFAULT_LIST_MAX_LISTPACK_SIZE);
+
+        // The tests don't start from a config file
+        assert_eq!(
+            config(&mut ctx, &["REWRITE"]),
+            RespValue::error("ERR The server is running without a config file")
+        );
+    }
+
+    #[test]
+    fn test_role_is_master() {
+        let mut ctx = CommandContext::new();
+        let reply = RoleCommand.execute(&mut ctx, &[]);
+        let parts = reply.as_array().unwrap();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], RespValue::bulk_string("master"));
+        // Advanced by the writes of the other tests' shards meanwhile
+        assert!(matches!(parts[1], RespValue::Integer(offset) if offset >= 0));
+        assert_eq!(parts[2], RespValue::array(vec![]));
     }
 }
@@ -0,0 +1,498 @@
+//! Bitmap commands (SETBIT, GETBIT, BITOP, BITPOS)
+//!
+//! Bitmaps are not a separate type: they operate on string values, with
+//! bit 0 being the most significant bit of the first byte (like Redis).
+
+use super::{Command, CommandContext, extract_bulk_string, extract_integer, log_to_aof, MAX_STRING_SIZE, WRONGTYPE};
+use crate::protocol::RespValue;
+use crate::store::Value;
+use crate::aof::AofOperation;
+use bytes::Bytes;
+
+/// Largest bit offset accepted by SETBIT (512MB strings, like Redis)
+const MAX_BIT_OFFSET: i64 = (MAX_STRING_SIZE as i64 * 8) - 1;
+
+/// Parse a bit offset argument
+fn parse_offset(value: &RespValue) -> Result<usize, &'static str> {
+    match extract_integer(value) {
+        Ok(offset) if (0..=MAX_BIT_OFFSET).contains(&offset) => Ok(offset as usize),
+        _ => Err("bit offset is not an integer or out of range"),
+    }
+}
+
+/// Read a single bit from a byte string (bits past the end are 0)
+fn get_bit(bytes: &[u8], offset: usize) -> u8 {
+    match bytes.get(offset / 8) {
+        Some(byte) => (byte >> (7 - (offset % 8))) & 1,
+        None => 0,
+    }
+}
+
+/// Bitwise operation supported by BITOP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+impl BitOp {
+    /// Parse an operation name (case-insensitive)
+    pub fn parse(name: &[u8]) -> Option<Self> {
+        match name.to_ascii_uppercase().as_slice() {
+            b"AND" => Some(BitOp::And),
+            b"OR" => Some(BitOp::Or),
+            b"XOR" => Some(BitOp::Xor),
+            b"NOT" => Some(BitOp::Not),
+            _ => None,
+        }
+    }
+}
+
+/// Compute a bitwise operation over source strings
+///
+/// Shorter sources are zero-extended to the length of the longest one.
+/// Shared with the cluster layer, which gathers sources from several shards.
+pub fn bitop(op: BitOp, sources: &[Bytes]) -> Vec<u8> {
+    let len = sources.iter().map(|s| s.len()).max().unwrap_or(0);
+    let byte_at = |src: &Bytes, i: usize| src.get(i).copied().unwrap_or(0);
+
+    (0..len)
+        .map(|i| match op {
+            BitOp::Not => !byte_at(&sources[0], i),
+            BitOp::And => sources.iter().fold(0xFF, |acc, s| acc & byte_at(s, i)),
+            BitOp::Or => sources.iter().fold(0x00, |acc, s| acc | byte_at(s, i)),
+            BitOp::Xor => sources.iter().fold(0x00, |acc, s| acc ^ byte_at(s, i)),
+        })
+        .collect()
+}
+
+/// Store a BITOP result in the destination key and return the reply
+///
+/// An empty result deletes the destination, like Redis.
+fn store_bitop_result(ctx: &mut CommandContext, dest: Bytes, result: Vec<u8>) -> RespValue {
+    let len = result.len() as i64;
+
+    if result.is_empty() {
+        if ctx.store.delete(&dest) {
+            log_to_aof(ctx, AofOperation::Del, dest, vec![]);
+        }
+    } else {
+        let result = Bytes::from(result);
+        log_to_aof(ctx, AofOperation::Set, dest.clone(), vec![result.clone()]);
+        ctx.store.set(dest, Value::String(result));
+    }
+
+    RespValue::integer(len)
+}
+
+/// SETBIT command - Set or clear the bit at offset in the string value
+///
+/// Syntax: SETBIT key offset value
+///
+/// Returns the original bit value. The string grows (zero-filled) as needed.
+pub struct SetBitCommand;
+
+impl Command for SetBitCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() != 3 {
+            return RespValue::error("ERR wrong number of arguments for 'SETBIT' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let offset = match parse_offset(&args[1]) {
+            Ok(o) => o,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let bit = match extract_integer(&args[2]) {
+            Ok(b @ (0 | 1)) => b as u8,
+            _ => return RespValue::error("ERR bit is not an integer or out of range"),
+        };
+
+        let mut bytes = match ctx.store.get(&key) {
+            Some(value) => match value.as_string_bytes() {
+                Some(b) => b.to_vec(),
+                None => return RespValue::error(WRONGTYPE),
+            },
+            None => Vec::new(),
+        };
+
+        let byte_index = offset / 8;
+        if bytes.len() <= byte_index {
+            bytes.resize(byte_index + 1, 0);
+        }
+
+        let old_bit = get_bit(&bytes, offset);
+        let mask = 1u8 << (7 - (offset % 8));
+        if bit == 1 {
+            bytes[byte_index] |= mask;
+        } else {
+            bytes[byte_index] &= !mask;
+        }
+
+        // Keep any TTL: update the value in place when the key exists
+        let bytes = Bytes::from(bytes);
+        match ctx.store.get_mut(&key) {
+            Some(value) => {
+                *value = Value::String(bytes.clone());
+                ctx.store.notify_modified(&key);
+            }
+            None => {
+                ctx.store.set(key.clone(), Value::String(bytes.clone()));
+            }
+        }
+        if let Some(entry) = ctx.store.get_entry_mut(&key) {
+            entry.raw_string = true;
+        }
+
+        // Logged as a SET of the resulting string, like INCR
+        log_to_aof(ctx, AofOperation::Set, key, vec![bytes]);
+
+        RespValue::integer(old_bit as i64)
+    }
+
+    fn name(&self) -> &'static str {
+        "SETBIT"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(3)
+    }
+}
+
+/// GETBIT command - Get the bit value at offset in the string value
+///
+/// Syntax: GETBIT key offset
+pub struct GetBitCommand;
+
+impl Command for GetBitCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() != 2 {
+            return RespValue::error("ERR wrong number of arguments for 'GETBIT' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let offset = match parse_offset(&args[1]) {
+            Ok(o) => o,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        match ctx.store.lookup_read(key) {
+            Some(value) => match value.as_string_bytes() {
+                Some(bytes) => RespValue::integer(get_bit(&bytes, offset) as i64),
+                None => RespValue::error(WRONGTYPE),
+            },
+            None => RespValue::integer(0),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "GETBIT"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+/// BITOP command - Perform a bitwise operation between strings
+///
+/// Syntax: BITOP AND|OR|XOR|NOT destkey key [key ...]
+///
+/// Missing source keys are treated as empty strings. Returns the length of
+/// the resulting string; a zero-length result deletes the destination.
+pub struct BitOpCommand;
+
+impl Command for BitOpCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() < 3 {
+            return RespValue::error("ERR wrong number of arguments for 'BITOP' command");
+        }
+
+        let op = match extract_bulk_string(&args[0]).ok().and_then(|b| BitOp::parse(b)) {
+            Some(op) => op,
+            None => return RespValue::error("ERR syntax error"),
+        };
+
+        if op == BitOp::Not && args.len() != 3 {
+            return RespValue::error("ERR BITOP NOT must be called with a single source key.");
+        }
+
+        let dest = match extract_bulk_string(&args[1]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        // Collect source values
+        let mut sources = Vec::with_capacity(args.len() - 2);
+        for arg in &args[2..] {
+            let key = match extract_bulk_string(arg) {
+                Ok(k) => k,
+                Err(e) => return RespValue::error(format!("ERR {}", e)),
+            };
+            match ctx.store.get(key) {
+                Some(value) => match value.as_string_bytes() {
+                    Some(bytes) => sources.push(bytes),
+                    None => return RespValue::error(WRONGTYPE),
+                },
+                None => sources.push(Bytes::new()),
+            }
+        }
+
+        let result = bitop(op, &sources);
+        store_bitop_result(ctx, dest, result)
+    }
+
+    fn name(&self) -> &'static str {
+        "BITOP"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (2, -1, 1)
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+}
+
+/// BITPOS command - Find the first bit set to 1 or 0 in a string
+///
+/// Syntax: BITPOS key bit [start [end [BYTE|BIT]]]
+///
+/// `start` and `end` are inclusive byte indexes (bit indexes with BIT) and
+/// may be negative to count from the end. When looking for a 0 without an
+/// explicit end, the string is considered padded with zeros on the right,
+/// so an all-ones string returns the first bit past its end; with an
+/// explicit end, -1 is returned instead.
+pub struct BitPosCommand;
+
+impl Command for BitPosCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() < 2 || args.len() > 5 {
+            return RespValue::error("ERR wrong number of arguments for 'BITPOS' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let bit = match extract_integer(&args[1]) {
+            Ok(b @ (0 | 1)) => b as u8,
+            _ => return RespValue::error("ERR The bit argument must be 1 or 0."),
+        };
+
+        let start = match args.get(2).map(extract_integer).transpose() {
+            Ok(s) => s.unwrap_or(0),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let end = match args.get(3).map(extract_integer).transpose() {
+            Ok(e) => e,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let bit_unit = match args.get(4).map(extract_bulk_string) {
+            None => false,
+            Some(Ok(unit)) if unit.eq_ignore_ascii_case(b"BYTE") => false,
+            Some(Ok(unit)) if unit.eq_ignore_ascii_case(b"BIT") => true,
+            Some(_) => return RespValue::error("ERR syntax error"),
+        };
+
+        let bytes = match ctx.store.lookup_read(key) {
+            Some(value) => match value.as_string_bytes() {
+                Some(b) => b,
+                None => return RespValue::error(WRONGTYPE),
+            },
+            // A missing key is an empty string: no 1 bit, first 0 bit at 0
+            None => return RespValue::integer(if bit == 1 { -1 } else { 0 }),
+        };
+
+        let len = if bit_unit { bytes.len() * 8 } else { bytes.len() } as i64;
+        let normalize = |index: i64| if index < 0 { (index + len).max(0) } else { index };
+        let start = normalize(start);
+        let end_index = normalize(end.unwrap_or(len - 1)).min(len - 1);
+
+        if bytes.is_empty() || start > end_index {
+            return RespValue::integer(-1);
+        }
+
+        // Convert to an inclusive bit range
+        let (first_bit, last_bit) = if bit_unit {
+            (start as usize, end_index as usize)
+        } else {
+            (start as usize * 8, end_index as usize * 8 + 7)
+        };
+
+        if let Some(pos) = (first_bit..=last_bit).find(|&offset| get_bit(&bytes, offset) == bit) {
+            return RespValue::integer(pos as i64);
+        }
+
+        // Not found: only a search for 0 with an open end continues past the range
+        if bit == 0 && end.is_none() {
+            RespValue::integer(last_bit as i64 + 1)
+        } else {
+            RespValue::integer(-1)
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "BITPOS"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitop_cmd(ctx: &mut CommandContext, args: &[&str]) -> RespValue {
+        let args: Vec<RespValue> = args.iter().map(|a| RespValue::bulk_string(a.to_string())).collect();
+        BitOpCommand.execute(ctx, &args)
+    }
+
+    fn dest_bytes(ctx: &mut CommandContext) -> Vec<u8> {
+        ctx.store.get(&Bytes::from("dest")).unwrap().as_string().unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_setbit_getbit() {
+        let mut ctx = CommandContext::new();
+
+        let args = vec![
+            RespValue::bulk_string("bits"),
+            RespValue::bulk_string("7"),
+            RespValue::bulk_string("1"),
+        ];
+        assert_eq!(SetBitCommand.execute(&mut ctx, &args), RespValue::integer(0));
+        assert_eq!(SetBitCommand.execute(&mut ctx, &args), RespValue::integer(1));
+        assert_eq!(ctx.store.get(&Bytes::from("bits")).unwrap().as_string().unwrap(), &Bytes::from_static(&[0x01]));
+
+        let args = vec![RespValue::bulk_string("bits"), RespValue::bulk_string("7")];
+        assert_eq!(GetBitCommand.execute(&mut ctx, &args), RespValue::integer(1));
+
+        // Past the end of the string reads as 0
+        let args = vec![RespValue::bulk_string("bits"), RespValue::bulk_string("100")];
+        assert_eq!(GetBitCommand.execute(&mut ctx, &args), RespValue::integer(0));
+    }
+
+    #[test]
+    fn test_bitop_length_mismatch() {
+        let mut ctx = CommandContext::new();
+        ctx.store.set("a", Value::String(Bytes::from_static(&[0xF0, 0xFF])));
+        ctx.store.set("b", Value::String(Bytes::from_static(&[0x3C])));
+
+        // The shorter source is zero-extended
+        assert_eq!(bitop_cmd(&mut ctx, &["AND", "dest", "a", "b"]), RespValue::integer(2));
+        assert_eq!(dest_bytes(&mut ctx), vec![0x30, 0x00]);
+
+        assert_eq!(bitop_cmd(&mut ctx, &["OR", "dest", "a", "b"]), RespValue::integer(2));
+        assert_eq!(dest_bytes(&mut ctx), vec![0xFC, 0xFF]);
+
+        assert_eq!(bitop_cmd(&mut ctx, &["xor", "dest", "a", "b", "missing"]), RespValue::integer(2));
+        assert_eq!(dest_bytes(&mut ctx), vec![0xCC, 0xFF]);
+    }
+
+    #[test]
+    fn test_bitop_not_single_source() {
+        let mut ctx = CommandContext::new();
+        ctx.store.set("a", Value::String(Bytes::from_static(&[0x0F])));
+
+        let result = bitop_cmd(&mut ctx, &["NOT", "dest", "a", "b"]);
+        assert_eq!(result, RespValue::error("ERR BITOP NOT must be called with a single source key."));
+
+        assert_eq!(bitop_cmd(&mut ctx, &["NOT", "dest", "a"]), RespValue::integer(1));
+        assert_eq!(dest_bytes(&mut ctx), vec![0xF0]);
+    }
+
+    #[test]
+    fn test_bitop_empty_result_deletes_dest() {
+        let mut ctx = CommandContext::new();
+        ctx.store.set("dest", Value::string("old"));
+
+        assert_eq!(bitop_cmd(&mut ctx, &["OR", "dest", "missing1", "missing2"]), RespValue::integer(0));
+        assert!(!ctx.store.exists(&Bytes::from("dest")));
+    }
+
+    fn bitpos_cmd(ctx: &mut CommandContext, args: &[&str]) -> RespValue {
+        let args: Vec<RespValue> = args.iter().map(|a| RespValue::bulk_string(a.to_string())).collect();
+        BitPosCommand.execute(ctx, &args)
+    }
+
+    #[test]
+    fn test_bitpos_all_ones() {
+        let mut ctx = CommandContext::new();
+        ctx.store.set("ones", Value::String(Bytes::from_static(&[0xFF, 0xFF, 0xFF])));
+
+        assert_eq!(bitpos_cmd(&mut ctx, &["ones", "1"]), RespValue::integer(0));
+
+        // Without an end, the string is considered zero-padded on the right
+        assert_eq!(bitpos_cmd(&mut ctx, &["ones", "0"]), RespValue::integer(24));
+        assert_eq!(bitpos_cmd(&mut ctx, &["ones", "0", "1"]), RespValue::integer(24));
+
+        // With an explicit end, no clear bit is found
+        assert_eq!(bitpos_cmd(&mut ctx, &["ones", "0", "0", "-1"]), RespValue::integer(-1));
+        assert_eq!(bitpos_cmd(&mut ctx, &["ones", "0", "2", "20", "BIT"]), RespValue::integer(-1));
+    }
+
+    #[test]
+    fn test_bitpos_bit_in_middle() {
+        let mut ctx = CommandContext::new();
+        ctx.store.set("bits", Value::String(Bytes::from_static(&[0x00, 0x10, 0x00])));
+
+        assert_eq!(bitpos_cmd(&mut ctx, &["bits", "1"]), RespValue::integer(11));
+        assert_eq!(bitpos_cmd(&mut ctx, &["bits", "1", "1"]), RespValue::integer(11));
+        assert_eq!(bitpos_cmd(&mut ctx, &["bits", "1", "2"]), RespValue::integer(-1));
+        assert_eq!(bitpos_cmd(&mut ctx, &["bits", "1", "9", "11", "BIT"]), RespValue::integer(11));
+        assert_eq!(bitpos_cmd(&mut ctx, &["bits", "1", "-1"]), RespValue::integer(-1));
+        assert_eq!(bitpos_cmd(&mut ctx, &["bits", "0"]), RespValue::integer(0));
+
+        // Missing key: no 1 bit, first 0 bit at position 0
+        assert_eq!(bitpos_cmd(&mut ctx, &["missing", "1"]), RespValue::integer(-1));
+        assert_eq!(bitpos_cmd(&mut ctx, &["missing", "0"]), RespValue::integer(0));
+
+        assert_eq!(bitpos_cmd(&mut ctx, &["bits", "2"]), RespValue::error("ERR The bit argument must be 1 or 0."));
+    }
+}
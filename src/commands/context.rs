@@ -1,9 +1,28 @@
 //! Command execution context
 
+use super::{CommandRegistry, IdempotencyCache};
 use crate::store::MemoryStore;
 use crate::aof::AofWriter;
+use crate::audit::AuditLogger;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
+/// Per-client options applied while executing a command
+///
+/// These belong to the connection issuing the command; the shard (or the
+/// dispatcher) applies them to the context before each command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientOptions {
+    /// CLIENT NO-TOUCH: reads don't update the keys' access time
+    pub no_touch: bool,
+
+    /// CLIENT DURABLE: write replies wait until their AOF entry is fsynced
+    pub durable: bool,
+
+    /// Address of the client, recorded in the audit log
+    pub addr: Option<SocketAddr>,
+}
+
 /// Context provided to commands during execution
 ///
 /// This context gives commands access to the store and any other
@@ -14,6 +33,18 @@ pub struct CommandContext {
 
     /// Optional AOF writer for persistence
     pub aof_writer: Option<Arc<AofWriter>>,
+
+    /// Optional audit log of write commands
+    pub audit_logger: Option<Arc<AuditLogger>>,
+
+    /// Registry the commands are dispatched from (for introspection)
+    pub registry: Arc<CommandRegistry>,
+
+    /// Options of the client issuing the current command
+    pub client: ClientOptions,
+
+    /// Replies of the recent IDEMPOTENT tokens
+    pub idempotency: IdempotencyCache,
 }
 
 impl CommandContext {
@@ -22,6 +53,10 @@ impl CommandContext {
         CommandContext {
             store: MemoryStore::new(),
             aof_writer: None,
+            audit_logger: None,
+            registry: Arc::new(CommandRegistry::new()),
+            client: ClientOptions::default(),
+            idempotency: IdempotencyCache::default(),
         }
     }
 
@@ -30,13 +65,28 @@ impl CommandContext {
         CommandContext {
             store: MemoryStore::with_capacity(capacity),
             aof_writer: None,
+            audit_logger: None,
+            registry: Arc::new(CommandRegistry::new()),
+            client: ClientOptions::default(),
+            idempotency: IdempotencyCache::default(),
         }
     }
 
+    /// Apply the options of the client issuing the next command
+    pub fn apply_client_options(&mut self, options: ClientOptions) {
+        self.client = options;
+        self.store.set_no_touch(options.no_touch);
+    }
+
     /// Set the AOF writer
     pub fn set_aof_writer(&mut self, writer: Arc<AofWriter>) {
         self.aof_writer = Some(writer);
     }
+
+    /// Set the audit logger
+    pub fn set_audit_logger(&mut self, logger: Arc<AuditLogger>) {
+        self.audit_logger = Some(logger);
+    }
 }
 
 impl Default for CommandContext {
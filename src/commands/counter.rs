@@ -1,6 +1,6 @@
 //! Counter commands (INCR, INCRBY, DECR, DECRBY)
 
-use super::{Command, CommandContext, extract_bulk_string, extract_integer, log_to_aof};
+use super::{Command, CommandContext, extract_bulk_string, extract_integer, log_to_aof, parse_redis_int, WRONGTYPE};
 use crate::protocol::RespValue;
 use crate::store::Value;
 use crate::aof::AofOperation;
@@ -25,7 +25,7 @@ impl Command for IncrCommand {
         // Get current value or initialize to 0
         let new_value = match ctx.store.get_mut(&key) {
             Some(value) => {
-                match value {
+                let new_value = match value {
                     Value::Integer(ref mut i) => {
                         *i = match i.checked_add(1) {
                             Some(v) => v,
@@ -35,13 +35,9 @@ impl Command for IncrCommand {
                     }
                     Value::String(bytes) => {
                         // Try to parse as integer
-                        let s = match std::str::from_utf8(bytes) {
-                            Ok(s) => s,
-                            Err(_) => return RespValue::error("ERR value is not an integer or out of range"),
-                        };
-                        let mut i = match s.parse::<i64>() {
+                        let mut i = match parse_redis_int(bytes) {
                             Ok(i) => i,
-                            Err(_) => return RespValue::error("ERR value is not an integer or out of range"),
+                            Err(e) => return RespValue::error(format!("ERR {}", e)),
                         };
                         i = match i.checked_add(1) {
                             Some(v) => v,
@@ -50,8 +46,10 @@ impl Command for IncrCommand {
                         *value = Value::Integer(i);
                         i
                     }
-                    _ => return RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
-                }
+                    _ => return RespValue::error(WRONGTYPE),
+                };
+                ctx.store.notify_modified(&key);
+                new_value
             }
             None => {
                 // Key doesn't exist, initialize to 1
@@ -70,6 +68,10 @@ impl Command for IncrCommand {
         "INCR"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         1
     }
@@ -103,7 +105,7 @@ impl Command for IncrByCommand {
         // Get current value or initialize to 0
         let new_value = match ctx.store.get_mut(&key) {
             Some(value) => {
-                match value {
+                let new_value = match value {
                     Value::Integer(ref mut i) => {
                         *i = match i.checked_add(increment) {
                             Some(v) => v,
@@ -113,13 +115,9 @@ impl Command for IncrByCommand {
                     }
                     Value::String(bytes) => {
                         // Try to parse as integer
-                        let s = match std::str::from_utf8(bytes) {
-                            Ok(s) => s,
-                            Err(_) => return RespValue::error("ERR value is not an integer or out of range"),
-                        };
-                        let mut i = match s.parse::<i64>() {
+                        let mut i = match parse_redis_int(bytes) {
                             Ok(i) => i,
-                            Err(_) => return RespValue::error("ERR value is not an integer or out of range"),
+                            Err(e) => return RespValue::error(format!("ERR {}", e)),
                         };
                         i = match i.checked_add(increment) {
                             Some(v) => v,
@@ -128,8 +126,10 @@ impl Command for IncrByCommand {
                         *value = Value::Integer(i);
                         i
                     }
-                    _ => return RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
-                }
+                    _ => return RespValue::error(WRONGTYPE),
+                };
+                ctx.store.notify_modified(&key);
+                new_value
             }
             None => {
                 // Key doesn't exist, initialize to increment
@@ -145,6 +145,10 @@ impl Command for IncrByCommand {
         "INCRBY"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         2
     }
@@ -173,7 +177,7 @@ impl Command for DecrCommand {
         // Get current value or initialize to 0
         let new_value = match ctx.store.get_mut(&key) {
             Some(value) => {
-                match value {
+                let new_value = match value {
                     Value::Integer(ref mut i) => {
                         *i = match i.checked_sub(1) {
                             Some(v) => v,
@@ -183,13 +187,9 @@ impl Command for DecrCommand {
                     }
                     Value::String(bytes) => {
                         // Try to parse as integer
-                        let s = match std::str::from_utf8(bytes) {
-                            Ok(s) => s,
-                            Err(_) => return RespValue::error("ERR value is not an integer or out of range"),
-                        };
-                        let mut i = match s.parse::<i64>() {
+                        let mut i = match parse_redis_int(bytes) {
                             Ok(i) => i,
-                            Err(_) => return RespValue::error("ERR value is not an integer or out of range"),
+                            Err(e) => return RespValue::error(format!("ERR {}", e)),
                         };
                         i = match i.checked_sub(1) {
                             Some(v) => v,
@@ -198,8 +198,10 @@ impl Command for DecrCommand {
                         *value = Value::Integer(i);
                         i
                     }
-                    _ => return RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
-                }
+                    _ => return RespValue::error(WRONGTYPE),
+                };
+                ctx.store.notify_modified(&key);
+                new_value
             }
             None => {
                 // Key doesn't exist, initialize to -1
@@ -215,6 +217,10 @@ impl Command for DecrCommand {
         "DECR"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         1
     }
@@ -245,10 +251,15 @@ impl Command for DecrByCommand {
             Err(e) => return RespValue::error(format!("ERR {}", e)),
         };
 
+        // -i64::MIN is not representable, so reject it up front like Redis
+        if decrement == i64::MIN {
+            return RespValue::error("ERR decrement would overflow");
+        }
+
         // Get current value or initialize to 0
         let new_value = match ctx.store.get_mut(&key) {
             Some(value) => {
-                match value {
+                let new_value = match value {
                     Value::Integer(ref mut i) => {
                         *i = match i.checked_sub(decrement) {
                             Some(v) => v,
@@ -258,13 +269,9 @@ impl Command for DecrByCommand {
                     }
                     Value::String(bytes) => {
                         // Try to parse as integer
-                        let s = match std::str::from_utf8(bytes) {
-                            Ok(s) => s,
-                            Err(_) => return RespValue::error("ERR value is not an integer or out of range"),
-                        };
-                        let mut i = match s.parse::<i64>() {
+                        let mut i = match parse_redis_int(bytes) {
                             Ok(i) => i,
-                            Err(_) => return RespValue::error("ERR value is not an integer or out of range"),
+                            Err(e) => return RespValue::error(format!("ERR {}", e)),
                         };
                         i = match i.checked_sub(decrement) {
                             Some(v) => v,
@@ -273,8 +280,10 @@ impl Command for DecrByCommand {
                         *value = Value::Integer(i);
                         i
                     }
-                    _ => return RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
-                }
+                    _ => return RespValue::error(WRONGTYPE),
+                };
+                ctx.store.notify_modified(&key);
+                new_value
             }
             None => {
                 // Key doesn't exist, initialize to -decrement
@@ -290,6 +299,10 @@ impl Command for DecrByCommand {
         "DECRBY"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         2
     }
@@ -368,4 +381,48 @@ mod tests {
         let result = cmd.execute(&mut ctx, &args);
         assert_eq!(result, RespValue::integer(-10));
     }
+
+    #[test]
+    fn test_out_of_range_argument() {
+        let mut ctx = CommandContext::new();
+
+        // One past i64::MAX is reported like a non-numeric argument, and so
+        // are the non-canonical forms Redis' string2ll refuses
+        for arg in ["9223372036854775808", "abc", "+5", "007", " 5", "-0"] {
+            let args = vec![
+                RespValue::bulk_string("counter"),
+                RespValue::bulk_string(arg),
+            ];
+            let result = IncrByCommand.execute(&mut ctx, &args);
+            assert_eq!(result, RespValue::error("ERR value is not an integer or out of range"));
+        }
+
+        // i64::MAX itself is accepted
+        let args = vec![
+            RespValue::bulk_string("counter"),
+            RespValue::bulk_string("9223372036854775807"),
+        ];
+        let result = IncrByCommand.execute(&mut ctx, &args);
+        assert_eq!(result, RespValue::integer(i64::MAX));
+    }
+
+    #[test]
+    fn test_decrby_min_negation() {
+        let mut ctx = CommandContext::new();
+        let cmd = DecrByCommand;
+
+        // DECRBY missing i64::MIN would need to store -i64::MIN
+        let args = vec![
+            RespValue::bulk_string("counter"),
+            RespValue::bulk_string(i64::MIN.to_string()),
+        ];
+        let result = cmd.execute(&mut ctx, &args);
+        assert_eq!(result, RespValue::error("ERR decrement would overflow"));
+        assert!(!ctx.store.exists(&Bytes::from("counter")));
+
+        // Same on an existing key
+        ctx.store.set("counter", Value::Integer(-1));
+        let result = cmd.execute(&mut ctx, &args);
+        assert_eq!(result, RespValue::error("ERR decrement would overflow"));
+    }
 }
@@ -0,0 +1,626 @@
+//! Debug commands (DEBUG)
+//!
+//! Subcommands meant for tests and troubleshooting, not for production use.
+
+use super::{Command, CommandContext, extract_bulk_string, extract_integer, parse_redis_float, NOT_A_FLOAT};
+use crate::protocol::RespValue;
+use bytes::Bytes;
+use crate::store::{maxmemory_policy, serialized_length, unix_ms_to_instant, MaxmemoryPolicy};
+use std::time::{Duration, Instant};
+
+/// Error of DEBUG SET-FREQ, like OBJECT FREQ under a non-LFU policy
+const LFU_NOT_SELECTED: &str = "ERR An LFU maxmemory policy is not selected, access frequency not tracked. \
+Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.";
+
+/// Parse the arguments of `DEBUG SLEEP seconds [ASYNC]`
+///
+/// Returns the sleep duration and whether ASYNC was given.
+fn parse_sleep(args: &[RespValue]) -> Result<(Duration, bool), RespValue> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(RespValue::error("ERR wrong number of arguments for 'DEBUG SLEEP' command"));
+    }
+
+    let seconds = extract_bulk_string(&args[0])
+        .and_then(|b| parse_redis_float(b))
+        .ok()
+        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+        .ok_or_else(|| RespValue::error(format!("ERR {}", NOT_A_FLOAT)))?;
+
+    let is_async = match args.get(1) {
+        None => false,
+        Some(arg) => match extract_bulk_string(arg) {
+            Ok(opt) if opt.eq_ignore_ascii_case(b"ASYNC") => true,
+            _ => return Err(RespValue::error("ERR syntax error")),
+        },
+    };
+
+    Ok((seconds, is_async))
+}
+
+/// Longest DEBUG QUIESCE waits for the background work to be done
+///
+/// The wait holds up the shard's loop (or its stripe's lock), so it is
+/// kept short: past it QUIESCE fails, and the client retries.
+const QUIESCE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Pause between two checks for the end of a background AOF rewrite
+const QUIESCE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Handle `DEBUG QUIESCE`: wait for the store's background work
+///
+/// The work left running after a command is the freeing of the keys of a
+/// FLUSHDB/FLUSHALL ASYNC and the automatic AOF rewrite, whose file is
+/// written on a thread of its own. The active expiration sweep runs on the
+/// shard itself (or under its stripe's lock), between commands, so it
+/// can't be in progress while this one executes.
+fn quiesce(ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+    if !args.is_empty() {
+        return RespValue::error("ERR wrong number of arguments for 'DEBUG QUIESCE' command");
+    }
+    let deadline = Instant::now() + QUIESCE_TIMEOUT;
+    let freed = ctx.store.wait_detached_freed(QUIESCE_TIMEOUT);
+    let rewritten = ctx.aof_writer.as_ref().is_none_or(|writer| {
+        while writer.is_rewriting() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(QUIESCE_POLL_INTERVAL);
+        }
+        true
+    });
+    if freed && rewritten {
+        RespValue::simple_string("OK")
+    } else {
+        RespValue::error("ERR timed out waiting for background work to complete")
+    }
+}
+
+/// Handle `DEBUG COMMANDREGISTRY`: check the registered commands' arity
+/// and key spec (`CommandRegistry::check_integrity`)
+fn command_registry(ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+    if !args.is_empty() {
+        return RespValue::error("ERR wrong number of arguments for 'DEBUG COMMANDREGISTRY' command");
+    }
+    let violations = ctx.registry.check_integrity();
+    if violations.is_empty() {
+        RespValue::simple_string("OK")
+    } else {
+        RespValue::error(format!("ERR command registry integrity check failed: {}", violations.join("; ")))
+    }
+}
+
+/// Handle `DEBUG SET-EXPIRE key unix-time-ms`
+fn set_expire(ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+    if args.len() != 2 {
+        return RespValue::error("ERR wrong number of arguments for 'DEBUG SET-EXPIRE' command");
+    }
+
+    let key = match extract_bulk_string(&args[0]) {
+        Ok(k) => k.clone(),
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    };
+
+    let expire_at = match extract_integer(&args[1]) {
+        Ok(ms) => match unix_ms_to_instant(ms) {
+            Some(at) => at,
+            None => return RespValue::error("ERR invalid expire time in 'debug' command"),
+        },
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    };
+
+    if ctx.store.set_expire_at(&key, expire_at) {
+        RespValue::simple_string("OK")
+    } else {
+        RespValue::error("ERR no such key")
+    }
+}
+
+/// Handle `DEBUG SET-LRU key seconds`: backdate the key's last access
+fn set_lru(ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+    if args.len() != 2 {
+        return RespValue::error("ERR wrong number of arguments for 'DEBUG SET-LRU' command");
+    }
+
+    let key = match extract_bulk_string(&args[0]) {
+        Ok(k) => k.clone(),
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    };
+
+    let last_access = match extract_integer(&args[1]) {
+        Ok(seconds) => match u64::try_from(seconds).ok().and_then(|s| Instant::now().checked_sub(Duration::from_secs(s))) {
+            Some(at) => at,
+            None => return RespValue::error("ERR invalid idle time in 'debug' command"),
+        },
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    };
+
+    match ctx.store.get_entry_mut(&key) {
+        Some(entry) if !entry.is_expired() => {
+            entry.last_access = last_access;
+            RespValue::simple_string("OK")
+        }
+        _ => RespValue::error("ERR no such key"),
+    }
+}
+
+/// Handle `DEBUG SET-FREQ key count`: preset the key's LFU counter
+fn set_freq(ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+    if args.len() != 2 {
+        return RespValue::error("ERR wrong number of arguments for 'DEBUG SET-FREQ' command");
+    }
+    if maxmemory_policy() != MaxmemoryPolicy::AllKeysLfu {
+        return RespValue::error(LFU_NOT_SELECTED);
+    }
+
+    let key = match extract_bulk_string(&args[0]) {
+        Ok(k) => k.clone(),
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    };
+
+    let frequency = match extract_integer(&args[1]) {
+        Ok(count) => match u8::try_from(count) {
+            Ok(count) => count,
+            Err(_) => return RespValue::error("ERR invalid frequency in 'debug' command, must be between 0 and 255"),
+        },
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    };
+
+    match ctx.store.get_entry_mut(&key) {
+        Some(entry) if !entry.is_expired() => {
+            // Counted as of now, before any decay
+            entry.frequency = frequency;
+            entry.last_access = Instant::now();
+            RespValue::simple_string("OK")
+        }
+        _ => RespValue::error("ERR no such key"),
+    }
+}
+
+/// Handle `DEBUG LISTPACK-ENTRIES key`: the element count OBJECT ENCODING
+/// decides on
+fn listpack_entries(ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+    if args.len() != 1 {
+        return RespValue::error("ERR wrong number of arguments for 'debug|listpack-entries' command");
+    }
+
+    let key = match extract_bulk_string(&args[0]) {
+        Ok(k) => k.clone(),
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    };
+
+    match ctx.store.get(&key).map(|value| value.element_count()) {
+        Some(Some(count)) => RespValue::integer(count as i64),
+        Some(None) => RespValue::error("ERR LISTPACK-ENTRIES needs a list, hash, set or sorted set"),
+        None => RespValue::error("ERR no such key"),
+    }
+}
+
+/// Handle `DEBUG OBJECT key`: low-level information about a key
+///
+/// `serializedlength` is the size of the DUMP payload without its footer,
+/// not the in-memory estimate of MEMORY USAGE. Like OBJECT, inspecting the
+/// key does not count as an access.
+fn object(ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+    if args.len() != 1 {
+        return RespValue::error("ERR wrong number of arguments for 'debug|object' command");
+    }
+
+    let key = match extract_bulk_string(&args[0]) {
+        Ok(k) => k,
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    };
+
+    let entry = match ctx.store.get_entry(key) {
+        Some(entry) if !entry.is_expired() => entry,
+        _ => return RespValue::error("ERR no such key"),
+    };
+
+    RespValue::simple_string(format!(
+        "Value at:{:p} refcount:1 encoding:{} serializedlength:{} lru_seconds_idle:{}",
+        &entry.value,
+        entry.encoding(),
+        serialized_length(&entry.value),
+        entry.idle_time().as_secs()
+    ))
+}
+
+/// Get the duration of a `DEBUG SLEEP seconds ASYNC` command
+///
+/// Used by the shard loop, which sleeps on its runtime instead of running
+/// the command, so other commands keep being processed meanwhile.
+/// Returns None for any other command (including blocking DEBUG SLEEP).
+pub(crate) fn async_sleep_duration(command: &RespValue) -> Option<Duration> {
+    let parts = command.as_array()?;
+    let is_sleep = parts.len() >= 2
+        && parts[0].as_bulk_string()?.eq_ignore_ascii_case(b"DEBUG")
+        && parts[1].as_bulk_string()?.eq_ignore_ascii_case(b"SLEEP");
+    if !is_sleep {
+        return None;
+    }
+
+    match parse_sleep(&parts[2..]) {
+        Ok((duration, true)) => Some(duration),
+        _ => None,
+    }
+}
+
+/// The key of a DEBUG subcommand taking one, for routing: DEBUG declares
+/// no key spec, since most of its subcommands take no key
+pub(crate) fn debug_subcommand_key(parts: &[RespValue]) -> Option<&Bytes> {
+    if !parts.first()?.as_bulk_string()?.eq_ignore_ascii_case(b"DEBUG") {
+        return None;
+    }
+    let subcommand = parts.get(1)?.as_bulk_string()?.to_ascii_uppercase();
+    match subcommand.as_slice() {
+        b"SET-EXPIRE" | b"SET-LRU" | b"SET-FREQ" | b"LISTPACK-ENTRIES" | b"OBJECT" => parts.get(2)?.as_bulk_string(),
+        _ => None,
+    }
+}
+
+/// DEBUG command - Debugging helpers
+///
+/// Syntax:
+/// - DEBUG SLEEP seconds [ASYNC]
+/// - DEBUG SET-EXPIRE key unix-time-ms
+/// - DEBUG SET-LRU key seconds
+/// - DEBUG SET-FREQ key count
+/// - DEBUG LISTPACK-ENTRIES key
+/// - DEBUG OBJECT key
+/// - DEBUG CHANGE-REPL-ID
+/// - DEBUG QUIESCE
+///
+/// SLEEP blocks the executing thread and the whole shard with it. With
+/// ASYNC, the shard loop sleeps asynchronously and keeps serving other
+/// commands; outside of a shard (single store mode) ASYNC blocks as well.
+///
+/// SET-EXPIRE writes the key's expiration directly, bypassing EXPIRE, so a
+/// test can leave a key expired but not yet reaped.
+///
+/// SET-LRU makes a key idle for the given number of seconds, as seen by
+/// OBJECT IDLETIME and `allkeys-lru`, to stage an eviction without waiting.
+/// SET-FREQ presets the LFU counter (0 to 255) `allkeys-lfu` evicts by;
+/// under another policy access frequencies are not tracked, so it fails
+/// like OBJECT FREQ does in Redis.
+///
+/// LISTPACK-ENTRIES returns the element count of a collection, the size
+/// the OBJECT ENCODING thresholds are checked against.
+///
+/// OBJECT describes a key; its `serializedlength` predicts the size of the
+/// DUMP payload.
+///
+/// CHANGE-REPL-ID replaces the replication ID reported by INFO with a new
+/// random one.
+///
+/// QUIESCE replies OK once the background work of the shard is done, so a
+/// test can assert the state an async operation leaves behind; it gives up
+/// with an error after a second.
+///
+/// COMMANDREGISTRY replies OK if every registered command declares an
+/// arity and key spec consistent with each other, or an error listing the
+/// commands that don't.
+pub struct DebugCommand;
+
+impl Command for DebugCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.is_empty() {
+            return RespValue::error("ERR wrong number of arguments for 'DEBUG' command");
+        }
+
+        let subcommand = match extract_bulk_string(&args[0]) {
+            Ok(s) => String::from_utf8_lossy(s).to_uppercase(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        match subcommand.as_str() {
+            "SLEEP" => match parse_sleep(&args[1..]) {
+                Ok((duration, _)) => {
+                    std::thread::sleep(duration);
+                    RespValue::simple_string("OK")
+                }
+                Err(e) => e,
+            },
+            "SET-EXPIRE" => set_expire(ctx, &args[1..]),
+            "SET-LRU" => set_lru(ctx, &args[1..]),
+            "SET-FREQ" => set_freq(ctx, &args[1..]),
+            "LISTPACK-ENTRIES" => listpack_entries(ctx, &args[1..]),
+            "OBJECT" => object(ctx, &args[1..]),
+            "QUIESCE" => quiesce(ctx, &args[1..]),
+            "COMMANDREGISTRY" => command_registry(ctx, &args[1..]),
+            "CHANGE-REPL-ID" => {
+                crate::replication::change_replid();
+                RespValue::simple_string("OK")
+            }
+            _ => RespValue::error(format!("ERR unknown subcommand '{}'. Try DEBUG HELP.", subcommand)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "DEBUG"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["admin", "noscript", "loading", "stale"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{args, command};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_debug_command_registry() {
+        let mut ctx = CommandContext::new();
+        let args = command(&["COMMANDREGISTRY"]);
+        assert_eq!(DebugCommand.execute(&mut ctx, args.as_array().unwrap()), RespValue::simple_string("OK"));
+
+        // A key past the minimum arity, and a maximum below the minimum
+        struct Misconfigured(&'static str, usize, Option<usize>);
+        impl Command for Misconfigured {
+            fn execute(&self, _ctx: &mut CommandContext, _args: &[RespValue]) -> RespValue {
+                RespValue::Null
+            }
+            fn name(&self) -> &'static str {
+                self.0
+            }
+            fn min_args(&self) -> usize {
+                self.1
+            }
+            fn max_args(&self) -> Option<usize> {
+                self.2
+            }
+        }
+        let mut registry = crate::commands::CommandRegistry::new();
+        registry.register(std::sync::Arc::new(Misconfigured("NOKEY", 0, None)));
+        registry.register(std::sync::Arc::new(Misconfigured("NARROW", 2, Some(1))));
+        ctx.registry = std::sync::Arc::new(registry);
+        assert_eq!(
+            DebugCommand.execute(&mut ctx, args.as_array().unwrap()),
+            RespValue::error(
+                "ERR command registry integrity check failed: NARROW: max_args 1 is below min_args 2; \
+                NOKEY: key spec (1, 1, 1) starts beyond min_args 0"
+            )
+        );
+    }
+
+    #[test]
+    fn test_debug_sleep() {
+        let mut ctx = CommandContext::new();
+
+        let args = vec![RespValue::bulk_string("SLEEP"), RespValue::bulk_string("0")];
+        assert_eq!(DebugCommand.execute(&mut ctx, &args), RespValue::simple_string("OK"));
+
+        let args = vec![RespValue::bulk_string("SLEEP"), RespValue::bulk_string("-1")];
+        assert_eq!(DebugCommand.execute(&mut ctx, &args), RespValue::error("ERR value is not a valid float"));
+
+        // Only the ASYNC variant is taken over by the shard loop
+        assert_eq!(async_sleep_duration(&command(&["debug", "sleep", "0.5", "async"])), Some(Duration::from_millis(500)));
+        assert_eq!(async_sleep_duration(&command(&["DEBUG", "SLEEP", "0.5"])), None);
+        assert_eq!(async_sleep_duration(&command(&["DEBUG", "SLEEP", "0.5", "LATER"])), None);
+    }
+
+    #[test]
+    fn test_debug_set_expire_lazy_and_active_reaping() {
+        use crate::commands::string::GetCommand;
+        use crate::store::{Value, KEYSPACE_STATS};
+        use bytes::Bytes;
+
+        let mut ctx = CommandContext::new();
+        ctx.store.set("lazy", Value::string("v"));
+        ctx.store.set("active", Value::string("v"));
+        let past = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() - 1000).to_string();
+
+        for key in ["lazy", "active"] {
+            let args = command(&["SET-EXPIRE", key, &past]);
+            assert_eq!(DebugCommand.execute(&mut ctx, args.as_array().unwrap()), RespValue::simple_string("OK"));
+        }
+        let args = command(&["SET-EXPIRE", "missing", &past]);
+        assert_eq!(DebugCommand.execute(&mut ctx, args.as_array().unwrap()), RespValue::error("ERR no such key"));
+
+        // Expired but still present until something reaps them
+        assert!(ctx.store.get_entry(&Bytes::from("lazy")).is_some_and(|e| e.is_expired()));
+        assert!(ctx.store.get_entry(&Bytes::from("active")).is_some());
+
+        // Lazy: reaped by the GET that finds it expired
+        let expired = KEYSPACE_STATS.expired_keys();
+        assert_eq!(GetCommand.execute(&mut ctx, &[RespValue::bulk_string("lazy")]), RespValue::null());
+        assert!(ctx.store.get_entry(&Bytes::from("lazy")).is_none());
+        assert!(KEYSPACE_STATS.expired_keys() > expired);
+
+        // Active: reaped by the cleanup, without any access
+        let expired = KEYSPACE_STATS.expired_keys();
+        assert_eq!(ctx.store.cleanup_expired(), 1);
+        assert!(ctx.store.get_entry(&Bytes::from("active")).is_none());
+        assert!(KEYSPACE_STATS.expired_keys() > expired);
+    }
+
+    #[test]
+    fn test_debug_quiesce_waits_for_async_flush() {
+        use crate::commands::admin::FlushDbCommand;
+        use crate::store::Value;
+        use bytes::Bytes;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        /// Buffer slow to free, reporting when it is gone
+        struct SlowDrop(Vec<u8>, Arc<AtomicBool>);
+
+        impl AsRef<[u8]> for SlowDrop {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl Drop for SlowDrop {
+            fn drop(&mut self) {
+                std::thread::sleep(Duration::from_millis(200));
+                self.1.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let mut ctx = CommandContext::new();
+        for i in 0..10_000 {
+            ctx.store.set(format!("key:{}", i), Value::string("v"));
+        }
+        let freed = Arc::new(AtomicBool::new(false));
+        ctx.store.set("slow", Value::String(Bytes::from_owner(SlowDrop(b"v".to_vec(), freed.clone()))));
+
+        let flush = FlushDbCommand.execute(&mut ctx, &[RespValue::bulk_string("ASYNC")]);
+        assert_eq!(flush, RespValue::simple_string("OK"));
+        assert!(!freed.load(Ordering::SeqCst));
+
+        let args = command(&["QUIESCE"]);
+        assert_eq!(DebugCommand.execute(&mut ctx, args.as_array().unwrap()), RespValue::simple_string("OK"));
+        assert!(freed.load(Ordering::SeqCst));
+        assert_eq!(ctx.store.len(), 0);
+        assert_eq!(ctx.store.stats().used_memory_bytes, 0);
+
+        // Nothing pending: returns at once
+        assert_eq!(DebugCommand.execute(&mut ctx, args.as_array().unwrap()), RespValue::simple_string("OK"));
+        let args = command(&["QUIESCE", "now"]);
+        assert_eq!(
+            DebugCommand.execute(&mut ctx, args.as_array().unwrap()),
+            RespValue::error("ERR wrong number of arguments for 'DEBUG QUIESCE' command")
+        );
+    }
+
+    #[test]
+    fn test_debug_quiesce_waits_for_background_rewrite() {
+        use crate::aof::{AofWriter, SyncPolicy};
+        use std::sync::Arc;
+
+        let path = std::env::temp_dir().join(format!("ferrumdb_quiesce_{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let writer = Arc::new(AofWriter::new(&path, SyncPolicy::No).unwrap());
+        let mut ctx = CommandContext::new();
+        ctx.aof_writer = Some(writer.clone());
+        let args = command(&["QUIESCE"]);
+
+        // A rewrite still running past the limit is an error
+        let ticket = writer.start_rewrite().unwrap();
+        let start = Instant::now();
+        assert_eq!(
+            DebugCommand.execute(&mut ctx, args.as_array().unwrap()),
+            RespValue::error("ERR timed out waiting for background work to complete")
+        );
+        assert!(start.elapsed() >= QUIESCE_TIMEOUT);
+
+        // One done while QUIESCE waits lets it reply OK
+        let finisher = std::thread::spawn({
+            let writer = writer.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(50));
+                writer.finish_rewrite(ticket, &[]).unwrap();
+            }
+        });
+        assert_eq!(DebugCommand.execute(&mut ctx, args.as_array().unwrap()), RespValue::simple_string("OK"));
+        assert!(!writer.is_rewriting());
+        finisher.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_debug_set_lru_presets_idle_time() {
+        use crate::commands::key::ObjectCommand;
+        use crate::store::Value;
+
+        let mut ctx = CommandContext::new();
+        ctx.store.set("stale", Value::string("v"));
+        ctx.store.set("recent", Value::string("v"));
+        for (key, seconds) in [("stale", "3600"), ("recent", "10")] {
+            let args = command(&["SET-LRU", key, seconds]);
+            assert_eq!(DebugCommand.execute(&mut ctx, args.as_array().unwrap()), RespValue::simple_string("OK"));
+        }
+
+        let mut idle = |key: &str| ObjectCommand.execute(&mut ctx, &[RespValue::bulk_string("IDLETIME"), RespValue::bulk_string(key.to_string())]);
+        assert_eq!(idle("stale"), RespValue::integer(3600));
+        assert_eq!(idle("recent"), RespValue::integer(10));
+
+        for (args, error) in [
+            (command(&["SET-LRU", "missing", "10"]), "ERR no such key"),
+            (command(&["SET-LRU", "stale", "-1"]), "ERR invalid idle time in 'debug' command"),
+            (command(&["SET-LRU", "stale"]), "ERR wrong number of arguments for 'DEBUG SET-LRU' command"),
+            (command(&["SET-FREQ", "stale", "5"]), LFU_NOT_SELECTED),
+        ] {
+            assert_eq!(DebugCommand.execute(&mut ctx, args.as_array().unwrap()), RespValue::error(error));
+        }
+    }
+
+    #[test]
+    fn test_listpack_entries_matches_collection_lengths() {
+        use crate::commands::hash::HSetCommand;
+        use crate::commands::list::{LLenCommand, RPushCommand};
+        use crate::commands::set::{SAddCommand, SCardCommand};
+        use crate::commands::string::SetCommand;
+        use crate::commands::zset::{ZAddCommand, ZCardCommand};
+
+        let mut ctx = CommandContext::new();
+        RPushCommand.execute(&mut ctx, &args(&["list", "a", "b", "c"]));
+        let added = HSetCommand.execute(&mut ctx, &args(&["hash", "f1", "v1", "f2", "v2"]));
+        SAddCommand.execute(&mut ctx, &args(&["set", "1", "2", "3", "4"]));
+        ZAddCommand.execute(&mut ctx, &args(&["zset", "1", "a", "2", "b"]));
+        SetCommand.execute(&mut ctx, &args(&["string", "v"]));
+
+        let mut entries = |key: &str| DebugCommand.execute(&mut ctx, &args(&["LISTPACK-ENTRIES", key]));
+        let (list, hash, set, zset) = (entries("list"), entries("hash"), entries("set"), entries("zset"));
+        assert_eq!(entries("string"), RespValue::error("ERR LISTPACK-ENTRIES needs a list, hash, set or sorted set"));
+        assert_eq!(entries("missing"), RespValue::error("ERR no such key"));
+
+        assert_eq!(list, LLenCommand.execute(&mut ctx, &args(&["list"])));
+        assert_eq!(hash, added);
+        assert_eq!(set, SCardCommand.execute(&mut ctx, &args(&["set"])));
+        assert_eq!(zset, ZCardCommand.execute(&mut ctx, &args(&["zset"])));
+        assert_eq!(list, RespValue::integer(3));
+    }
+
+    #[test]
+    fn test_object_serializedlength_matches_dump() {
+        use crate::commands::migrate::DumpCommand;
+        use crate::store::{SortedSet, Stream, StreamId, Value, DUMP_FOOTER_LEN};
+        use bytes::Bytes;
+
+        let mut ctx = CommandContext::new();
+        let mut zset = SortedSet::new();
+        zset.insert(Bytes::from("a"), 1.0);
+        zset.insert(Bytes::from("b"), 2.5);
+        let mut stream = Stream::new();
+        stream.add(StreamId::new(1, 0), vec![(Bytes::from("f"), Bytes::from("v"))]).unwrap();
+        ctx.store.set("string", Value::string("hello world"));
+        ctx.store.set("int", Value::Integer(12345));
+        ctx.store.set("list", Value::list(["a", "bb", "ccc"].into_iter().map(Bytes::from)));
+        ctx.store.set("set", Value::Set(["x", "y"].into_iter().map(Bytes::from).collect()));
+        ctx.store.set("hash", Value::Hash([(Bytes::from("f"), Bytes::from("v"))].into_iter().collect()));
+        ctx.store.set("zset", Value::ZSet(zset));
+        ctx.store.set("stream", Value::Stream(stream));
+
+        for key in ["string", "int", "list", "set", "hash", "zset", "stream"] {
+            let info = match DebugCommand.execute(&mut ctx, command(&["OBJECT", key]).as_array().unwrap()) {
+                RespValue::SimpleString(info) => info,
+                other => panic!("unexpected DEBUG OBJECT reply: {:?}", other),
+            };
+            let serialized: usize = info
+                .split(' ')
+                .find_map(|field| field.strip_prefix("serializedlength:"))
+                .and_then(|len| len.parse().ok())
+                .unwrap_or_else(|| panic!("no serializedlength in '{}'", info));
+
+            let payload = match DumpCommand.execute(&mut ctx, &[RespValue::bulk_string(key)]) {
+                RespValue::BulkString(payload) => payload,
+                other => panic!("unexpected DUMP reply: {:?}", other),
+            };
+            assert_eq!(serialized, payload.len() - DUMP_FOOTER_LEN, "{}", key);
+        }
+
+        let args = command(&["OBJECT", "missing"]);
+        assert_eq!(DebugCommand.execute(&mut ctx, args.as_array().unwrap()), RespValue::error("ERR no such key"));
+    }
+}
@@ -0,0 +1,341 @@
+//! Geospatial commands (GEOADD, GEOPOS, GEODIST)
+//!
+//! Locations are members of a sorted set, scored by the 52-bit geohash of
+//! their coordinates like in Redis: longitude and latitude are each
+//! quantized to 26 bits and their bits interleaved, which an f64 holds
+//! exactly. GEOPOS decodes the score back to the center of its geohash
+//! cell, so coordinates come back within the precision of the cell (well
+//! under a meter).
+
+use super::{Command, CommandContext, extract_bulk_string, log_to_aof, parse_redis_float, WRONGTYPE};
+use super::zset::format_score;
+use crate::protocol::RespValue;
+use crate::store::Value;
+use crate::aof::AofOperation;
+use bytes::Bytes;
+
+/// Longitude range
+const LON_MIN: f64 = -180.0;
+const LON_MAX: f64 = 180.0;
+
+/// Latitude range, limited like Redis to what Web Mercator projects
+const LAT_MIN: f64 = -85.05112878;
+const LAT_MAX: f64 = 85.05112878;
+
+/// Bits per coordinate in a geohash
+const GEO_STEP: u32 = 26;
+
+/// Earth radius in meters used for distances, the one Redis uses
+const EARTH_RADIUS_M: f64 = 6372797.560856;
+
+/// Spread the low 32 bits of `x` to the even bits of the result
+fn spread(x: u32) -> u64 {
+    (0..32).fold(0, |bits, i| bits | (((x as u64 >> i) & 1) << (2 * i)))
+}
+
+/// Gather the even bits of `bits`, the inverse of `spread`
+fn squash(bits: u64) -> u32 {
+    (0..32).fold(0, |x, i| x | (((bits >> (2 * i)) & 1) << i) as u32)
+}
+
+/// Geohash of a location, as the integer value of a sorted set score
+///
+/// The latitude takes the even bits and the longitude the odd ones.
+fn geohash_encode(lon: f64, lat: f64) -> u64 {
+    let cells = (1u64 << GEO_STEP) as f64;
+    let lat_cell = ((lat - LAT_MIN) / (LAT_MAX - LAT_MIN) * cells) as u32;
+    let lon_cell = ((lon - LON_MIN) / (LON_MAX - LON_MIN) * cells) as u32;
+    // The maximum of each range belongs to the last cell
+    let last = (1u32 << GEO_STEP) - 1;
+    spread(lat_cell.min(last)) | (spread(lon_cell.min(last)) << 1)
+}
+
+/// Center of the geohash cell of `hash`, as `(longitude, latitude)`
+fn geohash_decode(hash: u64) -> (f64, f64) {
+    let cells = (1u64 << GEO_STEP) as f64;
+    let lat_cell = squash(hash) as f64;
+    let lon_cell = squash(hash >> 1) as f64;
+    let lat = LAT_MIN + (lat_cell + 0.5) * (LAT_MAX - LAT_MIN) / cells;
+    let lon = LON_MIN + (lon_cell + 0.5) * (LON_MAX - LON_MIN) / cells;
+    (lon.clamp(LON_MIN, LON_MAX), lat.clamp(LAT_MIN, LAT_MAX))
+}
+
+/// Great-circle distance in meters between two `(longitude, latitude)` points
+fn geo_distance((lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    2.0 * EARTH_RADIUS_M * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+/// Meters per unit of a GEODIST unit name
+fn unit_factor(unit: &[u8]) -> Option<f64> {
+    match unit.to_ascii_lowercase().as_slice() {
+        b"m" => Some(1.0),
+        b"km" => Some(1000.0),
+        b"mi" => Some(1609.34),
+        b"ft" => Some(0.3048),
+        _ => None,
+    }
+}
+
+/// Location of a member of the sorted set at key
+///
+/// Ok(None) when the key or the member does not exist.
+fn member_location(ctx: &mut CommandContext, key: &Bytes, member: &Bytes) -> Result<Option<(f64, f64)>, RespValue> {
+    match ctx.store.get(key) {
+        Some(value) => match value.as_zset() {
+            Some(zset) => Ok(zset.score(member).map(|score| geohash_decode(score as u64))),
+            None => Err(RespValue::error(WRONGTYPE)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// GEOADD command - Add locations to the sorted set at key
+///
+/// Syntax: GEOADD key longitude latitude member [longitude latitude member ...]
+///
+/// Returns the number of members added (not those whose location was updated).
+pub struct GeoAddCommand;
+
+impl Command for GeoAddCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() < 4 || !(args.len() - 1).is_multiple_of(3) {
+            return RespValue::error("ERR wrong number of arguments for 'GEOADD' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        // Parse all locations before touching the store
+        let mut locations = Vec::with_capacity((args.len() - 1) / 3);
+        for triple in args[1..].chunks(3) {
+            let lon = extract_bulk_string(&triple[0]).and_then(|b| parse_redis_float(b));
+            let lat = extract_bulk_string(&triple[1]).and_then(|b| parse_redis_float(b));
+            let (lon, lat) = match (lon, lat) {
+                (Ok(lon), Ok(lat)) => (lon, lat),
+                (Err(e), _) | (_, Err(e)) => return RespValue::error(format!("ERR {}", e)),
+            };
+            if !(LON_MIN..=LON_MAX).contains(&lon) || !(LAT_MIN..=LAT_MAX).contains(&lat) {
+                return RespValue::error(format!("ERR invalid longitude,latitude pair {:.6},{:.6}", lon, lat));
+            }
+            let member = match extract_bulk_string(&triple[2]) {
+                Ok(m) => m.clone(),
+                Err(e) => return RespValue::error(format!("ERR {}", e)),
+            };
+            locations.push((geohash_encode(lon, lat) as f64, member));
+        }
+
+        // Get or create sorted set
+        let zset = match ctx.store.get_mut(&key) {
+            Some(value) => match value.as_zset_mut() {
+                Some(zset) => zset,
+                None => return RespValue::error(WRONGTYPE),
+            },
+            None => {
+                ctx.store.set(key.clone(), Value::empty_zset());
+                ctx.store.get_mut(&key).unwrap().as_zset_mut().unwrap()
+            }
+        };
+
+        let mut added = 0;
+        let mut payload = Vec::with_capacity(locations.len() * 2);
+        for (score, member) in locations {
+            if zset.insert(member.clone(), score) {
+                added += 1;
+            }
+            payload.push(Bytes::from(format_score(score)));
+            payload.push(member);
+        }
+        ctx.store.notify_modified(&key);
+
+        // Replayed as the ZADD of the geohash scores
+        log_to_aof(ctx, AofOperation::ZAdd, key, payload);
+
+        RespValue::integer(added)
+    }
+
+    fn name(&self) -> &'static str {
+        "GEOADD"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        4
+    }
+}
+
+/// GEOPOS command - Get the locations of members of the sorted set at key
+///
+/// Syntax: GEOPOS key member [member ...]
+///
+/// Returns `[longitude, latitude]` for each member, nil for a missing one.
+pub struct GeoPosCommand;
+
+impl Command for GeoPosCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let mut positions = Vec::with_capacity(args.len() - 1);
+        for arg in &args[1..] {
+            let member = match extract_bulk_string(arg) {
+                Ok(m) => m,
+                Err(e) => return RespValue::error(format!("ERR {}", e)),
+            };
+            let position = match member_location(ctx, &key, member) {
+                Ok(Some((lon, lat))) => RespValue::array(vec![
+                    RespValue::bulk_string(lon.to_string()),
+                    RespValue::bulk_string(lat.to_string()),
+                ]),
+                Ok(None) => RespValue::null(),
+                Err(error) => return error,
+            };
+            positions.push(position);
+        }
+
+        RespValue::array(positions)
+    }
+
+    fn name(&self) -> &'static str {
+        "GEOPOS"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+}
+
+/// GEODIST command - Get the distance between two members of the sorted set at key
+///
+/// Syntax: GEODIST key member1 member2 [M|KM|FT|MI]
+///
+/// Returns the distance with 4 decimals, in meters by default, or nil if
+/// either member does not exist.
+pub struct GeoDistCommand;
+
+impl Command for GeoDistCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        let (key, first, second) = match (
+            extract_bulk_string(&args[0]),
+            extract_bulk_string(&args[1]),
+            extract_bulk_string(&args[2]),
+        ) {
+            (Ok(k), Ok(a), Ok(b)) => (k.clone(), a.clone(), b.clone()),
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let factor = match args.get(3).map(extract_bulk_string) {
+            None => 1.0,
+            Some(Ok(unit)) => match unit_factor(unit) {
+                Some(factor) => factor,
+                None => return RespValue::error("ERR unsupported unit provided. please use M, KM, FT, MI"),
+            },
+            Some(Err(e)) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let locations = member_location(ctx, &key, &first).and_then(|a| Ok((a, member_location(ctx, &key, &second)?)));
+        match locations {
+            Ok((Some(a), Some(b))) => RespValue::bulk_string(format!("{:.4}", geo_distance(a, b) / factor)),
+            Ok(_) => RespValue::null(),
+            Err(error) => error,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "GEODIST"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::args;
+
+    fn run(command: &dyn Command, ctx: &mut CommandContext, parts: &[&str]) -> RespValue {
+        command.execute(ctx, &args(parts))
+    }
+
+    fn float(value: &RespValue) -> f64 {
+        match value {
+            RespValue::BulkString(bytes) => std::str::from_utf8(bytes).unwrap().parse().unwrap(),
+            other => panic!("Expected a bulk string, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_geohash_round_trip() {
+        for (lon, lat) in [(13.361389, 38.115556), (-122.4194, 37.7749), (0.0, 0.0), (180.0, LAT_MAX), (-180.0, LAT_MIN)] {
+            let hash = geohash_encode(lon, lat);
+            assert!(hash < 1 << 52);
+            let (decoded_lon, decoded_lat) = geohash_decode(hash);
+            assert!((decoded_lon - lon).abs() < 1e-5, "{} {}", lon, decoded_lon);
+            assert!((decoded_lat - lat).abs() < 1e-5, "{} {}", lat, decoded_lat);
+        }
+    }
+
+    #[test]
+    fn test_geoadd_geopos_round_trip() {
+        let mut ctx = CommandContext::new();
+        let added = run(&GeoAddCommand, &mut ctx, &["Sicily", "13.361389", "38.115556", "Palermo", "15.087269", "37.502669", "Catania"]);
+        assert_eq!(added, RespValue::integer(2));
+        assert_eq!(run(&GeoAddCommand, &mut ctx, &["Sicily", "13.361389", "38.115556", "Palermo"]), RespValue::integer(0));
+
+        match run(&GeoPosCommand, &mut ctx, &["Sicily", "Palermo", "Nowhere"]) {
+            RespValue::Array(positions) => {
+                let RespValue::Array(palermo) = &positions[0] else { panic!("{:?}", positions) };
+                assert!((float(&palermo[0]) - 13.361389).abs() < 1e-5);
+                assert!((float(&palermo[1]) - 38.115556).abs() < 1e-5);
+                assert_eq!(positions[1], RespValue::null());
+            }
+            other => panic!("Expected an array, got {:?}", other),
+        }
+
+        assert_eq!(
+            run(&GeoAddCommand, &mut ctx, &["Sicily", "200", "10", "Bad"]),
+            RespValue::error("ERR invalid longitude,latitude pair 200.000000,10.000000")
+        );
+    }
+
+    #[test]
+    fn test_geodist() {
+        let mut ctx = CommandContext::new();
+        run(&GeoAddCommand, &mut ctx, &["Sicily", "13.361389", "38.115556", "Palermo", "15.087269", "37.502669", "Catania"]);
+
+        // Redis reports 166274.1516 m between Palermo and Catania
+        let meters = float(&run(&GeoDistCommand, &mut ctx, &["Sicily", "Palermo", "Catania"]));
+        assert!((meters - 166274.1516).abs() < 1.0, "{}", meters);
+        let km = float(&run(&GeoDistCommand, &mut ctx, &["Sicily", "Palermo", "Catania", "KM"]));
+        assert!((km - 166.2742).abs() < 0.001, "{}", km);
+
+        assert_eq!(run(&GeoDistCommand, &mut ctx, &["Sicily", "Palermo", "Nowhere"]), RespValue::null());
+        assert_eq!(
+            run(&GeoDistCommand, &mut ctx, &["Sicily", "Palermo", "Catania", "parsec"]),
+            RespValue::error("ERR unsupported unit provided. please use M, KM, FT, MI")
+        );
+    }
+}
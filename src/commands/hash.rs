@@ -1,6 +1,7 @@
 //! Hash commands (HSET, HGET, HGETALL, HDEL, HKEYS, HINCRBY)
 
-use super::{Command, CommandContext, extract_bulk_string, extract_integer, log_to_aof};
+use super::{Command, CommandContext, extract_bulk_string, extract_integer, log_to_aof, parse_redis_int, WRONGTYPE};
+use super::{matches_pattern, parse_cursor, DEFAULT_SCAN_COUNT};
 use crate::protocol::RespValue;
 use crate::store::Value;
 use crate::aof::AofOperation;
@@ -17,7 +18,7 @@ impl Command for HSetCommand {
         }
 
         // Check that we have pairs of field/value
-        if (args.len() - 1) % 2 != 0 {
+        if !(args.len() - 1).is_multiple_of(2) {
             return RespValue::error("ERR wrong number of arguments for 'HSET' command");
         }
 
@@ -50,7 +51,7 @@ impl Command for HSetCommand {
                 Some(value) => {
                     match value.as_hash_mut() {
                         Some(hash) => hash,
-                        None => return RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                        None => return RespValue::error(WRONGTYPE),
                     }
                 }
                 None => {
@@ -60,20 +61,26 @@ impl Command for HSetCommand {
                 }
             };
 
-            // Insert pairs
+            // Insert pairs, keeping those that changed the hash
             let mut added = 0;
-            for (field, value) in &pairs {
-                if hash.insert(field.clone(), value.clone()).is_none() {
+            pairs.retain(|(field, value)| match hash.insert(field.clone(), value.clone()) {
+                None => {
                     added += 1;
+                    true
                 }
+                Some(previous) => previous != value,
+            });
+            if !pairs.is_empty() {
+                ctx.store.notify_modified(&key);
             }
 
-            // Log to AOF after releasing mutable borrow
+            // Log to AOF after releasing mutable borrow; a value overwritten
+            // with itself needs no entry
             for (field, value) in pairs {
                 log_to_aof(ctx, AofOperation::HSet, key.clone(), vec![field, value]);
             }
 
-            return RespValue::integer(added);
+            RespValue::integer(added)
         }
     }
 
@@ -81,6 +88,10 @@ impl Command for HSetCommand {
         "HSET"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         3
     }
@@ -117,7 +128,7 @@ impl Command for HGetCommand {
                             None => RespValue::null(),
                         }
                     }
-                    None => RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => RespValue::error(WRONGTYPE),
                 }
             }
             None => RespValue::null(),
@@ -128,6 +139,10 @@ impl Command for HGetCommand {
         "HGET"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         2
     }
@@ -140,6 +155,9 @@ impl Command for HGetCommand {
 /// HGETALL command - Get all fields and values in a hash
 ///
 /// Syntax: HGETALL key
+///
+/// Replies with a map of the fields to their values: a flat
+/// [field, value, ...] array in RESP2.
 pub struct HGetAllCommand;
 
 impl Command for HGetAllCommand {
@@ -160,15 +178,14 @@ impl Command for HGetAllCommand {
                     Some(hash) => {
                         let mut result = Vec::new();
                         for (field, value) in hash.iter() {
-                            result.push(RespValue::bulk_string(field.clone()));
-                            result.push(RespValue::bulk_string(value.clone()));
+                            result.push((RespValue::bulk_string(field.clone()), RespValue::bulk_string(value.clone())));
                         }
-                        RespValue::array(result)
+                        RespValue::map(result)
                     }
-                    None => RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => RespValue::error(WRONGTYPE),
                 }
             }
-            None => RespValue::array(vec![]),
+            None => RespValue::map(vec![]),
         }
     }
 
@@ -176,6 +193,10 @@ impl Command for HGetAllCommand {
         "HGETALL"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
     fn min_args(&self) -> usize {
         1
     }
@@ -204,11 +225,11 @@ impl Command for HDelCommand {
         // Get hash and delete fields
         let mut deleted_fields = Vec::new();
         {
-            let hash = match ctx.store.get_mut(&key) {
+            let hash = match ctx.store.get_mut(key) {
                 Some(value) => {
                     match value.as_hash_mut() {
                         Some(hash) => hash,
-                        None => return RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                        None => return RespValue::error(WRONGTYPE),
                     }
                 }
                 None => return RespValue::integer(0),
@@ -231,6 +252,10 @@ impl Command for HDelCommand {
         for field in &deleted_fields {
             log_to_aof(ctx, AofOperation::HDel, key.clone(), vec![field.clone()]);
         }
+        if !deleted_fields.is_empty() {
+            ctx.store.notify_modified(key);
+        }
+        ctx.store.remove_if_empty(key);
 
         RespValue::integer(deleted_fields.len() as i64)
     }
@@ -239,6 +264,10 @@ impl Command for HDelCommand {
         "HDEL"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         2
     }
@@ -271,7 +300,7 @@ impl Command for HKeysCommand {
                             .collect();
                         RespValue::array(keys)
                     }
-                    None => RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => RespValue::error(WRONGTYPE),
                 }
             }
             None => RespValue::array(vec![]),
@@ -282,6 +311,10 @@ impl Command for HKeysCommand {
         "HKEYS"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
     fn min_args(&self) -> usize {
         1
     }
@@ -291,6 +324,106 @@ impl Command for HKeysCommand {
     }
 }
 
+/// HSCAN command - Incrementally iterate over the fields of a hash
+///
+/// Syntax: HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]
+///
+/// Same cursor and guarantees as SCAN: the fields are spread over virtual
+/// buckets by a fixed hash of their name, visited in reverse-binary order,
+/// so every field present during the whole iteration is returned at least
+/// once even if the hash grows or shrinks between calls. COUNT buckets are
+/// visited per call (default 10), a range of the hash's scan index
+/// (`Hash::scan`), which never walks the other fields.
+///
+/// The reply is the next cursor and a flat [field, value, ...] array, or
+/// only the field names with NOVALUES.
+pub struct HScanCommand;
+
+impl Command for HScanCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() < 2 {
+            return RespValue::error("ERR wrong number of arguments for 'HSCAN' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+        let cursor = match parse_cursor(&args[1]) {
+            Ok(c) => c,
+            Err(e) => return e,
+        };
+
+        // The last COUNT and MATCH win, NOVALUES takes no value
+        let mut count = DEFAULT_SCAN_COUNT;
+        let mut pattern = None;
+        let mut novalues = false;
+        let mut options = args[2..].iter();
+        while let Some(option) = options.next() {
+            let option = match extract_bulk_string(option) {
+                Ok(opt) => opt,
+                Err(e) => return RespValue::error(format!("ERR {}", e)),
+            };
+            if option.eq_ignore_ascii_case(b"NOVALUES") {
+                novalues = true;
+                continue;
+            }
+            let Some(value) = options.next() else {
+                return RespValue::error("ERR syntax error");
+            };
+            if option.eq_ignore_ascii_case(b"COUNT") {
+                match extract_integer(value) {
+                    Ok(c) if c >= 1 => count = c as usize,
+                    Ok(_) => return RespValue::error("ERR syntax error"),
+                    Err(e) => return RespValue::error(format!("ERR {}", e)),
+                }
+            } else if option.eq_ignore_ascii_case(b"MATCH") {
+                match extract_bulk_string(value) {
+                    Ok(p) => pattern = Some(p),
+                    Err(e) => return RespValue::error(format!("ERR {}", e)),
+                }
+            } else {
+                return RespValue::error("ERR syntax error");
+            }
+        }
+
+        let hash = match ctx.store.get(key) {
+            Some(value) => match value.as_hash() {
+                Some(hash) => hash,
+                None => return RespValue::error(WRONGTYPE),
+            },
+            None => return RespValue::array(vec![RespValue::bulk_string("0"), RespValue::array(vec![])]),
+        };
+
+        let (next_cursor, fields) = hash.scan(cursor, count);
+        let pattern = pattern.filter(|p| p.as_ref() != b"*");
+        let mut elements = Vec::new();
+        for (field, value) in fields {
+            if pattern.is_some_and(|pattern| !matches_pattern(field, pattern)) {
+                continue;
+            }
+            elements.push(RespValue::bulk_string(field.clone()));
+            if !novalues {
+                elements.push(RespValue::bulk_string(value.clone()));
+            }
+        }
+
+        RespValue::array(vec![RespValue::bulk_string(next_cursor.to_string()), RespValue::array(elements)])
+    }
+
+    fn name(&self) -> &'static str {
+        "HSCAN"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+}
+
 /// HINCRBY command - Increment the integer value of a hash field by the given number
 ///
 /// Syntax: HINCRBY key field increment
@@ -322,7 +455,7 @@ impl Command for HIncrByCommand {
             Some(value) => {
                 match value.as_hash_mut() {
                     Some(hash) => hash,
-                    None => return RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => return RespValue::error(WRONGTYPE),
                 }
             }
             None => {
@@ -335,11 +468,8 @@ impl Command for HIncrByCommand {
         // Get current value or initialize to 0
         let current = match hash.get(&field) {
             Some(bytes) => {
-                let s = match std::str::from_utf8(bytes) {
-                    Ok(s) => s,
-                    Err(_) => return RespValue::error("ERR hash value is not an integer"),
-                };
-                match s.parse::<i64>() {
+                // Redis words this one differently from argument errors
+                match parse_redis_int(bytes) {
                     Ok(i) => i,
                     Err(_) => return RespValue::error("ERR hash value is not an integer"),
                 }
@@ -355,6 +485,7 @@ impl Command for HIncrByCommand {
 
         // Store new value as string
         hash.insert(field.clone(), new_value.to_string().into());
+        ctx.store.notify_modified(&key);
 
         // Log to AOF
         use bytes::Bytes;
@@ -372,6 +503,10 @@ impl Command for HIncrByCommand {
         "HINCRBY"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         3
     }
@@ -384,6 +519,8 @@ impl Command for HIncrByCommand {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::commands::args;
+    use crate::protocol::{ProtocolVersion, RespEncoder};
     use bytes::Bytes;
 
     #[test]
@@ -438,14 +575,37 @@ mod tests {
         let args = vec![RespValue::bulk_string("myhash")];
         let result = hgetall_cmd.execute(&mut ctx, &args);
 
-        // Should return array with 4 elements (2 fields + 2 values)
-        if let RespValue::Array(arr) = result {
-            assert_eq!(arr.len(), 4);
+        // Should return a map of 2 fields
+        if let RespValue::Map(pairs) = result {
+            assert_eq!(pairs.len(), 2);
         } else {
-            panic!("Expected array response");
+            panic!("Expected map response");
         }
     }
 
+    #[test]
+    fn test_hgetall_encoding_per_protocol() {
+        let mut ctx = CommandContext::new();
+        let args = vec![
+            RespValue::bulk_string("myhash"),
+            RespValue::bulk_string("field"),
+            RespValue::bulk_string("value"),
+        ];
+        HSetCommand.execute(&mut ctx, &args);
+        let result = HGetAllCommand.execute(&mut ctx, &args[..1]);
+
+        let encode = |version| {
+            let mut buf = bytes::BytesMut::new();
+            RespEncoder::encode_for(&mut buf, &result, version);
+            buf
+        };
+        assert_eq!(&encode(ProtocolVersion::Resp2)[..], b"*2\r\n$5\r\nfield\r\n$5\r\nvalue\r\n");
+        assert_eq!(&encode(ProtocolVersion::Resp3)[..], b"%1\r\n$5\r\nfield\r\n$5\r\nvalue\r\n");
+
+        let empty = HGetAllCommand.execute(&mut ctx, &[RespValue::bulk_string("nokey")]);
+        assert_eq!(&RespEncoder::encode(&empty)[..], b"*0\r\n");
+    }
+
     #[test]
     fn test_hdel() {
         let mut ctx = CommandContext::new();
@@ -475,6 +635,19 @@ mod tests {
         assert_eq!(result, RespValue::integer(0));
     }
 
+    #[test]
+    fn test_hdel_last_field_removes_key() {
+        use crate::commands::key::ExistsCommand;
+
+        let mut ctx = CommandContext::new();
+        HSetCommand.execute(&mut ctx, &args(&["myhash", "f1", "v1", "f2", "v2"]));
+
+        assert_eq!(HDelCommand.execute(&mut ctx, &args(&["myhash", "f1"])), RespValue::integer(1));
+        assert_eq!(ExistsCommand.execute(&mut ctx, &args(&["myhash"])), RespValue::integer(1));
+        assert_eq!(HDelCommand.execute(&mut ctx, &args(&["myhash", "f2", "nope"])), RespValue::integer(1));
+        assert_eq!(ExistsCommand.execute(&mut ctx, &args(&["myhash"])), RespValue::integer(0));
+    }
+
     #[test]
     fn test_hincrby() {
         let mut ctx = CommandContext::new();
@@ -498,4 +671,81 @@ mod tests {
         let result = hincrby_cmd.execute(&mut ctx, &args);
         assert_eq!(result, RespValue::integer(15));
     }
+
+    #[test]
+    fn test_hset_same_value_logged_once() {
+        use crate::aof::{AofReader, AofWriter, SyncPolicy};
+        use std::sync::Arc;
+
+        let path = std::env::temp_dir().join(format!("ferrumdb_hset_unchanged_{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let writer = Arc::new(AofWriter::new(&path, SyncPolicy::No).unwrap());
+        let mut ctx = CommandContext::new();
+        ctx.set_aof_writer(writer.clone());
+
+        let hset = |ctx: &mut CommandContext, parts: &[&str]| {
+            HSetCommand.execute(ctx, &args(parts))
+        };
+        assert_eq!(hset(&mut ctx, &["h", "f", "v"]), RespValue::integer(1));
+        assert_eq!(hset(&mut ctx, &["h", "f", "v"]), RespValue::integer(0));
+        assert_eq!(hset(&mut ctx, &["h", "f", "v", "g", "w"]), RespValue::integer(1));
+        assert_eq!(hset(&mut ctx, &["h", "f", "v2"]), RespValue::integer(0));
+        writer.flush().unwrap();
+
+        let entries = AofReader::load(&path).unwrap().parse_entries();
+        let pairs: Vec<_> = entries.iter().map(|entry| entry.payload.clone()).collect();
+        assert_eq!(
+            pairs,
+            vec![
+                vec![Bytes::from("f"), Bytes::from("v")],
+                vec![Bytes::from("g"), Bytes::from("w")],
+                vec![Bytes::from("f"), Bytes::from("v2")],
+            ]
+        );
+
+        drop(ctx);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_hscan_novalues() {
+        let mut ctx = CommandContext::new();
+        for i in 0..100 {
+            let (field, value) = (format!("field:{}", i), format!("value:{}", i));
+            HSetCommand.execute(&mut ctx, &args(&["myhash", &field, &value]));
+        }
+
+        // A full iteration, with the elements of every call
+        let mut scan = |options: &[&str]| {
+            let (mut cursor, mut elements) = ("0".to_string(), Vec::new());
+            loop {
+                let mut parts = vec!["myhash", &cursor, "COUNT", "5"];
+                parts.extend(options);
+                let reply = HScanCommand.execute(&mut ctx, &args(&parts));
+                let reply = reply.as_array().unwrap();
+                elements.extend(reply[1].as_array().unwrap().iter().map(|e| e.as_bulk_string().unwrap().clone()));
+                cursor = String::from_utf8(reply[0].as_bulk_string().unwrap().to_vec()).unwrap();
+                if cursor == "0" {
+                    return elements;
+                }
+            }
+        };
+        let pairs = scan(&[]);
+        let mut fields = scan(&["NOVALUES"]);
+
+        // The pairs are the fields and their values, NOVALUES only the fields
+        assert_eq!(pairs.len(), 200);
+        for pair in pairs.chunks(2) {
+            let field = std::str::from_utf8(pair[0].as_ref()).unwrap();
+            assert_eq!(pair[1], field.replace("field:", "value:"));
+        }
+        let mut expected: Vec<Bytes> = pairs.chunks(2).map(|pair| pair[0].clone()).collect();
+        expected.sort();
+        fields.sort();
+        assert_eq!(fields, expected);
+        assert_eq!(fields.len(), 100);
+
+        let reply = HScanCommand.execute(&mut ctx, &args(&["myhash", "0", "NOVALUES", "COUNT"]));
+        assert_eq!(reply, RespValue::error("ERR syntax error"));
+    }
 }
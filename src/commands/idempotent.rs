@@ -0,0 +1,329 @@
+//! Idempotency keys (IDEMPOTENT)
+//!
+//! A client retrying a command after a timeout can't tell whether the first
+//! attempt was applied, and INCR or LPUSH would be applied twice. Wrapped as
+//! `IDEMPOTENT token command...`, a command runs once per token: the shard
+//! remembers the reply of each token for a short window, and a retry with
+//! the same token gets that reply back without running the command again.
+//!
+//! Each shard (or dispatcher) keeps its own tokens, in a bounded LRU: a
+//! retry routes to the same shard as the first attempt, since the wrapped
+//! command is routed by its key. Only single-key commands can be wrapped,
+//! the others may need several shards.
+//!
+//! IDEMPOTENT has no flags of its own: the dispatch paths look at those of
+//! the wrapped command (`command_flags`), and a retry answered from the
+//! cache is not a write.
+
+use super::{
+    check_maxmemory, execute_traced, extract_bulk_string, log_to_audit, unknown_command, Command, CommandContext,
+    CommandRegistry,
+};
+use crate::protocol::RespValue;
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// Default number of tokens remembered per shard
+pub const DEFAULT_IDEMPOTENCY_CAPACITY: usize = 10_000;
+
+/// Default time a token's reply is remembered, from its first use
+pub const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(60);
+
+/// The reply of a token
+#[derive(Debug)]
+struct CachedReply {
+    /// Reply of the command's first run
+    reply: RespValue,
+
+    /// Hash of the wrapped command, to detect a token reused for another one
+    fingerprint: u64,
+
+    /// When the command ran
+    stored: Instant,
+
+    /// Position in the LRU order
+    seq: u64,
+}
+
+/// Recently used tokens and their replies, least recently used first out
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    /// Replies by token
+    entries: HashMap<Bytes, CachedReply>,
+
+    /// Tokens by LRU position, least recently used first
+    order: BTreeMap<u64, Bytes>,
+
+    /// Next LRU position
+    next_seq: u64,
+
+    /// Most tokens remembered
+    capacity: usize,
+
+    /// How long a reply is remembered
+    window: Duration,
+}
+
+impl IdempotencyCache {
+    /// Create a cache of up to `capacity` tokens, each remembered for
+    /// `window`
+    pub fn new(capacity: usize, window: Duration) -> Self {
+        IdempotencyCache {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            next_seq: 0,
+            capacity,
+            window,
+        }
+    }
+
+    /// Number of tokens remembered, including expired ones not dropped yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no token is remembered
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether a token still has its reply remembered at `now`
+    fn remembers(&self, token: &[u8], now: Instant) -> bool {
+        self.entries.get(token).is_some_and(|entry| now.saturating_duration_since(entry.stored) < self.window)
+    }
+
+    /// The reply of a token still in its window, marked as recently used
+    fn get(&mut self, token: &[u8], now: Instant) -> Option<&CachedReply> {
+        let entry = self.entries.get(token)?;
+        if now.saturating_duration_since(entry.stored) >= self.window {
+            let seq = entry.seq;
+            self.entries.remove(token);
+            self.order.remove(&seq);
+            return None;
+        }
+
+        let entry = self.entries.get_mut(token)?;
+        let token = self.order.remove(&entry.seq)?;
+        entry.seq = self.next_seq;
+        self.order.insert(self.next_seq, token);
+        self.next_seq += 1;
+        Some(entry)
+    }
+
+    /// Remember the reply of a token, dropping the least recently used ones
+    /// beyond the capacity
+    fn insert(&mut self, token: Bytes, fingerprint: u64, reply: RespValue, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.order.insert(seq, token.clone());
+        self.entries.insert(token, CachedReply { reply, fingerprint, stored: now, seq });
+    }
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_IDEMPOTENCY_CAPACITY, DEFAULT_IDEMPOTENCY_WINDOW)
+    }
+}
+
+/// The token and the wrapped command of an IDEMPOTENT command, None for any
+/// other command
+fn unwrap_idempotent(argv: &[RespValue]) -> Option<(&[u8], &[RespValue])> {
+    let name = argv.first()?.as_bulk_string()?;
+    if !name.eq_ignore_ascii_case(b"IDEMPOTENT") || argv.len() < 3 {
+        return None;
+    }
+    Some((argv[1].as_bulk_string()?, &argv[2..]))
+}
+
+/// Flags of a command as sent: for IDEMPOTENT, those of the command it
+/// wraps (none for an unknown command)
+pub(crate) fn command_flags(registry: &CommandRegistry, argv: &[RespValue]) -> &'static [&'static str] {
+    let argv = unwrap_idempotent(argv).map_or(argv, |(_, wrapped)| wrapped);
+    argv.first()
+        .and_then(|name| name.as_bulk_string())
+        .and_then(|name| std::str::from_utf8(name).ok())
+        .and_then(|name| registry.get(name))
+        .map_or(&[], |command| command.flags())
+}
+
+/// Whether a command is an IDEMPOTENT retry that will be answered from the
+/// cache, without running the wrapped command
+pub(crate) fn is_cached_retry(ctx: &CommandContext, argv: &[RespValue]) -> bool {
+    unwrap_idempotent(argv).is_some_and(|(token, _)| ctx.idempotency.remembers(token, Instant::now()))
+}
+
+/// Hash of a wrapped command's name and arguments
+fn fingerprint(argv: &[RespValue]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for arg in argv {
+        arg.as_bulk_string().map(Bytes::as_ref).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// IDEMPOTENT command - Run a command at most once per token
+///
+/// Syntax: IDEMPOTENT token command key [arg ...]
+///
+/// The first use of a token runs the command and replies with its reply,
+/// which the following uses of the token get back for as long as it is
+/// remembered (60 seconds, among the 10000 most recently used tokens of
+/// the shard). A token used again with a different command is an error.
+/// The wrapped command must take a single key, by which it is routed like
+/// when it is sent alone.
+pub struct IdempotentCommand;
+
+impl Command for IdempotentCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() < 3 {
+            return RespValue::error("ERR wrong number of arguments for 'IDEMPOTENT' command");
+        }
+
+        let token = match extract_bulk_string(&args[0]) {
+            Ok(t) => t.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+        let argv = &args[1..];
+        let fingerprint = fingerprint(argv);
+
+        let now = Instant::now();
+        if let Some(cached) = ctx.idempotency.get(&token, now) {
+            if cached.fingerprint != fingerprint {
+                return RespValue::error("ERR idempotency token already used by a different command");
+            }
+            return cached.reply.clone();
+        }
+
+        let name = match extract_bulk_string(&argv[0]) {
+            Ok(name) => name,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+        let command = match std::str::from_utf8(name).ok().and_then(|name| ctx.registry.get(name)) {
+            Some(command) => command,
+            None => return unknown_command(name, &argv[1..]),
+        };
+        if command.name() == self.name() {
+            return RespValue::error("ERR IDEMPOTENT can't wrap itself");
+        }
+        let (first_key, last_key, _) = command.key_spec();
+        if first_key != 1 || last_key != 1 {
+            return RespValue::error(format!(
+                "ERR IDEMPOTENT can only wrap single-key commands, not '{}'",
+                String::from_utf8_lossy(name)
+            ));
+        }
+
+        let inner_args = argv.len() - 1;
+        if inner_args < command.min_args() || command.max_args().is_some_and(|max| inner_args > max) {
+            return RespValue::error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                String::from_utf8_lossy(name)
+            ));
+        }
+
+        // The checks of the dispatch paths, for the wrapped command
+        if let Some(oom) = check_maxmemory(ctx, command.as_ref(), &argv[1..]) {
+            return oom;
+        }
+        let reply = execute_traced(ctx, command.as_ref(), argv);
+        log_to_audit(ctx, command.as_ref(), argv, &reply);
+        ctx.idempotency.insert(token, fingerprint, reply.clone(), now);
+        reply
+    }
+
+    fn name(&self) -> &'static str {
+        "IDEMPOTENT"
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (3, 3, 1)
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::args;
+
+    #[test]
+    fn test_retried_incr_applies_once() {
+        let mut ctx = CommandContext::new();
+        let first = IdempotentCommand.execute(&mut ctx, &args(&["tok-1", "INCR", "counter"]));
+        let retry = IdempotentCommand.execute(&mut ctx, &args(&["tok-1", "INCR", "counter"]));
+        assert_eq!(first, RespValue::integer(1));
+        assert_eq!(retry, first);
+
+        // Another token runs the command again
+        let next = IdempotentCommand.execute(&mut ctx, &args(&["tok-2", "incr", "counter"]));
+        assert_eq!(next, RespValue::integer(2));
+
+        let reused = IdempotentCommand.execute(&mut ctx, &args(&["tok-1", "INCR", "other"]));
+        assert_eq!(reused, RespValue::error("ERR idempotency token already used by a different command"));
+        let nested = IdempotentCommand.execute(&mut ctx, &args(&["tok-3", "IDEMPOTENT", "tok-4", "INCR", "counter"]));
+        assert_eq!(nested, RespValue::error("ERR IDEMPOTENT can't wrap itself"));
+        let arity = IdempotentCommand.execute(&mut ctx, &args(&["tok-5", "GET", "a", "b"]));
+        assert_eq!(arity, RespValue::error("ERR wrong number of arguments for 'GET' command"));
+    }
+
+    #[test]
+    fn test_only_single_key_commands_wrapped() {
+        let mut ctx = CommandContext::new();
+        for command in [&["MSET", "k0", "v", "k1", "v"][..], &["KEYS", "*"], &["COPY", "a", "b"], &["SUNIONSTORE", "d", "s"]] {
+            let reply = IdempotentCommand.execute(&mut ctx, &args(&[&["tok"][..], command].concat()));
+            let expected = format!("ERR IDEMPOTENT can only wrap single-key commands, not '{}'", command[0]);
+            assert_eq!(reply, RespValue::error(expected));
+        }
+        assert!(ctx.idempotency.is_empty());
+    }
+
+    #[test]
+    fn test_flags_of_wrapped_command() {
+        let mut ctx = CommandContext::new();
+        let get = args(&["IDEMPOTENT", "tok-1", "GET", "k"]);
+        let incr = args(&["IDEMPOTENT", "tok-2", "INCR", "k"]);
+        assert!(!command_flags(&ctx.registry, &get).contains(&"write"));
+        assert!(command_flags(&ctx.registry, &incr).contains(&"denyoom"));
+
+        // Only the first use of a token runs the command
+        assert!(!is_cached_retry(&ctx, &incr));
+        IdempotentCommand.execute(&mut ctx, &incr[1..]);
+        assert!(is_cached_retry(&ctx, &incr));
+        assert!(!is_cached_retry(&ctx, &get));
+    }
+
+    #[test]
+    fn test_cache_is_bounded_lru_with_window() {
+        let mut cache = IdempotencyCache::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        cache.insert(Bytes::from("a"), 0, RespValue::integer(1), now);
+        cache.insert(Bytes::from("b"), 0, RespValue::integer(2), now);
+
+        // Using `a` makes `b` the least recently used, dropped for `c`
+        assert!(cache.get(b"a", now).is_some());
+        cache.insert(Bytes::from("c"), 0, RespValue::integer(3), now);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(b"b", now).is_none());
+        assert_eq!(cache.get(b"a", now).map(|cached| cached.reply.clone()), Some(RespValue::integer(1)));
+
+        // Past the window, a token is forgotten
+        assert!(cache.get(b"c", now + Duration::from_secs(60)).is_none());
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,189 @@
+//! JSON commands (JSON.SET, JSON.GET)
+
+use super::{Command, CommandContext, extract_bulk_string, log_to_aof, wrong_type};
+use crate::protocol::RespValue;
+use crate::store::{JsonPath, Value};
+use crate::aof::AofOperation;
+use bytes::Bytes;
+
+/// Parse a path argument, with the reply to send when it is invalid
+fn parse_path(arg: &RespValue) -> Result<JsonPath, RespValue> {
+    let path = extract_bulk_string(arg).map_err(|e| RespValue::error(format!("ERR {}", e)))?;
+    let path = std::str::from_utf8(path).map_err(|_| RespValue::error("ERR JSON Path error: invalid UTF-8"))?;
+    JsonPath::parse(path).map_err(|e| RespValue::error(format!("ERR JSON Path error: {}", e)))
+}
+
+/// JSON.SET command - Set the JSON value at path in the document at key
+///
+/// Syntax: JSON.SET key path value
+///
+/// A new key must be created at the root. On an existing document, the
+/// parent of the path must exist (nil reply otherwise); the TTL of the key
+/// is kept. The whole document is logged to the AOF.
+pub struct JsonSetCommand;
+
+impl Command for JsonSetCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+        let path = match parse_path(&args[1]) {
+            Ok(path) => path,
+            Err(error) => return error,
+        };
+        let value = match extract_bulk_string(&args[2]).map(|v| serde_json::from_slice(v)) {
+            Ok(Ok(value)) => value,
+            Ok(Err(e)) => return RespValue::error(format!("ERR {}", e)),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let document = match ctx.store.get_mut(&key) {
+            Some(Value::Json(doc)) => {
+                if !path.set(doc, value) {
+                    return RespValue::null();
+                }
+                let document = doc.to_string();
+                ctx.store.notify_modified(&key);
+                document
+            }
+            Some(other) => return wrong_type("JSON.SET", &key, other.type_name()),
+            None if path.is_root() => {
+                let document = value.to_string();
+                ctx.store.set(key.clone(), Value::Json(value));
+                document
+            }
+            None => return RespValue::error("ERR new objects must be created at the root"),
+        };
+
+        log_to_aof(ctx, AofOperation::JsonSet, key, vec![Bytes::from(document)]);
+        RespValue::simple_string("OK")
+    }
+
+    fn name(&self) -> &'static str {
+        "JSON.SET"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(3)
+    }
+}
+
+/// JSON.GET command - Get the JSON value at path in the document at key
+///
+/// Syntax: JSON.GET key [path]
+///
+/// Replies with the serialized value. A JSONPath (`$...`) replies with the
+/// array of the values it matches, empty when there are none; a legacy path
+/// replies with the value itself, or an error when it does not exist. The
+/// default path is the root, in the legacy syntax.
+pub struct JsonGetCommand;
+
+impl Command for JsonGetCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+        let path = match args.get(1).map(parse_path) {
+            Some(Ok(path)) => path,
+            Some(Err(error)) => return error,
+            None => JsonPath { segments: Vec::new(), legacy: true },
+        };
+
+        let doc = match ctx.store.get(&key) {
+            Some(Value::Json(doc)) => doc,
+            Some(other) => return wrong_type("JSON.GET", &key, other.type_name()),
+            None => return RespValue::null(),
+        };
+
+        match (path.get(doc), path.legacy) {
+            (Some(value), true) => RespValue::bulk_string(value.to_string()),
+            (Some(value), false) => RespValue::bulk_string(format!("[{}]", value)),
+            (None, false) => RespValue::bulk_string("[]"),
+            (None, true) => {
+                let path = args[1].as_bulk_string().map(|p| String::from_utf8_lossy(p).into_owned());
+                RespValue::error(format!("ERR Path '{}' does not exist", path.unwrap_or_default()))
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "JSON.GET"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::args;
+
+    fn run(command: &dyn Command, ctx: &mut CommandContext, parts: &[&str]) -> RespValue {
+        command.execute(ctx, &args(parts))
+    }
+
+    #[test]
+    fn test_json_set_nested_and_get_leaf() {
+        let mut ctx = CommandContext::new();
+        let ok = RespValue::simple_string("OK");
+        assert_eq!(run(&JsonSetCommand, &mut ctx, &["doc", "$", r#"{"a":{"b":42},"c":[1,2]}"#]), ok);
+
+        assert_eq!(run(&JsonGetCommand, &mut ctx, &["doc", "$.a.b"]), RespValue::bulk_string("[42]"));
+        assert_eq!(run(&JsonGetCommand, &mut ctx, &["doc", ".a.b"]), RespValue::bulk_string("42"));
+        assert_eq!(run(&JsonGetCommand, &mut ctx, &["doc", "$.c[-1]"]), RespValue::bulk_string("[2]"));
+        assert_eq!(run(&JsonGetCommand, &mut ctx, &["doc"]), RespValue::bulk_string(r#"{"a":{"b":42},"c":[1,2]}"#));
+        assert_eq!(run(&JsonGetCommand, &mut ctx, &["doc", "$.missing"]), RespValue::bulk_string("[]"));
+        assert_eq!(
+            run(&JsonGetCommand, &mut ctx, &["doc", ".missing"]),
+            RespValue::error("ERR Path '.missing' does not exist")
+        );
+        assert_eq!(run(&JsonGetCommand, &mut ctx, &["nokey", "$"]), RespValue::null());
+
+        // Setting inside the document, where the parent exists
+        assert_eq!(run(&JsonSetCommand, &mut ctx, &["doc", "$.a.d", r#""new""#]), ok);
+        assert_eq!(run(&JsonGetCommand, &mut ctx, &["doc", "$.a"]), RespValue::bulk_string(r#"[{"b":42,"d":"new"}]"#));
+        assert_eq!(run(&JsonSetCommand, &mut ctx, &["doc", "$.x.y", "1"]), RespValue::null());
+    }
+
+    #[test]
+    fn test_json_errors() {
+        let mut ctx = CommandContext::new();
+        assert_eq!(
+            run(&JsonSetCommand, &mut ctx, &["doc", "$.a", "1"]),
+            RespValue::error("ERR new objects must be created at the root")
+        );
+        assert!(matches!(run(&JsonSetCommand, &mut ctx, &["doc", "$", "{oops"]), RespValue::Error(_)));
+        for path in ["$.a[1:2]", "$..a", "$.a[?(@.b)]", "$.*"] {
+            match run(&JsonGetCommand, &mut ctx, &["doc", path]) {
+                RespValue::Error(e) => assert!(e.starts_with("ERR JSON Path error"), "{}", e),
+                other => panic!("Expected an error for {}, got {:?}", path, other),
+            }
+        }
+
+        ctx.store.set("string", Value::string("value"));
+        assert_eq!(
+            run(&JsonGetCommand, &mut ctx, &["string"]),
+            RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+        );
+    }
+}
@@ -1,6 +1,6 @@
-//! Key commands (DEL, EXISTS)
+//! Key commands (DEL, EXISTS, OBJECT, COPY)
 
-use super::{Command, CommandContext, extract_bulk_string, log_to_aof};
+use super::{Command, CommandContext, extract_bulk_string, extract_integer, log_key_to_aof, log_to_aof};
 use crate::protocol::RespValue;
 use crate::aof::AofOperation;
 
@@ -39,6 +39,14 @@ impl Command for DelCommand {
         "DEL"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (1, -1, 1)
+    }
+
     fn min_args(&self) -> usize {
         1
     }
@@ -77,15 +85,172 @@ impl Command for ExistsCommand {
         "EXISTS"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly", "fast"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (1, -1, 1)
+    }
+
     fn min_args(&self) -> usize {
         1
     }
 }
 
+/// OBJECT command - Inspect the internals of a key
+///
+/// Syntax: OBJECT IDLETIME|ENCODING key
+///
+/// IDLETIME returns the number of seconds since the key was last read or
+/// written, ENCODING the internal representation of the value (e.g. `int`
+/// for numeric strings). Inspecting a key does not count as an access.
+pub struct ObjectCommand;
+
+impl Command for ObjectCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() != 2 {
+            return RespValue::error("ERR wrong number of arguments for 'OBJECT' command");
+        }
+
+        let subcommand = match extract_bulk_string(&args[0]) {
+            Ok(s) => String::from_utf8_lossy(s).to_uppercase(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let key = match extract_bulk_string(&args[1]) {
+            Ok(k) => k,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        // Read the entry directly so the access time is left untouched
+        let entry = match ctx.store.get_entry(key) {
+            Some(entry) if !entry.is_expired() => entry,
+            _ => return RespValue::null(),
+        };
+
+        match subcommand.as_str() {
+            "IDLETIME" => RespValue::integer(entry.idle_time().as_secs() as i64),
+            "ENCODING" => RespValue::bulk_string(entry.encoding()),
+            _ => RespValue::error(format!("ERR unknown subcommand '{}'. Try OBJECT HELP.", subcommand)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "OBJECT"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (2, 2, 1)
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+/// Parse the options of COPY after its two keys: `[DB destination-db] [REPLACE]`
+///
+/// Returns whether REPLACE was given. A FerrumDB server has a single
+/// database, so the only destination accepted is DB 0.
+pub(crate) fn parse_copy_options(args: &[RespValue]) -> Result<bool, RespValue> {
+    let mut replace = false;
+    let mut i = 0;
+    while i < args.len() {
+        match extract_bulk_string(&args[i]) {
+            Ok(opt) if opt.eq_ignore_ascii_case(b"REPLACE") => replace = true,
+            Ok(opt) if opt.eq_ignore_ascii_case(b"DB") && i + 1 < args.len() => {
+                i += 1;
+                match extract_integer(&args[i]) {
+                    Ok(0) => {}
+                    Ok(_) => return Err(RespValue::error("ERR DB index is out of range")),
+                    Err(e) => return Err(RespValue::error(format!("ERR {}", e))),
+                }
+            }
+            _ => return Err(RespValue::error("ERR syntax error")),
+        }
+        i += 1;
+    }
+    Ok(replace)
+}
+
+/// COPY command - Copy the value of a key to another key
+///
+/// Syntax: COPY source destination [DB destination-db] [REPLACE]
+///
+/// The copy keeps the TTL of the source. Returns 1 if the key was copied,
+/// 0 if the source does not exist or the destination exists without REPLACE.
+/// FerrumDB has a single database, so any destination-db but 0 is an error.
+pub struct CopyCommand;
+
+impl Command for CopyCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        let (source, destination) = match (extract_bulk_string(&args[0]), extract_bulk_string(&args[1])) {
+            (Ok(s), Ok(d)) => (s.clone(), d.clone()),
+            (Err(e), _) | (_, Err(e)) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let replace = match parse_copy_options(&args[2..]) {
+            Ok(replace) => replace,
+            Err(error) => return error,
+        };
+
+        if source == destination {
+            return RespValue::error("ERR source and destination objects are the same");
+        }
+
+        // Checked through `exists` so that an expired source is removed first
+        if !ctx.store.exists(&source) || (!replace && ctx.store.exists(&destination)) {
+            return RespValue::integer(0);
+        }
+        let Some(entry) = ctx.store.get_entry(&source) else {
+            return RespValue::integer(0);
+        };
+        let (value, expire_at) = (entry.value.clone(), entry.expire_at);
+
+        ctx.store.set(destination.clone(), value);
+        if let Some(expire_at) = expire_at {
+            ctx.store.set_expire_at(&destination, expire_at);
+        }
+
+        // Replayed as a fresh key, like RESTORE
+        log_to_aof(ctx, AofOperation::Del, destination.clone(), vec![]);
+        log_key_to_aof(ctx, &destination);
+
+        RespValue::integer(1)
+    }
+
+    fn name(&self) -> &'static str {
+        "COPY"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (1, 2, 1)
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::commands::args;
     use crate::store::Value;
+    use bytes::Bytes;
 
     #[test]
     fn test_del() {
@@ -120,4 +285,192 @@ mod tests {
         let result = exists_cmd.execute(&mut ctx, &args);
         assert_eq!(result, RespValue::integer(1));
     }
+
+    #[test]
+    fn test_object_idletime_with_no_touch() {
+        use crate::commands::ClientOptions;
+        use bytes::Bytes;
+        use std::time::{Duration, Instant};
+
+        let mut ctx = CommandContext::new();
+        ctx.store.set("key1", Value::string("value1"));
+        ctx.store.get_entry_mut(&Bytes::from("key1")).unwrap().last_access = Instant::now() - Duration::from_secs(10);
+
+        let idletime = |ctx: &mut CommandContext| {
+            let args = vec![RespValue::bulk_string("IDLETIME"), RespValue::bulk_string("key1")];
+            ObjectCommand.execute(ctx, &args).as_integer().unwrap()
+        };
+        let get = |ctx: &mut CommandContext| {
+            crate::commands::string::GetCommand.execute(ctx, &[RespValue::bulk_string("key1")])
+        };
+
+        // With NO-TOUCH, reads don't reset the idle time
+        ctx.apply_client_options(ClientOptions { no_touch: true, ..ClientOptions::default() });
+        let before = idletime(&mut ctx);
+        assert!(before >= 10);
+        get(&mut ctx);
+        get(&mut ctx);
+        assert!(idletime(&mut ctx) >= before);
+
+        // A regular read resets it
+        ctx.apply_client_options(ClientOptions::default());
+        get(&mut ctx);
+        assert_eq!(idletime(&mut ctx), 0);
+
+        // Missing keys return nil
+        let args = vec![RespValue::bulk_string("IDLETIME"), RespValue::bulk_string("missing")];
+        assert_eq!(ObjectCommand.execute(&mut ctx, &args), RespValue::null());
+    }
+
+    #[test]
+    fn test_object_encoding_numeric_strings() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
+        let mut ctx = CommandContext::new();
+
+        let encoding = |ctx: &mut CommandContext, value: &str| {
+            let args = vec![RespValue::bulk_string("n"), RespValue::bulk_string(value.to_string())];
+            crate::commands::string::SetCommand.execute(ctx, &args);
+            let args = vec![RespValue::bulk_string("ENCODING"), RespValue::bulk_string("n")];
+            ObjectCommand.execute(ctx, &args)
+        };
+
+        assert_eq!(encoding(&mut ctx, "12345"), RespValue::bulk_string("int"));
+        assert_eq!(encoding(&mut ctx, "-42"), RespValue::bulk_string("int"));
+
+        // Non-canonical forms stay strings
+        for value in ["12345x", "007", "+1", "-0", " 1", "99999999999999999999"] {
+            assert_eq!(encoding(&mut ctx, value), RespValue::bulk_string("embstr"), "value {:?}", value);
+        }
+
+        // GET still returns the exact bytes that were set
+        encoding(&mut ctx, "12345");
+        let result = crate::commands::string::GetCommand.execute(&mut ctx, &[RespValue::bulk_string("n")]);
+        assert_eq!(result, RespValue::bulk_string("12345"));
+    }
+
+    #[test]
+    fn test_object_encoding_strings() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
+        let mut ctx = CommandContext::new();
+        let run = |ctx: &mut CommandContext, command: &dyn Command, parts: &[&str]| {
+            command.execute(ctx, &args(parts))
+        };
+        let set = crate::commands::string::SetCommand;
+        let encoding = |ctx: &mut CommandContext| run(ctx, &ObjectCommand, &["ENCODING", "s"]);
+
+        run(&mut ctx, &set, &["s", &"x".repeat(44)]);
+        assert_eq!(encoding(&mut ctx), RespValue::bulk_string("embstr"));
+        run(&mut ctx, &set, &["s", &"x".repeat(45)]);
+        assert_eq!(encoding(&mut ctx), RespValue::bulk_string("raw"));
+        run(&mut ctx, &set, &["s", "42"]);
+        assert_eq!(encoding(&mut ctx), RespValue::bulk_string("int"));
+
+        // A string modified in place is raw, however short, until set again
+        run(&mut ctx, &set, &["s", "short"]);
+        run(&mut ctx, &crate::commands::string::AppendCommand, &["s", "er"]);
+        assert_eq!(encoding(&mut ctx), RespValue::bulk_string("raw"));
+        run(&mut ctx, &set, &["s", "short"]);
+        assert_eq!(encoding(&mut ctx), RespValue::bulk_string("embstr"));
+
+        // APPEND to a missing key creates it like SET, only appending to it
+        // again makes it raw
+        let append = crate::commands::string::AppendCommand;
+        let appended = |ctx: &mut CommandContext| run(ctx, &ObjectCommand, &["ENCODING", "appended"]);
+        run(&mut ctx, &append, &["appended", "short"]);
+        assert_eq!(appended(&mut ctx), RespValue::bulk_string("embstr"));
+        run(&mut ctx, &append, &["appended", "er"]);
+        assert_eq!(appended(&mut ctx), RespValue::bulk_string("raw"));
+        run(&mut ctx, &append, &["number", "42"]);
+        assert_eq!(run(&mut ctx, &ObjectCommand, &["ENCODING", "number"]), RespValue::bulk_string("int"));
+
+        // The threshold follows embstr-size-limit
+        crate::store::set_embstr_size_limit(4);
+        let short = encoding(&mut ctx);
+        crate::store::set_embstr_size_limit(crate::store::DEFAULT_EMBSTR_SIZE_LIMIT);
+        assert_eq!(short, RespValue::bulk_string("raw"));
+    }
+
+    #[test]
+    fn test_object_encoding_lists() {
+        use crate::store::Value;
+        use bytes::Bytes;
+
+        let mut ctx = CommandContext::new();
+        let encoding = |ctx: &mut CommandContext, len: usize| {
+            let list = (0..len).map(|i| Bytes::from(format!("element:{}", i)));
+            ctx.store.set("list", Value::list(list));
+            let args = vec![RespValue::bulk_string("ENCODING"), RespValue::bulk_string("list")];
+            ObjectCommand.execute(ctx, &args)
+        };
+
+        // Default list-max-listpack-size is -2: up to 8 KB in a listpack
+        assert_eq!(encoding(&mut ctx, 5), RespValue::bulk_string("listpack"));
+        assert_eq!(encoding(&mut ctx, 1000), RespValue::bulk_string("quicklist"));
+
+        // A positive size is a number of elements
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
+        crate::store::set_list_max_listpack_size(10);
+        let (small, large) = (encoding(&mut ctx, 10), encoding(&mut ctx, 11));
+        crate::store::set_list_max_listpack_size(crate::store::DEFAULT_LIST_MAX_LISTPACK_SIZE);
+        assert_eq!(small, RespValue::bulk_string("listpack"));
+        assert_eq!(large, RespValue::bulk_string("quicklist"));
+    }
+
+    #[test]
+    fn test_object_encoding_zsets() {
+        let mut ctx = CommandContext::new();
+        let encoding = |ctx: &mut CommandContext, len: usize| {
+            let mut zset = Value::empty_zset();
+            for i in 0..len {
+                zset.as_zset_mut().unwrap().insert(Bytes::from(format!("member:{}", i)), i as f64);
+            }
+            ctx.store.set("zset", zset);
+            let args = vec![RespValue::bulk_string("ENCODING"), RespValue::bulk_string("zset")];
+            ObjectCommand.execute(ctx, &args)
+        };
+
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
+        let (small, large) = (encoding(&mut ctx, 128), encoding(&mut ctx, 129));
+        assert_eq!(small, RespValue::bulk_string("listpack"));
+        assert_eq!(large, RespValue::bulk_string("skiplist"));
+
+        // The threshold is zset-max-listpack-entries
+        crate::store::set_zset_max_listpack_entries(4);
+        let (small, large) = (encoding(&mut ctx, 4), encoding(&mut ctx, 5));
+        crate::store::set_zset_max_listpack_entries(crate::store::DEFAULT_ZSET_MAX_LISTPACK_ENTRIES);
+        assert_eq!(small, RespValue::bulk_string("listpack"));
+        assert_eq!(large, RespValue::bulk_string("skiplist"));
+    }
+
+    #[test]
+    fn test_copy() {
+        let mut ctx = CommandContext::new();
+        ctx.store.set("src", Value::string("value"));
+        ctx.store.expire(&Bytes::from("src"), 100);
+        let copy = |ctx: &mut CommandContext, parts: &[&str]| {
+            CopyCommand.execute(ctx, &args(parts))
+        };
+
+        // The source is kept and the copy has its value and TTL
+        assert_eq!(copy(&mut ctx, &["src", "dst", "DB", "0"]), RespValue::integer(1));
+        assert!(ctx.store.exists(&Bytes::from("src")));
+        assert_eq!(ctx.store.get_entry(&Bytes::from("dst")).unwrap().value, Value::string("value"));
+        assert!((99_000..=100_000).contains(&ctx.store.pttl(&Bytes::from("dst"))));
+
+        // An existing destination is only overwritten with REPLACE
+        ctx.store.set("other", Value::string("other"));
+        assert_eq!(copy(&mut ctx, &["other", "dst"]), RespValue::integer(0));
+        assert_eq!(copy(&mut ctx, &["other", "dst", "replace"]), RespValue::integer(1));
+        assert_eq!(ctx.store.get_entry(&Bytes::from("dst")).unwrap().value, Value::string("other"));
+        assert_eq!(ctx.store.ttl(&Bytes::from("dst")), -1);
+
+        assert_eq!(copy(&mut ctx, &["missing", "dst2"]), RespValue::integer(0));
+        assert_eq!(
+            copy(&mut ctx, &["src", "src"]),
+            RespValue::error("ERR source and destination objects are the same")
+        );
+        assert_eq!(copy(&mut ctx, &["src", "dst2", "DB", "1"]), RespValue::error("ERR DB index is out of range"));
+        assert_eq!(copy(&mut ctx, &["src", "dst2", "DB"]), RespValue::error("ERR syntax error"));
+        assert!(!ctx.store.exists(&Bytes::from("dst2")));
+    }
 }
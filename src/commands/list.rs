@@ -1,57 +1,85 @@
-//! List commands (LPUSH, RPUSH, LRANGE, LLEN)
+//! List commands (LPUSH, RPUSH, LPUSHIDX, RPUSHIDX, LPOP, RPOP, LRANGE, LLEN)
 
-use super::{Command, CommandContext, extract_bulk_string, extract_integer};
+use super::{Command, CommandContext, extract_bulk_string, extract_integer, log_to_aof, normalize_range, WRONGTYPE};
+use crate::aof::AofOperation;
 use crate::protocol::RespValue;
-use crate::store::Value;
-//use bytes::Bytes;
+use crate::store::{ListItem, Value};
+use bytes::Bytes;
 
-/// LPUSH command - Prepend one or multiple values to a list
+/// Shared implementation of LPUSH / RPUSH and their WITHINDEX variants
 ///
-/// Syntax: LPUSH key value [value ...]
-pub struct LPushCommand;
+/// Replies with the new length of the list, or with `with_index` the
+/// 0-based positions the values landed at once all of them are pushed, in
+/// argument order.
+fn push_values(ctx: &mut CommandContext, args: &[RespValue], name: &str, to_front: bool, with_index: bool) -> RespValue {
+    if args.len() < 2 {
+        return RespValue::error(format!("ERR wrong number of arguments for '{}' command", name));
+    }
 
-impl Command for LPushCommand {
-    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
-        if args.len() < 2 {
-            return RespValue::error("ERR wrong number of arguments for 'LPUSH' command");
+    let key = match extract_bulk_string(&args[0]) {
+        Ok(k) => k.clone(),
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    };
+
+    // Get or create list
+    let list = match ctx.store.get_mut(&key) {
+        Some(value) => {
+            match value.as_list_mut() {
+                Some(list) => list,
+                None => return RespValue::error(WRONGTYPE),
+            }
+        }
+        None => {
+            // Create new list
+            ctx.store.set(key.clone(), Value::empty_list());
+            ctx.store.get_mut(&key).unwrap().as_list_mut().unwrap()
         }
+    };
 
-        let key = match extract_bulk_string(&args[0]) {
-            Ok(k) => k.clone(),
+    // Push all values to the front or the back
+    for arg in &args[1..] {
+        let value = match extract_bulk_string(arg) {
+            Ok(v) => v.clone(),
             Err(e) => return RespValue::error(format!("ERR {}", e)),
         };
+        if to_front {
+            list.push_front(ListItem::new(value));
+        } else {
+            list.push_back(ListItem::new(value));
+        }
+    }
 
-        // Get or create list
-        let list = match ctx.store.get_mut(&key) {
-            Some(value) => {
-                match value.as_list_mut() {
-                    Some(list) => list,
-                    None => return RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
-                }
-            }
-            None => {
-                // Create new list
-                ctx.store.set(key.clone(), Value::empty_list());
-                ctx.store.get_mut(&key).unwrap().as_list_mut().unwrap()
-            }
-        };
+    let len = list.len();
+    ctx.store.notify_modified(&key);
 
-        // Push all values to the front
-        for i in 1..args.len() {
-            let value = match extract_bulk_string(&args[i]) {
-                Ok(v) => v.clone(),
-                Err(e) => return RespValue::error(format!("ERR {}", e)),
-            };
-            list.push_front(value);
-        }
+    if !with_index {
+        return RespValue::integer(len as i64);
+    }
+    let pushed = args.len() - 1;
+    let positions: Vec<usize> = if to_front { (0..pushed).rev().collect() } else { (len - pushed..len).collect() };
+    RespValue::array(positions.into_iter().map(|i| RespValue::integer(i as i64)).collect())
+}
+
+/// LPUSH command - Prepend one or multiple values to a list
+///
+/// Syntax: LPUSH key value [value ...]
+///
+/// Returns the length of the list.
+pub struct LPushCommand;
 
-        RespValue::integer(list.len() as i64)
+impl Command for LPushCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        push_values(ctx, args, "LPUSH", true, false)
     }
 
     fn name(&self) -> &'static str {
         "LPUSH"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         2
     }
@@ -60,48 +88,76 @@ impl Command for LPushCommand {
 /// RPUSH command - Append one or multiple values to a list
 ///
 /// Syntax: RPUSH key value [value ...]
+///
+/// Returns the length of the list.
 pub struct RPushCommand;
 
 impl Command for RPushCommand {
     fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
-        if args.len() < 2 {
-            return RespValue::error("ERR wrong number of arguments for 'RPUSH' command");
-        }
+        push_values(ctx, args, "RPUSH", false, false)
+    }
 
-        let key = match extract_bulk_string(&args[0]) {
-            Ok(k) => k.clone(),
-            Err(e) => return RespValue::error(format!("ERR {}", e)),
-        };
+    fn name(&self) -> &'static str {
+        "RPUSH"
+    }
 
-        // Get or create list
-        let list = match ctx.store.get_mut(&key) {
-            Some(value) => {
-                match value.as_list_mut() {
-                    Some(list) => list,
-                    None => return RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
-                }
-            }
-            None => {
-                // Create new list
-                ctx.store.set(key.clone(), Value::empty_list());
-                ctx.store.get_mut(&key).unwrap().as_list_mut().unwrap()
-            }
-        };
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
+    }
 
-        // Push all values to the back
-        for i in 1..args.len() {
-            let value = match extract_bulk_string(&args[i]) {
-                Ok(v) => v.clone(),
-                Err(e) => return RespValue::error(format!("ERR {}", e)),
-            };
-            list.push_back(value);
-        }
+    fn min_args(&self) -> usize {
+        2
+    }
+}
+
+/// LPUSHIDX command - LPUSH replying with the positions of the values (a
+/// FerrumDB extension)
+///
+/// Syntax: LPUSHIDX key value [value ...]
+///
+/// Returns the positions the values landed at once all of them are pushed
+/// (the last value is at 0). Any value can be pushed, so the reply shape is
+/// chosen by the command name rather than by an option among the values.
+pub struct LPushIdxCommand;
 
-        RespValue::integer(list.len() as i64)
+impl Command for LPushIdxCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        push_values(ctx, args, "LPUSHIDX", true, true)
     }
 
     fn name(&self) -> &'static str {
-        "RPUSH"
+        "LPUSHIDX"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+}
+
+/// RPUSHIDX command - RPUSH replying with the positions of the values (a
+/// FerrumDB extension)
+///
+/// Syntax: RPUSHIDX key value [value ...]
+///
+/// Returns the positions the values landed at, from the previous length
+/// onwards.
+pub struct RPushIdxCommand;
+
+impl Command for RPushIdxCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        push_values(ctx, args, "RPUSHIDX", false, true)
+    }
+
+    fn name(&self) -> &'static str {
+        "RPUSHIDX"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
     }
 
     fn min_args(&self) -> usize {
@@ -140,7 +196,7 @@ impl Command for LRangeCommand {
             Some(value) => {
                 match value.as_list() {
                     Some(list) => list,
-                    None => return RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => return RespValue::error(WRONGTYPE),
                 }
             }
             None => {
@@ -149,27 +205,12 @@ impl Command for LRangeCommand {
             }
         };
 
-        let len = list.len() as i64;
-
-        // Handle negative indices
-        let start_idx = if start < 0 {
-            (len + start).max(0) as usize
-        } else {
-            start.min(len) as usize
-        };
-
-        let stop_idx = if stop < 0 {
-            (len + stop).max(-1) as usize
-        } else {
-            stop.min(len - 1) as usize
-        };
-
         // Extract range
         let mut result = Vec::new();
-        if start_idx <= stop_idx && start_idx < list.len() {
-            for i in start_idx..=stop_idx.min(list.len() - 1) {
+        if let Some((start_idx, stop_idx)) = normalize_range(start, stop, list.len()) {
+            for i in start_idx..=stop_idx {
                 if let Some(value) = list.get(i) {
-                    result.push(RespValue::bulk_string(value.clone()));
+                    result.push(RespValue::bulk_string(value.to_bytes()));
                 }
             }
         }
@@ -181,6 +222,10 @@ impl Command for LRangeCommand {
         "LRANGE"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
     fn min_args(&self) -> usize {
         3
     }
@@ -190,6 +235,114 @@ impl Command for LRangeCommand {
     }
 }
 
+/// Shared implementation of LPOP / RPOP
+///
+/// Without a count, replies with the popped element (nil for a missing
+/// key); with one, with an array of up to `count` elements. The key is
+/// deleted once the list is emptied.
+fn pop_elements(ctx: &mut CommandContext, args: &[RespValue], name: &str, from_back: bool) -> RespValue {
+    if args.is_empty() || args.len() > 2 {
+        return RespValue::error(format!("ERR wrong number of arguments for '{}' command", name));
+    }
+
+    let key = match extract_bulk_string(&args[0]) {
+        Ok(k) => k.clone(),
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    };
+
+    let count = match args.get(1) {
+        Some(arg) => match extract_integer(arg) {
+            Ok(c) if c >= 0 => Some(c as usize),
+            Ok(_) => return RespValue::error("ERR value is out of range, must be positive"),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        },
+        None => None,
+    };
+
+    let popped: Vec<_> = match ctx.store.get_mut(&key) {
+        Some(value) => match value.as_list_mut() {
+            Some(list) => {
+                let n = count.unwrap_or(1).min(list.len());
+                if from_back {
+                    (0..n).filter_map(|_| list.pop_back()).collect()
+                } else {
+                    list.drain(..n).collect()
+                }
+            }
+            None => return RespValue::error(WRONGTYPE),
+        },
+        None => return RespValue::null(),
+    };
+    if !popped.is_empty() {
+        ctx.store.notify_modified(&key);
+    }
+    ctx.store.remove_if_empty(&key);
+
+    if !popped.is_empty() {
+        let op = if from_back { AofOperation::RPop } else { AofOperation::LPop };
+        log_to_aof(ctx, op, key, vec![Bytes::from(popped.len().to_string())]);
+    }
+
+    match count {
+        Some(_) => RespValue::array(popped.into_iter().map(RespValue::bulk_string).collect()),
+        None => popped.into_iter().next().map_or(RespValue::null(), RespValue::bulk_string),
+    }
+}
+
+/// LPOP command - Remove and return the first elements of a list
+///
+/// Syntax: LPOP key [count]
+pub struct LPopCommand;
+
+impl Command for LPopCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        pop_elements(ctx, args, "LPOP", false)
+    }
+
+    fn name(&self) -> &'static str {
+        "LPOP"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+/// RPOP command - Remove and return the last elements of a list
+///
+/// Syntax: RPOP key [count]
+pub struct RPopCommand;
+
+impl Command for RPopCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        pop_elements(ctx, args, "RPOP", true)
+    }
+
+    fn name(&self) -> &'static str {
+        "RPOP"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
 /// LLEN command - Get the length of a list
 ///
 /// Syntax: LLEN key
@@ -211,7 +364,7 @@ impl Command for LLenCommand {
             Some(value) => {
                 match value.as_list() {
                     Some(list) => RespValue::integer(list.len() as i64),
-                    None => RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => RespValue::error(WRONGTYPE),
                 }
             }
             None => {
@@ -225,6 +378,10 @@ impl Command for LLenCommand {
         "LLEN"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         1
     }
@@ -237,6 +394,8 @@ impl Command for LLenCommand {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::commands::args;
+    use bytes::Bytes;
 
     #[test]
     fn test_lpush_rpush() {
@@ -279,6 +438,37 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_push_with_index() {
+        let mut ctx = CommandContext::new();
+        let indexes = |positions: &[i64]| RespValue::array(positions.iter().map(|&i| RespValue::integer(i)).collect());
+
+        assert_eq!(RPushCommand.execute(&mut ctx, &args(&["q", "a", "b"])), RespValue::integer(2));
+        assert_eq!(RPushIdxCommand.execute(&mut ctx, &args(&["q", "c", "d"])), indexes(&[2, 3]));
+
+        // LPUSHIDX e f: f ends up at the head, e right after it
+        assert_eq!(LPushIdxCommand.execute(&mut ctx, &args(&["q", "e", "f"])), indexes(&[1, 0]));
+        assert_eq!(LLenCommand.execute(&mut ctx, &args(&["q"])), RespValue::integer(6));
+
+        // WITHINDEX is a value like any other, wherever it appears
+        assert_eq!(RPushCommand.execute(&mut ctx, &args(&["l", "a", "withindex"])), RespValue::integer(2));
+        assert_eq!(LPushCommand.execute(&mut ctx, &args(&["l", "WITHINDEX"])), RespValue::integer(3));
+        assert_eq!(RPushIdxCommand.execute(&mut ctx, &args(&["l", "withindex"])), indexes(&[3]));
+        assert_eq!(
+            LRangeCommand.execute(&mut ctx, &args(&["l", "0", "-1"])),
+            RespValue::array(
+                ["WITHINDEX", "a", "withindex", "withindex"]
+                    .iter()
+                    .map(|v| RespValue::bulk_string(Bytes::from(*v)))
+                    .collect()
+            )
+        );
+        assert_eq!(
+            RPushIdxCommand.execute(&mut ctx, &args(&["l"])),
+            RespValue::error("ERR wrong number of arguments for 'RPUSHIDX' command")
+        );
+    }
+
     #[test]
     fn test_llen() {
         let mut ctx = CommandContext::new();
@@ -305,6 +495,82 @@ mod tests {
         assert_eq!(result, RespValue::integer(0));
     }
 
+    #[test]
+    fn test_pop_last_element_removes_key() {
+        use crate::commands::key::ExistsCommand;
+
+        let mut ctx = CommandContext::new();
+        RPushCommand.execute(&mut ctx, &args(&["mylist", "a", "b", "c", "d"]));
+
+        assert_eq!(LPopCommand.execute(&mut ctx, &args(&["mylist"])), RespValue::bulk_string("a"));
+        assert_eq!(
+            RPopCommand.execute(&mut ctx, &args(&["mylist", "2"])),
+            RespValue::array(vec![RespValue::bulk_string("d"), RespValue::bulk_string("c")])
+        );
+        assert_eq!(ExistsCommand.execute(&mut ctx, &args(&["mylist"])), RespValue::integer(1));
+        assert_eq!(
+            LPopCommand.execute(&mut ctx, &args(&["mylist", "5"])),
+            RespValue::array(vec![RespValue::bulk_string("b")])
+        );
+        assert_eq!(ExistsCommand.execute(&mut ctx, &args(&["mylist"])), RespValue::integer(0));
+
+        assert_eq!(LPopCommand.execute(&mut ctx, &args(&["mylist"])), RespValue::null());
+        assert_eq!(
+            RPopCommand.execute(&mut ctx, &args(&["mylist", "-1"])),
+            RespValue::error("ERR value is out of range, must be positive")
+        );
+    }
+
+    #[test]
+    fn test_pop_logs_count() {
+        use crate::aof::{replay_entries, AofOperation, AofReader, AofWriter, SyncPolicy};
+        use std::sync::Arc;
+
+        let path = std::env::temp_dir().join(format!("ferrumdb_pop_{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let writer = Arc::new(AofWriter::new(&path, SyncPolicy::No).unwrap());
+        let populated = || {
+            let mut ctx = CommandContext::new();
+            RPushCommand.execute(&mut ctx, &args(&["mylist", "a", "b", "c", "d", "e"]));
+            RPushCommand.execute(&mut ctx, &args(&["short", "x"]));
+            ctx
+        };
+
+        let mut ctx = populated();
+        ctx.set_aof_writer(writer.clone());
+        LPopCommand.execute(&mut ctx, &args(&["mylist"]));
+        RPopCommand.execute(&mut ctx, &args(&["mylist", "2"]));
+        LPopCommand.execute(&mut ctx, &args(&["nope"]));
+        RPopCommand.execute(&mut ctx, &args(&["short", "3"]));
+        writer.flush().unwrap();
+
+        // The number of elements popped, never the rest of the list
+        let entries = AofReader::load(&path).unwrap().parse_entries();
+        let pops: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.op, entry.payload.clone()))
+            .collect();
+        assert_eq!(
+            pops,
+            vec![
+                (AofOperation::LPop, vec![Bytes::from("1")]),
+                (AofOperation::RPop, vec![Bytes::from("2")]),
+                (AofOperation::RPop, vec![Bytes::from("1")]),
+            ]
+        );
+
+        let mut replayed = populated();
+        replay_entries(&mut replayed.store, entries).unwrap();
+        assert_eq!(
+            LRangeCommand.execute(&mut replayed, &args(&["mylist", "0", "-1"])),
+            RespValue::array(vec![RespValue::bulk_string("b"), RespValue::bulk_string("c")])
+        );
+        assert!(!replayed.store.exists(&Bytes::from("short")));
+
+        drop(ctx);
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn test_lrange() {
         let mut ctx = CommandContext::new();
@@ -349,4 +615,66 @@ mod tests {
         ]);
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_lrange_out_of_bounds() {
+        let mut ctx = CommandContext::new();
+        RPushCommand.execute(&mut ctx, &args(&["mylist", "a", "b", "c"]));
+        let lrange = |ctx: &mut CommandContext, start: &str, stop: &str| {
+            let args = vec![RespValue::bulk_string("mylist"), RespValue::bulk_string(start.to_string()), RespValue::bulk_string(stop.to_string())];
+            LRangeCommand.execute(ctx, &args)
+        };
+        let elements = |names: &[&str]| RespValue::array(names.iter().map(|n| RespValue::bulk_string(n.to_string())).collect());
+
+        // A stop before the first element is an empty range, not the whole list
+        assert_eq!(lrange(&mut ctx, "0", "-4"), elements(&[]));
+        assert_eq!(lrange(&mut ctx, "0", "-100"), elements(&[]));
+        assert_eq!(lrange(&mut ctx, "-100", "-9223372036854775808"), elements(&[]));
+
+        // Out of bounds ends are clamped
+        assert_eq!(lrange(&mut ctx, "-100", "100"), elements(&["a", "b", "c"]));
+        assert_eq!(lrange(&mut ctx, "-9223372036854775808", "0"), elements(&["a"]));
+        assert_eq!(lrange(&mut ctx, "2", "9223372036854775807"), elements(&["c"]));
+        assert_eq!(lrange(&mut ctx, "3", "10"), elements(&[]));
+
+        // Both ends before the head, or both past the tail
+        assert_eq!(lrange(&mut ctx, "-100", "-50"), elements(&[]));
+        assert_eq!(lrange(&mut ctx, "50", "100"), elements(&[]));
+        assert_eq!(lrange(&mut ctx, "100", "-100"), elements(&[]));
+
+        // One end out of bounds each way
+        assert_eq!(lrange(&mut ctx, "-100", "1"), elements(&["a", "b"]));
+        assert_eq!(lrange(&mut ctx, "1", "100"), elements(&["b", "c"]));
+        assert_eq!(lrange(&mut ctx, "-100", "-3"), elements(&["a"]));
+        assert_eq!(lrange(&mut ctx, "-1", "100"), elements(&["c"]));
+    }
+
+    #[test]
+    fn test_integer_and_string_elements_round_trip() {
+        let mut ctx = CommandContext::new();
+        let elements = ["1", "-42", "007", "job", "9223372036854775807", "+5", ""];
+        let mut push = vec!["mixed"];
+        push.extend(elements);
+        RPushCommand.execute(&mut ctx, &args(&push));
+
+        let stored = ctx.store.get(&Bytes::from("mixed")).and_then(|v| v.as_list()).unwrap();
+        let kinds: Vec<bool> = stored.iter().map(|item| matches!(item, ListItem::Int(_))).collect();
+        assert_eq!(kinds, [true, true, false, false, true, false, false]);
+
+        let expected = RespValue::array(elements.iter().map(|e| RespValue::bulk_string(e.to_string())).collect());
+        assert_eq!(LRangeCommand.execute(&mut ctx, &args(&["mixed", "0", "-1"])), expected);
+        assert_eq!(LPopCommand.execute(&mut ctx, &args(&["mixed"])), RespValue::bulk_string("1"));
+        assert_eq!(RPopCommand.execute(&mut ctx, &args(&["mixed", "2"])), RespValue::array(vec![
+            RespValue::bulk_string(""),
+            RespValue::bulk_string("+5"),
+        ]));
+    }
+
+    #[test]
+    fn test_integer_lists_use_less_memory() {
+        let ids: Vec<Bytes> = (0..1000).map(|i| Bytes::from((1_000_000 + i).to_string())).collect();
+        let compact = Value::list(ids.iter().cloned());
+        let as_bytes = Value::List(ids.into_iter().map(ListItem::Bytes).collect());
+        assert!(compact.memory_usage() < as_bytes.memory_usage());
+    }
 }
@@ -0,0 +1,391 @@
+//! Key serialization and migration commands (DUMP, RESTORE, MIGRATE)
+
+use super::{Command, CommandContext, extract_bulk_string, extract_integer, log_key_to_aof, log_to_aof};
+use crate::aof::AofOperation;
+use crate::protocol::{RespEncoder, RespParser, RespValue};
+use crate::store::{dump_value, restore_value};
+use bytes::{Bytes, BytesMut};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Timeout used by MIGRATE when the one given isn't positive, like Redis
+const DEFAULT_MIGRATE_TIMEOUT_MS: u64 = 1000;
+
+/// DUMP command - Serialize the value of a key
+///
+/// Syntax: DUMP key [WITHTTL]
+///
+/// Returns the serialized value (see `store::dump_value`), or nil if the
+/// key doesn't exist. The TTL is not part of the payload: WITHTTL (not in
+/// Redis) replies with the payload and the remaining TTL in milliseconds
+/// (-1 without one) instead, read together so the cluster can copy a key
+/// across shards with a single read.
+pub struct DumpCommand;
+
+impl Command for DumpCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.is_empty() || args.len() > 2 {
+            return RespValue::error("ERR wrong number of arguments for 'DUMP' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let with_ttl = match args.get(1).map(extract_bulk_string) {
+            None => false,
+            Some(Ok(opt)) if opt.eq_ignore_ascii_case(b"WITHTTL") => true,
+            Some(_) => return RespValue::error("ERR syntax error"),
+        };
+
+        let payload = match ctx.store.lookup_read(key) {
+            Some(value) => RespValue::bulk_string(dump_value(value)),
+            None => return RespValue::null(),
+        };
+        if with_ttl {
+            RespValue::array(vec![payload, RespValue::integer(ctx.store.pttl(key))])
+        } else {
+            payload
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "DUMP"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (1, 1, 1)
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+/// RESTORE command - Create a key from a DUMP payload
+///
+/// Syntax: RESTORE key ttl serialized-value [REPLACE]
+///
+/// `ttl` is in milliseconds, 0 for no expiration. An existing key is only
+/// overwritten with REPLACE.
+pub struct RestoreCommand;
+
+impl Command for RestoreCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() < 3 {
+            return RespValue::error("ERR wrong number of arguments for 'RESTORE' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let ttl_ms = match extract_integer(&args[1]) {
+            Ok(ms) if ms < 0 => return RespValue::error("ERR Invalid TTL value, must be >= 0"),
+            Ok(ms) => ms as u64,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let payload = match extract_bulk_string(&args[2]) {
+            Ok(p) => p,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let mut replace = false;
+        for arg in &args[3..] {
+            match extract_bulk_string(arg) {
+                Ok(opt) if opt.eq_ignore_ascii_case(b"REPLACE") => replace = true,
+                _ => return RespValue::error("ERR syntax error"),
+            }
+        }
+
+        if !replace && ctx.store.exists(&key) {
+            return RespValue::error("BUSYKEY Target key name already exists.");
+        }
+
+        let value = match restore_value(payload) {
+            Ok(v) => v,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        ctx.store.set(key.clone(), value);
+        if ttl_ms > 0 {
+            if let Some(expire_at) = Instant::now().checked_add(Duration::from_millis(ttl_ms)) {
+                ctx.store.set_expire_at(&key, expire_at);
+            }
+        }
+
+        // Replayed as a fresh key: whatever was there is deleted first
+        log_to_aof(ctx, AofOperation::Del, key.clone(), vec![]);
+        log_key_to_aof(ctx, &key);
+
+        RespValue::simple_string("OK")
+    }
+
+    fn name(&self) -> &'static str {
+        "RESTORE"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (1, 1, 1)
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+}
+
+/// Options of a MIGRATE command
+struct MigrateOptions {
+    addr: String,
+    key: Bytes,
+    timeout: Duration,
+    copy: bool,
+    replace: bool,
+}
+
+/// Parse `host port key destination-db timeout [COPY] [REPLACE]`
+fn parse_migrate(args: &[RespValue]) -> Result<MigrateOptions, RespValue> {
+    fn bulk(arg: &RespValue) -> Result<&Bytes, RespValue> {
+        extract_bulk_string(arg).map_err(|e| RespValue::error(format!("ERR {}", e)))
+    }
+    let integer = |arg: &RespValue| extract_integer(arg).map_err(|e| RespValue::error(format!("ERR {}", e)));
+
+    let host = bulk(&args[0])?;
+    let port = integer(&args[1])?;
+    let key = bulk(&args[2])?.clone();
+
+    // A FerrumDB server has a single database
+    if integer(&args[3])? != 0 {
+        return Err(RespValue::error("ERR DB index is out of range"));
+    }
+
+    let timeout = match integer(&args[4])? {
+        ms if ms > 0 => Duration::from_millis(ms as u64),
+        _ => Duration::from_millis(DEFAULT_MIGRATE_TIMEOUT_MS),
+    };
+
+    let mut copy = false;
+    let mut replace = false;
+    for arg in &args[5..] {
+        match bulk(arg)?.to_ascii_uppercase().as_slice() {
+            b"COPY" => copy = true,
+            b"REPLACE" => replace = true,
+            _ => return Err(RespValue::error("ERR syntax error")),
+        }
+    }
+
+    Ok(MigrateOptions {
+        addr: format!("{}:{}", String::from_utf8_lossy(host), port),
+        key,
+        timeout,
+        copy,
+        replace,
+    })
+}
+
+/// Open a connection to the target instance
+fn connect(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no address to connect to");
+    for socket_addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Send a command on a blocking connection and wait for its reply
+fn send_command(stream: &mut TcpStream, command: &RespValue) -> io::Result<RespValue> {
+    stream.write_all(&RespEncoder::encode(command))?;
+
+    let mut buf = BytesMut::with_capacity(128);
+    let mut chunk = [0u8; 512];
+    loop {
+        match RespParser::parse(&mut buf) {
+            Ok(Some(reply)) => return Ok(reply),
+            Ok(None) => {}
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+        }
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// MIGRATE command - Move a key to another FerrumDB instance
+///
+/// Syntax: MIGRATE host port key destination-db timeout [COPY] [REPLACE]
+///
+/// DUMPs the key, RESTOREs it on the target with its remaining TTL and,
+/// unless COPY is given, deletes it locally once the target acknowledged.
+/// With REPLACE an existing key on the target is overwritten, otherwise the
+/// target's BUSYKEY error is returned and the local key is kept.
+///
+/// The connection is blocking: the shard waits for the target, for at
+/// most `timeout` milliseconds per operation. Only database 0 exists.
+pub struct MigrateCommand;
+
+impl Command for MigrateCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() < 5 {
+            return RespValue::error("ERR wrong number of arguments for 'MIGRATE' command");
+        }
+
+        let options = match parse_migrate(args) {
+            Ok(o) => o,
+            Err(e) => return e,
+        };
+
+        let (payload, ttl_ms) = match ctx.store.get_entry(&options.key) {
+            Some(entry) if !entry.is_expired() => {
+                let ttl = entry
+                    .expire_at
+                    .map(|at| at.saturating_duration_since(Instant::now()).as_millis().max(1) as i64)
+                    .unwrap_or(0);
+                (dump_value(&entry.value), ttl)
+            }
+            _ => return RespValue::simple_string("NOKEY"),
+        };
+
+        let mut restore = vec![
+            RespValue::bulk_string("RESTORE"),
+            RespValue::bulk_string(options.key.clone()),
+            RespValue::bulk_string(ttl_ms.to_string()),
+            RespValue::bulk_string(payload),
+        ];
+        if options.replace {
+            restore.push(RespValue::bulk_string("REPLACE"));
+        }
+
+        let mut stream = match connect(&options.addr, options.timeout) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("MIGRATE: failed to connect to {}: {}", options.addr, e);
+                return RespValue::error("IOERR error or timeout connecting to the client");
+            }
+        };
+
+        match send_command(&mut stream, &RespValue::array(restore)) {
+            Ok(RespValue::Error(e)) => {
+                return RespValue::error(format!("ERR Target instance replied with error: {}", e));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("MIGRATE: no reply from {}: {}", options.addr, e);
+                return RespValue::error("IOERR error or timeout reading to target instance");
+            }
+        }
+
+        if !options.copy && ctx.store.delete(&options.key) {
+            log_to_aof(ctx, AofOperation::Del, options.key, vec![]);
+        }
+
+        RespValue::simple_string("OK")
+    }
+
+    fn name(&self) -> &'static str {
+        "MIGRATE"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (3, 3, 1)
+    }
+
+    fn min_args(&self) -> usize {
+        5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Value;
+
+    fn args(parts: &[&[u8]]) -> Vec<RespValue> {
+        parts.iter().map(|p| RespValue::bulk_string(Bytes::copy_from_slice(p))).collect()
+    }
+
+    #[test]
+    fn test_dump_and_restore() {
+        let mut ctx = CommandContext::new();
+        ctx.store.set("src", Value::list([Bytes::from("a"), Bytes::from("b")]));
+
+        let payload = match DumpCommand.execute(&mut ctx, &args(&[b"src"])) {
+            RespValue::BulkString(p) => p,
+            other => panic!("unexpected DUMP reply: {:?}", other),
+        };
+        assert_eq!(DumpCommand.execute(&mut ctx, &args(&[b"missing"])), RespValue::null());
+        assert_eq!(DumpCommand.execute(&mut ctx, &args(&[b"missing", b"WITHTTL"])), RespValue::null());
+        assert_eq!(
+            DumpCommand.execute(&mut ctx, &args(&[b"src", b"withttl"])),
+            RespValue::array(vec![RespValue::bulk_string(payload.clone()), RespValue::integer(-1)])
+        );
+        assert_eq!(DumpCommand.execute(&mut ctx, &args(&[b"src", b"TTL"])), RespValue::error("ERR syntax error"));
+
+        assert_eq!(RestoreCommand.execute(&mut ctx, &args(&[b"dst", b"0", &payload])), RespValue::simple_string("OK"));
+        let src = ctx.store.get(&Bytes::from("src")).cloned();
+        assert_eq!(ctx.store.get(&Bytes::from("dst")).cloned(), src);
+        assert_eq!(ctx.store.ttl(&Bytes::from("dst")), -1);
+
+        // Existing keys are only overwritten with REPLACE
+        assert_eq!(
+            RestoreCommand.execute(&mut ctx, &args(&[b"dst", b"0", &payload])),
+            RespValue::error("BUSYKEY Target key name already exists.")
+        );
+        assert_eq!(
+            RestoreCommand.execute(&mut ctx, &args(&[b"dst", b"5000", &payload, b"replace"])),
+            RespValue::simple_string("OK")
+        );
+        assert!(ctx.store.ttl(&Bytes::from("dst")) > 0);
+
+        assert_eq!(
+            RestoreCommand.execute(&mut ctx, &args(&[b"new", b"-1", &payload])),
+            RespValue::error("ERR Invalid TTL value, must be >= 0")
+        );
+        assert_eq!(
+            RestoreCommand.execute(&mut ctx, &args(&[b"new", b"0", b"bogus"])),
+            RespValue::error("ERR DUMP payload version or checksum are wrong")
+        );
+    }
+
+    #[test]
+    fn test_migrate_missing_key_and_bad_db() {
+        let mut ctx = CommandContext::new();
+        assert_eq!(
+            MigrateCommand.execute(&mut ctx, &args(&[b"127.0.0.1", b"1", b"missing", b"0", b"100"])),
+            RespValue::simple_string("NOKEY")
+        );
+        assert_eq!(
+            MigrateCommand.execute(&mut ctx, &args(&[b"127.0.0.1", b"1", b"k", b"1", b"100"])),
+            RespValue::error("ERR DB index is out of range")
+        );
+    }
+}
@@ -14,13 +14,33 @@ mod counter;
 mod list;
 mod set;
 mod hash;
+mod zset;
+mod geo;
+mod stream;
+mod json;
+mod bitmap;
 mod admin;
+mod debug;
 mod search;
+mod migrate;
+mod idempotent;
 
-pub use context::CommandContext;
-pub use registry::CommandRegistry;
+pub use context::{ClientOptions, CommandContext};
+pub use idempotent::{IdempotencyCache, DEFAULT_IDEMPOTENCY_CAPACITY, DEFAULT_IDEMPOTENCY_WINDOW};
+pub(crate) use idempotent::{command_flags, is_cached_retry};
+pub use registry::{CommandInfo, CommandRegistry};
+pub use search::{disable_keys_command, keys_scan_limit, set_disable_keys_command, set_keys_scan_limit};
+pub(crate) use bitmap::{bitop, BitOp};
+pub(crate) use set::SetOp;
+pub(crate) use key::parse_copy_options;
+pub(crate) use debug::{async_sleep_duration, debug_subcommand_key};
+pub(crate) use search::{keys_scan_aborted, matches_pattern, parse_cursor, DEFAULT_SCAN_COUNT};
+pub(crate) use admin::{authenticate, hello_reply, parse_hello};
 
 use crate::protocol::RespValue;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tracing::{debug, field, info_span, warn};
 
 /// Command execution trait
 ///
@@ -49,6 +69,19 @@ pub trait Command: Send + Sync {
     fn max_args(&self) -> Option<usize> {
         None
     }
+
+    /// Get the command flags reported by COMMAND INFO (e.g. "write", "readonly")
+    fn flags(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Get the key positions as (first key, last key, step), 1-based like Redis
+    ///
+    /// A negative last key counts from the end (-1 = last argument), and
+    /// (0, 0, 0) means the command takes no key.
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (1, 1, 1)
+    }
 }
 
 /// Helper function to extract bulk string from RespValue
@@ -56,15 +89,264 @@ pub(crate) fn extract_bulk_string(value: &RespValue) -> Result<&bytes::Bytes, &'
     value.as_bulk_string().ok_or("Expected bulk string")
 }
 
+/// Error text used by Redis for any integer argument that can't be parsed,
+/// whether it is non-numeric or outside the i64 range
+pub(crate) const NOT_AN_INTEGER: &str = "value is not an integer or out of range";
+
+/// Error text used by Redis for any float argument that can't be parsed
+pub(crate) const NOT_A_FLOAT: &str = "value is not a valid float";
+
+/// Error returned by any command run against a key of another type,
+/// byte for byte the one Redis sends
+pub(crate) const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// Error returned under `maxmemory-policy noeviction` to commands that may
+/// grow memory while the limit is reached
+pub(crate) const OOM_ERROR: &str = "OOM command not allowed when used memory > 'maxmemory'.";
+
+/// strict-type-errors: log type mismatches as warnings instead of debug
+static STRICT_TYPE_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable strict-type-errors (process-wide, off by default)
+///
+/// The reply is WRONGTYPE either way; strict mode only makes every
+/// mismatch visible in production logs, with its command, key and type.
+pub fn set_strict_type_errors(enabled: bool) {
+    STRICT_TYPE_ERRORS.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether strict-type-errors is enabled
+pub fn strict_type_errors() -> bool {
+    STRICT_TYPE_ERRORS.load(Ordering::Relaxed)
+}
+
+/// Build the WRONGTYPE error, logging the command, key and type found
+pub(crate) fn wrong_type(command: &str, key: &[u8], found: &'static str) -> RespValue {
+    let key = String::from_utf8_lossy(key);
+    if strict_type_errors() {
+        warn!("{} against key {:?} holding a {}: WRONGTYPE", command, key, found);
+    } else {
+        debug!("{} against key {:?} holding a {}: WRONGTYPE", command, key, found);
+    }
+    RespValue::error(WRONGTYPE)
+}
+
+/// Parse an integer the way Redis does (`string2ll`)
+///
+/// Only the canonical form is accepted: "-7" or "0", but not "+7", "007",
+/// " 7" or "-0". Non-numeric and out-of-range values (e.g.
+/// `9223372036854775808`) both report `NOT_AN_INTEGER`.
+pub(crate) fn parse_redis_int(bytes: &[u8]) -> Result<i64, &'static str> {
+    crate::store::parse_canonical_int(bytes).ok_or(NOT_AN_INTEGER)
+}
+
+/// Parse a float the way Redis does
+///
+/// Accepts "inf", "+inf" and "-inf" (any case) and exponents, rejects NaN,
+/// surrounding spaces, trailing garbage and values overflowing to infinity
+/// (e.g. `1e400`) with `NOT_A_FLOAT`.
+pub(crate) fn parse_redis_float(bytes: &[u8]) -> Result<f64, &'static str> {
+    // Rust's parser already accepts "inf"/"infinity" in any case, like strtod
+    let s = std::str::from_utf8(bytes).map_err(|_| NOT_A_FLOAT)?;
+    let value = s.parse::<f64>().map_err(|_| NOT_A_FLOAT)?;
+    if value.is_nan() {
+        return Err(NOT_A_FLOAT);
+    }
+
+    // Only an explicit infinity may parse as one, not an overflow
+    let is_inf_literal = s
+        .trim_start_matches(['+', '-'])
+        .get(..3)
+        .is_some_and(|p| p.eq_ignore_ascii_case("inf"));
+    if value.is_infinite() && !is_inf_literal {
+        return Err(NOT_A_FLOAT);
+    }
+    Ok(value)
+}
+
 /// Helper function to extract integer from RespValue or parse from bulk string
+///
+/// Bulk strings go through `parse_redis_int`.
 pub(crate) fn extract_integer(value: &RespValue) -> Result<i64, &'static str> {
     match value {
         RespValue::Integer(i) => Ok(*i),
-        RespValue::BulkString(bytes) => {
-            let s = std::str::from_utf8(bytes).map_err(|_| "Invalid UTF-8")?;
-            s.parse::<i64>().map_err(|_| "Invalid integer")
+        RespValue::BulkString(bytes) => parse_redis_int(bytes),
+        _ => Err(NOT_AN_INTEGER),
+    }
+}
+
+/// Largest string a command may build (proto-max-bulk-len, 512MB like Redis)
+pub(crate) const MAX_STRING_SIZE: usize = 512 * 1024 * 1024;
+
+/// Check the length of a string a command is about to build
+///
+/// Called before allocating, so APPEND, SETRANGE or SETBIT can't grow a
+/// single value past `MAX_STRING_SIZE` and exhaust the shard's memory.
+pub(crate) fn check_string_length(len: usize) -> Result<(), &'static str> {
+    if len > MAX_STRING_SIZE {
+        return Err("string exceeds maximum allowed size (proto-max-bulk-len)");
+    }
+    Ok(())
+}
+
+/// Normalize a Redis `start`/`stop` range over `len` elements
+///
+/// Negative indexes count from the end (-1 is the last element), a start
+/// before the first element is clamped to it and a stop past the last one
+/// to it. Returns the inclusive index range, or None when it is empty: the
+/// start lands after the stop or past the end, or the stop before the
+/// first element (so `0 -100` over 5 elements is empty, not the first one).
+/// Shared by LRANGE and GETRANGE.
+pub(crate) fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = i64::try_from(len).unwrap_or(i64::MAX);
+    let resolve = |index: i64| if index < 0 { index.saturating_add(len) } else { index };
+    let start = resolve(start).max(0);
+    let stop = resolve(stop).min(len - 1);
+    if stop < 0 || start > stop {
+        return None;
+    }
+    Some((start as usize, stop as usize))
+}
+
+/// Maximum length of the command name and of the argument preview in the
+/// unknown command error, like Redis
+const UNKNOWN_COMMAND_PREVIEW: usize = 128;
+
+/// Build Redis' unknown command error, with a preview of the arguments
+///
+/// `ERR unknown command 'FOO', with args beginning with: 'bar' 'baz' `.
+/// Arguments are added until the preview reaches 128 bytes, the last one
+/// being cut to fit. Line breaks and other control bytes are replaced by
+/// spaces, so the error stays on a single line of the protocol.
+pub(crate) fn unknown_command(name: &[u8], args: &[RespValue]) -> RespValue {
+    let mut preview: Vec<u8> = Vec::new();
+    for arg in args {
+        if preview.len() >= UNKNOWN_COMMAND_PREVIEW {
+            break;
+        }
+        let bytes = match arg.as_bulk_string() {
+            Some(bytes) => bytes.to_vec(),
+            None => arg.to_string().into_bytes(),
+        };
+        let room = UNKNOWN_COMMAND_PREVIEW - preview.len();
+        preview.push(b'\'');
+        preview.extend_from_slice(&bytes[..bytes.len().min(room)]);
+        preview.extend_from_slice(b"' ");
+    }
+
+    let printable = |bytes: &[u8]| -> String {
+        let bytes: Vec<u8> = bytes.iter().map(|&b| if b.is_ascii_control() { b' ' } else { b }).collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    RespValue::error(format!(
+        "ERR unknown command '{}', with args beginning with: {}",
+        printable(&name[..name.len().min(UNKNOWN_COMMAND_PREVIEW)]),
+        printable(&preview)
+    ))
+}
+
+/// Execute a command within a `command` span
+///
+/// Called by the dispatch paths (dispatcher and shards) with the full
+/// command, name included. The span carries the `command` name, its first
+/// `key` (from the key spec) and, once it has run, its `duration_us`, for
+/// `tracing` layers to build per-command latency histograms. Nothing is
+/// measured when no subscriber is interested in the span.
+pub(crate) fn execute_traced(ctx: &mut CommandContext, command: &dyn Command, argv: &[RespValue]) -> RespValue {
+    let args = &argv[1..];
+    let span = info_span!("command", command = command.name(), key = field::Empty, duration_us = field::Empty);
+    if span.is_disabled() {
+        return command.execute(ctx, args);
+    }
+
+    let (first_key, _, _) = command.key_spec();
+    let key = usize::try_from(first_key).ok().filter(|&i| i > 0).and_then(|i| argv.get(i)?.as_bulk_string());
+    if let Some(key) = key {
+        span.record("key", String::from_utf8_lossy(key).as_ref());
+    }
+
+    let started = Instant::now();
+    let reply = span.in_scope(|| command.execute(ctx, args));
+    span.record("duration_us", started.elapsed().as_micros() as u64);
+    reply
+}
+
+/// Commands that may hold the thread running them for long: sleeping or
+/// waiting (DEBUG), a network round trip (MIGRATE), or writing the whole
+/// AOF (BGREWRITEAOF, FLUSHALL, FLUSHDB)
+const BLOCKING_COMMANDS: &[&str] = &["DEBUG", "MIGRATE", "BGREWRITEAOF", "FLUSHALL", "FLUSHDB"];
+
+/// Whether a command may block the thread running it for long
+///
+/// The dispatch paths running commands on the server's runtime (the
+/// striped stores, the shards of the shared runtime) move these ones off
+/// its worker threads.
+pub(crate) fn may_block(command: &RespValue) -> bool {
+    let name = command.as_array().and_then(|parts| parts.first()).and_then(RespValue::as_bulk_string);
+    name.is_some_and(|name| BLOCKING_COMMANDS.iter().any(|blocking| name.eq_ignore_ascii_case(blocking.as_bytes())))
+}
+
+/// Record a command in the audit log if it is flagged `write` and didn't
+/// reply with an error
+///
+/// Called by the dispatch paths (dispatcher and shards) once the command
+/// has run; the keys are found through the command's key spec.
+pub(crate) fn log_to_audit(ctx: &CommandContext, command: &dyn Command, argv: &[RespValue], reply: &RespValue) {
+    let Some(ref logger) = ctx.audit_logger else {
+        return;
+    };
+    if !command.flags().contains(&"write") || matches!(reply, RespValue::Error(_)) {
+        return;
+    }
+
+    let keys = ctx.registry.get_keys(argv).unwrap_or_default();
+    if let Err(e) = logger.log(ctx.client.addr, command.name(), &keys) {
+        warn!("Failed to write to the audit log: {}", e);
+    }
+}
+
+/// Make room for a command flagged `denyoom` when it would take memory
+/// over `maxmemory`, or refuse it
+///
+/// Called by the dispatch paths before the command runs. The projected
+/// growth is the size of its arguments; commands that don't grow memory
+/// (reads, deletions) are always allowed. Under an eviction policy, keys
+/// of the command's store are evicted (and deleted from its AOF) until the
+/// growth fits; the command is refused if it still doesn't once the store
+/// is empty.
+pub(crate) fn check_maxmemory(ctx: &mut CommandContext, command: &dyn Command, args: &[RespValue]) -> Option<RespValue> {
+    if crate::store::maxmemory() == 0 || !command.flags().contains(&"denyoom") {
+        return None;
+    }
+
+    // Bring this store's share of the total up to date
+    ctx.store.memory_usage();
+    let extra: usize = args.iter().filter_map(|arg| arg.as_bulk_string()).map(|arg| arg.len()).sum();
+    let policy = crate::store::maxmemory_policy();
+    while crate::store::exceeds_maxmemory(extra) {
+        let Some(key) = ctx.store.evict_one(policy) else {
+            return Some(RespValue::error(OOM_ERROR));
+        };
+        log_to_aof(ctx, crate::aof::AofOperation::Del, key, vec![]);
+    }
+    None
+}
+
+/// Log the whole current state of a key to the AOF (value, then TTL)
+///
+/// For commands writing a value and its expiration at once (SET with
+/// options, RESTORE), as the AOF rewrite would.
+pub(crate) fn log_key_to_aof(ctx: &CommandContext, key: &bytes::Bytes) {
+    let (Some(aof_writer), Some(entry)) = (&ctx.aof_writer, ctx.store.get_entry(key)) else {
+        return;
+    };
+
+    let mut entries = Vec::new();
+    crate::aof::push_key_entries(&mut entries, entry);
+    for entry in &entries {
+        if let Err(e) = aof_writer.write(entry) {
+            warn!("Failed to write to AOF: {}", e);
         }
-        _ => Err("Expected integer or bulk string"),
     }
 }
 
@@ -84,3 +366,59 @@ pub(crate) fn log_to_aof(
         }
     }
 }
+
+/// Build a command from its parts, for the tests
+#[cfg(test)]
+pub(crate) fn command(parts: &[&str]) -> RespValue {
+    RespValue::array(args(parts))
+}
+
+/// Build the arguments of a command from their parts, for the tests
+#[cfg(test)]
+pub(crate) fn args(parts: &[&str]) -> Vec<RespValue> {
+    parts.iter().map(|p| RespValue::bulk_string(p.to_string())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_range() {
+        // Whole range, and negative indexes from the end
+        assert_eq!(normalize_range(0, -1, 5), Some((0, 4)));
+        assert_eq!(normalize_range(-2, -1, 5), Some((3, 4)));
+        assert_eq!(normalize_range(1, 3, 5), Some((1, 3)));
+
+        // Out of bounds ends are clamped
+        assert_eq!(normalize_range(-100, 100, 5), Some((0, 4)));
+        assert_eq!(normalize_range(3, i64::MAX, 5), Some((3, 4)));
+        assert_eq!(normalize_range(i64::MIN, 0, 5), Some((0, 0)));
+
+        // A stop far before the start doesn't wrap around
+        assert_eq!(normalize_range(0, -6, 5), None);
+        assert_eq!(normalize_range(0, -100, 5), None);
+        assert_eq!(normalize_range(-100, i64::MIN, 5), None);
+
+        // Empty ranges
+        assert_eq!(normalize_range(5, 10, 5), None);
+        assert_eq!(normalize_range(3, 1, 5), None);
+        assert_eq!(normalize_range(-1, -2, 5), None);
+        assert_eq!(normalize_range(0, -1, 0), None);
+        assert_eq!(normalize_range(0, 0, 0), None);
+    }
+
+    #[test]
+    fn test_parse_redis_int() {
+        for (input, expected) in [("0", 0), ("-7", -7), ("12345", 12345), ("9223372036854775807", i64::MAX)] {
+            assert_eq!(parse_redis_int(input.as_bytes()), Ok(expected), "{:?}", input);
+        }
+        assert_eq!(parse_redis_int(b"-9223372036854775808"), Ok(i64::MIN));
+
+        // Only the canonical form, within range
+        for input in ["", "-", "+7", "007", "-0", " 7", "7 ", "1x", "9223372036854775808", "-9223372036854775809"] {
+            assert_eq!(parse_redis_int(input.as_bytes()), Err(NOT_AN_INTEGER), "{:?}", input);
+        }
+        assert_eq!(parse_redis_int(b"\xff1"), Err(NOT_AN_INTEGER));
+    }
+}
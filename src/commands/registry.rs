@@ -3,13 +3,45 @@
 //! Centralized registry for all available commands.
 //! This allows loose coupling between command implementations and the dispatcher.
 
-use super::{Command, string, key, ttl, counter, list, set, hash, admin, search};
+use super::{Command, string, key, ttl, counter, list, set, hash, zset, geo, stream, json, bitmap, admin, debug, search, migrate, idempotent};
+use crate::protocol::RespValue;
+use bytes::Bytes;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+/// Metadata about a registered command, as reported by COMMAND INFO
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandInfo {
+    /// Lowercase command name
+    pub name: String,
+
+    /// Redis-style arity: the number of arguments including the command
+    /// name, negative when it is a minimum
+    pub arity: i64,
+
+    /// Command flags
+    pub flags: &'static [&'static str],
+
+    /// Position of the first key (0 when the command takes no key)
+    pub first_key: i64,
+
+    /// Position of the last key (negative counts from the end)
+    pub last_key: i64,
+
+    /// Step between keys
+    pub step: i64,
+}
+
 /// Registry of all available commands
+///
+/// A command can also be registered under other names (aliases), which
+/// resolve to the same implementation: the deprecated names still sent by
+/// old clients, like SUBSTR for GETRANGE.
 pub struct CommandRegistry {
     commands: HashMap<String, Arc<dyn Command>>,
+
+    /// Uppercase alias → uppercase name of the command it resolves to
+    aliases: HashMap<String, String>,
 }
 
 impl CommandRegistry {
@@ -17,19 +49,36 @@ impl CommandRegistry {
     pub fn new() -> Self {
         let mut registry = CommandRegistry {
             commands: HashMap::new(),
+            aliases: HashMap::new(),
         };
 
         // Register string commands
         registry.register(Arc::new(string::SetCommand));
         registry.register(Arc::new(string::GetCommand));
+        registry.register(Arc::new(string::MSetCommand));
+        registry.register(Arc::new(string::AppendCommand));
+        registry.register(Arc::new(string::SetRangeCommand));
+        registry.register(Arc::new(string::GetRangeCommand));
+        registry.register(Arc::new(string::SetNxCommand));
+        registry.register(Arc::new(string::CasCommand));
+        registry.register(Arc::new(string::LReleaseCommand));
+        registry.register(Arc::new(string::SetExCommand));
+        registry.register(Arc::new(string::PSetExCommand));
+        registry.register_alias("SUBSTR", "GETRANGE");
 
         // Register key commands
         registry.register(Arc::new(key::DelCommand));
         registry.register(Arc::new(key::ExistsCommand));
+        registry.register(Arc::new(key::ObjectCommand));
+        registry.register(Arc::new(key::CopyCommand));
+        registry.register(Arc::new(migrate::DumpCommand));
+        registry.register(Arc::new(migrate::RestoreCommand));
+        registry.register(Arc::new(migrate::MigrateCommand));
 
         // Register TTL commands
         registry.register(Arc::new(ttl::ExpireCommand));
         registry.register(Arc::new(ttl::TtlCommand));
+        registry.register(Arc::new(ttl::PttlCommand));
 
         // Register counter commands
         registry.register(Arc::new(counter::IncrCommand));
@@ -40,13 +89,21 @@ impl CommandRegistry {
         // Register list commands
         registry.register(Arc::new(list::LPushCommand));
         registry.register(Arc::new(list::RPushCommand));
+        registry.register(Arc::new(list::LPushIdxCommand));
+        registry.register(Arc::new(list::RPushIdxCommand));
+        registry.register(Arc::new(list::LPopCommand));
+        registry.register(Arc::new(list::RPopCommand));
         registry.register(Arc::new(list::LRangeCommand));
         registry.register(Arc::new(list::LLenCommand));
 
         // Register set commands
         registry.register(Arc::new(set::SAddCommand));
+        registry.register(Arc::new(set::SRemCommand));
         registry.register(Arc::new(set::SMembersCommand));
         registry.register(Arc::new(set::SCardCommand));
+        registry.register(Arc::new(set::SUnionStoreCommand));
+        registry.register(Arc::new(set::SInterStoreCommand));
+        registry.register(Arc::new(set::SDiffStoreCommand));
 
         // Register hash commands
         registry.register(Arc::new(hash::HSetCommand));
@@ -54,39 +111,192 @@ impl CommandRegistry {
         registry.register(Arc::new(hash::HGetAllCommand));
         registry.register(Arc::new(hash::HDelCommand));
         registry.register(Arc::new(hash::HKeysCommand));
+        registry.register(Arc::new(hash::HScanCommand));
         registry.register(Arc::new(hash::HIncrByCommand));
 
+        // Register sorted set commands
+        registry.register(Arc::new(zset::ZAddCommand));
+        registry.register(Arc::new(zset::ZCardCommand));
+        registry.register(Arc::new(zset::ZScoreCommand));
+        registry.register(Arc::new(zset::ZPopMinCommand));
+        registry.register(Arc::new(zset::ZPopMaxCommand));
+
+        // Register geospatial commands
+        registry.register(Arc::new(geo::GeoAddCommand));
+        registry.register(Arc::new(geo::GeoPosCommand));
+        registry.register(Arc::new(geo::GeoDistCommand));
+
+        // Register stream commands
+        registry.register(Arc::new(stream::XAddCommand));
+        registry.register(Arc::new(stream::XLenCommand));
+        registry.register(Arc::new(stream::XRangeCommand));
+
+        // Register JSON commands
+        registry.register(Arc::new(json::JsonSetCommand));
+        registry.register(Arc::new(json::JsonGetCommand));
+
+        // Register bitmap commands
+        registry.register(Arc::new(bitmap::SetBitCommand));
+        registry.register(Arc::new(bitmap::GetBitCommand));
+        registry.register(Arc::new(bitmap::BitOpCommand));
+        registry.register(Arc::new(bitmap::BitPosCommand));
+
         // Register admin commands
         registry.register(Arc::new(admin::InfoCommand));
         registry.register(Arc::new(admin::FlushDbCommand));
+        registry.register(Arc::new(admin::FlushAllCommand));
         registry.register(Arc::new(admin::ClientCommand));
+        registry.register(Arc::new(admin::HelloCommand));
+        registry.register(Arc::new(admin::CommandCommand));
+        registry.register(Arc::new(admin::MemoryCommand));
+        registry.register(Arc::new(admin::BgRewriteAofCommand));
+        registry.register(Arc::new(admin::TimeCommand));
+        registry.register(Arc::new(admin::RoleCommand));
+        registry.register(Arc::new(admin::ConfigCommand));
+
+        // Register debug commands
+        registry.register(Arc::new(debug::DebugCommand));
 
         // Register search commands
         registry.register(Arc::new(search::KeysCommand));
+        registry.register(Arc::new(search::ScanCommand));
+
+        // Register the idempotency wrapper
+        registry.register(Arc::new(idempotent::IdempotentCommand));
 
         registry
     }
 
     /// Register a command
-    fn register(&mut self, command: Arc<dyn Command>) {
+    pub(crate) fn register(&mut self, command: Arc<dyn Command>) {
         let name = command.name().to_uppercase();
         self.commands.insert(name, command);
     }
 
-    /// Get a command by name (case-insensitive)
+    /// Register another name for a registered command
+    pub(crate) fn register_alias(&mut self, alias: &str, name: &str) {
+        debug_assert!(self.commands.contains_key(&name.to_uppercase()), "alias of unknown command {}", name);
+        self.aliases.insert(alias.to_uppercase(), name.to_uppercase());
+    }
+
+    /// Get a command by name or alias (case-insensitive)
     pub fn get(&self, name: &str) -> Option<Arc<dyn Command>> {
-        self.commands.get(&name.to_uppercase()).cloned()
+        let name = name.to_uppercase();
+        let name = self.aliases.get(&name).unwrap_or(&name);
+        self.commands.get(name).cloned()
     }
 
-    /// Check if a command exists
+    /// Check if a command exists, by name or alias
     pub fn has_command(&self, name: &str) -> bool {
-        self.commands.contains_key(&name.to_uppercase())
+        self.get(name).is_some()
     }
 
-    /// Get all command names
+    /// Get all command names, aliases included
     pub fn command_names(&self) -> Vec<String> {
-        self.commands.keys().cloned().collect()
+        self.commands.keys().chain(self.aliases.keys()).cloned().collect()
+    }
+
+    /// Get the metadata of a command by name or alias (case-insensitive)
+    ///
+    /// An alias is reported under its own name, with the metadata of the
+    /// command it resolves to.
+    pub fn info(&self, name: &str) -> Option<CommandInfo> {
+        let command = self.get(name)?;
+        let name = if self.aliases.contains_key(&name.to_uppercase()) { name } else { command.name() };
+
+        let required = command.min_args() as i64 + 1;
+        let arity = match command.max_args() {
+            Some(max) if max == command.min_args() => required,
+            _ => -required,
+        };
+        let (first_key, last_key, step) = command.key_spec();
+
+        Some(CommandInfo {
+            name: name.to_lowercase(),
+            arity,
+            flags: command.flags(),
+            first_key,
+            last_key,
+            step,
+        })
+    }
+
+    /// Check that every registered command declares a consistent arity and
+    /// key spec, returning the violations found
+    ///
+    /// The maximum number of arguments must not be below the minimum, and
+    /// the keys must be arguments every valid call has: a command without
+    /// keys is (0, 0, 0), otherwise the first key is within the minimum
+    /// arity, the last key follows it (or counts from the end) and fits in
+    /// the maximum one, and the step is positive.
+    pub fn check_integrity(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.commands.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .filter_map(|name| check_command(&*self.commands[name]).err().map(|e| format!("{}: {}", name, e)))
+            .collect()
+    }
+
+    /// Get the keys of a full command (name included), from its key spec
+    ///
+    /// Used by COMMAND GETKEYS. Errors use Redis' wording, without the
+    /// `ERR` prefix.
+    pub fn get_keys(&self, argv: &[RespValue]) -> Result<Vec<Bytes>, &'static str> {
+        let command = argv
+            .first()
+            .and_then(|name| name.as_bulk_string())
+            .and_then(|name| std::str::from_utf8(name).ok())
+            .and_then(|name| self.get(name))
+            .ok_or("Invalid command specified")?;
+
+        let args = argv.len() - 1;
+        if args < command.min_args() || command.max_args().is_some_and(|max| args > max) {
+            return Err("Invalid number of arguments specified for command");
+        }
+
+        let (first_key, last_key, step) = command.key_spec();
+        if first_key <= 0 {
+            return Err("The command has no key arguments");
+        }
+
+        let argc = argv.len() as i64;
+        let last_key = if last_key < 0 { argc + last_key } else { last_key.min(argc - 1) };
+        (first_key..=last_key)
+            .step_by(step.max(1) as usize)
+            .map(|i| argv[i as usize].as_bulk_string().cloned().ok_or("Invalid arguments specified for command"))
+            .collect()
+    }
+}
+
+/// Check the arity and key spec of a command (see `check_integrity`)
+fn check_command(command: &dyn Command) -> Result<(), String> {
+    let (min, max) = (command.min_args() as i64, command.max_args().map(|max| max as i64));
+    if let Some(max) = max.filter(|&max| max < min) {
+        return Err(format!("max_args {} is below min_args {}", max, min));
+    }
+
+    let (first, last, step) = command.key_spec();
+    let spec = format!("key spec ({}, {}, {})", first, last, step);
+    if first == 0 {
+        if last != 0 || step != 0 {
+            return Err(format!("{} has keys after no first key", spec));
+        }
+        return Ok(());
+    }
+    if first < 0 || step <= 0 {
+        return Err(format!("{} needs a positive first key and step", spec));
+    }
+    if first > min {
+        return Err(format!("{} starts beyond min_args {}", spec, min));
+    }
+    if last > 0 && last < first {
+        return Err(format!("{} ends before its first key", spec));
+    }
+    if let Some(max) = max.filter(|&max| last > max) {
+        return Err(format!("{} ends beyond max_args {}", spec, max));
     }
+    Ok(())
 }
 
 impl Default for CommandRegistry {
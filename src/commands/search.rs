@@ -1,17 +1,60 @@
 //! Search commands (KEYS, SCAN)
 
-use super::{Command, CommandContext, extract_bulk_string};
+use super::{Command, CommandContext, extract_bulk_string, extract_integer};
 use crate::protocol::RespValue;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Default number of buckets visited per SCAN call
+pub(crate) const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// disable-keys-command: KEYS replies with an error pointing to SCAN
+static DISABLE_KEYS_COMMAND: AtomicBool = AtomicBool::new(false);
+
+/// keys-scan-limit: keys a KEYS call may go through (0 means no limit)
+static KEYS_SCAN_LIMIT: AtomicUsize = AtomicUsize::new(0);
+
+/// Enable or disable KEYS (process-wide, enabled by default)
+pub fn set_disable_keys_command(disabled: bool) {
+    DISABLE_KEYS_COMMAND.store(disabled, Ordering::Relaxed);
+}
+
+/// Whether KEYS is disabled
+pub fn disable_keys_command() -> bool {
+    DISABLE_KEYS_COMMAND.load(Ordering::Relaxed)
+}
+
+/// Set how many keys a KEYS call may go through before it is aborted
+/// (process-wide, 0 for no limit)
+pub fn set_keys_scan_limit(limit: usize) {
+    KEYS_SCAN_LIMIT.store(limit, Ordering::Relaxed);
+}
+
+/// Keys a KEYS call may go through (0 means no limit)
+pub fn keys_scan_limit() -> usize {
+    KEYS_SCAN_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Reply of a KEYS call aborted by `keys-scan-limit`
+pub(crate) fn keys_scan_aborted(limit: usize) -> RespValue {
+    RespValue::error(format!(
+        "ERR KEYS aborted after scanning {} keys (keys-scan-limit), partial result discarded, use SCAN instead",
+        limit
+    ))
+}
 
 /// KEYS command - Find all keys matching a pattern
 ///
 /// Syntax: KEYS pattern
 ///
-/// Supported patterns:
-/// - * : matches all keys
-/// - prefix* : matches keys starting with prefix
-/// - *suffix : matches keys ending with suffix
-/// - *pattern* : matches keys containing pattern
+/// The pattern is a glob (`*`, `?`, `[...]`, `\` escapes, see
+/// `matches_pattern`). Keys are binary-safe: neither they nor the pattern
+/// need to be valid UTF-8.
+///
+/// KEYS blocks the shard while it goes through every key: with
+/// `disable-keys-command yes` it replies with an error instead, and with a
+/// `keys-scan-limit` it is aborted once it has gone through that many keys,
+/// without returning the keys matched so far. SCAN is the way to go
+/// through a large keyspace.
 pub struct KeysCommand;
 
 impl Command for KeysCommand {
@@ -25,21 +68,21 @@ impl Command for KeysCommand {
             Err(e) => return RespValue::error(format!("ERR {}", e)),
         };
 
-        // Convert pattern to string
-        let pattern_str = match std::str::from_utf8(pattern) {
-            Ok(s) => s,
-            Err(_) => return RespValue::error("ERR invalid pattern encoding"),
-        };
-
-        // Get all keys from the store
-        let all_keys = ctx.store.keys();
+        if disable_keys_command() {
+            return RespValue::error("ERR KEYS is disabled by disable-keys-command, use SCAN instead");
+        }
 
-        // Filter keys based on pattern
-        let matching_keys: Vec<RespValue> = all_keys
-            .iter()
-            .filter(|key| matches_pattern(key, pattern_str))
-            .map(|key| RespValue::BulkString((*key).clone()))
-            .collect();
+        // Only the matching keys are cloned
+        let limit = keys_scan_limit();
+        let mut matching_keys = Vec::new();
+        for (scanned, key) in ctx.store.iter_keys().enumerate() {
+            if limit > 0 && scanned == limit {
+                return keys_scan_aborted(limit);
+            }
+            if matches_pattern(key, pattern) {
+                matching_keys.push(RespValue::BulkString(key.clone()));
+            }
+        }
 
         RespValue::Array(matching_keys)
     }
@@ -48,6 +91,14 @@ impl Command for KeysCommand {
         "KEYS"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
     fn min_args(&self) -> usize {
         1
     }
@@ -57,54 +108,204 @@ impl Command for KeysCommand {
     }
 }
 
-/// Check if a key matches a pattern
+/// SCAN command - Incrementally iterate over the keys
 ///
-/// Supports:
-/// - * : matches everything
-/// - prefix* : matches keys starting with prefix
-/// - *suffix : matches keys ending with suffix
-/// - *pattern* : matches keys containing pattern
-fn matches_pattern(key: &[u8], pattern: &str) -> bool {
-    // Convert key to string for pattern matching
-    let key_str = match std::str::from_utf8(key) {
-        Ok(s) => s,
-        Err(_) => return false,
-    };
+/// Syntax: SCAN cursor [MATCH pattern] [COUNT count]
+///
+/// Start with cursor 0 and call again with the returned cursor until it is
+/// 0. Every key present during the whole iteration is returned at least
+/// once (possibly more), even if keys are added or removed meanwhile.
+///
+/// COUNT is the amount of work per call (buckets visited, default 10), not
+/// the number of keys returned. MATCH filters the keys of the visited
+/// buckets afterwards, so a call can return fewer keys than COUNT, or none
+/// at all with a nonzero cursor: only cursor 0 ends the iteration.
+pub struct ScanCommand;
 
-    // Handle wildcard patterns
-    if pattern == "*" {
-        return true;
+impl Command for ScanCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.is_empty() {
+            return RespValue::error("ERR wrong number of arguments for 'SCAN' command");
+        }
+
+        let cursor = match parse_cursor(&args[0]) {
+            Ok(c) => c,
+            Err(e) => return e,
+        };
+
+        // Options come in pairs, the last one of each kind wins
+        let mut count = DEFAULT_SCAN_COUNT;
+        let mut pattern = None;
+        for pair in args[1..].chunks(2) {
+            let (option, value) = match pair {
+                [option, value] => (extract_bulk_string(option), value),
+                _ => return RespValue::error("ERR syntax error"),
+            };
+            match option {
+                Ok(opt) if opt.eq_ignore_ascii_case(b"COUNT") => match extract_integer(value) {
+                    Ok(c) if c >= 1 => count = c as usize,
+                    Ok(_) => return RespValue::error("ERR syntax error"),
+                    Err(e) => return RespValue::error(format!("ERR {}", e)),
+                },
+                Ok(opt) if opt.eq_ignore_ascii_case(b"MATCH") => match extract_bulk_string(value) {
+                    Ok(p) => pattern = Some(p),
+                    Err(e) => return RespValue::error(format!("ERR {}", e)),
+                },
+                _ => return RespValue::error("ERR syntax error"),
+            }
+        }
+
+        let (next_cursor, mut keys) = ctx.store.scan_cursor(cursor, count);
+        if let Some(pattern) = pattern.filter(|p| p.as_ref() != b"*") {
+            keys.retain(|key| matches_pattern(key, pattern));
+        }
+
+        RespValue::array(vec![
+            RespValue::bulk_string(next_cursor.to_string()),
+            RespValue::array(keys.into_iter().map(RespValue::bulk_string).collect()),
+        ])
+    }
+
+    fn name(&self) -> &'static str {
+        "SCAN"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
     }
 
-    if pattern.starts_with('*') && pattern.ends_with('*') {
-        // *pattern* : contains
-        let inner = &pattern[1..pattern.len() - 1];
-        return key_str.contains(inner);
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// The cursor argument of the SCAN family
+pub(crate) fn parse_cursor(arg: &RespValue) -> Result<u64, RespValue> {
+    extract_bulk_string(arg)
+        .ok()
+        .and_then(|c| std::str::from_utf8(c).ok())
+        .and_then(|c| c.parse::<u64>().ok())
+        .ok_or_else(|| RespValue::error("ERR invalid cursor"))
+}
+
+/// Check if a key matches a glob-style pattern, like Redis
+///
+/// Works on raw bytes: keys and patterns don't have to be valid UTF-8.
+///
+/// Supports:
+/// - * : any sequence of bytes, including none
+/// - ? : any single byte
+/// - [abc], [a-z], [^abc] : one byte in (or not in) the set
+/// - \x : the byte x itself, even if special
+pub(crate) fn matches_pattern(key: &[u8], pattern: &[u8]) -> bool {
+    let (mut p, mut k) = (0, 0);
+
+    // Where to resume after the last star: pattern after it, key position
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    while pattern.get(p) == Some(&b'*') {
+                        p += 1;
+                    }
+                    if p == pattern.len() {
+                        return true;
+                    }
+                    backtrack = Some((p, k));
+                    continue;
+                }
+                b'?' => {
+                    p += 1;
+                    k += 1;
+                    continue;
+                }
+                b'[' => {
+                    let (matched, next) = match_class(pattern, p, key[k]);
+                    if matched {
+                        p = next;
+                        k += 1;
+                        continue;
+                    }
+                }
+                b'\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == key[k] {
+                        p += 2;
+                        k += 1;
+                        continue;
+                    }
+                }
+                byte => {
+                    if byte == key[k] {
+                        p += 1;
+                        k += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // Mismatch: let the last star swallow one more byte
+        match backtrack {
+            Some((star_p, star_k)) => {
+                backtrack = Some((star_p, star_k + 1));
+                p = star_p;
+                k = star_k + 1;
+            }
+            None => return false,
+        }
     }
 
-    if pattern.starts_with('*') {
-        // *suffix : ends with
-        let suffix = &pattern[1..];
-        return key_str.ends_with(suffix);
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+/// Match a byte against the `[...]` class starting at `pattern[start]`
+///
+/// Returns whether it matched and the position right after the class. An
+/// unterminated class extends to the end of the pattern.
+fn match_class(pattern: &[u8], start: usize, byte: u8) -> (bool, usize) {
+    let mut p = start + 1;
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
     }
 
-    if pattern.ends_with('*') {
-        // prefix* : starts with
-        let prefix = &pattern[..pattern.len() - 1];
-        return key_str.starts_with(prefix);
+    let mut matched = false;
+    while p < pattern.len() && pattern[p] != b']' {
+        if pattern[p] == b'\\' && p + 1 < pattern.len() {
+            matched |= pattern[p + 1] == byte;
+            p += 2;
+        } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' && pattern[p + 2] != b']' {
+            let (low, high) = (pattern[p].min(pattern[p + 2]), pattern[p].max(pattern[p + 2]));
+            matched |= (low..=high).contains(&byte);
+            p += 3;
+        } else {
+            matched |= pattern[p] == byte;
+            p += 1;
+        }
     }
 
-    // Exact match
-    key_str == pattern
+    (matched != negate, (p + 1).min(pattern.len()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::commands::args;
     use crate::store::Value;
 
     #[test]
     fn test_keys_all() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
         let mut ctx = CommandContext::new();
 
         // Add some keys
@@ -125,6 +326,7 @@ mod tests {
 
     #[test]
     fn test_keys_prefix() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
         let mut ctx = CommandContext::new();
 
         ctx.store.set("user:1", Value::string("Alice"));
@@ -144,6 +346,7 @@ mod tests {
 
     #[test]
     fn test_keys_suffix() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
         let mut ctx = CommandContext::new();
 
         ctx.store.set("data:cache", Value::string("1"));
@@ -163,6 +366,7 @@ mod tests {
 
     #[test]
     fn test_keys_contains() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
         let mut ctx = CommandContext::new();
 
         ctx.store.set("user_admin", Value::string("1"));
@@ -182,6 +386,7 @@ mod tests {
 
     #[test]
     fn test_keys_exact() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
         let mut ctx = CommandContext::new();
 
         ctx.store.set("exact_key", Value::string("1"));
@@ -200,4 +405,177 @@ mod tests {
             panic!("Expected array response");
         }
     }
+
+    #[test]
+    fn test_glob_patterns() {
+        let cases: &[(&[u8], &[u8], bool)] = &[
+            (b"h?llo", b"hello", true),
+            (b"h?llo", b"hllo", false),
+            (b"h*llo", b"heeeello", true),
+            (b"h[ae]llo", b"hallo", true),
+            (b"h[ae]llo", b"hillo", false),
+            (b"h[^e]llo", b"hallo", true),
+            (b"h[^e]llo", b"hello", false),
+            (b"h[a-b]llo", b"hbllo", true),
+            (b"h[b-a]llo", b"hallo", true),
+            (b"h\\*llo", b"h*llo", true),
+            (b"h\\*llo", b"hello", false),
+            (b"*a*b*c*", b"xxaxxbxxcxx", true),
+            (b"*a*b*c*", b"xxaxxcxxbxx", false),
+            (b"a*", b"", false),
+            (b"*", b"", true),
+            (b"\xff*", b"\xff\x00\x80", true),
+            (b"k[\x80-\xff]", b"k\x90", true),
+        ];
+        for (pattern, key, expected) in cases {
+            assert_eq!(matches_pattern(key, pattern), *expected, "{:?} against {:?}", pattern, key);
+        }
+    }
+
+    #[test]
+    fn test_keys_binary_safe() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
+        let mut ctx = CommandContext::new();
+        let binary = bytes::Bytes::from_static(b"\x08\xde\xad\xbe\xef");
+        ctx.store.set(binary.clone(), Value::string("proto"));
+        ctx.store.set("text", Value::string("v"));
+
+        let result = KeysCommand.execute(&mut ctx, &[RespValue::bulk_string("*")]);
+        let keys = result.as_array().expect("Expected array response");
+        assert_eq!(keys.len(), 2);
+        assert!(keys.contains(&RespValue::bulk_string(binary.clone())));
+
+        // Binary patterns work too
+        let pattern = RespValue::bulk_string(bytes::Bytes::from_static(b"\x08\xde*"));
+        assert_eq!(KeysCommand.execute(&mut ctx, &[pattern]), RespValue::array(vec![RespValue::bulk_string(binary)]));
+    }
+
+    #[test]
+    fn test_keys_skips_expired() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
+        let mut ctx = CommandContext::new();
+        ctx.store.set("user:1", Value::string("v"));
+        ctx.store.set("user:2", Value::string("v"));
+        ctx.store.set("other", Value::string("v"));
+        let past = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        ctx.store.set_expire_at(&bytes::Bytes::from("user:2"), past);
+
+        let result = KeysCommand.execute(&mut ctx, &[RespValue::bulk_string("user:*")]);
+        assert_eq!(result, RespValue::array(vec![RespValue::bulk_string("user:1")]));
+        let result = KeysCommand.execute(&mut ctx, &[RespValue::bulk_string("*")]);
+        assert_eq!(result.as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_keys_disabled() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
+        let mut ctx = CommandContext::new();
+        ctx.store.set("key", Value::string("v"));
+
+        set_disable_keys_command(true);
+        let result = KeysCommand.execute(&mut ctx, &[RespValue::bulk_string("*")]);
+        set_disable_keys_command(false);
+        assert_eq!(result, RespValue::error("ERR KEYS is disabled by disable-keys-command, use SCAN instead"));
+        assert_eq!(KeysCommand.execute(&mut ctx, &[RespValue::bulk_string("*")]).as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_keys_scan_limit() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
+        let mut ctx = CommandContext::new();
+        for i in 0..1000 {
+            ctx.store.set(format!("key:{}", i), Value::string("v"));
+        }
+
+        // Even a pattern matching nothing scans the whole keyspace
+        set_keys_scan_limit(100);
+        let over = KeysCommand.execute(&mut ctx, &[RespValue::bulk_string("nomatch*")]);
+        set_keys_scan_limit(1000);
+        let at_limit = KeysCommand.execute(&mut ctx, &[RespValue::bulk_string("*")]);
+        set_keys_scan_limit(0);
+        assert_eq!(
+            over,
+            RespValue::error(
+                "ERR KEYS aborted after scanning 100 keys (keys-scan-limit), partial result discarded, use SCAN instead"
+            )
+        );
+        assert_eq!(at_limit.as_array().unwrap().len(), 1000);
+    }
+
+    #[test]
+    fn test_scan_full_iteration() {
+        let mut ctx = CommandContext::new();
+        for i in 0..100 {
+            ctx.store.set(format!("key:{}", i), Value::string("v"));
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        let mut cursor = "0".to_string();
+        loop {
+            let args = vec![
+                RespValue::bulk_string(cursor.clone()),
+                RespValue::bulk_string("COUNT"),
+                RespValue::bulk_string("5"),
+            ];
+            let parts = match ScanCommand.execute(&mut ctx, &args) {
+                RespValue::Array(parts) => parts,
+                other => panic!("Unexpected reply: {:?}", other),
+            };
+            cursor = String::from_utf8(parts[0].as_bulk_string().unwrap().to_vec()).unwrap();
+            for key in parts[1].as_array().unwrap() {
+                seen.insert(key.as_bulk_string().unwrap().clone());
+            }
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(seen.len(), 100);
+
+        let result = ScanCommand.execute(&mut ctx, &[RespValue::bulk_string("abc")]);
+        assert_eq!(result, RespValue::error("ERR invalid cursor"));
+    }
+
+    #[test]
+    fn test_scan_match_filters_after_count() {
+        let mut ctx = CommandContext::new();
+        for i in 0..1000 {
+            ctx.store.set(format!("key:{}", i), Value::string("v"));
+        }
+        ctx.store.set("needle:1", Value::string("v"));
+        ctx.store.set("needle:2", Value::string("v"));
+
+        let mut found = std::collections::HashSet::new();
+        let (mut calls, mut empty_pages) = (0, 0);
+        let mut cursor = "0".to_string();
+        loop {
+            let parts = match ScanCommand.execute(&mut ctx, &args(&[cursor.as_str(), "MATCH", "needle:*", "COUNT", "2"])) {
+                RespValue::Array(parts) => parts,
+                other => panic!("Unexpected reply: {:?}", other),
+            };
+            cursor = String::from_utf8(parts[0].as_bulk_string().unwrap().to_vec()).unwrap();
+            let keys = parts[1].as_array().unwrap();
+            for key in keys {
+                found.insert(key.as_bulk_string().unwrap().clone());
+            }
+            calls += 1;
+
+            // An empty page with a nonzero cursor doesn't end the iteration
+            if cursor == "0" {
+                break;
+            }
+            if keys.is_empty() {
+                empty_pages += 1;
+            }
+        }
+
+        assert_eq!(found.len(), 2);
+        assert!(found.contains(&bytes::Bytes::from("needle:1")));
+        assert!(found.contains(&bytes::Bytes::from("needle:2")));
+        assert!(calls > 2 && empty_pages > 0, "{} calls, {} empty pages", calls, empty_pages);
+
+        // Options must come in pairs
+        assert_eq!(ScanCommand.execute(&mut ctx, &args(&["0", "MATCH"])), RespValue::error("ERR syntax error"));
+        assert_eq!(ScanCommand.execute(&mut ctx, &args(&["0", "COUNT", "0"])), RespValue::error("ERR syntax error"));
+    }
+
 }
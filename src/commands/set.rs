@@ -1,8 +1,10 @@
-//! Set commands (SADD, SMEMBERS, SCARD)
+//! Set commands (SADD, SREM, SMEMBERS, SCARD, SUNIONSTORE, SINTERSTORE, SDIFFSTORE)
 
-use super::{Command, CommandContext, extract_bulk_string};
+use super::{Command, CommandContext, extract_bulk_string, log_key_to_aof, log_to_aof, OOM_ERROR, WRONGTYPE};
+use crate::aof::AofOperation;
 use crate::protocol::RespValue;
-use crate::store::Value;
+use crate::store::{self, Set, Value};
+use bytes::Bytes;
 
 /// SADD command - Add one or more members to a set
 ///
@@ -25,7 +27,7 @@ impl Command for SAddCommand {
             Some(value) => {
                 match value.as_set_mut() {
                     Some(set) => set,
-                    None => return RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => return RespValue::error(WRONGTYPE),
                 }
             }
             None => {
@@ -47,6 +49,9 @@ impl Command for SAddCommand {
                 added += 1;
             }
         }
+        if added > 0 {
+            ctx.store.notify_modified(&key);
+        }
 
         RespValue::integer(added)
     }
@@ -55,6 +60,74 @@ impl Command for SAddCommand {
         "SADD"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+}
+
+/// SREM command - Remove one or more members from a set
+///
+/// Syntax: SREM key member [member ...]
+///
+/// Returns the number of members removed. The key is deleted once the set
+/// is emptied.
+pub struct SRemCommand;
+
+impl Command for SRemCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() < 2 {
+            return RespValue::error("ERR wrong number of arguments for 'SREM' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let set = match ctx.store.get_mut(&key) {
+            Some(value) => match value.as_set_mut() {
+                Some(set) => set,
+                None => return RespValue::error(WRONGTYPE),
+            },
+            None => return RespValue::integer(0),
+        };
+
+        let mut removed = Vec::new();
+        for arg in &args[1..] {
+            match extract_bulk_string(arg) {
+                Ok(member) => {
+                    if set.remove(member) {
+                        removed.push(member.clone());
+                    }
+                }
+                Err(e) => return RespValue::error(format!("ERR {}", e)),
+            }
+        }
+        if !removed.is_empty() {
+            ctx.store.notify_modified(&key);
+        }
+        ctx.store.remove_if_empty(&key);
+
+        let count = removed.len() as i64;
+        if count > 0 {
+            log_to_aof(ctx, AofOperation::SRem, key, removed);
+        }
+
+        RespValue::integer(count)
+    }
+
+    fn name(&self) -> &'static str {
+        "SREM"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         2
     }
@@ -87,7 +160,7 @@ impl Command for SMembersCommand {
                             .collect();
                         RespValue::array(members)
                     }
-                    None => RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => RespValue::error(WRONGTYPE),
                 }
             }
             None => {
@@ -101,6 +174,10 @@ impl Command for SMembersCommand {
         "SMEMBERS"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
     fn min_args(&self) -> usize {
         1
     }
@@ -131,7 +208,7 @@ impl Command for SCardCommand {
             Some(value) => {
                 match value.as_set() {
                     Some(set) => RespValue::integer(set.len() as i64),
-                    None => RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => RespValue::error(WRONGTYPE),
                 }
             }
             None => {
@@ -145,6 +222,10 @@ impl Command for SCardCommand {
         "SCARD"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         1
     }
@@ -154,10 +235,200 @@ impl Command for SCardCommand {
     }
 }
 
+/// Set algebra operation of the *STORE commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOp {
+    Union,
+    Inter,
+    Diff,
+}
+
+impl SetOp {
+    /// Operation of a *STORE command name (case-insensitive)
+    pub fn from_store_command(name: &[u8]) -> Option<SetOp> {
+        match name.to_ascii_uppercase().as_slice() {
+            b"SUNIONSTORE" => Some(SetOp::Union),
+            b"SINTERSTORE" => Some(SetOp::Inter),
+            b"SDIFFSTORE" => Some(SetOp::Diff),
+            _ => None,
+        }
+    }
+
+    /// Estimated memory of the result, without computing it
+    ///
+    /// An upper bound in the units of `Value::memory_usage`: every member
+    /// of the sources for a union, of the smallest source for an
+    /// intersection and of the first one for a difference, in a table up to
+    /// twice as large as their count.
+    pub fn estimated_size(self, sets: &[&Set]) -> usize {
+        let size = |set: &Set| (set.len(), set.iter().map(|member| member.len()).sum::<usize>());
+        let (count, bytes) = match self {
+            SetOp::Union => sets.iter().map(|set| size(set)).fold((0, 0), |(c, b), (n, s)| (c + n, b + s)),
+            SetOp::Inter => sets.iter().min_by_key(|set| set.len()).map_or((0, 0), |set| size(set)),
+            SetOp::Diff => sets.first().map_or((0, 0), |set| size(set)),
+        };
+        bytes + store::hash_table_size::<Bytes>(2 * count) + std::mem::size_of::<Set>()
+    }
+
+    /// Compute the result of the operation over the sources
+    pub fn apply(self, sets: &[&Set]) -> Set {
+        let Some((first, rest)) = sets.split_first() else {
+            return Set::new();
+        };
+        match self {
+            SetOp::Union => sets.iter().flat_map(|set| set.iter().cloned()).collect(),
+            SetOp::Inter => {
+                // Probe the other sets with the members of the smallest one
+                let smallest = sets.iter().min_by_key(|set| set.len()).unwrap();
+                smallest.iter().filter(|member| sets.iter().all(|set| set.contains(member))).cloned().collect()
+            }
+            SetOp::Diff => first.iter().filter(|member| !rest.iter().any(|set| set.contains(member))).cloned().collect(),
+        }
+    }
+}
+
+/// Shared implementation of SUNIONSTORE, SINTERSTORE and SDIFFSTORE
+///
+/// Missing source keys are empty sets. Under `maxmemory` the size of the
+/// result is estimated first, so an oversized result is refused with an
+/// OOM error before it is computed; the destination is then left as is.
+/// Otherwise the destination is replaced (deleted when the result is
+/// empty) and logged to the AOF as a DEL followed by its new members.
+fn execute_store(op: SetOp, name: &str, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+    if args.len() < 2 {
+        return RespValue::error(format!("ERR wrong number of arguments for '{}' command", name));
+    }
+
+    let keys: Vec<&Bytes> = match args.iter().map(extract_bulk_string).collect() {
+        Ok(keys) => keys,
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    };
+    let (dest, sources) = (keys[0].clone(), &keys[1..]);
+
+    // Collections modified in place are measured again before comparing
+    if store::maxmemory() > 0 {
+        ctx.store.memory_usage();
+    }
+
+    // Expired sources are removed and types checked first, so the sets can
+    // then be borrowed together
+    for key in sources {
+        if ctx.store.get(key).is_some_and(|value| value.as_set().is_none()) {
+            return RespValue::error(WRONGTYPE);
+        }
+    }
+
+    let empty = Set::new();
+    let result = {
+        let sets: Vec<&Set> = sources
+            .iter()
+            .map(|key| ctx.store.get_entry(key).and_then(|entry| entry.value.as_set()).unwrap_or(&empty))
+            .collect();
+
+        if store::exceeds_maxmemory(op.estimated_size(&sets)) {
+            return RespValue::error(OOM_ERROR);
+        }
+        op.apply(&sets)
+    };
+
+    let len = result.len() as i64;
+    if ctx.store.delete(&dest) {
+        log_to_aof(ctx, AofOperation::Del, dest.clone(), vec![]);
+    }
+    if !result.is_empty() {
+        ctx.store.set(dest.clone(), Value::Set(result));
+        log_key_to_aof(ctx, &dest);
+    }
+
+    RespValue::integer(len)
+}
+
+/// SUNIONSTORE command - Store the union of sets
+///
+/// Syntax: SUNIONSTORE destination key [key ...]
+pub struct SUnionStoreCommand;
+
+impl Command for SUnionStoreCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        execute_store(SetOp::Union, "SUNIONSTORE", ctx, args)
+    }
+
+    fn name(&self) -> &'static str {
+        "SUNIONSTORE"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (1, -1, 1)
+    }
+}
+
+/// SINTERSTORE command - Store the intersection of sets
+///
+/// Syntax: SINTERSTORE destination key [key ...]
+pub struct SInterStoreCommand;
+
+impl Command for SInterStoreCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        execute_store(SetOp::Inter, "SINTERSTORE", ctx, args)
+    }
+
+    fn name(&self) -> &'static str {
+        "SINTERSTORE"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (1, -1, 1)
+    }
+}
+
+/// SDIFFSTORE command - Store the members of the first set missing from
+/// the others
+///
+/// Syntax: SDIFFSTORE destination key [key ...]
+pub struct SDiffStoreCommand;
+
+impl Command for SDiffStoreCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        execute_store(SetOp::Diff, "SDIFFSTORE", ctx, args)
+    }
+
+    fn name(&self) -> &'static str {
+        "SDIFFSTORE"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (1, -1, 1)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bytes::Bytes;
+    use crate::commands::args;
 
     #[test]
     fn test_sadd() {
@@ -185,6 +456,68 @@ mod tests {
         assert_eq!(result, RespValue::integer(1)); // Only d was added
     }
 
+    #[test]
+    fn test_srem_last_member_removes_key() {
+        use crate::commands::key::ExistsCommand;
+
+        let mut ctx = CommandContext::new();
+        SAddCommand.execute(&mut ctx, &args(&["myset", "a", "b"]));
+
+        assert_eq!(SRemCommand.execute(&mut ctx, &args(&["myset", "a", "x"])), RespValue::integer(1));
+        assert_eq!(ExistsCommand.execute(&mut ctx, &args(&["myset"])), RespValue::integer(1));
+        assert_eq!(SRemCommand.execute(&mut ctx, &args(&["myset", "b"])), RespValue::integer(1));
+        assert_eq!(ExistsCommand.execute(&mut ctx, &args(&["myset"])), RespValue::integer(0));
+        assert_eq!(SRemCommand.execute(&mut ctx, &args(&["myset", "b"])), RespValue::integer(0));
+
+        ctx.store.set("string", Value::string("v"));
+        assert_eq!(SRemCommand.execute(&mut ctx, &args(&["string", "v"])), RespValue::error(WRONGTYPE));
+    }
+
+    #[test]
+    fn test_srem_logs_removed_members() {
+        use crate::aof::{replay_entries, AofOperation, AofReader, AofWriter, SyncPolicy};
+        use std::sync::Arc;
+
+        let path = std::env::temp_dir().join(format!("ferrumdb_srem_{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let writer = Arc::new(AofWriter::new(&path, SyncPolicy::No).unwrap());
+        let populated = || {
+            let mut ctx = CommandContext::new();
+            SAddCommand.execute(&mut ctx, &args(&["myset", "a", "b", "c"]));
+            SAddCommand.execute(&mut ctx, &args(&["other", "x"]));
+            ctx
+        };
+
+        let mut ctx = populated();
+        ctx.set_aof_writer(writer.clone());
+        SRemCommand.execute(&mut ctx, &args(&["myset", "a", "nope"]));
+        SRemCommand.execute(&mut ctx, &args(&["myset", "nope"]));
+        SRemCommand.execute(&mut ctx, &args(&["other", "x"]));
+        writer.flush().unwrap();
+
+        // Only the members actually removed, never the rest of the set
+        let entries = AofReader::load(&path).unwrap().parse_entries();
+        let srems: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.key.clone(), entry.payload.clone()))
+            .collect();
+        assert_eq!(
+            srems,
+            vec![(Bytes::from("myset"), vec![Bytes::from("a")]), (Bytes::from("other"), vec![Bytes::from("x")])]
+        );
+
+        assert!(entries.iter().all(|entry| entry.op == AofOperation::SRem));
+
+        let mut replayed = populated();
+        replay_entries(&mut replayed.store, entries).unwrap();
+        assert_eq!(SCardCommand.execute(&mut replayed, &args(&["myset"])), RespValue::integer(2));
+        assert!(!replayed.store.get(&Bytes::from("myset")).unwrap().as_set().unwrap().contains(b"a"));
+        assert!(!replayed.store.exists(&Bytes::from("other")));
+
+        drop(ctx);
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn test_smembers() {
         let mut ctx = CommandContext::new();
@@ -243,4 +576,93 @@ mod tests {
         let result = scard_cmd.execute(&mut ctx, &args);
         assert_eq!(result, RespValue::integer(0));
     }
+
+    #[test]
+    fn test_intset_overflow_flips_to_listpack() {
+        use crate::commands::key::ObjectCommand;
+
+        let mut ctx = CommandContext::new();
+        let encoding = |ctx: &mut CommandContext| {
+            let args = vec![RespValue::bulk_string("ENCODING"), RespValue::bulk_string("nums")];
+            ObjectCommand.execute(ctx, &args)
+        };
+
+        let args = vec![RespValue::bulk_string("nums"), RespValue::bulk_string("9223372036854775807")];
+        assert_eq!(SAddCommand.execute(&mut ctx, &args), RespValue::integer(1));
+        assert_eq!(encoding(&mut ctx), RespValue::bulk_string("intset"));
+
+        // One past i64::MAX is not an integer member: no error, a listpack
+        let args = vec![RespValue::bulk_string("nums"), RespValue::bulk_string("99999999999999999999")];
+        assert_eq!(SAddCommand.execute(&mut ctx, &args), RespValue::integer(1));
+        assert_eq!(encoding(&mut ctx), RespValue::bulk_string("listpack"));
+
+        let result = SMembersCommand.execute(&mut ctx, &[RespValue::bulk_string("nums")]);
+        let members = result.as_array().expect("Expected array response");
+        assert_eq!(members.len(), 2);
+        assert!(members.contains(&RespValue::bulk_string("9223372036854775807")));
+        assert!(members.contains(&RespValue::bulk_string("99999999999999999999")));
+    }
+
+    #[test]
+    fn test_set_store_commands() {
+        let mut ctx = CommandContext::new();
+        let run = |ctx: &mut CommandContext, command: &dyn Command, parts: &[&str]| {
+            command.execute(ctx, &args(parts))
+        };
+        let members = |ctx: &mut CommandContext, key: &str| {
+            let mut members: Vec<Bytes> = match ctx.store.get(&Bytes::from(key.to_string())) {
+                Some(value) => value.as_set().unwrap().iter().cloned().collect(),
+                None => vec![],
+            };
+            members.sort();
+            members
+        };
+        run(&mut ctx, &SAddCommand, &["a", "1", "2", "3"]);
+        run(&mut ctx, &SAddCommand, &["b", "2", "3", "4"]);
+
+        assert_eq!(run(&mut ctx, &SUnionStoreCommand, &["dest", "a", "b", "missing"]), RespValue::integer(4));
+        assert_eq!(members(&mut ctx, "dest"), vec!["1", "2", "3", "4"]);
+        assert_eq!(run(&mut ctx, &SInterStoreCommand, &["dest", "a", "b"]), RespValue::integer(2));
+        assert_eq!(members(&mut ctx, "dest"), vec!["2", "3"]);
+        assert_eq!(run(&mut ctx, &SDiffStoreCommand, &["dest", "a", "b"]), RespValue::integer(1));
+        assert_eq!(members(&mut ctx, "dest"), vec!["1"]);
+
+        // The destination may be a source, and an empty result deletes it
+        assert_eq!(run(&mut ctx, &SInterStoreCommand, &["a", "a", "missing"]), RespValue::integer(0));
+        assert!(!ctx.store.exists(&Bytes::from("a")));
+
+        // Sources must be sets, any destination is overwritten
+        ctx.store.set("str", Value::string("v"));
+        assert_eq!(run(&mut ctx, &SUnionStoreCommand, &["dest", "b", "str"]), RespValue::error(WRONGTYPE));
+        assert_eq!(run(&mut ctx, &SUnionStoreCommand, &["str", "b"]), RespValue::integer(3));
+        assert_eq!(members(&mut ctx, "str"), vec!["2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_changes_are_reported_to_the_observer() {
+        use crate::store::{KeyEvent, KeyEventKind};
+        use std::sync::{Arc, Mutex};
+
+        let mut ctx = CommandContext::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        ctx.store.set_observer(Some(Box::new(move |event: KeyEvent<'_>| {
+            seen.lock().unwrap().push((event.kind, event.key.clone()));
+        })));
+
+        SAddCommand.execute(&mut ctx, &args(&["s", "a", "b"]));
+        events.lock().unwrap().clear();
+
+        // An in-place change is a Set, a no-op isn't reported
+        SAddCommand.execute(&mut ctx, &args(&["s", "c"]));
+        SAddCommand.execute(&mut ctx, &args(&["s", "c"]));
+        SRemCommand.execute(&mut ctx, &args(&["s", "nope"]));
+        SRemCommand.execute(&mut ctx, &args(&["s", "a", "b", "c"]));
+
+        let key = Bytes::from("s");
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![(KeyEventKind::Set, key.clone()), (KeyEventKind::Set, key.clone()), (KeyEventKind::Del, key)]
+        );
+    }
 }
@@ -0,0 +1,357 @@
+//! Stream commands (XADD, XLEN, XRANGE)
+
+use super::{Command, CommandContext, extract_bulk_string, extract_integer, log_to_aof, WRONGTYPE};
+use crate::protocol::RespValue;
+use crate::store::{StreamId, Value};
+use crate::aof::AofOperation;
+use bytes::Bytes;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Error returned when an ID argument cannot be parsed
+const INVALID_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+
+/// Parse a range bound for XRANGE ("-", "+", "<ms>" or "<ms>-<seq>")
+fn parse_range_bound(bytes: &Bytes, is_end: bool) -> Option<StreamId> {
+    match bytes.as_ref() {
+        b"-" => Some(StreamId::MIN),
+        b"+" => Some(StreamId::MAX),
+        raw => StreamId::parse(raw, if is_end { u64::MAX } else { 0 }),
+    }
+}
+
+/// XADD command - Append an entry to a stream
+///
+/// Syntax: XADD key <* | id> field value [field value ...]
+///
+/// Returns the ID of the added entry. With `*`, the ID is generated from the
+/// current time in milliseconds, bumping the sequence within the same
+/// millisecond so IDs are always strictly increasing.
+pub struct XAddCommand;
+
+impl Command for XAddCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() < 4 || !args.len().is_multiple_of(2) {
+            return RespValue::error("ERR wrong number of arguments for 'XADD' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let id_arg = match extract_bulk_string(&args[1]) {
+            Ok(id) => id.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let explicit_id = if id_arg.as_ref() == b"*" {
+            None
+        } else {
+            match StreamId::parse(&id_arg, 0) {
+                Some(id) => Some(id),
+                None => return RespValue::error(INVALID_ID),
+            }
+        };
+
+        // Collect field/value pairs
+        let mut fields = Vec::with_capacity((args.len() - 2) / 2);
+        for pair in args[2..].chunks(2) {
+            let field = match extract_bulk_string(&pair[0]) {
+                Ok(f) => f.clone(),
+                Err(e) => return RespValue::error(format!("ERR {}", e)),
+            };
+            let value = match extract_bulk_string(&pair[1]) {
+                Ok(v) => v.clone(),
+                Err(e) => return RespValue::error(format!("ERR {}", e)),
+            };
+            fields.push((field, value));
+        }
+
+        // Get or create stream
+        let stream = match ctx.store.get_mut(&key) {
+            Some(value) => {
+                match value.as_stream_mut() {
+                    Some(stream) => stream,
+                    None => return RespValue::error(WRONGTYPE),
+                }
+            }
+            None => {
+                ctx.store.set(key.clone(), Value::empty_stream());
+                ctx.store.get_mut(&key).unwrap().as_stream_mut().unwrap()
+            }
+        };
+
+        let id = match explicit_id {
+            Some(id) => id,
+            None => {
+                let now_ms = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64;
+                stream.next_id(now_ms)
+            }
+        };
+
+        let mut payload = Vec::with_capacity(fields.len() * 2 + 1);
+        payload.push(Bytes::from(id.to_string()));
+        for (field, value) in &fields {
+            payload.push(field.clone());
+            payload.push(value.clone());
+        }
+
+        if let Err(e) = stream.add(id, fields) {
+            let is_empty = stream.is_empty();
+            // Don't leave an empty stream behind when the first XADD fails
+            if is_empty {
+                ctx.store.delete(&key);
+            }
+            return RespValue::error(format!("ERR {}", e));
+        }
+        ctx.store.notify_modified(&key);
+
+        // Log the resolved ID so replay reproduces the same entry IDs
+        log_to_aof(ctx, AofOperation::XAdd, key, payload);
+
+        RespValue::bulk_string(id.to_string())
+    }
+
+    fn name(&self) -> &'static str {
+        "XADD"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        4
+    }
+}
+
+/// XLEN command - Get the number of entries in a stream
+///
+/// Syntax: XLEN key
+pub struct XLenCommand;
+
+impl Command for XLenCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.is_empty() {
+            return RespValue::error("ERR wrong number of arguments for 'XLEN' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        match ctx.store.get(key) {
+            Some(value) => {
+                match value.as_stream() {
+                    Some(stream) => RespValue::integer(stream.len() as i64),
+                    None => RespValue::error(WRONGTYPE),
+                }
+            }
+            None => RespValue::integer(0),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "XLEN"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(1)
+    }
+}
+
+/// XRANGE command - Get entries with IDs in a range
+///
+/// Syntax: XRANGE key start end [COUNT count]
+///
+/// `-` and `+` are the lowest and highest possible IDs. An ID without a
+/// sequence part matches the whole millisecond.
+pub struct XRangeCommand;
+
+impl Command for XRangeCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() != 3 && args.len() != 5 {
+            return RespValue::error("ERR wrong number of arguments for 'XRANGE' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let start = match extract_bulk_string(&args[1]).ok().and_then(|b| parse_range_bound(b, false)) {
+            Some(id) => id,
+            None => return RespValue::error(INVALID_ID),
+        };
+
+        let end = match extract_bulk_string(&args[2]).ok().and_then(|b| parse_range_bound(b, true)) {
+            Some(id) => id,
+            None => return RespValue::error(INVALID_ID),
+        };
+
+        let count = if args.len() == 5 {
+            match extract_bulk_string(&args[3]) {
+                Ok(opt) if opt.eq_ignore_ascii_case(b"COUNT") => {}
+                _ => return RespValue::error("ERR syntax error"),
+            }
+            match extract_integer(&args[4]) {
+                Ok(c) => Some(c.max(0) as usize),
+                Err(e) => return RespValue::error(format!("ERR {}", e)),
+            }
+        } else {
+            None
+        };
+
+        let stream = match ctx.store.get(key) {
+            Some(value) => {
+                match value.as_stream() {
+                    Some(stream) => stream,
+                    None => return RespValue::error(WRONGTYPE),
+                }
+            }
+            None => return RespValue::array(vec![]),
+        };
+
+        let entries = stream
+            .range(start, end, count)
+            .into_iter()
+            .map(|(id, fields)| {
+                let mut flat = Vec::with_capacity(fields.len() * 2);
+                for (field, value) in fields {
+                    flat.push(RespValue::bulk_string(field.clone()));
+                    flat.push(RespValue::bulk_string(value.clone()));
+                }
+                RespValue::array(vec![
+                    RespValue::bulk_string(id.to_string()),
+                    RespValue::array(flat),
+                ])
+            })
+            .collect();
+
+        RespValue::array(entries)
+    }
+
+    fn name(&self) -> &'static str {
+        "XRANGE"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xadd(ctx: &mut CommandContext, id: &str, field: &str, value: &str) -> RespValue {
+        let args = vec![
+            RespValue::bulk_string("events"),
+            RespValue::bulk_string(id.to_string()),
+            RespValue::bulk_string(field.to_string()),
+            RespValue::bulk_string(value.to_string()),
+        ];
+        XAddCommand.execute(ctx, &args)
+    }
+
+    fn entry_id(entry: &RespValue) -> StreamId {
+        match entry {
+            RespValue::BulkString(b) => StreamId::parse(b, 0).unwrap(),
+            RespValue::Array(parts) => entry_id(&parts[0]),
+            other => panic!("Unexpected reply: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_xadd_ids_monotonic() {
+        let mut ctx = CommandContext::new();
+
+        // Many adds within the same millisecond must still be strictly increasing
+        let mut previous = StreamId::MIN;
+        for i in 0..100 {
+            let id = entry_id(&xadd(&mut ctx, "*", "n", &i.to_string()));
+            assert!(id > previous, "{} should be greater than {}", id, previous);
+            previous = id;
+        }
+
+        // An explicit ID far in the future forces sequence disambiguation
+        let future = StreamId::new(previous.ms + 1_000_000, 7);
+        assert_eq!(xadd(&mut ctx, &future.to_string(), "n", "x"), RespValue::bulk_string(future.to_string()));
+        assert_eq!(entry_id(&xadd(&mut ctx, "*", "n", "y")), StreamId::new(future.ms, 8));
+
+        // IDs not greater than the top item are rejected
+        assert!(matches!(xadd(&mut ctx, "1-1", "n", "z"), RespValue::Error(_)));
+
+        let result = XLenCommand.execute(&mut ctx, &[RespValue::bulk_string("events")]);
+        assert_eq!(result, RespValue::integer(102));
+    }
+
+    #[test]
+    fn test_xrange_by_id() {
+        let mut ctx = CommandContext::new();
+        xadd(&mut ctx, "1-1", "a", "1");
+        xadd(&mut ctx, "1-2", "b", "2");
+        xadd(&mut ctx, "2-0", "c", "3");
+        xadd(&mut ctx, "3-5", "d", "4");
+
+        let range = |ctx: &mut CommandContext, start: &str, end: &str, count: Option<&str>| {
+            let mut args = vec![
+                RespValue::bulk_string("events"),
+                RespValue::bulk_string(start.to_string()),
+                RespValue::bulk_string(end.to_string()),
+            ];
+            if let Some(c) = count {
+                args.push(RespValue::bulk_string("COUNT"));
+                args.push(RespValue::bulk_string(c.to_string()));
+            }
+            match XRangeCommand.execute(ctx, &args) {
+                RespValue::Array(entries) => entries.iter().map(entry_id).collect::<Vec<_>>(),
+                other => panic!("Unexpected reply: {:?}", other),
+            }
+        };
+
+        // Full range
+        assert_eq!(range(&mut ctx, "-", "+", None).len(), 4);
+
+        // Millisecond-only bounds cover every sequence in that millisecond
+        assert_eq!(range(&mut ctx, "1", "1", None), vec![StreamId::new(1, 1), StreamId::new(1, 2)]);
+
+        // Exact IDs and COUNT
+        assert_eq!(range(&mut ctx, "1-2", "3-5", Some("2")), vec![StreamId::new(1, 2), StreamId::new(2, 0)]);
+
+        // Reversed bounds return nothing
+        assert!(range(&mut ctx, "3", "1", None).is_empty());
+
+        // Entries carry their field/value pairs
+        let args = vec![
+            RespValue::bulk_string("events"),
+            RespValue::bulk_string("2-0"),
+            RespValue::bulk_string("2-0"),
+        ];
+        let result = XRangeCommand.execute(&mut ctx, &args);
+        assert_eq!(result, RespValue::array(vec![RespValue::array(vec![
+            RespValue::bulk_string("2-0"),
+            RespValue::array(vec![RespValue::bulk_string("c"), RespValue::bulk_string("3")]),
+        ])]));
+    }
+}
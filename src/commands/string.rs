@@ -1,13 +1,108 @@
-//! String commands (SET, GET)
+//! String commands (SET, SETNX, CAS, LRELEASE, SETEX, PSETEX, GET, MSET, APPEND, SETRANGE, GETRANGE)
 
-use super::{Command, CommandContext, extract_bulk_string, log_to_aof};
+use super::{Command, CommandContext, check_string_length, extract_bulk_string, extract_integer, log_key_to_aof, log_to_aof, normalize_range, wrong_type, WRONGTYPE};
 use crate::protocol::RespValue;
-use crate::store::Value;
+use crate::store::{expire_jitter_percent, jitter_ttl, unix_ms_to_instant, Value};
 use crate::aof::AofOperation;
+use bytes::Bytes;
+use std::time::{Duration, Instant};
+
+/// Store the new string of an existing or new key, keeping any TTL, and log
+/// it as a SET of the whole value
+fn store_string(ctx: &mut CommandContext, key: Bytes, bytes: Bytes) {
+    replace_string(ctx, &key, bytes.clone());
+    log_to_aof(ctx, AofOperation::Set, key, vec![bytes]);
+}
+
+/// Store the new string of an existing or new key, keeping any TTL
+///
+/// The string was modified in place, so it is `raw` from now on, like in
+/// Redis (until it is set again, or reloaded from the AOF).
+fn replace_string(ctx: &mut CommandContext, key: &Bytes, bytes: Bytes) {
+    match ctx.store.get_mut(key) {
+        Some(value) => {
+            *value = Value::String(bytes);
+            ctx.store.notify_modified(key);
+        }
+        None => {
+            ctx.store.set(key.clone(), Value::String(bytes));
+        }
+    }
+    if let Some(entry) = ctx.store.get_entry_mut(key) {
+        entry.raw_string = true;
+    }
+}
+
+/// Options of a SET command
+#[derive(Default)]
+struct SetOptions {
+    nx: bool,
+    xx: bool,
+    get: bool,
+    keep_ttl: bool,
+    expire_at: Option<Instant>,
+
+    /// Absolute expiry given with EXAT or PXAT, in Unix milliseconds
+    expire_at_unix_ms: Option<i64>,
+}
+
+/// Parse `[NX | XX] [GET] [EX seconds | PX milliseconds | EXAT unix-seconds
+/// | PXAT unix-milliseconds | KEEPTTL]`
+fn parse_set_options(args: &[RespValue]) -> Result<SetOptions, RespValue> {
+    let mut options = SetOptions::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let option = extract_bulk_string(arg).map_err(|e| RespValue::error(format!("ERR {}", e)))?;
+        match option.to_ascii_uppercase().as_slice() {
+            b"NX" if !options.xx => options.nx = true,
+            b"XX" if !options.nx => options.xx = true,
+            b"GET" => options.get = true,
+            b"KEEPTTL" if options.expire_at.is_none() => options.keep_ttl = true,
+            unit @ (b"EX" | b"PX") if !options.keep_ttl && options.expire_at.is_none() => {
+                let Some(ttl) = args.next() else {
+                    return Err(RespValue::error("ERR syntax error"));
+                };
+                let ttl = extract_integer(ttl).map_err(|e| RespValue::error(format!("ERR {}", e)))?;
+                let ms = if unit == b"EX" { ttl.checked_mul(1000) } else { Some(ttl) };
+                let expire_at = ms
+                    .filter(|&ms| ttl > 0 && ms > 0)
+                    .map(|ms| jitter_ttl(Duration::from_millis(ms as u64), expire_jitter_percent()))
+                    .and_then(|ttl| Instant::now().checked_add(ttl))
+                    .ok_or_else(|| RespValue::error("ERR invalid expire time in 'set' command"))?;
+                options.expire_at = Some(expire_at);
+            }
+            unit @ (b"EXAT" | b"PXAT") if !options.keep_ttl && options.expire_at.is_none() => {
+                let Some(time) = args.next() else {
+                    return Err(RespValue::error("ERR syntax error"));
+                };
+                let time = extract_integer(time).map_err(|e| RespValue::error(format!("ERR {}", e)))?;
+                let unix_ms = if unit == b"EXAT" { time.checked_mul(1000) } else { Some(time) };
+                let (unix_ms, expire_at) = unix_ms
+                    .filter(|&ms| time > 0 && ms > 0)
+                    .and_then(|ms| Some((ms, unix_ms_to_instant(ms)?)))
+                    .ok_or_else(|| RespValue::error("ERR invalid expire time in 'set' command"))?;
+                options.expire_at = Some(expire_at);
+                options.expire_at_unix_ms = Some(unix_ms);
+            }
+            _ => return Err(RespValue::error("ERR syntax error")),
+        }
+    }
+
+    Ok(options)
+}
 
 /// SET command - Set a key to a value
 ///
-/// Syntax: SET key value
+/// Syntax: SET key value [NX | XX] [GET] [EX seconds | PX milliseconds |
+/// EXAT unix-seconds | PXAT unix-milliseconds | KEEPTTL]
+///
+/// NX only sets a missing key, XX an existing one. GET replies with the
+/// previous value (nil if there was none) instead of OK, even when NX or XX
+/// skip the write; it fails with WRONGTYPE, writing nothing, if the
+/// previous value isn't a string. The TTL is cleared unless EX, PX, EXAT,
+/// PXAT or KEEPTTL is given; an EXAT or PXAT time already passed deletes
+/// the key.
 pub struct SetCommand;
 
 impl Command for SetCommand {
@@ -28,21 +123,360 @@ impl Command for SetCommand {
             Err(e) => return RespValue::error(format!("ERR {}", e)),
         };
 
-        // TODO: Parse optional arguments (EX, PX, NX, XX, etc.) in future phases
+        let options = match parse_set_options(&args[2..]) {
+            Ok(o) => o,
+            Err(e) => return e,
+        };
 
-        // Log to AOF
-        log_to_aof(ctx, AofOperation::Set, key.clone(), vec![value.clone()]);
+        // SET NX without GET nor an absolute expiry: decided and inserted
+        // in a single lookup
+        if options.nx && !options.get && options.expire_at_unix_ms.is_none() {
+            if !ctx.store.set_if_absent(key.clone(), Value::from_bytes(value)) {
+                return RespValue::null();
+            }
+            if let Some(expire_at) = options.expire_at {
+                ctx.store.set_expire_at(&key, expire_at);
+            }
+            log_key_to_aof(ctx, &key);
+            return RespValue::simple_string("OK");
+        }
 
-        // Set the value
-        ctx.store.set(key, Value::String(value));
+        // Also reaps the key if it has expired
+        let exists = ctx.store.exists(&key);
 
-        RespValue::simple_string("OK")
+        let previous = if options.get {
+            match ctx.store.get(&key) {
+                Some(old) => match old.as_string_bytes() {
+                    Some(bytes) => Some(bytes),
+                    None => return RespValue::error(WRONGTYPE),
+                },
+                None => None,
+            }
+        } else {
+            None
+        };
+
+        let skipped = (options.nx && exists) || (options.xx && !exists);
+        if !skipped {
+            let expire_at = if options.keep_ttl {
+                ctx.store.get_entry(&key).and_then(|entry| entry.expire_at)
+            } else {
+                options.expire_at
+            };
+
+            match (options.expire_at_unix_ms, expire_at) {
+                // Set then expired at once: nothing is stored
+                (Some(_), Some(at)) if at <= Instant::now() => {
+                    ctx.store.delete(&key);
+                    log_to_aof(ctx, AofOperation::Del, key, vec![]);
+                }
+                // Logged with the absolute time, which replays to the same expiry
+                (Some(unix_ms), Some(at)) => {
+                    ctx.store.set(key.clone(), Value::from_bytes(value.clone()));
+                    ctx.store.set_expire_at(&key, at);
+                    log_to_aof(ctx, AofOperation::Set, key.clone(), vec![value]);
+                    log_to_aof(ctx, AofOperation::PExpireAt, key, vec![Bytes::from(unix_ms.to_string())]);
+                }
+                _ => {
+                    // Set the value (canonical integers are stored as such, like Redis)
+                    ctx.store.set(key.clone(), Value::from_bytes(value));
+                    if let Some(expire_at) = expire_at {
+                        ctx.store.set_expire_at(&key, expire_at);
+                    }
+
+                    // Logged as a SET followed by an EXPIRE when the key has a TTL
+                    log_key_to_aof(ctx, &key);
+                }
+            }
+        }
+
+        match (options.get, skipped) {
+            (true, _) => previous.map(RespValue::bulk_string).unwrap_or_else(RespValue::null),
+            (false, true) => RespValue::null(),
+            (false, false) => RespValue::simple_string("OK"),
+        }
     }
 
     fn name(&self) -> &'static str {
         "SET"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+}
+
+/// Shared implementation of SETEX / PSETEX: a SET with EX or PX
+///
+/// The TTL must be positive, otherwise nothing is written.
+fn set_with_ttl(ctx: &mut CommandContext, args: &[RespValue], name: &str, unit: &str) -> RespValue {
+    if args.len() != 3 {
+        return RespValue::error(format!("ERR wrong number of arguments for '{}' command", name));
+    }
+
+    match extract_integer(&args[1]) {
+        Ok(ttl) if ttl > 0 => {}
+        Ok(_) => return RespValue::error(format!("ERR invalid expire time in '{}' command", name.to_lowercase())),
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    }
+
+    let set_args = [args[0].clone(), args[2].clone(), RespValue::bulk_string(unit.to_string()), args[1].clone()];
+    match SetCommand.execute(ctx, &set_args) {
+        // A TTL overflowing an Instant, reported under this command's name
+        RespValue::Error(error) if error == "ERR invalid expire time in 'set' command" => {
+            RespValue::error(format!("ERR invalid expire time in '{}' command", name.to_lowercase()))
+        }
+        reply => reply,
+    }
+}
+
+/// SETNX command - Set a key to a value only if it doesn't exist
+///
+/// Syntax: SETNX key value
+///
+/// Replies 1 if the key was set, 0 otherwise.
+pub struct SetNxCommand;
+
+impl Command for SetNxCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+        let value = match extract_bulk_string(&args[1]) {
+            Ok(v) => v.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        if !ctx.store.set_if_absent(key.clone(), Value::from_bytes(value)) {
+            return RespValue::integer(0);
+        }
+        log_key_to_aof(ctx, &key);
+        RespValue::integer(1)
+    }
+
+    fn name(&self) -> &'static str {
+        "SETNX"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+/// CAS command - Set a key to a new value only if it holds the expected one
+///
+/// Syntax: CAS key new IFEQ expected | NX
+///
+/// Not a Redis command: an atomic compare-and-set for optimistic locking
+/// without MULTI/WATCH. Replies 1 if the key was set, 0 otherwise. With
+/// IFEQ the key must hold `expected`, and keeps its TTL; with NX it must be
+/// missing, and is set like SETNX (an existing key of any type replies 0). Any string can be compared, so a
+/// missing key has an option of its own rather than a reserved value. Only
+/// a successful CAS is logged to the AOF.
+pub struct CasCommand;
+
+impl Command for CasCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+        let value = match extract_bulk_string(&args[1]) {
+            Ok(v) => v.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+        let expected = match (extract_bulk_string(&args[2]), args.get(3)) {
+            (Ok(option), Some(expected)) if option.eq_ignore_ascii_case(b"IFEQ") => match extract_bulk_string(expected) {
+                Ok(v) => Some(v.as_ref()),
+                Err(e) => return RespValue::error(format!("ERR {}", e)),
+            },
+            (Ok(option), None) if option.eq_ignore_ascii_case(b"NX") => None,
+            _ => return RespValue::error("ERR syntax error"),
+        };
+
+        match ctx.store.compare_and_set(key.clone(), expected, Value::from_bytes(value)) {
+            Ok(true) => {
+                log_key_to_aof(ctx, &key);
+                RespValue::integer(1)
+            }
+            Ok(false) => RespValue::integer(0),
+            Err(found) => wrong_type("CAS", &key, found),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "CAS"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(4)
+    }
+}
+
+/// LRELEASE command - Release a lock only if it is still held with a token
+///
+/// Syntax: LRELEASE key token
+///
+/// Not a Redis command: the release matching an acquisition with
+/// `SET key token NX PX ttl`. The key is deleted only if it holds `token`,
+/// so a lock that expired and was taken by another owner isn't released;
+/// replies 1 if the key was deleted, 0 otherwise. Only a release is logged
+/// to the AOF, as a DEL.
+pub struct LReleaseCommand;
+
+impl Command for LReleaseCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+        let token = match extract_bulk_string(&args[1]) {
+            Ok(t) => t,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        match ctx.store.compare_and_delete(&key, token) {
+            Ok(true) => {
+                log_to_aof(ctx, AofOperation::Del, key, vec![]);
+                RespValue::integer(1)
+            }
+            Ok(false) => RespValue::integer(0),
+            Err(found) => wrong_type("LRELEASE", &key, found),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "LRELEASE"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+/// SETEX command - Set a key to a value with a TTL in seconds
+///
+/// Syntax: SETEX key seconds value
+pub struct SetExCommand;
+
+impl Command for SetExCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        set_with_ttl(ctx, args, "SETEX", "EX")
+    }
+
+    fn name(&self) -> &'static str {
+        "SETEX"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(3)
+    }
+}
+
+/// PSETEX command - Set a key to a value with a TTL in milliseconds
+///
+/// Syntax: PSETEX key milliseconds value
+pub struct PSetExCommand;
+
+impl Command for PSetExCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        set_with_ttl(ctx, args, "PSETEX", "PX")
+    }
+
+    fn name(&self) -> &'static str {
+        "PSETEX"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(3)
+    }
+}
+
+/// MSET command - Set several keys at once
+///
+/// Syntax: MSET key value [key value ...]
+pub struct MSetCommand;
+
+impl Command for MSetCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() < 2 || !args.len().is_multiple_of(2) {
+            return RespValue::error("ERR wrong number of arguments for 'MSET' command");
+        }
+
+        // Validate every pair before writing anything
+        let mut pairs = Vec::with_capacity(args.len() / 2);
+        for pair in args.chunks(2) {
+            match (extract_bulk_string(&pair[0]), extract_bulk_string(&pair[1])) {
+                (Ok(key), Ok(value)) => pairs.push((key.clone(), value.clone())),
+                (Err(e), _) | (_, Err(e)) => return RespValue::error(format!("ERR {}", e)),
+            }
+        }
+
+        for (key, value) in pairs {
+            log_to_aof(ctx, AofOperation::Set, key.clone(), vec![value.clone()]);
+            ctx.store.set(key, Value::from_bytes(value));
+        }
+
+        RespValue::simple_string("OK")
+    }
+
+    fn name(&self) -> &'static str {
+        "MSET"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn key_spec(&self) -> (i64, i64, i64) {
+        (1, -1, 2)
+    }
+
     fn min_args(&self) -> usize {
         2
     }
@@ -67,7 +501,7 @@ impl Command for GetCommand {
         };
 
         // Get the value
-        match ctx.store.get(key) {
+        match ctx.store.lookup_read(key) {
             Some(value) => {
                 match value {
                     Value::String(bytes) => RespValue::bulk_string(bytes.clone()),
@@ -75,7 +509,7 @@ impl Command for GetCommand {
                         // Convert integer to string
                         RespValue::bulk_string(i.to_string())
                     }
-                    _ => RespValue::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    other => wrong_type("GET", key, other.type_name()),
                 }
             }
             None => RespValue::null(),
@@ -86,6 +520,10 @@ impl Command for GetCommand {
         "GET"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         1
     }
@@ -95,10 +533,222 @@ impl Command for GetCommand {
     }
 }
 
+/// APPEND command - Append a value to a string
+///
+/// Syntax: APPEND key value
+///
+/// Creates the key when it doesn't exist and returns the new length. Only
+/// an existing string is modified in place, and becomes `raw` (see
+/// `replace_string`). Only the suffix is logged to the AOF, not the whole
+/// new value.
+pub struct AppendCommand;
+
+impl Command for AppendCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() != 2 {
+            return RespValue::error("ERR wrong number of arguments for 'APPEND' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let suffix = match extract_bulk_string(&args[1]) {
+            Ok(v) => v.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let current = match ctx.store.get(&key) {
+            Some(value) => match value.as_string_bytes() {
+                Some(b) => Some(b),
+                None => return RespValue::error(WRONGTYPE),
+            },
+            None => None,
+        };
+        let current_len = current.as_ref().map_or(0, Bytes::len);
+
+        if let Err(e) = check_string_length(current_len + suffix.len()) {
+            return RespValue::error(format!("ERR {}", e));
+        }
+
+        match current {
+            Some(current) => {
+                let mut bytes = Vec::with_capacity(current.len() + suffix.len());
+                bytes.extend_from_slice(&current);
+                bytes.extend_from_slice(&suffix);
+                replace_string(ctx, &key, Bytes::from(bytes));
+            }
+            // A new key is set like SET would, with its regular encoding
+            None => {
+                ctx.store.set(key.clone(), Value::from_bytes(suffix.clone()));
+            }
+        }
+        let len = current_len + suffix.len();
+        log_to_aof(ctx, AofOperation::Append, key, vec![suffix]);
+
+        RespValue::integer(len as i64)
+    }
+
+    fn name(&self) -> &'static str {
+        "APPEND"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+/// SETRANGE command - Overwrite part of a string at an offset
+///
+/// Syntax: SETRANGE key offset value
+///
+/// The string is zero-padded up to the offset when shorter. Returns the new
+/// length; an empty value leaves the key untouched (and doesn't create it).
+pub struct SetRangeCommand;
+
+impl Command for SetRangeCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() != 3 {
+            return RespValue::error("ERR wrong number of arguments for 'SETRANGE' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let offset = match extract_integer(&args[1]) {
+            Ok(o) if o >= 0 => o as usize,
+            Ok(_) => return RespValue::error("ERR offset is out of range"),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let patch = match extract_bulk_string(&args[2]) {
+            Ok(v) => v.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let current = match ctx.store.get(&key) {
+            Some(value) => match value.as_string_bytes() {
+                Some(b) => b,
+                None => return RespValue::error(WRONGTYPE),
+            },
+            None => Bytes::new(),
+        };
+
+        if patch.is_empty() {
+            return RespValue::integer(current.len() as i64);
+        }
+
+        // Checked before the zero-filled buffer is allocated
+        let end = offset.saturating_add(patch.len());
+        if let Err(e) = check_string_length(end) {
+            return RespValue::error(format!("ERR {}", e));
+        }
+
+        let mut bytes = current.to_vec();
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[offset..end].copy_from_slice(&patch);
+        let len = bytes.len();
+
+        store_string(ctx, key, Bytes::from(bytes));
+
+        RespValue::integer(len as i64)
+    }
+
+    fn name(&self) -> &'static str {
+        "SETRANGE"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(3)
+    }
+}
+
+/// GETRANGE command - Get part of a string
+///
+/// Syntax: GETRANGE key start end
+///
+/// `start` and `end` are inclusive byte indexes, negative ones counting from
+/// the end; out of bounds indexes are clamped. A missing key is an empty
+/// string.
+pub struct GetRangeCommand;
+
+impl Command for GetRangeCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() != 3 {
+            return RespValue::error("ERR wrong number of arguments for 'GETRANGE' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let start = match extract_integer(&args[1]) {
+            Ok(i) => i,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let end = match extract_integer(&args[2]) {
+            Ok(i) => i,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let bytes = match ctx.store.lookup_read(key) {
+            Some(value) => match value.as_string_bytes() {
+                Some(b) => b,
+                None => return wrong_type("GETRANGE", key, value.type_name()),
+            },
+            None => Bytes::new(),
+        };
+
+        match normalize_range(start, end, bytes.len()) {
+            Some((start, end)) => RespValue::bulk_string(bytes.slice(start..=end)),
+            None => RespValue::bulk_string(Bytes::new()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "GETRANGE"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly"]
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(3)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bytes::Bytes;
+    use crate::commands::{args, MAX_STRING_SIZE};
 
     #[test]
     fn test_set_get() {
@@ -120,6 +770,194 @@ mod tests {
         assert_eq!(result, RespValue::bulk_string(Bytes::from("myvalue")));
     }
 
+    fn set(ctx: &mut CommandContext, parts: &[&str]) -> RespValue {
+        SetCommand.execute(ctx, &args(parts))
+    }
+
+    #[test]
+    fn test_set_get_option() {
+        let mut ctx = CommandContext::new();
+
+        // Missing key: nil, and the value is set
+        assert_eq!(set(&mut ctx, &["k", "v1", "GET"]), RespValue::null());
+        assert_eq!(set(&mut ctx, &["k", "v2", "GET"]), RespValue::bulk_string(Bytes::from("v1")));
+
+        // NX on an existing key: not set, but the old value is returned
+        assert_eq!(set(&mut ctx, &["k", "v3", "NX", "GET"]), RespValue::bulk_string(Bytes::from("v2")));
+        assert_eq!(set(&mut ctx, &["k", "v3", "NX"]), RespValue::null());
+        assert_eq!(ctx.store.get(&Bytes::from("k")), Some(&Value::string("v2")));
+
+        // XX on a missing key: not set either
+        assert_eq!(set(&mut ctx, &["other", "v", "XX", "GET"]), RespValue::null());
+        assert!(!ctx.store.exists(&Bytes::from("other")));
+
+        // Numbers stored as integers are returned as strings
+        set(&mut ctx, &["n", "42"]);
+        assert_eq!(set(&mut ctx, &["n", "43", "GET"]), RespValue::bulk_string(Bytes::from("42")));
+
+        // Non-string old value: WRONGTYPE and nothing written
+        ctx.store.set("list", Value::empty_list());
+        assert_eq!(set(&mut ctx, &["list", "v", "GET"]), RespValue::error(WRONGTYPE));
+        assert!(ctx.store.get(&Bytes::from("list")).is_some_and(|v| v.as_list().is_some()));
+    }
+
+    #[test]
+    fn test_set_expire_options() {
+        let mut ctx = CommandContext::new();
+        let key = Bytes::from("k");
+
+        assert_eq!(set(&mut ctx, &["k", "v", "EX", "100"]), RespValue::simple_string("OK"));
+        assert!((99..=100).contains(&ctx.store.ttl(&key)));
+        assert_eq!(set(&mut ctx, &["k", "v", "KEEPTTL"]), RespValue::simple_string("OK"));
+        assert!((99..=100).contains(&ctx.store.ttl(&key)));
+        assert_eq!(set(&mut ctx, &["k", "v", "PX", "5000"]), RespValue::simple_string("OK"));
+        assert!((4..=5).contains(&ctx.store.ttl(&key)));
+        assert_eq!(set(&mut ctx, &["k", "v"]), RespValue::simple_string("OK"));
+        assert_eq!(ctx.store.ttl(&key), -1);
+
+        let syntax_error = RespValue::error("ERR syntax error");
+        assert_eq!(set(&mut ctx, &["k", "v", "NX", "XX"]), syntax_error);
+        assert_eq!(set(&mut ctx, &["k", "v", "EX", "1", "KEEPTTL"]), syntax_error);
+        assert_eq!(set(&mut ctx, &["k", "v", "EX"]), syntax_error);
+        assert_eq!(set(&mut ctx, &["k", "v", "LATER"]), syntax_error);
+        assert_eq!(set(&mut ctx, &["k", "v", "EX", "0"]), RespValue::error("ERR invalid expire time in 'set' command"));
+        assert_eq!(set(&mut ctx, &["k", "v", "EX", "x"]), RespValue::error("ERR value is not an integer or out of range"));
+    }
+
+    #[test]
+    fn test_setnx_and_set_nx() {
+        let mut ctx = CommandContext::new();
+        let key = Bytes::from("k");
+
+        assert_eq!(SetNxCommand.execute(&mut ctx, &args(&["k", "1"])), RespValue::integer(1));
+        assert_eq!(SetNxCommand.execute(&mut ctx, &args(&["k", "2"])), RespValue::integer(0));
+        assert_eq!(ctx.store.get(&key), Some(&Value::Integer(1)));
+
+        assert_eq!(set(&mut ctx, &["k", "3", "NX"]), RespValue::null());
+        assert_eq!(set(&mut ctx, &["new", "v", "NX", "EX", "100"]), RespValue::simple_string("OK"));
+        assert!((99..=100).contains(&ctx.store.ttl(&Bytes::from("new"))));
+
+        // An expired key counts as absent
+        ctx.store.set_expire_at(&key, Instant::now());
+        assert_eq!(SetNxCommand.execute(&mut ctx, &args(&["k", "4"])), RespValue::integer(1));
+        assert_eq!(ctx.store.get(&key), Some(&Value::Integer(4)));
+        assert_eq!(ctx.store.ttl(&key), -1);
+    }
+
+    #[test]
+    fn test_cas() {
+        let mut ctx = CommandContext::new();
+        let key = Bytes::from("lock");
+
+        // A missing key is only set with NX
+        assert_eq!(CasCommand.execute(&mut ctx, &args(&["lock", "owner-a", "IFEQ", ""])), RespValue::integer(0));
+        assert_eq!(CasCommand.execute(&mut ctx, &args(&["lock", "owner-a", "IFEQ", "(nil)"])), RespValue::integer(0));
+        assert_eq!(ctx.store.get(&key), None);
+        assert_eq!(CasCommand.execute(&mut ctx, &args(&["lock", "owner-a", "nx"])), RespValue::integer(1));
+
+        // Match, then mismatch
+        assert_eq!(CasCommand.execute(&mut ctx, &args(&["lock", "owner-b", "IFEQ", "owner-a"])), RespValue::integer(1));
+        assert_eq!(CasCommand.execute(&mut ctx, &args(&["lock", "owner-c", "IFEQ", "owner-a"])), RespValue::integer(0));
+        assert_eq!(CasCommand.execute(&mut ctx, &args(&["lock", "owner-c", "NX"])), RespValue::integer(0));
+        assert_eq!(ctx.store.get(&key), Some(&Value::string("owner-b")));
+
+        // Any string can be compared, "(nil)" included
+        ctx.store.set("nil", Value::string("(nil)"));
+        assert_eq!(CasCommand.execute(&mut ctx, &args(&["nil", "set", "IFEQ", "(nil)"])), RespValue::integer(1));
+
+        let syntax_error = RespValue::error("ERR syntax error");
+        assert_eq!(CasCommand.execute(&mut ctx, &args(&["lock", "owner-a", "owner-b"])), syntax_error);
+        assert_eq!(CasCommand.execute(&mut ctx, &args(&["lock", "owner-a", "NX", "extra"])), syntax_error);
+        assert_eq!(CasCommand.execute(&mut ctx, &args(&["lock", "owner-a", "IFEQ"])), syntax_error);
+
+        ctx.store.set("list", Value::list([Bytes::from("a")]));
+        assert_eq!(CasCommand.execute(&mut ctx, &args(&["list", "b", "IFEQ", "a"])), RespValue::error(WRONGTYPE));
+
+        // NX only checks that the key exists, whatever its type
+        assert_eq!(CasCommand.execute(&mut ctx, &args(&["list", "b", "NX"])), RespValue::integer(0));
+        assert_eq!(ctx.store.get(&Bytes::from("list")), Some(&Value::list([Bytes::from("a")])));
+    }
+
+    #[test]
+    fn test_lock_acquire_and_release() {
+        let mut ctx = CommandContext::new();
+        let key = Bytes::from("resource");
+
+        assert_eq!(set(&mut ctx, &["resource", "token-a", "NX", "PX", "30000"]), RespValue::simple_string("OK"));
+        assert_eq!(set(&mut ctx, &["resource", "token-b", "NX", "PX", "30000"]), RespValue::null());
+
+        // The wrong token leaves the lock held
+        assert_eq!(LReleaseCommand.execute(&mut ctx, &args(&["resource", "token-b"])), RespValue::integer(0));
+        assert_eq!(ctx.store.get(&key), Some(&Value::string("token-a")));
+
+        assert_eq!(LReleaseCommand.execute(&mut ctx, &args(&["resource", "token-a"])), RespValue::integer(1));
+        assert_eq!(ctx.store.get(&key), None);
+        assert_eq!(LReleaseCommand.execute(&mut ctx, &args(&["resource", "token-a"])), RespValue::integer(0));
+    }
+
+    #[test]
+    fn test_setex_psetex() {
+        let mut ctx = CommandContext::new();
+        let key = Bytes::from("k");
+
+        assert_eq!(SetExCommand.execute(&mut ctx, &args(&["k", "100", "v"])), RespValue::simple_string("OK"));
+        assert!((99..=100).contains(&ctx.store.ttl(&key)));
+        assert_eq!(PSetExCommand.execute(&mut ctx, &args(&["k", "5000", "v2"])), RespValue::simple_string("OK"));
+        assert!((4..=5).contains(&ctx.store.ttl(&key)));
+        assert_eq!(ctx.store.get(&key), Some(&Value::string("v2")));
+
+        // A non-positive TTL is refused and the key left as it was
+        for ttl in ["0", "-5"] {
+            assert_eq!(
+                SetExCommand.execute(&mut ctx, &args(&["k", ttl, "other"])),
+                RespValue::error("ERR invalid expire time in 'setex' command")
+            );
+            assert_eq!(
+                PSetExCommand.execute(&mut ctx, &args(&["k", ttl, "other"])),
+                RespValue::error("ERR invalid expire time in 'psetex' command")
+            );
+        }
+        assert_eq!(
+            SetExCommand.execute(&mut ctx, &args(&["k", "9223372036854775807", "other"])),
+            RespValue::error("ERR invalid expire time in 'setex' command")
+        );
+        assert_eq!(ctx.store.get(&key), Some(&Value::string("v2")));
+        assert_eq!(
+            SetExCommand.execute(&mut ctx, &args(&["k", "x", "v"])),
+            RespValue::error("ERR value is not an integer or out of range")
+        );
+    }
+
+    #[test]
+    fn test_set_absolute_expire_options() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let mut ctx = CommandContext::new();
+        let key = Bytes::from("k");
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
+
+        // In the future: set with the TTL left until then
+        let exat = (now_ms / 1000 + 100).to_string();
+        assert_eq!(set(&mut ctx, &["k", "v", "EXAT", &exat]), RespValue::simple_string("OK"));
+        assert!((99..=100).contains(&ctx.store.ttl(&key)));
+        let pxat = (now_ms + 5000).to_string();
+        assert_eq!(set(&mut ctx, &["k", "v", "PXAT", &pxat]), RespValue::simple_string("OK"));
+        assert!((4..=5).contains(&ctx.store.ttl(&key)));
+
+        // In the past: the key is gone, the previous value still returned
+        let past = (now_ms - 1000).to_string();
+        assert_eq!(set(&mut ctx, &["k", "w", "PXAT", &past, "GET"]), RespValue::bulk_string("v"));
+        assert!(!ctx.store.exists(&key));
+        assert_eq!(ctx.store.len(), 0);
+
+        let syntax_error = RespValue::error("ERR syntax error");
+        assert_eq!(set(&mut ctx, &["k", "v", "EXAT", &exat, "EX", "10"]), syntax_error);
+        assert_eq!(set(&mut ctx, &["k", "v", "PX", "10", "PXAT", &pxat]), syntax_error);
+        assert_eq!(set(&mut ctx, &["k", "v", "KEEPTTL", "EXAT", &exat]), syntax_error);
+        assert_eq!(set(&mut ctx, &["k", "v", "EXAT", &exat, "PXAT", &pxat]), syntax_error);
+        assert_eq!(set(&mut ctx, &["k", "v", "EXAT", "0"]), RespValue::error("ERR invalid expire time in 'set' command"));
+    }
+
     #[test]
     fn test_get_nonexistent() {
         let mut ctx = CommandContext::new();
@@ -129,4 +967,191 @@ mod tests {
         let result = get_cmd.execute(&mut ctx, &args);
         assert_eq!(result, RespValue::null());
     }
+
+    #[test]
+    fn test_append_logs_suffix() {
+        use crate::aof::{replay_entries, AofReader, AofWriter, SyncPolicy};
+        use std::sync::Arc;
+
+        let path = std::env::temp_dir().join(format!("ferrumdb_append_{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let writer = Arc::new(AofWriter::new(&path, SyncPolicy::No).unwrap());
+
+        let mut ctx = CommandContext::new();
+        ctx.set_aof_writer(writer.clone());
+        SetCommand.execute(&mut ctx, &args(&["log", "first"]));
+        AppendCommand.execute(&mut ctx, &args(&["log", ",second"]));
+        AppendCommand.execute(&mut ctx, &args(&["log", ",third"]));
+        AppendCommand.execute(&mut ctx, &args(&["new", "x"]));
+        writer.flush().unwrap();
+
+        // Each APPEND logs its suffix only, never the whole value
+        let entries = AofReader::load(&path).unwrap().parse_entries();
+        let logged: Vec<_> = entries.iter().map(|entry| (entry.op, entry.payload.clone())).collect();
+        assert_eq!(
+            logged,
+            vec![
+                (AofOperation::Set, vec![Bytes::from("first")]),
+                (AofOperation::Append, vec![Bytes::from(",second")]),
+                (AofOperation::Append, vec![Bytes::from(",third")]),
+                (AofOperation::Append, vec![Bytes::from("x")]),
+            ]
+        );
+
+        let mut replayed = CommandContext::new();
+        replay_entries(&mut replayed.store, entries).unwrap();
+        assert_eq!(GetCommand.execute(&mut replayed, &args(&["log"])), RespValue::bulk_string("first,second,third"));
+        assert_eq!(GetCommand.execute(&mut replayed, &args(&["new"])), RespValue::bulk_string("x"));
+
+        drop(ctx);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_append_setrange() {
+        let mut ctx = CommandContext::new();
+
+        let args = vec![RespValue::bulk_string("greeting"), RespValue::bulk_string("Hello")];
+        assert_eq!(AppendCommand.execute(&mut ctx, &args), RespValue::integer(5));
+        let args = vec![RespValue::bulk_string("greeting"), RespValue::bulk_string(" World")];
+        assert_eq!(AppendCommand.execute(&mut ctx, &args), RespValue::integer(11));
+
+        let args = vec![
+            RespValue::bulk_string("greeting"),
+            RespValue::bulk_string("6"),
+            RespValue::bulk_string("Redis"),
+        ];
+        assert_eq!(SetRangeCommand.execute(&mut ctx, &args), RespValue::integer(11));
+        let args = vec![RespValue::bulk_string("greeting")];
+        assert_eq!(GetCommand.execute(&mut ctx, &args), RespValue::bulk_string("Hello Redis"));
+
+        // Past the end, the gap is zero-filled
+        let args = vec![
+            RespValue::bulk_string("padded"),
+            RespValue::bulk_string("2"),
+            RespValue::bulk_string("x"),
+        ];
+        assert_eq!(SetRangeCommand.execute(&mut ctx, &args), RespValue::integer(3));
+        let args = vec![RespValue::bulk_string("padded")];
+        assert_eq!(GetCommand.execute(&mut ctx, &args), RespValue::bulk_string(Bytes::from_static(b"\0\0x")));
+    }
+
+    #[test]
+    fn test_string_commands_on_incremented_key() {
+        use crate::commands::counter::IncrCommand;
+
+        let mut ctx = CommandContext::new();
+        let key = Bytes::from("counter");
+        for _ in 0..5 {
+            IncrCommand.execute(&mut ctx, &args(&["counter"]));
+        }
+        assert_eq!(ctx.store.get(&key), Some(&Value::Integer(5)));
+        assert_eq!(GetRangeCommand.execute(&mut ctx, &args(&["counter", "0", "-1"])), RespValue::bulk_string("5"));
+
+        // APPEND sees the decimal form, and leaves a string
+        assert_eq!(AppendCommand.execute(&mut ctx, &args(&["counter", "0x"])), RespValue::integer(3));
+        assert_eq!(ctx.store.get(&key), Some(&Value::string("50x")));
+        assert_eq!(GetCommand.execute(&mut ctx, &args(&["counter"])), RespValue::bulk_string("50x"));
+
+        // Even when the result is still a number
+        ctx.store.set("n", Value::Integer(-12));
+        assert_eq!(AppendCommand.execute(&mut ctx, &args(&["n", "3"])), RespValue::integer(4));
+        assert_eq!(ctx.store.get(&Bytes::from("n")), Some(&Value::string("-123")));
+        assert_eq!(IncrCommand.execute(&mut ctx, &args(&["n"])), RespValue::integer(-122));
+
+        ctx.store.set("n", Value::Integer(1000));
+        assert_eq!(SetRangeCommand.execute(&mut ctx, &args(&["n", "1", "ab"])), RespValue::integer(4));
+        assert_eq!(GetCommand.execute(&mut ctx, &args(&["n"])), RespValue::bulk_string("1ab0"));
+    }
+
+    #[test]
+    fn test_string_size_limit() {
+        let mut ctx = CommandContext::new();
+
+        // Rejected before the zero-filled buffer is allocated
+        let args = vec![
+            RespValue::bulk_string("huge"),
+            RespValue::bulk_string(MAX_STRING_SIZE.to_string()),
+            RespValue::bulk_string("x"),
+        ];
+        assert_eq!(
+            SetRangeCommand.execute(&mut ctx, &args),
+            RespValue::error("ERR string exceeds maximum allowed size (proto-max-bulk-len)")
+        );
+        assert!(!ctx.store.exists(&Bytes::from("huge")));
+
+        let args = vec![
+            RespValue::bulk_string("huge"),
+            RespValue::bulk_string("-1"),
+            RespValue::bulk_string("x"),
+        ];
+        assert_eq!(SetRangeCommand.execute(&mut ctx, &args), RespValue::error("ERR offset is out of range"));
+
+        // SETBIT offsets are bounded by the same size
+        let args = vec![
+            RespValue::bulk_string("huge"),
+            RespValue::bulk_string((MAX_STRING_SIZE * 8).to_string()),
+            RespValue::bulk_string("1"),
+        ];
+        assert_eq!(
+            crate::commands::bitmap::SetBitCommand.execute(&mut ctx, &args),
+            RespValue::error("ERR bit offset is not an integer or out of range")
+        );
+        assert!(!ctx.store.exists(&Bytes::from("huge")));
+    }
+
+    #[test]
+    fn test_wrongtype_is_identical_everywhere() {
+        use crate::commands::counter::IncrCommand;
+        use crate::commands::list::LPushCommand;
+        use crate::protocol::RespEncoder;
+
+        let expected = b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let mut ctx = CommandContext::new();
+        ctx.store.set("list", Value::list([Bytes::from("a")]));
+        ctx.store.set("string", Value::string("a"));
+
+        let replies = [
+            GetCommand.execute(&mut ctx, &[RespValue::bulk_string("list")]),
+            IncrCommand.execute(&mut ctx, &[RespValue::bulk_string("list")]),
+            LPushCommand.execute(&mut ctx, &[RespValue::bulk_string("string"), RespValue::bulk_string("b")]),
+        ];
+        for reply in replies {
+            assert_eq!(&RespEncoder::encode(&reply)[..], &expected[..]);
+        }
+
+        // Strict mode only changes the logs, never the reply
+        crate::commands::set_strict_type_errors(true);
+        let strict = GetCommand.execute(&mut ctx, &[RespValue::bulk_string("list")]);
+        crate::commands::set_strict_type_errors(false);
+        assert_eq!(&RespEncoder::encode(&strict)[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_getrange() {
+        let mut ctx = CommandContext::new();
+        set(&mut ctx, &["k", "Hello World"]);
+        let getrange = |ctx: &mut CommandContext, key: &str, start: &str, end: &str| {
+            GetRangeCommand.execute(ctx, &args(&[key, start, end]))
+        };
+        let bulk = |s: &str| RespValue::bulk_string(Bytes::from(s.to_string()));
+
+        assert_eq!(getrange(&mut ctx, "k", "0", "4"), bulk("Hello"));
+        assert_eq!(getrange(&mut ctx, "k", "0", "-1"), bulk("Hello World"));
+        assert_eq!(getrange(&mut ctx, "k", "-5", "-1"), bulk("World"));
+
+        // Out of bounds indexes clamp instead of failing
+        assert_eq!(getrange(&mut ctx, "k", "-100", "100"), bulk("Hello World"));
+        assert_eq!(getrange(&mut ctx, "k", "6", "9223372036854775807"), bulk("World"));
+        assert_eq!(getrange(&mut ctx, "k", "0", "-100"), bulk(""));
+        assert_eq!(getrange(&mut ctx, "k", "-1", "-9223372036854775808"), bulk(""));
+        assert_eq!(getrange(&mut ctx, "k", "20", "30"), bulk(""));
+        assert_eq!(getrange(&mut ctx, "k", "5", "3"), bulk(""));
+
+        // Integers are ranged over their decimal form, missing keys are empty
+        set(&mut ctx, &["n", "12345"]);
+        assert_eq!(getrange(&mut ctx, "n", "1", "2"), bulk("23"));
+        assert_eq!(getrange(&mut ctx, "missing", "0", "-1"), bulk(""));
+    }
+
 }
@@ -1,13 +1,18 @@
-//! TTL commands (EXPIRE, TTL)
+//! TTL commands (EXPIRE, TTL, PTTL)
 
 use super::{Command, CommandContext, extract_bulk_string, extract_integer, log_to_aof};
 use crate::protocol::RespValue;
 use crate::aof::AofOperation;
+use crate::store::{expire_jitter_percent, instant_to_unix_ms};
 use bytes::Bytes;
 
 /// EXPIRE command - Set a timeout on a key
 ///
 /// Syntax: EXPIRE key seconds
+///
+/// A TTL of 0 or less deletes the key, and counts as set (reply 1) when the
+/// key existed. A TTL jittered by `expire-jitter-percent` is logged as the
+/// absolute time it was given, which replays to the same expiry.
 pub struct ExpireCommand;
 
 impl Command for ExpireCommand {
@@ -31,13 +36,22 @@ impl Command for ExpireCommand {
 
         // Set expiration
         if ctx.store.expire(key, seconds) {
-            // Log to AOF
-            log_to_aof(
-                ctx,
-                AofOperation::Expire,
-                key.clone(),
-                vec![Bytes::from(seconds.to_string())],
-            );
+            let expire_at = ctx.store.get_entry(key).and_then(|entry| entry.expire_at);
+
+            // Log to AOF, as the DEL it amounts to for a non-positive TTL
+            if seconds <= 0 {
+                log_to_aof(ctx, AofOperation::Del, key.clone(), vec![]);
+            } else if let Some(at) = expire_at.filter(|_| expire_jitter_percent() > 0) {
+                let unix_ms = instant_to_unix_ms(at);
+                log_to_aof(ctx, AofOperation::PExpireAt, key.clone(), vec![Bytes::from(unix_ms.to_string())]);
+            } else {
+                log_to_aof(
+                    ctx,
+                    AofOperation::Expire,
+                    key.clone(),
+                    vec![Bytes::from(seconds.to_string())],
+                );
+            }
             RespValue::integer(1)
         } else {
             RespValue::integer(0)
@@ -48,6 +62,10 @@ impl Command for ExpireCommand {
         "EXPIRE"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         2
     }
@@ -89,6 +107,44 @@ impl Command for TtlCommand {
         "TTL"
     }
 
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(1)
+    }
+}
+
+/// PTTL command - Get the time to live for a key in milliseconds
+///
+/// Syntax: PTTL key
+///
+/// Same replies as TTL, in milliseconds.
+pub struct PttlCommand;
+
+impl Command for PttlCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        RespValue::integer(ctx.store.pttl(key))
+    }
+
+    fn name(&self) -> &'static str {
+        "PTTL"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly", "fast"]
+    }
+
     fn min_args(&self) -> usize {
         1
     }
@@ -123,12 +179,27 @@ mod tests {
         let args = vec![RespValue::bulk_string("key1")];
         let result = ttl_cmd.execute(&mut ctx, &args);
         if let RespValue::Integer(ttl) = result {
-            assert!(ttl >= 99 && ttl <= 100);
+            assert!((99..=100).contains(&ttl));
         } else {
             panic!("Expected integer response");
         }
     }
 
+    #[test]
+    fn test_expire_non_positive_deletes() {
+        let mut ctx = CommandContext::new();
+        for ttl in ["0", "-1"] {
+            ctx.store.set("key1", Value::string("value1"));
+            let args = vec![RespValue::bulk_string("key1"), RespValue::bulk_string(ttl)];
+            assert_eq!(ExpireCommand.execute(&mut ctx, &args), RespValue::integer(1));
+            assert_eq!(TtlCommand.execute(&mut ctx, &args[..1]), RespValue::integer(-2));
+            assert!(!ctx.store.exists(&Bytes::from("key1")));
+
+            // Nothing left to delete
+            assert_eq!(ExpireCommand.execute(&mut ctx, &args), RespValue::integer(0));
+        }
+    }
+
     #[test]
     fn test_ttl_no_key() {
         let mut ctx = CommandContext::new();
@@ -150,4 +221,20 @@ mod tests {
         let result = ttl_cmd.execute(&mut ctx, &args);
         assert_eq!(result, RespValue::integer(-1));
     }
+
+    #[test]
+    fn test_pttl() {
+        let mut ctx = CommandContext::new();
+        let args = vec![RespValue::bulk_string("key1")];
+        assert_eq!(PttlCommand.execute(&mut ctx, &args), RespValue::integer(-2));
+
+        ctx.store.set("key1", Value::string("value1"));
+        assert_eq!(PttlCommand.execute(&mut ctx, &args), RespValue::integer(-1));
+
+        ctx.store.expire(&Bytes::from("key1"), 10);
+        match PttlCommand.execute(&mut ctx, &args) {
+            RespValue::Integer(ms) => assert!((9_000..=10_000).contains(&ms), "{}", ms),
+            other => panic!("Expected integer response, got {:?}", other),
+        }
+    }
 }
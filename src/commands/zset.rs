@@ -0,0 +1,401 @@
+//! Sorted set commands (ZADD, ZCARD, ZSCORE, ZPOPMIN, ZPOPMAX)
+
+use super::{Command, CommandContext, extract_bulk_string, extract_integer, log_to_aof, parse_redis_float, WRONGTYPE};
+use crate::protocol::RespValue;
+use crate::store::Value;
+use crate::aof::AofOperation;
+use bytes::Bytes;
+
+/// Parse a score argument (accepts "inf", "+inf" and "-inf" like Redis)
+fn parse_score(value: &RespValue) -> Result<f64, &'static str> {
+    parse_redis_float(extract_bulk_string(value)?)
+}
+
+/// Format a score the way Redis does ("1" rather than "1.0", "inf" for infinity)
+pub(crate) fn format_score(score: f64) -> String {
+    if score.is_infinite() {
+        if score > 0.0 { "inf".to_string() } else { "-inf".to_string() }
+    } else {
+        score.to_string()
+    }
+}
+
+/// ZADD command - Add one or more members to a sorted set, or update their scores
+///
+/// Syntax: ZADD key score member [score member ...]
+pub struct ZAddCommand;
+
+impl Command for ZAddCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() < 3 || !(args.len() - 1).is_multiple_of(2) {
+            return RespValue::error("ERR wrong number of arguments for 'ZADD' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k.clone(),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        // Parse all score/member pairs before touching the store
+        let mut pairs = Vec::with_capacity((args.len() - 1) / 2);
+        for pair in args[1..].chunks(2) {
+            let score = match parse_score(&pair[0]) {
+                Ok(s) => s,
+                Err(e) => return RespValue::error(format!("ERR {}", e)),
+            };
+            let member = match extract_bulk_string(&pair[1]) {
+                Ok(m) => m.clone(),
+                Err(e) => return RespValue::error(format!("ERR {}", e)),
+            };
+            pairs.push((score, member));
+        }
+
+        // Get or create sorted set
+        let zset = match ctx.store.get_mut(&key) {
+            Some(value) => {
+                match value.as_zset_mut() {
+                    Some(zset) => zset,
+                    None => return RespValue::error(WRONGTYPE),
+                }
+            }
+            None => {
+                ctx.store.set(key.clone(), Value::empty_zset());
+                ctx.store.get_mut(&key).unwrap().as_zset_mut().unwrap()
+            }
+        };
+
+        let mut added = 0;
+        let mut payload = Vec::with_capacity(pairs.len() * 2);
+        for (score, member) in pairs {
+            if zset.insert(member.clone(), score) {
+                added += 1;
+            }
+            payload.push(Bytes::from(format_score(score)));
+            payload.push(member);
+        }
+        ctx.store.notify_modified(&key);
+
+        log_to_aof(ctx, AofOperation::ZAdd, key, payload);
+
+        RespValue::integer(added)
+    }
+
+    fn name(&self) -> &'static str {
+        "ZADD"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        3
+    }
+}
+
+/// ZCARD command - Get the number of members in a sorted set
+///
+/// Syntax: ZCARD key
+pub struct ZCardCommand;
+
+impl Command for ZCardCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.is_empty() {
+            return RespValue::error("ERR wrong number of arguments for 'ZCARD' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        match ctx.store.get(key) {
+            Some(value) => {
+                match value.as_zset() {
+                    Some(zset) => RespValue::integer(zset.len() as i64),
+                    None => RespValue::error(WRONGTYPE),
+                }
+            }
+            None => RespValue::integer(0),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "ZCARD"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(1)
+    }
+}
+
+/// ZSCORE command - Get the score of a member in a sorted set
+///
+/// Syntax: ZSCORE key member
+pub struct ZScoreCommand;
+
+impl Command for ZScoreCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        if args.len() < 2 {
+            return RespValue::error("ERR wrong number of arguments for 'ZSCORE' command");
+        }
+
+        let key = match extract_bulk_string(&args[0]) {
+            Ok(k) => k,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        let member = match extract_bulk_string(&args[1]) {
+            Ok(m) => m,
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        };
+
+        match ctx.store.get(key) {
+            Some(value) => {
+                match value.as_zset() {
+                    Some(zset) => match zset.score(member) {
+                        Some(score) => RespValue::bulk_string(format_score(score)),
+                        None => RespValue::null(),
+                    },
+                    None => RespValue::error(WRONGTYPE),
+                }
+            }
+            None => RespValue::null(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "ZSCORE"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["readonly", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+/// Shared implementation of ZPOPMIN / ZPOPMAX
+///
+/// Pops up to `count` members (default 1) and returns them as a flat
+/// `[member, score, ...]` array. The key is deleted once the set is emptied.
+fn pop_members(ctx: &mut CommandContext, args: &[RespValue], name: &str, from_max: bool) -> RespValue {
+    if args.is_empty() || args.len() > 2 {
+        return RespValue::error(format!("ERR wrong number of arguments for '{}' command", name));
+    }
+
+    let key = match extract_bulk_string(&args[0]) {
+        Ok(k) => k.clone(),
+        Err(e) => return RespValue::error(format!("ERR {}", e)),
+    };
+
+    let count = match args.get(1) {
+        Some(arg) => match extract_integer(arg) {
+            Ok(c) if c >= 0 => c as usize,
+            Ok(_) => return RespValue::error("ERR value is out of range, must be positive"),
+            Err(e) => return RespValue::error(format!("ERR {}", e)),
+        },
+        None => 1,
+    };
+
+    let popped = match ctx.store.get_mut(&key) {
+        Some(value) => {
+            match value.as_zset_mut() {
+                Some(zset) => if from_max { zset.pop_max(count) } else { zset.pop_min(count) },
+                None => return RespValue::error(WRONGTYPE),
+            }
+        }
+        None => return RespValue::array(vec![]),
+    };
+    if !popped.is_empty() {
+        ctx.store.notify_modified(&key);
+    }
+    ctx.store.remove_if_empty(&key);
+
+    if !popped.is_empty() {
+        let members = popped.iter().map(|(member, _)| member.clone()).collect();
+        log_to_aof(ctx, AofOperation::ZRem, key, members);
+    }
+
+    let mut result = Vec::with_capacity(popped.len() * 2);
+    for (member, score) in popped {
+        result.push(RespValue::bulk_string(member));
+        result.push(RespValue::bulk_string(format_score(score)));
+    }
+
+    RespValue::array(result)
+}
+
+/// ZPOPMIN command - Remove and return the members with the lowest scores
+///
+/// Syntax: ZPOPMIN key [count]
+pub struct ZPopMinCommand;
+
+impl Command for ZPopMinCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        pop_members(ctx, args, "ZPOPMIN", false)
+    }
+
+    fn name(&self) -> &'static str {
+        "ZPOPMIN"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+/// ZPOPMAX command - Remove and return the members with the highest scores
+///
+/// Syntax: ZPOPMAX key [count]
+pub struct ZPopMaxCommand;
+
+impl Command for ZPopMaxCommand {
+    fn execute(&self, ctx: &mut CommandContext, args: &[RespValue]) -> RespValue {
+        pop_members(ctx, args, "ZPOPMAX", true)
+    }
+
+    fn name(&self) -> &'static str {
+        "ZPOPMAX"
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "fast"]
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zadd_tasks(ctx: &mut CommandContext) {
+        // ZADD tasks 1 low 5 mid 10 high
+        let args = vec![
+            RespValue::bulk_string("tasks"),
+            RespValue::bulk_string("1"),
+            RespValue::bulk_string("low"),
+            RespValue::bulk_string("5"),
+            RespValue::bulk_string("mid"),
+            RespValue::bulk_string("10"),
+            RespValue::bulk_string("high"),
+        ];
+        let result = ZAddCommand.execute(ctx, &args);
+        assert_eq!(result, RespValue::integer(3));
+    }
+
+    #[test]
+    fn test_zadd_zscore_zcard() {
+        let mut ctx = CommandContext::new();
+        zadd_tasks(&mut ctx);
+
+        let args = vec![RespValue::bulk_string("tasks"), RespValue::bulk_string("mid")];
+        let result = ZScoreCommand.execute(&mut ctx, &args);
+        assert_eq!(result, RespValue::bulk_string("5"));
+
+        let args = vec![RespValue::bulk_string("tasks")];
+        let result = ZCardCommand.execute(&mut ctx, &args);
+        assert_eq!(result, RespValue::integer(3));
+    }
+
+    #[test]
+    fn test_zadd_invalid_score() {
+        let mut ctx = CommandContext::new();
+
+        // Non-numeric and overflowing scores get the same Redis error
+        for score in ["abc", "1e400", "nan", " 1"] {
+            let args = vec![
+                RespValue::bulk_string("tasks"),
+                RespValue::bulk_string(score),
+                RespValue::bulk_string("member"),
+            ];
+            let result = ZAddCommand.execute(&mut ctx, &args);
+            assert_eq!(result, RespValue::error("ERR value is not a valid float"));
+        }
+
+        let args = vec![
+            RespValue::bulk_string("tasks"),
+            RespValue::bulk_string("-INF"),
+            RespValue::bulk_string("member"),
+        ];
+        assert_eq!(ZAddCommand.execute(&mut ctx, &args), RespValue::integer(1));
+    }
+
+    #[test]
+    fn test_zpopmin_zpopmax() {
+        let mut ctx = CommandContext::new();
+        zadd_tasks(&mut ctx);
+
+        // ZPOPMIN tasks
+        let args = vec![RespValue::bulk_string("tasks")];
+        let result = ZPopMinCommand.execute(&mut ctx, &args);
+        assert_eq!(result, RespValue::array(vec![
+            RespValue::bulk_string("low"),
+            RespValue::bulk_string("1"),
+        ]));
+
+        // ZPOPMAX tasks
+        let result = ZPopMaxCommand.execute(&mut ctx, &args);
+        assert_eq!(result, RespValue::array(vec![
+            RespValue::bulk_string("high"),
+            RespValue::bulk_string("10"),
+        ]));
+
+        let result = ZCardCommand.execute(&mut ctx, &args);
+        assert_eq!(result, RespValue::integer(1));
+    }
+
+    #[test]
+    fn test_zpop_more_than_cardinality_removes_key() {
+        let mut ctx = CommandContext::new();
+        zadd_tasks(&mut ctx);
+
+        // ZPOPMAX tasks 10 returns all three members, highest first
+        let args = vec![RespValue::bulk_string("tasks"), RespValue::bulk_string("10")];
+        let result = ZPopMaxCommand.execute(&mut ctx, &args);
+        assert_eq!(result, RespValue::array(vec![
+            RespValue::bulk_string("high"),
+            RespValue::bulk_string("10"),
+            RespValue::bulk_string("mid"),
+            RespValue::bulk_string("5"),
+            RespValue::bulk_string("low"),
+            RespValue::bulk_string("1"),
+        ]));
+
+        // The key is removed once the last member is popped
+        assert!(!ctx.store.exists(&Bytes::from("tasks")));
+
+        // Popping a missing key returns an empty array
+        let result = ZPopMinCommand.execute(&mut ctx, &args);
+        assert_eq!(result, RespValue::array(vec![]));
+    }
+}
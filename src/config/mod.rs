@@ -0,0 +1,574 @@
+//! Runtime configuration module
+//!
+//! The parameters settable with CONFIG SET live with the subsystems that
+//! read them (process-wide statics with setters, such as
+//! `store::set_list_max_listpack_size`); this module maps their Redis names
+//! to those accessors. The server can be started with a config file, a
+//! Redis-style list of `name value` lines, which CONFIG REWRITE updates
+//! with the current values.
+
+use crate::aof;
+use crate::audit;
+use crate::cluster::{self, ShardMode, ShardRuntime};
+use crate::commands::{self, matches_pattern, parse_redis_int};
+use crate::server;
+use crate::store::{self, MaxmemoryPolicy};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A parameter settable with CONFIG SET
+struct ConfigParam {
+    /// Name of the parameter, lowercase
+    name: &'static str,
+
+    /// Current value, as written in the config file
+    get: fn() -> String,
+
+    /// Parse and apply a new value
+    set: fn(&str) -> Result<(), &'static str>,
+}
+
+fn parse_integer(value: &str) -> Result<i64, &'static str> {
+    parse_redis_int(value.as_bytes()).map_err(|_| "argument couldn't be parsed into an integer")
+}
+
+fn parse_bool(value: &str) -> Result<bool, &'static str> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'"),
+    }
+}
+
+fn format_bool(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+/// Every runtime parameter, in the order they are appended to a config file
+const PARAMS: &[ConfigParam] = &[
+    ConfigParam {
+        name: "list-max-listpack-size",
+        get: || store::list_max_listpack_size().to_string(),
+        set: |value| {
+            store::set_list_max_listpack_size(parse_integer(value)?);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "set-max-intset-entries",
+        get: || store::max_set_intset_entries().to_string(),
+        set: |value| {
+            let entries = usize::try_from(parse_integer(value)?)
+                .map_err(|_| "argument must be between 0 and 9223372036854775807 inclusive")?;
+            store::set_set_max_intset_entries(entries);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "set-max-listpack-entries",
+        get: || store::max_set_listpack_entries().to_string(),
+        set: |value| {
+            let entries = usize::try_from(parse_integer(value)?)
+                .map_err(|_| "argument must be between 0 and 9223372036854775807 inclusive")?;
+            store::set_set_max_listpack_entries(entries);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "zset-max-listpack-entries",
+        get: || store::zset_max_listpack_entries().to_string(),
+        set: |value| {
+            let entries = usize::try_from(parse_integer(value)?)
+                .map_err(|_| "argument must be between 0 and 9223372036854775807 inclusive")?;
+            store::set_zset_max_listpack_entries(entries);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "embstr-size-limit",
+        get: || store::embstr_size_limit().to_string(),
+        set: |value| {
+            let len = usize::try_from(parse_integer(value)?)
+                .map_err(|_| "argument must be between 0 and 9223372036854775807 inclusive")?;
+            store::set_embstr_size_limit(len);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "maxmemory",
+        get: || store::maxmemory().to_string(),
+        set: |value| {
+            let bytes = store::parse_memory(value).ok_or("argument must be a memory value")?;
+            store::set_maxmemory(bytes);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "maxmemory-policy",
+        get: || store::maxmemory_policy().name().to_string(),
+        set: |value| {
+            let policy = MaxmemoryPolicy::from_name(value)
+                .ok_or("argument(s) must be one of the following: noeviction, allkeys-lru, allkeys-lfu")?;
+            store::set_maxmemory_policy(policy);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "hz",
+        get: || store::hz().to_string(),
+        set: |value| {
+            // Out of range values are clamped, like Redis
+            let hz = parse_integer(value)?.clamp(0, store::MAX_HZ as i64);
+            store::set_hz(hz as u32);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "active-expire-effort",
+        get: || store::active_expire_effort().to_string(),
+        set: |value| {
+            let effort = parse_integer(value)?;
+            if !(1..=store::MAX_ACTIVE_EXPIRE_EFFORT as i64).contains(&effort) {
+                return Err("argument must be between 1 and 10 inclusive");
+            }
+            store::set_active_expire_effort(effort as u32);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "expire-jitter-percent",
+        get: || store::expire_jitter_percent().to_string(),
+        set: |value| {
+            let percent = parse_integer(value)?;
+            if !(0..=store::MAX_EXPIRE_JITTER_PERCENT as i64).contains(&percent) {
+                return Err("argument must be between 0 and 100 inclusive");
+            }
+            store::set_expire_jitter_percent(percent as u32);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        // Read when the server starts: a change applies at the next start
+        name: "auto-aof-rewrite-percentage",
+        get: || aof::auto_rewrite_percentage().to_string(),
+        set: |value| {
+            let percentage = u64::try_from(parse_integer(value)?)
+                .map_err(|_| "argument must be between 0 and 9223372036854775807 inclusive")?;
+            aof::set_auto_rewrite_percentage(percentage);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        // Read when the server starts: a change applies at the next start
+        name: "auto-aof-rewrite-min-size",
+        get: || aof::auto_rewrite_min_size().to_string(),
+        set: |value| {
+            let bytes = store::parse_memory(value).ok_or("argument must be a memory value")?;
+            aof::set_auto_rewrite_min_size(bytes);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        // Read when the server starts: a change applies at the next start
+        name: "shard-mode",
+        get: || cluster::shard_mode().name().to_string(),
+        set: |value| {
+            let mode = ShardMode::from_name(value).ok_or("argument(s) must be one of the following: actor, striped")?;
+            cluster::set_shard_mode(mode);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        // Read when the server starts: a change applies at the next start
+        name: "shard-runtime",
+        get: || cluster::shard_runtime().name().to_string(),
+        set: |value| {
+            let runtime = ShardRuntime::from_name(value)
+                .ok_or("argument(s) must be one of the following: dedicated, shared")?;
+            cluster::set_shard_runtime(runtime);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        // Read when the server starts: a change applies at the next start
+        name: "hash-tags",
+        get: || format_bool(cluster::hash_tags()),
+        set: |value| {
+            cluster::set_hash_tags(parse_bool(value)?);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        // Read when the server starts: a change applies at the next start
+        name: "expected-keys",
+        get: || cluster::expected_keys().to_string(),
+        set: |value| {
+            let keys = usize::try_from(parse_integer(value)?)
+                .map_err(|_| "argument must be between 0 and 9223372036854775807 inclusive")?;
+            cluster::set_expected_keys(keys);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        // Read when the server starts: a change applies at the next start
+        name: "audit-log",
+        get: || format_bool(audit::audit_config().enabled),
+        set: |value| {
+            audit::set_audit_log(parse_bool(value)?);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        // Read when the server starts: a change applies at the next start
+        name: "audit-log-file",
+        get: audit::audit_log_file,
+        set: |value| {
+            if value.is_empty() {
+                return Err("argument must not be empty");
+            }
+            audit::set_audit_log_file(value);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        // Read when the server starts: a change applies at the next start
+        name: "max-commands-per-second",
+        get: || server::max_commands_per_second().to_string(),
+        set: |value| {
+            let rate = u32::try_from(parse_integer(value)?)
+                .map_err(|_| "argument must be between 0 and 4294967295 inclusive")?;
+            server::set_max_commands_per_second(rate);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "disable-keys-command",
+        get: || format_bool(commands::disable_keys_command()),
+        set: |value| {
+            commands::set_disable_keys_command(parse_bool(value)?);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "keys-scan-limit",
+        get: || commands::keys_scan_limit().to_string(),
+        set: |value| {
+            let limit = usize::try_from(parse_integer(value)?)
+                .map_err(|_| "argument must be between 0 and 9223372036854775807 inclusive")?;
+            commands::set_keys_scan_limit(limit);
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "strict-type-errors",
+        get: || format_bool(commands::strict_type_errors()),
+        set: |value| {
+            commands::set_strict_type_errors(parse_bool(value)?);
+            Ok(())
+        },
+    },
+];
+
+fn find_param(name: &str) -> Option<&'static ConfigParam> {
+    PARAMS.iter().find(|param| param.name.eq_ignore_ascii_case(name))
+}
+
+/// Configuration errors, displayed as the Redis error message (without the
+/// `ERR` prefix)
+#[derive(Debug)]
+pub enum ConfigError {
+    /// CONFIG SET of a parameter that doesn't exist
+    UnknownOption(String),
+
+    /// A value the parameter can't take
+    InvalidValue { name: String, reason: &'static str },
+
+    /// A config file line that isn't `name value` for a known parameter
+    BadDirective { line: usize, content: String },
+
+    /// CONFIG REWRITE without a config file
+    NoConfigFile,
+
+    /// Reading or writing the config file failed
+    Io(io::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnknownOption(name) => {
+                write!(f, "Unknown option or number of arguments for CONFIG SET - '{}'", name)
+            }
+            ConfigError::InvalidValue { name, reason } => {
+                write!(f, "CONFIG SET failed (possibly related to argument '{}') - {}", name, reason)
+            }
+            ConfigError::BadDirective { line, content } => {
+                write!(f, "Bad directive or wrong number of arguments at line {}: {}", line, content)
+            }
+            ConfigError::NoConfigFile => write!(f, "The server is running without a config file"),
+            ConfigError::Io(e) => write!(f, "Rewriting config file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+/// Split a config file line into its name and value
+///
+/// Returns None for blank lines and comments. A value may be wrapped in
+/// double quotes.
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+    Some((name, value))
+}
+
+/// Format a `name value` line, quoting values that need it
+fn format_line(name: &str, value: &str) -> String {
+    if value.is_empty() || value.contains(char::is_whitespace) {
+        format!("{} \"{}\"", name, value)
+    } else {
+        format!("{} {}", name, value)
+    }
+}
+
+/// Runtime configuration of the server
+///
+/// Parameter values are process-wide; this only remembers the config file
+/// they were loaded from, for CONFIG REWRITE.
+#[derive(Debug)]
+pub struct ServerConfig {
+    file: Mutex<Option<PathBuf>>,
+}
+
+/// Configuration of the whole process, used by the CONFIG command
+pub static SERVER_CONFIG: ServerConfig = ServerConfig::new();
+
+impl ServerConfig {
+    /// A configuration without a config file
+    pub const fn new() -> Self {
+        ServerConfig { file: Mutex::new(None) }
+    }
+
+    /// Current value of a parameter (names are case-insensitive)
+    pub fn get(&self, name: &str) -> Option<String> {
+        find_param(name).map(|param| (param.get)())
+    }
+
+    /// Names and values of the parameters matching a glob pattern
+    pub fn matching(&self, pattern: &[u8]) -> Vec<(&'static str, String)> {
+        let pattern = pattern.to_ascii_lowercase();
+        PARAMS
+            .iter()
+            .filter(|param| matches_pattern(param.name.as_bytes(), &pattern))
+            .map(|param| (param.name, (param.get)()))
+            .collect()
+    }
+
+    /// Set a parameter
+    pub fn set(&self, name: &str, value: &str) -> Result<(), ConfigError> {
+        let param = find_param(name).ok_or_else(|| ConfigError::UnknownOption(name.to_string()))?;
+        (param.set)(value).map_err(|reason| ConfigError::InvalidValue { name: param.name.to_string(), reason })
+    }
+
+    /// Apply a config file and remember it for CONFIG REWRITE
+    ///
+    /// Every line must set a known parameter; nothing is remembered if one
+    /// doesn't (parameters on the previous lines are already applied).
+    pub fn load_file<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        for (index, line) in contents.lines().enumerate() {
+            let Some((name, value)) = parse_line(line) else {
+                continue;
+            };
+            let bad_directive = || ConfigError::BadDirective { line: index + 1, content: line.to_string() };
+            let param = find_param(name).ok_or_else(bad_directive)?;
+            (param.set)(value).map_err(|_| bad_directive())?;
+        }
+
+        *self.file.lock().unwrap() = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Config file loaded at startup, if any
+    pub fn file(&self) -> Option<PathBuf> {
+        self.file.lock().unwrap().clone()
+    }
+
+    /// Write the current values back to the config file
+    ///
+    /// Lines setting a parameter get its current value, comments and other
+    /// lines are kept, and parameters missing from the file are appended.
+    /// The file is replaced through a temporary file, so a crash leaves
+    /// either the old or the new version.
+    pub fn rewrite(&self) -> Result<(), ConfigError> {
+        let path = self.file().ok_or(ConfigError::NoConfigFile)?;
+        let contents = fs::read_to_string(&path).or_else(|e| match e.kind() {
+            io::ErrorKind::NotFound => Ok(String::new()),
+            _ => Err(e),
+        })?;
+
+        let mut written = Vec::new();
+        let mut lines = Vec::new();
+        for line in contents.lines() {
+            match parse_line(line).and_then(|(name, _)| find_param(name)) {
+                // Only the first line of a parameter is kept
+                Some(param) if written.contains(&param.name) => {}
+                Some(param) => {
+                    lines.push(format_line(param.name, &(param.get)()));
+                    written.push(param.name);
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+        for param in PARAMS.iter().filter(|param| !written.contains(&param.name)) {
+            lines.push(format_line(param.name, &(param.get)()));
+        }
+
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(".rewrite");
+        fs::write(&temp_path, lines.join("\n") + "\n")?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serializes the tests changing process-wide parameters
+#[cfg(test)]
+pub(crate) static TEST_PARAMS_LOCK: Mutex<()> = Mutex::new(());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_persists_set_values() {
+        let _guard = TEST_PARAMS_LOCK.lock().unwrap();
+        let path = std::env::temp_dir().join(format!("ferrumdb_config_{}.conf", std::process::id()));
+        fs::write(&path, "# FerrumDB\nlist-max-listpack-size -2\n").unwrap();
+
+        let config = ServerConfig::new();
+        assert!(matches!(config.rewrite(), Err(ConfigError::NoConfigFile)));
+        config.load_file(&path).unwrap();
+
+        config.set("LIST-MAX-LISTPACK-SIZE", "128").unwrap();
+        config.rewrite().unwrap();
+        store::set_list_max_listpack_size(store::DEFAULT_LIST_MAX_LISTPACK_SIZE);
+
+        // The comment is kept, the value updated in place and the missing
+        // parameters appended
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("# FerrumDB\nlist-max-listpack-size 128\n"));
+        let values: Vec<_> = contents.lines().filter_map(parse_line).collect();
+        assert_eq!(
+            values,
+            vec![
+                ("list-max-listpack-size", "128"),
+                ("set-max-intset-entries", "512"),
+                ("set-max-listpack-entries", "128"),
+                ("zset-max-listpack-entries", "128"),
+                ("embstr-size-limit", "44"),
+                ("maxmemory", "0"),
+                ("maxmemory-policy", "noeviction"),
+                ("hz", "10"),
+                ("active-expire-effort", "1"),
+                ("expire-jitter-percent", "0"),
+                ("auto-aof-rewrite-percentage", "100"),
+                ("auto-aof-rewrite-min-size", "67108864"),
+                ("shard-mode", "actor"),
+                ("shard-runtime", "dedicated"),
+                ("hash-tags", "no"),
+                ("expected-keys", "0"),
+                ("audit-log", "no"),
+                ("audit-log-file", "ferrumdb_audit.log"),
+                ("max-commands-per-second", "0"),
+                ("disable-keys-command", "no"),
+                ("keys-scan-limit", "0"),
+                ("strict-type-errors", "no")
+            ]
+        );
+
+        // Loading it again applies the rewritten value
+        config.load_file(&path).unwrap();
+        assert_eq!(config.get("list-max-listpack-size").as_deref(), Some("128"));
+        store::set_list_max_listpack_size(store::DEFAULT_LIST_MAX_LISTPACK_SIZE);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_set_errors() {
+        let config = ServerConfig::new();
+        assert_eq!(
+            config.set("no-such-option", "1").unwrap_err().to_string(),
+            "Unknown option or number of arguments for CONFIG SET - 'no-such-option'"
+        );
+        assert_eq!(
+            config.set("list-max-listpack-size", "big").unwrap_err().to_string(),
+            "CONFIG SET failed (possibly related to argument 'list-max-listpack-size') - argument couldn't be parsed into an integer"
+        );
+        assert_eq!(
+            config.set("maxmemory-policy", "volatile-lru").unwrap_err().to_string(),
+            "CONFIG SET failed (possibly related to argument 'maxmemory-policy') - argument(s) must be one of the following: noeviction, allkeys-lru, allkeys-lfu"
+        );
+        assert!(config.set("maxmemory", "10 gigabytes").is_err());
+        assert_eq!(
+            config.set("active-expire-effort", "11").unwrap_err().to_string(),
+            "CONFIG SET failed (possibly related to argument 'active-expire-effort') - argument must be between 1 and 10 inclusive"
+        );
+        assert_eq!(
+            config.set("expire-jitter-percent", "101").unwrap_err().to_string(),
+            "CONFIG SET failed (possibly related to argument 'expire-jitter-percent') - argument must be between 0 and 100 inclusive"
+        );
+        assert_eq!(
+            config.set("shard-mode", "threads").unwrap_err().to_string(),
+            "CONFIG SET failed (possibly related to argument 'shard-mode') - argument(s) must be one of the following: actor, striped"
+        );
+        assert_eq!(
+            config.set("shard-runtime", "pool").unwrap_err().to_string(),
+            "CONFIG SET failed (possibly related to argument 'shard-runtime') - argument(s) must be one of the following: dedicated, shared"
+        );
+        assert_eq!(
+            config.set("disable-keys-command", "maybe").unwrap_err().to_string(),
+            "CONFIG SET failed (possibly related to argument 'disable-keys-command') - argument must be 'yes' or 'no'"
+        );
+        assert_eq!(
+            config.set("auto-aof-rewrite-percentage", "-1").unwrap_err().to_string(),
+            "CONFIG SET failed (possibly related to argument 'auto-aof-rewrite-percentage') - argument must be between 0 and 9223372036854775807 inclusive"
+        );
+        assert!(config.set("auto-aof-rewrite-min-size", "big").is_err());
+        assert!(config.set("expected-keys", "-1").is_err());
+        assert_eq!(
+            config.set("max-commands-per-second", "4294967296").unwrap_err().to_string(),
+            "CONFIG SET failed (possibly related to argument 'max-commands-per-second') - argument must be between 0 and 4294967295 inclusive"
+        );
+        assert_eq!(config.matching(b"auto-aof-rewrite-*").len(), 2);
+        assert_eq!(config.matching(b"list-*").len(), 1);
+        assert_eq!(config.matching(b"maxmemory*").len(), 2);
+        assert!(config.matching(b"nothing*").is_empty());
+    }
+}
@@ -3,9 +3,10 @@
 //! Routes incoming commands to the appropriate handler.
 //! This module provides loose coupling between the server and command implementations.
 
-use crate::commands::{CommandContext, CommandRegistry};
+use crate::commands::{check_maxmemory, command_flags, execute_traced, log_to_audit, unknown_command, CommandContext, CommandRegistry};
 use crate::protocol::RespValue;
 use crate::aof::{AofConfig, AofWriter, AofReader, replay_entries};
+use crate::audit::AuditLogger;
 use std::sync::Arc;
 use std::path::Path;
 use tracing::{debug, warn, info};
@@ -14,8 +15,8 @@ use tracing::{debug, warn, info};
 ///
 /// Receives RESP commands, validates them, and routes to appropriate handlers
 pub struct Dispatcher {
-    /// Command registry
-    registry: CommandRegistry,
+    /// Command registry (shared with the context)
+    registry: Arc<CommandRegistry>,
 
     /// Command execution context
     context: CommandContext,
@@ -24,17 +25,19 @@ pub struct Dispatcher {
 impl Dispatcher {
     /// Create a new dispatcher
     pub fn new() -> Self {
+        let context = CommandContext::new();
         Dispatcher {
-            registry: CommandRegistry::new(),
-            context: CommandContext::new(),
+            registry: context.registry.clone(),
+            context,
         }
     }
 
     /// Create a dispatcher with specified store capacity
     pub fn with_capacity(capacity: usize) -> Self {
+        let context = CommandContext::with_capacity(capacity);
         Dispatcher {
-            registry: CommandRegistry::new(),
-            context: CommandContext::with_capacity(capacity),
+            registry: context.registry.clone(),
+            context,
         }
     }
 
@@ -66,7 +69,7 @@ impl Dispatcher {
         }
 
         Ok(Dispatcher {
-            registry: CommandRegistry::new(),
+            registry: context.registry.clone(),
             context,
         })
     }
@@ -76,14 +79,51 @@ impl Dispatcher {
     /// Takes a RESP value (expected to be an array), extracts the command name
     /// and arguments, then routes to the appropriate handler.
     pub fn dispatch(&mut self, value: RespValue) -> RespValue {
+        let (response, durable) = self.execute(value);
+        match durable.then(|| self.sync_aof()).flatten() {
+            Some(error) => error,
+            None => response,
+        }
+    }
+
+    /// Dispatch a batch of commands, in order, replying to each
+    ///
+    /// Same replies as calling `dispatch` on each command, but the writes of
+    /// a CLIENT DURABLE client are fsynced once, after the whole batch: if
+    /// that fails, each of them replies with the error.
+    pub fn dispatch_batch(&mut self, commands: Vec<RespValue>) -> Vec<RespValue> {
+        let results: Vec<_> = commands.into_iter().map(|command| self.execute(command)).collect();
+        let error = if results.iter().any(|(_, durable)| *durable) { self.sync_aof() } else { None };
+
+        results
+            .into_iter()
+            .map(|(response, durable)| match &error {
+                Some(error) if durable => error.clone(),
+                _ => response,
+            })
+            .collect()
+    }
+
+    /// Fsync the AOF, returning the reply to send if it fails
+    fn sync_aof(&self) -> Option<RespValue> {
+        let writer = self.context.aof_writer.as_ref()?;
+        let e = writer.sync().err()?;
+        Some(RespValue::error(format!("ERR AOF fsync failed: {}", e)))
+    }
+
+    /// Execute a command, returning its reply and whether it is a CLIENT
+    /// DURABLE write, to fsync before replying
+    fn execute(&mut self, value: RespValue) -> (RespValue, bool) {
+        let fail = |error| (error, false);
+
         // Commands should be arrays
         let args = match value.as_array() {
             Some(arr) if !arr.is_empty() => arr,
             Some(_) => {
-                return RespValue::error("ERR empty command array");
+                return fail(RespValue::error("ERR empty command array"));
             }
             None => {
-                return RespValue::error("ERR expected array");
+                return fail(RespValue::error("ERR expected array"));
             }
         };
 
@@ -92,11 +132,11 @@ impl Dispatcher {
             Some(name) => match std::str::from_utf8(name) {
                 Ok(s) => s,
                 Err(_) => {
-                    return RespValue::error("ERR invalid command name encoding");
+                    return fail(RespValue::error("ERR invalid command name encoding"));
                 }
             },
             None => {
-                return RespValue::error("ERR command name must be a bulk string");
+                return fail(RespValue::error("ERR command name must be a bulk string"));
             }
         };
 
@@ -107,7 +147,7 @@ impl Dispatcher {
             Some(cmd) => cmd,
             None => {
                 warn!("Unknown command: {}", cmd_name);
-                return RespValue::error(format!("ERR unknown command '{}'", cmd_name));
+                return fail(unknown_command(cmd_name.as_bytes(), &args[1..]));
             }
         };
 
@@ -116,23 +156,37 @@ impl Dispatcher {
 
         // Validate argument count
         if cmd_args.len() < command.min_args() {
-            return RespValue::error(format!(
+            return fail(RespValue::error(format!(
                 "ERR wrong number of arguments for '{}' command",
                 cmd_name
-            ));
+            )));
         }
 
         if let Some(max) = command.max_args() {
             if cmd_args.len() > max {
-                return RespValue::error(format!(
+                return fail(RespValue::error(format!(
                     "ERR wrong number of arguments for '{}' command",
                     cmd_name
-                ));
+                )));
             }
         }
 
+        if let Some(oom) = check_maxmemory(&mut self.context, command.as_ref(), cmd_args) {
+            return fail(oom);
+        }
+
         // Execute the command
-        command.execute(&mut self.context, cmd_args)
+        let reply = execute_traced(&mut self.context, command.as_ref(), args);
+        log_to_audit(&self.context, command.as_ref(), args, &reply);
+
+        // CLIENT DURABLE: the write is on disk before it is acknowledged
+        let durable = self.context.client.durable && command_flags(&self.context.registry, args).contains(&"write");
+        (reply, durable)
+    }
+
+    /// Record write commands in an audit log
+    pub fn set_audit_logger(&mut self, logger: Arc<AuditLogger>) {
+        self.context.set_audit_logger(logger);
     }
 
     /// Get reference to the context (for testing/inspection)
@@ -155,8 +209,36 @@ impl Default for Dispatcher {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::commands::command;
     use bytes::Bytes;
 
+    #[test]
+    fn test_unknown_command_error() {
+        let mut dispatcher = Dispatcher::new();
+
+        assert_eq!(
+            dispatcher.dispatch(command(&["FOO", "bar", "baz"])),
+            RespValue::error("ERR unknown command 'FOO', with args beginning with: 'bar' 'baz' ")
+        );
+        assert_eq!(
+            dispatcher.dispatch(command(&["foo"])),
+            RespValue::error("ERR unknown command 'foo', with args beginning with: ")
+        );
+
+        // The preview stops around 128 bytes, cutting the last argument
+        let long = "x".repeat(100);
+        let RespValue::Error(message) = dispatcher.dispatch(command(&["FOO", &long, &long, &long])) else {
+            panic!("Expected an error");
+        };
+        let preview = format!("'{}' '{}' ", long, "x".repeat(128 - 103));
+        assert_eq!(message, format!("ERR unknown command 'FOO', with args beginning with: {}", preview));
+
+        // Line breaks in the name or arguments can't split the reply
+        let reply = dispatcher.dispatch(command(&["FOO\r\n+OK", "x\r\n:1"]));
+        let encoded = crate::protocol::RespEncoder::encode(&reply);
+        assert_eq!(&encoded[..], b"-ERR unknown command 'FOO  +OK', with args beginning with: 'x  :1' \r\n");
+    }
+
     #[test]
     fn test_dispatch_set_get() {
         let mut dispatcher = Dispatcher::new();
@@ -181,6 +263,83 @@ mod tests {
         assert_eq!(result, RespValue::bulk_string(Bytes::from("myvalue")));
     }
 
+    #[test]
+    fn test_audit_log_records_writes_only() {
+        use crate::commands::ClientOptions;
+
+        let path = std::env::temp_dir().join(format!("ferrumdb_audit_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.set_audit_logger(Arc::new(AuditLogger::new(&path).unwrap()));
+        let client = ClientOptions {
+            addr: Some("10.0.0.7:41234".parse().unwrap()),
+            ..ClientOptions::default()
+        };
+        dispatcher.context_mut().apply_client_options(client);
+
+        dispatcher.dispatch(command(&["SET", "audited", "secret"]));
+        dispatcher.dispatch(command(&["GET", "audited"]));
+
+        // Failed writes change nothing and are left out
+        dispatcher.dispatch(command(&["INCR", "audited"]));
+        dispatcher.dispatch(command(&["IDEMPOTENT", "token", "INCR", "audited"]));
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 1, "unexpected audit log: {:?}", log);
+        assert!(lines[0].ends_with(" 10.0.0.7:41234 SET audited"), "unexpected audit line: {}", lines[0]);
+        assert!(!log.contains("secret"));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_dispatch_batch_replies_in_order() {
+        let mut dispatcher = Dispatcher::new();
+
+        let responses = dispatcher.dispatch_batch(vec![
+            command(&["SET", "mykey", "myvalue"]),
+            command(&["GET", "mykey"]),
+            command(&["GET"]),
+            command(&["INCR", "mykey"]),
+        ]);
+        assert_eq!(
+            responses,
+            vec![
+                RespValue::simple_string("OK"),
+                RespValue::bulk_string("myvalue"),
+                RespValue::error("ERR wrong number of arguments for 'GET' command"),
+                RespValue::error("ERR value is not an integer or out of range"),
+            ]
+        );
+        assert!(dispatcher.dispatch_batch(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_substr_is_getrange_alias() {
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.dispatch(command(&["SET", "key", "This is a string"]));
+
+        for range in [["0", "3"], ["-3", "-1"], ["5", "100"], ["10", "2"]] {
+            let getrange = dispatcher.dispatch(command(&["GETRANGE", "key", range[0], range[1]]));
+            let substr = dispatcher.dispatch(command(&["substr", "key", range[0], range[1]]));
+            assert_eq!(substr, getrange, "{:?}", range);
+        }
+        assert_eq!(dispatcher.dispatch(command(&["SUBSTR", "key", "0", "3"])), RespValue::bulk_string("This"));
+        assert_eq!(
+            dispatcher.dispatch(command(&["SUBSTR", "key", "0"])),
+            RespValue::error("ERR wrong number of arguments for 'SUBSTR' command")
+        );
+
+        // Listed under its own name, with the arity and flags of GETRANGE
+        let registry = &dispatcher.context().registry;
+        let (substr, getrange) = (registry.info("substr").unwrap(), registry.info("GETRANGE").unwrap());
+        assert_eq!(substr.name, "substr");
+        assert_eq!((substr.arity, substr.flags), (getrange.arity, getrange.flags));
+        assert!(registry.command_names().contains(&"SUBSTR".to_string()));
+    }
+
     #[test]
     fn test_dispatch_unknown_command() {
         let mut dispatcher = Dispatcher::new();
@@ -205,4 +364,69 @@ mod tests {
         let result = dispatcher.dispatch(cmd);
         assert!(matches!(result, RespValue::Error(_)));
     }
+
+    type Fields = std::collections::HashMap<String, String>;
+
+    /// Names and fields of the spans created while it is installed
+    #[derive(Clone, Default)]
+    struct SpanCapture(Arc<std::sync::Mutex<Vec<(String, Fields)>>>);
+
+    struct SpanFields<'a>(&'a mut Fields);
+
+    impl tracing::field::Visit for SpanFields<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name().to_string(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanCapture {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _id: &tracing::span::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = Fields::new();
+            attrs.record(&mut SpanFields(&mut fields));
+            self.0.lock().unwrap().push((attrs.metadata().name().to_string(), fields));
+        }
+
+        fn on_record(
+            &self,
+            _id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if let Some((_, fields)) = self.0.lock().unwrap().last_mut() {
+                values.record(&mut SpanFields(fields));
+            }
+        }
+    }
+
+    #[test]
+    fn test_command_span() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let capture = SpanCapture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+        let mut dispatcher = Dispatcher::new();
+        let command = RespValue::array(vec![
+            RespValue::bulk_string("SET"),
+            RespValue::bulk_string("user:1"),
+            RespValue::bulk_string("value"),
+        ]);
+        let reply = tracing::subscriber::with_default(subscriber, || dispatcher.dispatch(command));
+        assert_eq!(reply, RespValue::simple_string("OK"));
+
+        let spans = capture.0.lock().unwrap();
+        let (name, fields) = spans.iter().find(|(name, _)| name == "command").expect("no command span");
+        assert_eq!(name, "command");
+        assert_eq!(fields["command"], "SET");
+        assert_eq!(fields["key"], "user:1");
+        assert!(fields["duration_us"].parse::<u64>().is_ok(), "{:?}", fields);
+    }
 }
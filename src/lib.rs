@@ -11,11 +11,17 @@ pub mod commands;
 pub mod dispatch;
 pub mod server;
 pub mod aof;
+pub mod audit;
+pub mod config;
+pub mod logging;
 pub mod web;
 pub mod cluster;
+pub mod pubsub;
+pub mod replication;
 
 /// Re-export commonly used types
 pub use store::{MemoryStore, Entry};
 pub use protocol::{RespValue, RespError};
 pub use commands::{Command, CommandContext};
 pub use cluster::{ClusterManager, Shard};
+pub use pubsub::PubSubBroker;
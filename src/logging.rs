@@ -0,0 +1,240 @@
+//! Logging setup
+//!
+//! Logs go to stdout, either human-readable (`text`, the default) or as
+//! one JSON object per line (`json`) for log aggregators. The format and
+//! the default level are read from the environment before anything else
+//! starts, so that even config file errors are logged in the chosen format:
+//! - `FERRUMDB_LOG_FORMAT`: `text` or `json`
+//! - `FERRUMDB_LOG_LEVEL`: `trace`, `debug`, `info` (default), `warn` or `error`
+//!
+//! `RUST_LOG` directives (e.g. `ferrumdb::cluster=trace`) still apply on
+//! top of the default level.
+
+use serde_json::{json, Map, Value as Json};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable selecting the log format
+pub const LOG_FORMAT_ENV: &str = "FERRUMDB_LOG_FORMAT";
+
+/// Environment variable selecting the default log level
+pub const LOG_LEVEL_ENV: &str = "FERRUMDB_LOG_LEVEL";
+
+/// Output format of the logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human-readable lines (tracing's default format)
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl LogFormat {
+    /// Parse a format name, case-insensitively
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Logging configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogConfig {
+    /// Output format
+    pub format: LogFormat,
+
+    /// Level logged when `RUST_LOG` says nothing about a target
+    pub level: Level,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig { format: LogFormat::Text, level: Level::INFO }
+    }
+}
+
+impl LogConfig {
+    /// Read the configuration from `FERRUMDB_LOG_FORMAT` and `FERRUMDB_LOG_LEVEL`
+    ///
+    /// Unset variables keep their default; an invalid value is an error
+    /// rather than being silently ignored.
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(
+            std::env::var(LOG_FORMAT_ENV).ok().as_deref(),
+            std::env::var(LOG_LEVEL_ENV).ok().as_deref(),
+        )
+    }
+
+    /// Build the configuration from the values of the two variables
+    pub fn from_vars(format: Option<&str>, level: Option<&str>) -> Result<Self, String> {
+        let mut config = LogConfig::default();
+        if let Some(format) = format {
+            config.format = LogFormat::parse(format)
+                .ok_or_else(|| format!("invalid {} '{}', expected text or json", LOG_FORMAT_ENV, format))?;
+        }
+        if let Some(level) = level {
+            config.level = level
+                .parse()
+                .map_err(|_| format!("invalid {} '{}', expected trace, debug, info, warn or error", LOG_LEVEL_ENV, level))?;
+        }
+        Ok(config)
+    }
+
+    /// Build the subscriber described by this configuration, writing to `writer`
+    pub fn subscriber<W>(&self, writer: W) -> Box<dyn Subscriber + Send + Sync>
+    where
+        W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+    {
+        let filter = EnvFilter::builder()
+            .with_default_directive(self.level.into())
+            .from_env_lossy();
+        let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(writer);
+
+        match self.format {
+            LogFormat::Text => Box::new(builder.finish()),
+            LogFormat::Json => Box::new(builder.event_format(JsonFormat).finish()),
+        }
+    }
+
+    /// Install the subscriber on stdout as the global default
+    pub fn init(&self) {
+        tracing::subscriber::set_global_default(self.subscriber(std::io::stdout))
+            .expect("the global subscriber is only set once");
+    }
+}
+
+/// Formats each event as a JSON object:
+/// `{"timestamp":<unix ms>,"level":"INFO","target":"ferrumdb::...","fields":{"message":...}}`,
+/// with the names of the enclosing spans under `spans` when there are any
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let mut fields = JsonFields(Map::new());
+        event.record(&mut fields);
+
+        let metadata = event.metadata();
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        let mut line = json!({
+            "timestamp": timestamp,
+            "level": metadata.level().as_str(),
+            "target": metadata.target(),
+            "fields": fields.0,
+        });
+        if let Some(scope) = ctx.event_scope() {
+            let spans: Vec<&str> = scope.from_root().map(|span| span.name()).collect();
+            line["spans"] = json!(spans);
+        }
+
+        writeln!(writer, "{}", line)
+    }
+}
+
+/// Collects the fields of an event into a JSON object
+struct JsonFields(Map<String, Json>);
+
+impl Visit for JsonFields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    /// Writer collecting everything logged into a shared buffer
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Buffer {
+        type Writer = Buffer;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    /// Everything logged by `f` under the subscriber of `config`
+    fn capture(config: LogConfig, f: impl FnOnce()) -> String {
+        let buffer = Buffer::default();
+        tracing::subscriber::with_default(config.subscriber(buffer.clone()), f);
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_from_vars() {
+        assert_eq!(LogConfig::from_vars(None, None), Ok(LogConfig::default()));
+        assert_eq!(
+            LogConfig::from_vars(Some("JSON"), Some("warn")),
+            Ok(LogConfig { format: LogFormat::Json, level: Level::WARN })
+        );
+        assert_eq!(LogConfig::from_vars(Some("text"), None).map(|config| config.format), Ok(LogFormat::Text));
+        assert!(LogConfig::from_vars(Some("xml"), None).is_err());
+        assert!(LogConfig::from_vars(None, Some("loud")).is_err());
+    }
+
+    #[test]
+    fn test_subscriber_formats() {
+        let json = LogConfig { format: LogFormat::Json, level: Level::INFO };
+        let output = capture(json, || {
+            tracing::debug!("filtered out");
+            tracing::info!(shard = 3, "Shard started");
+        });
+        let lines: Vec<Json> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 1, "{}", output);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["target"], "ferrumdb::logging::tests");
+        assert_eq!(lines[0]["fields"], json!({ "message": "Shard started", "shard": 3 }));
+        assert!(lines[0]["timestamp"].as_u64().unwrap() > 0);
+
+        let text = LogConfig { format: LogFormat::Text, level: Level::DEBUG };
+        let output = capture(text, || tracing::debug!("now visible"));
+        assert!(output.contains("DEBUG") && output.contains("now visible"), "{}", output);
+        assert!(serde_json::from_str::<Json>(output.trim()).is_err());
+    }
+}
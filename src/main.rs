@@ -1,22 +1,32 @@
-use ferrumdb::{server, web, cluster::ClusterManager};
+use ferrumdb::{audit, server, web, cluster::{self, ClusterManager}, config::SERVER_CONFIG, logging::LogConfig, pubsub::PubSubBroker};
 use tracing::{info, error};
-use tracing_subscriber;
 use std::sync::Arc;
 
 // taskkill /F /IM ferrumdb.exe
 
 #[tokio::main]
 async fn main() {
-    // Initialize logging (DEBUG level for detailed command tracing)
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive(tracing::Level::DEBUG.into())
-        )
-        .init();
+    // Initialize logging (format and level from FERRUMDB_LOG_FORMAT and
+    // FERRUMDB_LOG_LEVEL, see the logging module)
+    match LogConfig::from_env() {
+        Ok(log_config) => log_config.init(),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
 
     info!("FerrumDB starting...");
 
+    // Optional config file, like `redis-server /path/to/redis.conf`
+    if let Some(config_path) = std::env::args().nth(1) {
+        if let Err(e) = SERVER_CONFIG.load_file(&config_path) {
+            error!("Failed to load config file {}: {}", config_path, e);
+            std::process::exit(1);
+        }
+        info!("Loaded config file {}", config_path);
+    }
+
     // Server configuration
     let redis_addr = "127.0.0.1:6379";
     let web_addr = "127.0.0.1:8080";
@@ -26,8 +36,18 @@ async fn main() {
     let num_shards = num_cpus.clamp(1, 16);
     info!("Detected {} CPU cores, creating {} shards", num_cpus, num_shards);
 
-    // Create cluster manager with AOF enabled
-    let cluster = match ClusterManager::new(num_shards, true) {
+    // Create cluster manager with AOF enabled, its shards running as
+    // threads or as lock-guarded stores (`shard-mode` in the config file),
+    // the threads on runtimes of their own or on this one (`shard-runtime`),
+    // pre-sized for `expected-keys` keys, writes recorded in the audit log
+    // if `audit-log` is set
+    let cluster = match ClusterManager::with_audit_log(
+        num_shards,
+        true,
+        cluster::shard_mode(),
+        cluster::shard_runtime(),
+        &audit::audit_config(),
+    ) {
         Ok(c) => Arc::new(c),
         Err(e) => {
             error!("Failed to initialize cluster: {}", e);
@@ -35,13 +55,18 @@ async fn main() {
         }
     };
 
-    // Clone cluster for web server
+    // Global pub/sub broker, shared by the RESP and web servers, with its
+    // sharded channels spread like the keys
+    let pubsub = Arc::new(PubSubBroker::with_shards(num_shards));
+
+    // Clone cluster and broker for web server
     let web_cluster = cluster.clone();
+    let web_pubsub = pubsub.clone();
 
     // Start RESP server in background task
     let redis_handle = tokio::spawn(async move {
         info!("Starting RESP server on {}", redis_addr);
-        if let Err(e) = server::run_with_cluster(redis_addr, cluster).await {
+        if let Err(e) = server::run_with_cluster(redis_addr, cluster, pubsub).await {
             error!("RESP server error: {}", e);
         }
     });
@@ -49,7 +74,7 @@ async fn main() {
     // Start Web server in background task
     let web_handle = tokio::spawn(async move {
         info!("Starting Web server on {}", web_addr);
-        if let Err(e) = web::run_web_with_cluster(web_addr, web_cluster).await {
+        if let Err(e) = web::run_web_with_cluster(web_addr, web_cluster, web_pubsub).await {
             error!("Web server error: {}", e);
         }
     });
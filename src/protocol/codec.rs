@@ -0,0 +1,283 @@
+//! Framed codec for RESP connections
+//!
+//! `RespCodec` plugs the RESP parser and encoder into `tokio_util::codec`.
+//! The parser needs a complete value, so the decoder first finds where the
+//! next frame ends with an incremental scanner: only the header lines are
+//! read, bulk string data is skipped by its length, and when more data is
+//! needed the scan resumes where it stopped instead of starting over. A
+//! large frame arriving in many reads is scanned once, then split off the
+//! buffer and parsed once.
+
+use super::resp::{find_crlf, RespEncoder, RespParser};
+use super::types::{RespError, RespValue};
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Inline health check sent by load balancers
+const INLINE_PING: &[u8] = b"PING\r\n";
+
+/// A frame read from a client
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespFrame {
+    /// A bare inline `PING\r\n` (any case), recognized without parsing
+    InlinePing,
+
+    /// A RESP value
+    Value(RespValue),
+}
+
+/// Outcome of scanning the buffer for the end of the current frame
+enum Scan {
+    /// The frame is complete and this long
+    Complete(usize),
+    /// More data is needed
+    Incomplete,
+    /// The frame is malformed: the parser reports why
+    Invalid,
+}
+
+/// RESP decoder and encoder, keeping the scan progress between reads
+#[derive(Debug, Default)]
+pub struct RespCodec {
+    /// Bytes of the current frame already scanned
+    scanned: usize,
+
+    /// Elements still expected by each array being scanned, innermost last
+    remaining: Vec<i64>,
+}
+
+impl RespCodec {
+    /// Create a codec
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes of the frame being received that were already scanned, and
+    /// won't be scanned again when more data arrives
+    pub fn scanned(&self) -> usize {
+        self.scanned
+    }
+
+    /// Continue scanning the current frame in `buf`
+    fn scan(&mut self, buf: &[u8]) -> Scan {
+        loop {
+            while self.remaining.last() == Some(&0) {
+                self.remaining.pop();
+            }
+            if self.scanned > 0 && self.remaining.is_empty() {
+                return Scan::Complete(self.scanned);
+            }
+
+            let rest = &buf[self.scanned..];
+            let Some(end) = find_crlf(rest) else {
+                return Scan::Incomplete;
+            };
+            let length = || std::str::from_utf8(&rest[1..end]).ok()?.parse::<i64>().ok();
+
+            let value_len = match rest[0] {
+                b'+' | b'-' | b':' => end + 2,
+                b'$' => match length() {
+                    Some(len) if len < 0 => end + 2,
+                    Some(len) => match (end + 4).saturating_add(len as usize) {
+                        total if rest.len() < total => return Scan::Incomplete,
+                        total => total,
+                    },
+                    None => return Scan::Invalid,
+                },
+                b'*' => {
+                    let Some(count) = length() else {
+                        return Scan::Invalid;
+                    };
+                    self.scanned += end + 2;
+                    if let Some(parent) = self.remaining.last_mut() {
+                        *parent -= 1;
+                    }
+                    if count > 0 {
+                        self.remaining.push(count);
+                    }
+                    continue;
+                }
+                _ => return Scan::Invalid,
+            };
+
+            self.scanned += value_len;
+            if let Some(parent) = self.remaining.last_mut() {
+                *parent -= 1;
+            }
+        }
+    }
+
+    /// Forget the current frame, to start scanning the next one
+    fn reset(&mut self) {
+        self.scanned = 0;
+        self.remaining.clear();
+    }
+}
+
+impl Decoder for RespCodec {
+    type Item = RespFrame;
+    type Error = RespError;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<RespFrame>, RespError> {
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        if self.scanned == 0 {
+            if take_inline_ping(buf) {
+                return Ok(Some(RespFrame::InlinePing));
+            }
+            // The start of an inline PING, not an invalid frame yet
+            if buf.len() < INLINE_PING.len() && buf.eq_ignore_ascii_case(&INLINE_PING[..buf.len()]) {
+                return Ok(None);
+            }
+        }
+
+        match self.scan(buf) {
+            Scan::Complete(len) => {
+                self.reset();
+                let mut frame = buf.split_to(len);
+                match RespParser::parse(&mut frame)? {
+                    Some(value) => Ok(Some(RespFrame::Value(value))),
+                    None => Err(RespError::InvalidProtocol("Truncated frame".to_string())),
+                }
+            }
+            Scan::Incomplete => Ok(None),
+            Scan::Invalid => {
+                self.reset();
+                RespParser::parse(buf).map(|value| value.map(RespFrame::Value))
+            }
+        }
+    }
+}
+
+impl Encoder<RespValue> for RespCodec {
+    type Error = RespError;
+
+    fn encode(&mut self, value: RespValue, dst: &mut BytesMut) -> Result<(), RespError> {
+        RespEncoder::encode_to(dst, &value);
+        Ok(())
+    }
+}
+
+impl From<std::io::Error> for RespError {
+    fn from(e: std::io::Error) -> Self {
+        RespError::IoError(e.to_string())
+    }
+}
+
+/// Consume a bare inline `PING\r\n` (any case) at the start of the buffer
+///
+/// Health checks get their `+PONG` without going through the parser, the
+/// dispatcher or the store. Anything else, including PING with an argument,
+/// is left to the parser.
+fn take_inline_ping(buf: &mut BytesMut) -> bool {
+    if buf.len() < INLINE_PING.len() || !buf[..INLINE_PING.len()].eq_ignore_ascii_case(INLINE_PING) {
+        return false;
+    }
+    let _ = buf.split_to(INLINE_PING.len());
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_command(i: usize) -> RespValue {
+        RespValue::array(vec![
+            RespValue::bulk_string("SET"),
+            RespValue::bulk_string(format!("key:{}", i)),
+            RespValue::bulk_string("value"),
+        ])
+    }
+
+    #[test]
+    fn test_take_inline_ping() {
+        let mut buf = BytesMut::from(&b"ping\r\nPING\r\n*1\r\n"[..]);
+        assert!(take_inline_ping(&mut buf));
+        assert!(take_inline_ping(&mut buf));
+        assert!(!take_inline_ping(&mut buf));
+        assert_eq!(&buf[..], b"*1\r\n");
+
+        for other in [&b"PING"[..], b"PING hello\r\n", b"PINGS\r\n"] {
+            let mut buf = BytesMut::from(other);
+            assert!(!take_inline_ping(&mut buf));
+            assert_eq!(&buf[..], other);
+        }
+    }
+
+    #[test]
+    fn test_decode_pipeline_byte_by_byte() {
+        let mut input = BytesMut::new();
+        let mut expected = Vec::new();
+        for i in 0..50 {
+            let command = set_command(i);
+            RespEncoder::encode_to(&mut input, &command);
+            expected.push(RespFrame::Value(command));
+        }
+        input.extend_from_slice(b"PING\r\n$-1\r\n*0\r\n*2\r\n*1\r\n:1\r\n+ok\r\n");
+        expected.extend([
+            RespFrame::InlinePing,
+            RespFrame::Value(RespValue::Null),
+            RespFrame::Value(RespValue::array(vec![])),
+            RespFrame::Value(RespValue::array(vec![
+                RespValue::array(vec![RespValue::integer(1)]),
+                RespValue::simple_string("ok"),
+            ])),
+        ]);
+
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::new();
+        let mut frames = Vec::new();
+        for byte in input.iter() {
+            buf.extend_from_slice(&[*byte]);
+            while let Some(frame) = codec.decode(&mut buf).unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(frames, expected);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_large_frame_is_not_rescanned() {
+        // One MSET of 10000 pairs, received in 1 KB reads
+        let mut mset = vec![RespValue::bulk_string("MSET")];
+        for i in 0..10_000 {
+            mset.push(RespValue::bulk_string(format!("key:{}", i)));
+            mset.push(RespValue::bulk_string("value"));
+        }
+        let input = RespEncoder::encode(&RespValue::array(mset.clone()));
+
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::new();
+        let mut frame = None;
+        for chunk in input.chunks(1024) {
+            let before = codec.scanned();
+            buf.extend_from_slice(chunk);
+            frame = codec.decode(&mut buf).unwrap();
+            if frame.is_none() {
+                // The scan resumed from where it stopped and kept up with
+                // the data, short of the value cut by the end of the chunk
+                assert!(codec.scanned() >= before);
+                assert!(buf.len() - codec.scanned() < 32, "{} of {}", codec.scanned(), buf.len());
+            }
+        }
+        assert_eq!(frame, Some(RespFrame::Value(RespValue::array(mset))));
+        assert_eq!(codec.scanned(), 0);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"*1\r\n$abc\r\n"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+
+        let mut buf = BytesMut::from(&b"?\r\n"[..]);
+        assert!(matches!(codec.decode(&mut buf), Err(RespError::InvalidProtocol(_))));
+
+        // A bulk string of the announced length without its CRLF
+        let mut buf = BytesMut::from(&b"$3\r\nfooXY"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+}
@@ -5,6 +5,8 @@
 
 mod types;
 mod resp;
+mod codec;
 
-pub use types::{RespValue, RespError};
+pub use types::{ProtocolVersion, RespValue, RespError};
 pub use resp::{RespParser, RespEncoder};
+pub use codec::{RespCodec, RespFrame};
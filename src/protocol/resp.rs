@@ -2,7 +2,7 @@
 //!
 //! Implements parsing from bytes to RespValue and encoding from RespValue to bytes
 
-use super::types::{RespValue, RespError};
+use super::types::{ProtocolVersion, RespValue, RespError};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 const CRLF: &[u8] = b"\r\n";
@@ -186,15 +186,21 @@ impl RespParser {
 
     /// Peek a line from buffer without advancing (returns line without CRLF)
     fn peek_line(buf: &BytesMut) -> Result<Option<Vec<u8>>, RespError> {
-        for i in 0..buf.len() - 1 {
-            if &buf[i..i+2] == CRLF {
-                return Ok(Some(buf[..i].to_vec()));
-            }
-        }
-        Ok(None)
+        Ok(find_crlf(buf).map(|end| buf[..end].to_vec()))
     }
 }
 
+/// Position of the first CRLF in a buffer
+///
+/// Searches for `\n` with memchr (vectorized, much faster than comparing
+/// byte pairs on long lines) and keeps the first one preceded by `\r`; a
+/// bare `\n` is part of the line, as before. Safe for buffers of any length.
+pub(super) fn find_crlf(buf: &[u8]) -> Option<usize> {
+    memchr::memchr_iter(b'\n', buf)
+        .find(|&i| i > 0 && buf[i - 1] == b'\r')
+        .map(|i| i - 1)
+}
+
 /// RESP2 Encoder
 pub struct RespEncoder;
 
@@ -206,8 +212,18 @@ impl RespEncoder {
         buf.freeze()
     }
 
-    /// Encode a RESP value into an existing buffer
+    /// Encode a RESP value into an existing buffer, in RESP2
     pub fn encode_to(buf: &mut BytesMut, value: &RespValue) {
+        Self::encode_for(buf, value, ProtocolVersion::Resp2)
+    }
+
+    /// Encode a RESP value into an existing buffer for the given protocol
+    /// version
+    ///
+    /// Maps are `%` in RESP3, the flat array of their pairs in RESP2;
+    /// attributes are `|` in RESP3, and dropped in RESP2, where only the
+    /// reply they are attached to is sent.
+    pub fn encode_for(buf: &mut BytesMut, value: &RespValue, version: ProtocolVersion) {
         match value {
             RespValue::SimpleString(s) => {
                 buf.put_u8(b'+');
@@ -239,9 +255,35 @@ impl RespEncoder {
                 buf.put_slice(arr.len().to_string().as_bytes());
                 buf.put_slice(CRLF);
                 for elem in arr {
-                    Self::encode_to(buf, elem);
+                    Self::encode_for(buf, elem, version);
                 }
             }
+            RespValue::Map(pairs) => {
+                if version == ProtocolVersion::Resp3 {
+                    buf.put_u8(b'%');
+                    buf.put_slice(pairs.len().to_string().as_bytes());
+                } else {
+                    buf.put_u8(b'*');
+                    buf.put_slice((pairs.len() * 2).to_string().as_bytes());
+                }
+                buf.put_slice(CRLF);
+                for (key, value) in pairs {
+                    Self::encode_for(buf, key, version);
+                    Self::encode_for(buf, value, version);
+                }
+            }
+            RespValue::Attribute(attributes, value) => {
+                if version == ProtocolVersion::Resp3 {
+                    buf.put_u8(b'|');
+                    buf.put_slice(attributes.len().to_string().as_bytes());
+                    buf.put_slice(CRLF);
+                    for (key, value) in attributes {
+                        Self::encode_for(buf, key, version);
+                        Self::encode_for(buf, value, version);
+                    }
+                }
+                Self::encode_for(buf, value, version);
+            }
         }
     }
 }
@@ -295,6 +337,34 @@ mod tests {
         ])));
     }
 
+    #[test]
+    fn test_find_crlf() {
+        // Same results as comparing every pair of bytes
+        let naive = |buf: &[u8]| buf.windows(2).position(|pair| pair == CRLF);
+        let cases: &[&[u8]] = &[
+            b"", b"\r", b"\n", b"\r\n", b"\n\r", b"+OK", b"+OK\r", b"+OK\r\n", b"a\nb\r\n", b"\r\r\n",
+            b"\n\n\r\n", b"$3\r\nfoo\r\n", b"+OK\n\r",
+        ];
+        for case in cases {
+            assert_eq!(find_crlf(case), naive(case), "{:?}", case);
+        }
+
+        // 0 and 1 byte buffers don't panic
+        assert_eq!(RespParser::peek_line(&BytesMut::new()).unwrap(), None);
+        assert_eq!(RespParser::peek_line(&BytesMut::from("\r")).unwrap(), None);
+        assert_eq!(RespParser::parse(&mut BytesMut::from("+")).unwrap(), None);
+        assert_eq!(RespParser::parse(&mut BytesMut::from("*")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_long_line() {
+        let long = "x".repeat(100_000);
+        let mut buf = BytesMut::from(format!("+{}\r\n+a\nb\r\n", long).as_str());
+        assert_eq!(RespParser::parse(&mut buf).unwrap(), Some(RespValue::SimpleString(long)));
+        assert_eq!(RespParser::parse(&mut buf).unwrap(), Some(RespValue::SimpleString("a\nb".to_string())));
+        assert!(buf.is_empty());
+    }
+
     #[test]
     fn test_encode_simple_string() {
         let value = RespValue::SimpleString("OK".to_string());
@@ -308,4 +378,25 @@ mod tests {
         let encoded = RespEncoder::encode(&value);
         assert_eq!(encoded, Bytes::from("$6\r\nfoobar\r\n"));
     }
+
+    #[test]
+    fn test_encode_attribute_per_protocol() {
+        let reply = RespValue::bulk_string("value");
+        let hint = vec![(RespValue::simple_string("ttl"), RespValue::integer(60))];
+        let value = RespValue::attribute(hint, reply.clone());
+        let encode = |value: &RespValue, version| {
+            let mut buf = BytesMut::new();
+            RespEncoder::encode_for(&mut buf, value, version);
+            buf
+        };
+
+        // RESP3: the attribute, then the reply
+        let resp3 = encode(&value, ProtocolVersion::Resp3);
+        assert_eq!(&resp3[..], b"|1\r\n+ttl\r\n:60\r\n$5\r\nvalue\r\n");
+        assert!(resp3.ends_with(&encode(&reply, ProtocolVersion::Resp3)));
+
+        // RESP2: the reply alone
+        assert_eq!(encode(&value, ProtocolVersion::Resp2), encode(&reply, ProtocolVersion::Resp2));
+        assert_eq!(RespEncoder::encode(&value), Bytes::from("$5\r\nvalue\r\n"));
+    }
 }
@@ -25,6 +25,25 @@ pub enum RespValue {
 
     /// Arrays: *2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n
     Array(Vec<RespValue>),
+
+    /// Maps (key/value pairs): %1\r\n$3\r\nkey\r\n$3\r\nval\r\n in RESP3,
+    /// sent as the flat array of the pairs in RESP2
+    Map(Vec<(RespValue, RespValue)>),
+
+    /// A reply with out-of-band metadata (such as client-side caching
+    /// hints): |1\r\n+key\r\n:1\r\n followed by the reply in RESP3, only
+    /// the reply in RESP2
+    Attribute(Vec<(RespValue, RespValue)>, Box<RespValue>),
+}
+
+/// Protocol version of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProtocolVersion {
+    /// RESP2, the default
+    #[default]
+    Resp2,
+    /// RESP3, with typed replies such as maps
+    Resp3,
 }
 
 impl RespValue {
@@ -58,6 +77,16 @@ impl RespValue {
         RespValue::Array(v)
     }
 
+    /// Create a map
+    pub fn map(pairs: Vec<(RespValue, RespValue)>) -> Self {
+        RespValue::Map(pairs)
+    }
+
+    /// Attach metadata attributes to a reply
+    pub fn attribute(attributes: Vec<(RespValue, RespValue)>, value: RespValue) -> Self {
+        RespValue::Attribute(attributes, Box::new(value))
+    }
+
     /// Check if this is an array
     pub fn is_array(&self) -> bool {
         matches!(self, RespValue::Array(_))
@@ -97,6 +126,8 @@ impl fmt::Display for RespValue {
             RespValue::BulkString(b) => write!(f, "BulkString({} bytes)", b.len()),
             RespValue::Null => write!(f, "Null"),
             RespValue::Array(arr) => write!(f, "Array({} elements)", arr.len()),
+            RespValue::Map(pairs) => write!(f, "Map({} pairs)", pairs.len()),
+            RespValue::Attribute(attributes, value) => write!(f, "Attribute({} pairs, {})", attributes.len(), value),
         }
     }
 }
@@ -0,0 +1,487 @@
+//! Publish/subscribe module
+//!
+//! The broker is global: a single instance is shared by every RESP
+//! connection and the web layer, independently of the shards, so a message
+//! published anywhere reaches all subscribers of the channel.
+//!
+//! Each subscriber has a single bounded queue for all its channels, filled
+//! under the broker lock, so it receives messages in global publish order.
+//! A subscriber whose queue is full is dropped rather than blocking PUBLISH.
+//!
+//! Sharded channels (SSUBSCRIBE, SPUBLISH) are kept apart: each channel is
+//! routed with the cluster's `ShardRouter` to the channel map of one shard,
+//! with its own lock, so sharded publishers of different channels don't
+//! contend. Sharded and classic channels of the same name don't see each
+//! other's messages.
+
+use crate::cluster::ShardRouter;
+use crate::protocol::RespValue;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, Notify};
+use tracing::{debug, warn};
+
+/// Default capacity of a subscriber's message queue
+pub const DEFAULT_SUBSCRIBER_QUEUE_CAPACITY: usize = 1024;
+
+/// A message delivered to a subscriber
+#[derive(Debug, Clone, PartialEq)]
+pub struct PubSubMessage {
+    /// Channel the message was published on
+    pub channel: Bytes,
+
+    /// Message payload
+    pub payload: Bytes,
+
+    /// Published with SPUBLISH on a sharded channel
+    pub sharded: bool,
+}
+
+impl PubSubMessage {
+    /// Build the RESP push sent to subscribers: ["message", channel,
+    /// payload], or "smessage" for a sharded channel
+    pub fn to_resp(&self) -> RespValue {
+        RespValue::array(vec![
+            RespValue::bulk_string(if self.sharded { "smessage" } else { "message" }),
+            RespValue::bulk_string(self.channel.clone()),
+            RespValue::bulk_string(self.payload.clone()),
+        ])
+    }
+
+    /// Bytes accounted to a subscriber's output buffer for this message
+    pub fn size(&self) -> usize {
+        // Payloads plus the RESP framing of the push
+        self.channel.len() + self.payload.len() + 32
+    }
+}
+
+/// Output pending for a connection, shared with the broker
+///
+/// Both the connection (replies being written) and the broker (messages
+/// queued for a subscriber) account their bytes here. Once the limit is
+/// crossed the buffer is flagged as overflowed for good, and the connection
+/// is expected to close.
+#[derive(Debug)]
+pub struct OutputBuffer {
+    /// Bytes queued or being written
+    pending: AtomicUsize,
+
+    /// Maximum pending bytes (0 means no limit)
+    limit: usize,
+
+    /// Set once the limit has been crossed
+    overflowed: AtomicBool,
+
+    /// Wakes up the connection when the limit is crossed
+    notify: Notify,
+}
+
+impl OutputBuffer {
+    /// Create an empty output buffer with the given limit (0 for none)
+    pub fn new(limit: usize) -> Self {
+        OutputBuffer {
+            pending: AtomicUsize::new(0),
+            limit,
+            overflowed: AtomicBool::new(false),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Account for `bytes` more pending output
+    ///
+    /// Returns false, and flags the overflow, when this crosses the limit.
+    pub fn reserve(&self, bytes: usize) -> bool {
+        let pending = self.pending.fetch_add(bytes, Ordering::AcqRel) + bytes;
+        if self.limit > 0 && pending > self.limit {
+            self.mark_overflowed();
+            return false;
+        }
+        true
+    }
+
+    /// Flag the client as unable to keep up, so its connection closes
+    pub fn mark_overflowed(&self) {
+        self.overflowed.store(true, Ordering::Release);
+        self.notify.notify_one();
+    }
+
+    /// Release `bytes` of output once written (or dropped)
+    pub fn release(&self, bytes: usize) {
+        self.pending.fetch_sub(bytes, Ordering::AcqRel);
+    }
+
+    /// Bytes currently pending
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    /// Configured limit (0 means no limit)
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Whether the limit has been crossed
+    pub fn is_overflowed(&self) -> bool {
+        self.overflowed.load(Ordering::Acquire)
+    }
+
+    /// Wait until the limit is crossed
+    pub async fn overflowed(&self) {
+        // notify_one keeps a permit when nobody is waiting yet
+        while !self.is_overflowed() {
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Sender half given to the broker by each subscriber
+///
+/// Messages are accounted to the subscriber's output buffer when queued.
+#[derive(Debug, Clone)]
+pub struct MessageSender {
+    tx: mpsc::Sender<PubSubMessage>,
+    output: Arc<OutputBuffer>,
+}
+
+impl MessageSender {
+    /// Wrap the sender of a subscriber's queue
+    pub fn new(tx: mpsc::Sender<PubSubMessage>, output: Arc<OutputBuffer>) -> Self {
+        MessageSender { tx, output }
+    }
+
+    /// Queue a message for the subscriber, without ever waiting
+    ///
+    /// Returns false when the subscriber is gone, its queue is full or its
+    /// output buffer limit is crossed; the broker then drops the subscriber
+    /// and its connection is closed.
+    pub fn send(&self, message: PubSubMessage) -> bool {
+        if self.output.is_overflowed() {
+            return false;
+        }
+        let size = message.size();
+        if !self.output.reserve(size) {
+            return false;
+        }
+        match self.tx.try_send(message) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                warn!("Subscriber queue full, dropping the subscriber");
+                self.output.release(size);
+                self.output.mark_overflowed();
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                self.output.release(size);
+                false
+            }
+        }
+    }
+}
+
+/// Subscribers of each channel, by subscriber ID
+type ChannelMap = HashMap<Bytes, HashMap<u64, MessageSender>>;
+
+/// Add a subscriber to a channel, returns false if already subscribed
+fn subscribe_in(channels: &Mutex<ChannelMap>, subscriber_id: u64, channel: Bytes, tx: MessageSender) -> bool {
+    let mut channels = channels.lock().unwrap();
+    channels
+        .entry(channel)
+        .or_default()
+        .insert(subscriber_id, tx)
+        .is_none()
+}
+
+/// Remove a subscriber from a channel, returns false if not subscribed
+fn unsubscribe_in(channels: &Mutex<ChannelMap>, subscriber_id: u64, channel: &Bytes) -> bool {
+    let mut channels = channels.lock().unwrap();
+    let Some(subscribers) = channels.get_mut(channel) else {
+        return false;
+    };
+    let removed = subscribers.remove(&subscriber_id).is_some();
+    if subscribers.is_empty() {
+        channels.remove(channel);
+    }
+    removed
+}
+
+/// Deliver a message to the subscribers of its channel, returns how many
+/// received it
+///
+/// Subscribers whose connection is gone are dropped on the way.
+fn publish_in(channels: &Mutex<ChannelMap>, message: PubSubMessage) -> usize {
+    let mut channels = channels.lock().unwrap();
+    let Some(subscribers) = channels.get_mut(&message.channel) else {
+        return 0;
+    };
+
+    subscribers.retain(|_, tx| tx.send(message.clone()));
+    let receivers = subscribers.len();
+    if subscribers.is_empty() {
+        channels.remove(&message.channel);
+    }
+
+    debug!("Published on {:?} to {} subscribers", message.channel, receivers);
+    receivers
+}
+
+/// Global pub/sub broker
+pub struct PubSubBroker {
+    /// Subscribers of each classic channel
+    channels: Mutex<ChannelMap>,
+
+    /// Subscribers of each sharded channel, one map per shard
+    shard_channels: Vec<Mutex<ChannelMap>>,
+
+    /// Routes sharded channels to their shard, like keys
+    router: ShardRouter,
+
+    /// Next subscriber ID
+    next_id: AtomicU64,
+}
+
+impl PubSubBroker {
+    /// Create an empty broker, with a single shard for sharded channels
+    pub fn new() -> Self {
+        Self::with_shards(1)
+    }
+
+    /// Create an empty broker routing sharded channels over `num_shards`
+    /// shards (the number of shards of the cluster)
+    pub fn with_shards(num_shards: usize) -> Self {
+        PubSubBroker {
+            channels: Mutex::new(HashMap::new()),
+            shard_channels: (0..num_shards).map(|_| Mutex::new(HashMap::new())).collect(),
+            router: ShardRouter::new(num_shards),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Allocate a unique subscriber ID (one per connection)
+    pub fn next_subscriber_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Subscribe to a channel, returns false if already subscribed
+    pub fn subscribe(&self, subscriber_id: u64, channel: Bytes, tx: MessageSender) -> bool {
+        subscribe_in(&self.channels, subscriber_id, channel, tx)
+    }
+
+    /// Unsubscribe from a channel, returns false if not subscribed
+    pub fn unsubscribe(&self, subscriber_id: u64, channel: &Bytes) -> bool {
+        unsubscribe_in(&self.channels, subscriber_id, channel)
+    }
+
+    /// Publish a message, returns the number of subscribers that received it
+    ///
+    /// Subscribers whose connection is gone are dropped on the way.
+    pub fn publish(&self, channel: Bytes, payload: Bytes) -> usize {
+        publish_in(&self.channels, PubSubMessage { channel, payload, sharded: false })
+    }
+
+    /// Shard a sharded channel belongs to
+    pub fn shard_of(&self, channel: &Bytes) -> usize {
+        self.router.route_key(channel)
+    }
+
+    /// Subscribe to a sharded channel, returns false if already subscribed
+    pub fn ssubscribe(&self, subscriber_id: u64, channel: Bytes, tx: MessageSender) -> bool {
+        let shard = self.shard_of(&channel);
+        subscribe_in(&self.shard_channels[shard], subscriber_id, channel, tx)
+    }
+
+    /// Unsubscribe from a sharded channel, returns false if not subscribed
+    pub fn sunsubscribe(&self, subscriber_id: u64, channel: &Bytes) -> bool {
+        unsubscribe_in(&self.shard_channels[self.shard_of(channel)], subscriber_id, channel)
+    }
+
+    /// Publish a message on a sharded channel, returns the number of
+    /// subscribers of the channel's shard that received it
+    pub fn spublish(&self, channel: Bytes, payload: Bytes) -> usize {
+        let shard = self.shard_of(&channel);
+        publish_in(&self.shard_channels[shard], PubSubMessage { channel, payload, sharded: true })
+    }
+
+    /// Handle a `PUBLISH channel message` or `SPUBLISH channel message`
+    /// command
+    ///
+    /// Returns None for any other command, so callers (RESP connections and
+    /// the web layer) can fall back to the cluster.
+    pub fn try_publish(&self, command: &RespValue) -> Option<RespValue> {
+        let parts = command.as_array()?;
+        let name = parts.first()?.as_bulk_string()?;
+        let sharded = if name.eq_ignore_ascii_case(b"PUBLISH") {
+            false
+        } else if name.eq_ignore_ascii_case(b"SPUBLISH") {
+            true
+        } else {
+            return None;
+        };
+
+        if parts.len() != 3 {
+            let name = if sharded { "spublish" } else { "publish" };
+            return Some(RespValue::error(format!("ERR wrong number of arguments for '{}' command", name)));
+        }
+
+        match (parts[1].as_bulk_string(), parts[2].as_bulk_string()) {
+            (Some(channel), Some(payload)) => {
+                let receivers = if sharded {
+                    self.spublish(channel.clone(), payload.clone())
+                } else {
+                    self.publish(channel.clone(), payload.clone())
+                };
+                Some(RespValue::integer(receivers as i64))
+            }
+            _ => Some(RespValue::error("ERR Expected bulk string")),
+        }
+    }
+
+    /// Number of channels with at least one subscriber
+    pub fn num_channels(&self) -> usize {
+        self.channels.lock().unwrap().len()
+    }
+
+    /// Number of sharded channels of a shard with at least one subscriber
+    pub fn num_shard_channels(&self, shard: usize) -> usize {
+        self.shard_channels[shard].lock().unwrap().len()
+    }
+}
+
+impl Default for PubSubBroker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscriber(limit: usize) -> (MessageSender, mpsc::Receiver<PubSubMessage>, Arc<OutputBuffer>) {
+        let (tx, rx) = mpsc::channel(DEFAULT_SUBSCRIBER_QUEUE_CAPACITY);
+        let output = Arc::new(OutputBuffer::new(limit));
+        (MessageSender::new(tx, output.clone()), rx, output)
+    }
+
+    #[test]
+    fn test_publish_reaches_subscribers() {
+        let broker = PubSubBroker::new();
+        let (tx1, mut rx1, _) = subscriber(0);
+        let (tx2, rx2, _) = subscriber(0);
+
+        let id1 = broker.next_subscriber_id();
+        let id2 = broker.next_subscriber_id();
+        assert!(broker.subscribe(id1, Bytes::from("news"), tx1.clone()));
+        assert!(!broker.subscribe(id1, Bytes::from("news"), tx1));
+        assert!(broker.subscribe(id2, Bytes::from("news"), tx2));
+
+        assert_eq!(broker.publish(Bytes::from("news"), Bytes::from("hello")), 2);
+        assert_eq!(rx1.try_recv().unwrap().payload, Bytes::from("hello"));
+
+        // A closed subscriber is dropped on the next publish
+        drop(rx2);
+        assert_eq!(broker.publish(Bytes::from("news"), Bytes::from("again")), 1);
+
+        assert!(broker.unsubscribe(id1, &Bytes::from("news")));
+        assert_eq!(broker.num_channels(), 0);
+        assert_eq!(broker.publish(Bytes::from("news"), Bytes::from("lost")), 0);
+    }
+
+    #[test]
+    fn test_subscriber_dropped_over_output_limit() {
+        let broker = PubSubBroker::new();
+        let (tx, _rx, output) = subscriber(100);
+        let id = broker.next_subscriber_id();
+        broker.subscribe(id, Bytes::from("feed"), tx);
+
+        // Nothing is read from the queue, so pending output keeps growing
+        let payload = Bytes::from(vec![b'x'; 40]);
+        assert_eq!(broker.publish(Bytes::from("feed"), payload.clone()), 1);
+        assert_eq!(broker.publish(Bytes::from("feed"), payload.clone()), 0);
+        assert!(output.is_overflowed());
+        assert_eq!(broker.num_channels(), 0);
+    }
+
+    #[test]
+    fn test_messages_keep_publish_order_across_channels() {
+        let broker = PubSubBroker::new();
+        let (tx, mut rx, _) = subscriber(0);
+        let id = broker.next_subscriber_id();
+        broker.subscribe(id, Bytes::from("a"), tx.clone());
+        broker.subscribe(id, Bytes::from("b"), tx);
+
+        let mut published = Vec::new();
+        for i in 0..100 {
+            let channel = if i % 3 == 0 { "a" } else { "b" };
+            broker.publish(Bytes::from(channel), Bytes::from(i.to_string()));
+            published.push((Bytes::from(channel), Bytes::from(i.to_string())));
+        }
+
+        let received: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|m| (m.channel, m.payload))
+            .collect();
+        assert_eq!(received, published);
+    }
+
+    #[test]
+    fn test_full_queue_drops_subscriber() {
+        let broker = PubSubBroker::new();
+        let (tx, rx) = mpsc::channel(2);
+        let output = Arc::new(OutputBuffer::new(0));
+        let id = broker.next_subscriber_id();
+        broker.subscribe(id, Bytes::from("feed"), MessageSender::new(tx, output.clone()));
+
+        // The subscriber never reads: PUBLISH doesn't wait, it drops it
+        assert_eq!(broker.publish(Bytes::from("feed"), Bytes::from("1")), 1);
+        assert_eq!(broker.publish(Bytes::from("feed"), Bytes::from("2")), 1);
+        assert_eq!(broker.publish(Bytes::from("feed"), Bytes::from("3")), 0);
+        assert!(output.is_overflowed());
+        assert_eq!(broker.num_channels(), 0);
+        drop(rx);
+    }
+
+    #[test]
+    fn test_sharded_channels_stay_on_their_shard() {
+        let broker = PubSubBroker::with_shards(4);
+        let (tx1, mut rx1, _) = subscriber(0);
+        let (tx2, mut rx2, _) = subscriber(0);
+        let (tx3, mut rx3, _) = subscriber(0);
+        let (id1, id2, id3) = (broker.next_subscriber_id(), broker.next_subscriber_id(), broker.next_subscriber_id());
+
+        let channel = Bytes::from("orders");
+        let shard = broker.shard_of(&channel);
+        assert!(broker.ssubscribe(id1, channel.clone(), tx1.clone()));
+        assert!(!broker.ssubscribe(id1, channel.clone(), tx1));
+        assert!(broker.ssubscribe(id2, channel.clone(), tx2));
+        assert!(broker.subscribe(id3, channel.clone(), tx3));
+        assert_eq!(broker.num_shard_channels(shard), 1);
+        let others: usize = (0..4).filter(|&s| s != shard).map(|s| broker.num_shard_channels(s)).sum();
+        assert_eq!(others, 0);
+
+        // SPUBLISH counts the sharded subscribers only, who get an smessage
+        assert_eq!(broker.spublish(channel.clone(), Bytes::from("1")), 2);
+        let message = rx1.try_recv().unwrap();
+        assert_eq!(
+            message.to_resp(),
+            RespValue::array(vec![
+                RespValue::bulk_string("smessage"),
+                RespValue::bulk_string("orders"),
+                RespValue::bulk_string("1"),
+            ])
+        );
+        assert!(rx2.try_recv().unwrap().sharded);
+        assert!(rx3.try_recv().is_err());
+
+        // And PUBLISH the classic ones only
+        assert_eq!(broker.publish(channel.clone(), Bytes::from("2")), 1);
+        assert!(!rx3.try_recv().unwrap().sharded);
+        assert!(rx1.try_recv().is_err());
+
+        assert!(broker.sunsubscribe(id1, &channel));
+        assert!(broker.sunsubscribe(id2, &channel));
+        assert!(!broker.sunsubscribe(id2, &channel));
+        assert_eq!(broker.num_shard_channels(shard), 0);
+        assert_eq!(broker.spublish(channel, Bytes::from("lost")), 0);
+    }
+}
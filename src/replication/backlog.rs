@@ -0,0 +1,117 @@
+//! Replication offset and backlog of a shard
+//!
+//! Every write a shard applies advances its replication offset by one and
+//! is kept in a bounded backlog, the most recent ones last. A replica
+//! resuming at some offset (PSYNC) could then be sent the writes it missed,
+//! as long as the backlog still holds them all.
+
+use super::advance_master_repl_offset;
+use crate::protocol::RespValue;
+use std::collections::VecDeque;
+
+/// Default number of writes kept in a shard's backlog
+pub const DEFAULT_REPL_BACKLOG_ENTRIES: usize = 10_000;
+
+/// The replication offset of a shard and its recent writes
+#[derive(Debug)]
+pub struct ReplicationLog {
+    /// Writes applied so far
+    offset: u64,
+
+    /// The last writes, the one at `offset` last
+    backlog: VecDeque<RespValue>,
+
+    /// Most writes kept in the backlog
+    capacity: usize,
+}
+
+impl ReplicationLog {
+    /// Create a log at offset 0, keeping up to `capacity` writes
+    pub fn new(capacity: usize) -> Self {
+        ReplicationLog {
+            offset: 0,
+            backlog: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Writes applied so far
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Number of writes in the backlog
+    pub fn len(&self) -> usize {
+        self.backlog.len()
+    }
+
+    /// Whether the backlog is empty
+    pub fn is_empty(&self) -> bool {
+        self.backlog.is_empty()
+    }
+
+    /// Record an applied write, dropping the oldest one once the backlog is
+    /// full
+    pub fn record(&mut self, command: RespValue) {
+        self.offset += 1;
+        advance_master_repl_offset(1);
+        if self.capacity == 0 {
+            return;
+        }
+        if self.backlog.len() == self.capacity {
+            self.backlog.pop_front();
+        }
+        self.backlog.push_back(command);
+    }
+
+    /// The writes applied after `offset`, oldest first
+    ///
+    /// None when the backlog no longer holds them all, or `offset` is ahead
+    /// of this log: a replica would need a full resynchronization.
+    pub fn since(&self, offset: u64) -> Option<impl Iterator<Item = &RespValue>> {
+        let missed = usize::try_from(self.offset.checked_sub(offset)?).ok()?;
+        if missed > self.backlog.len() {
+            return None;
+        }
+        Some(self.backlog.range(self.backlog.len() - missed..))
+    }
+}
+
+impl Default for ReplicationLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_REPL_BACKLOG_ENTRIES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(i: usize) -> RespValue {
+        RespValue::array(vec![
+            RespValue::bulk_string("SET"),
+            RespValue::bulk_string(format!("key:{}", i)),
+            RespValue::bulk_string("value"),
+        ])
+    }
+
+    #[test]
+    fn test_backlog_keeps_recent_writes() {
+        let mut log = ReplicationLog::new(3);
+        assert_eq!(log.since(0).unwrap().count(), 0);
+        for i in 1..=5 {
+            log.record(set(i));
+        }
+        assert_eq!(log.offset(), 5);
+        assert_eq!(log.len(), 3);
+
+        // A replica at offset 3 missed the last two writes
+        assert_eq!(log.since(3).unwrap().cloned().collect::<Vec<_>>(), vec![set(4), set(5)]);
+        assert_eq!(log.since(2).unwrap().count(), 3);
+        assert_eq!(log.since(5).unwrap().count(), 0);
+
+        // Writes dropped from the backlog, or an offset from the future
+        assert!(log.since(1).is_none());
+        assert!(log.since(6).is_none());
+    }
+}
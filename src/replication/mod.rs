@@ -0,0 +1,117 @@
+//! Replication state
+//!
+//! FerrumDB has no replication yet: the server is always a master without
+//! replicas. This module holds what INFO `# Replication` and ROLE report
+//! meanwhile (role, replication ID and offset), so tooling inspecting them
+//! works before REPLICAOF exists, and the per-shard offsets and backlogs a
+//! future PSYNC would resume from.
+
+mod backlog;
+
+pub use backlog::{ReplicationLog, DEFAULT_REPL_BACKLOG_ENTRIES};
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Length of a replication ID, in hex characters
+pub const REPLID_LEN: usize = 40;
+
+/// Replication ID of this server, generated on first use
+static REPLID: OnceLock<Mutex<String>> = OnceLock::new();
+
+/// Replication offset: writes applied so far, by all shards
+static MASTER_REPL_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Generate a random replication ID (40 hex characters, like Redis)
+///
+/// Seeded from the process' random hasher keys, the time and a counter, so
+/// two IDs never repeat.
+fn generate_replid() -> String {
+    static GENERATED: AtomicU64 = AtomicU64::new(0);
+    let state = RandomState::new();
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+    let count = GENERATED.fetch_add(1, Ordering::Relaxed);
+
+    let mut replid = String::with_capacity(REPLID_LEN + 16);
+    for part in 0..3u64 {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(time);
+        hasher.write_u64(count);
+        hasher.write_u64(part);
+        replid.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    replid.truncate(REPLID_LEN);
+    replid
+}
+
+fn replid_cell() -> &'static Mutex<String> {
+    REPLID.get_or_init(|| Mutex::new(generate_replid()))
+}
+
+/// Role of the server (`master` until REPLICAOF exists)
+pub fn role() -> &'static str {
+    "master"
+}
+
+/// Current replication ID, stable for the life of the process unless
+/// changed with DEBUG CHANGE-REPL-ID
+pub fn replid() -> String {
+    replid_cell().lock().unwrap().clone()
+}
+
+/// Replace the replication ID with a new random one
+pub fn change_replid() {
+    *replid_cell().lock().unwrap() = generate_replid();
+}
+
+/// Current replication offset
+pub fn master_repl_offset() -> u64 {
+    MASTER_REPL_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Advance the replication offset by `writes` applied by a shard
+fn advance_master_repl_offset(writes: u64) {
+    MASTER_REPL_OFFSET.fetch_add(writes, Ordering::Relaxed);
+}
+
+/// The INFO `# Replication` section, without its header
+pub fn info_section() -> String {
+    format!(
+        "role:{}\r\n\
+        connected_slaves:0\r\n\
+        master_failover_state:no-failover\r\n\
+        master_replid:{}\r\n\
+        master_replid2:{}\r\n\
+        master_repl_offset:{}\r\n\
+        second_repl_offset:-1\r\n\
+        repl_backlog_active:0\r\n\
+        repl_backlog_size:0\r\n\
+        repl_backlog_first_byte_offset:0\r\n\
+        repl_backlog_histlen:0\r\n",
+        role(),
+        replid(),
+        "0".repeat(REPLID_LEN),
+        master_repl_offset()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replid_is_stable_until_changed() {
+        let first = replid();
+        assert_eq!(first.len(), REPLID_LEN);
+        assert!(first.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_eq!(replid(), first);
+
+        change_replid();
+        let second = replid();
+        assert_eq!(second.len(), REPLID_LEN);
+        assert_ne!(second, first);
+    }
+}
@@ -0,0 +1,194 @@
+//! Registry of the connected clients (CLIENT LIST, CLIENT INFO)
+//!
+//! Each connection registers itself when it is created and keeps a handle
+//! on its own entry, which it updates as it reads commands; the entry is
+//! removed when the connection is dropped.
+
+use crate::protocol::RespValue;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Commands reported with their subcommand, as `client|info`
+const CONTAINER_COMMANDS: &[&str] = &["client", "command", "config", "debug", "memory", "object"];
+
+/// What is known about a connected client
+#[derive(Debug)]
+pub struct ClientInfo {
+    /// Unique ID of the connection (CLIENT ID)
+    pub id: u64,
+
+    /// Address of the client
+    pub addr: Option<SocketAddr>,
+
+    /// Name set with CLIENT SETNAME (empty if none)
+    pub name: String,
+
+    /// When the connection was accepted
+    pub created: Instant,
+
+    /// When the last command was read
+    pub last_interaction: Instant,
+
+    /// Name of the last command, lowercase (`client|info` for subcommands)
+    pub last_command: String,
+
+    /// Commands read on this connection, the current one included
+    pub commands: u64,
+}
+
+impl ClientInfo {
+    fn new(id: u64, addr: Option<SocketAddr>) -> Self {
+        let now = Instant::now();
+        ClientInfo {
+            id,
+            addr,
+            name: String::new(),
+            created: now,
+            last_interaction: now,
+            last_command: "NULL".to_string(),
+            commands: 0,
+        }
+    }
+
+    /// Record a command read on the connection
+    pub fn record_command(&mut self, command: &RespValue) {
+        self.record(command_name(command));
+    }
+
+    /// Record a command by name (for the inline PING, never parsed)
+    pub fn record(&mut self, name: String) {
+        self.last_interaction = Instant::now();
+        self.last_command = name;
+        self.commands += 1;
+    }
+
+    /// CLIENT LIST / CLIENT INFO line describing the client, without the
+    /// trailing newline
+    pub fn line(&self) -> String {
+        let addr = self.addr.map(|addr| addr.to_string()).unwrap_or_default();
+        format!(
+            "id={} addr={} name={} age={} idle={} db=0 cmd={} tot-cmds={}",
+            self.id,
+            addr,
+            self.name,
+            self.created.elapsed().as_secs(),
+            self.last_interaction.elapsed().as_secs(),
+            self.last_command,
+            self.commands
+        )
+    }
+}
+
+/// Name of a command as CLIENT LIST reports it
+fn command_name(command: &RespValue) -> String {
+    let parts = command.as_array().map(Vec::as_slice).unwrap_or_default();
+    let Some(name) = parts.first().and_then(RespValue::as_bulk_string) else {
+        return "NULL".to_string();
+    };
+    let mut name = String::from_utf8_lossy(name).to_lowercase();
+    if CONTAINER_COMMANDS.contains(&name.as_str()) {
+        if let Some(subcommand) = parts.get(1).and_then(RespValue::as_bulk_string) {
+            name.push('|');
+            name.push_str(&String::from_utf8_lossy(subcommand).to_lowercase());
+        }
+    }
+    name
+}
+
+/// Whether a name can be given with CLIENT SETNAME (no spaces, newlines or
+/// other special characters)
+pub fn is_valid_client_name(name: &[u8]) -> bool {
+    name.iter().all(|&byte| (b'!'..=b'~').contains(&byte))
+}
+
+/// Connected clients, by ID
+#[derive(Debug)]
+pub struct ClientRegistry {
+    next_id: AtomicU64,
+    clients: Mutex<BTreeMap<u64, Arc<Mutex<ClientInfo>>>>,
+}
+
+/// Clients connected to this process
+pub static CLIENTS: ClientRegistry = ClientRegistry::new();
+
+impl ClientRegistry {
+    /// An empty registry
+    pub const fn new() -> Self {
+        ClientRegistry { next_id: AtomicU64::new(1), clients: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Register a new client and return its entry
+    pub fn register(&self, addr: Option<SocketAddr>) -> Arc<Mutex<ClientInfo>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let info = Arc::new(Mutex::new(ClientInfo::new(id, addr)));
+        self.clients.lock().unwrap().insert(id, info.clone());
+        info
+    }
+
+    /// Remove a client that disconnected
+    pub fn unregister(&self, id: u64) {
+        self.clients.lock().unwrap().remove(&id);
+    }
+
+    /// Number of connected clients
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// Whether no client is connected
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// CLIENT LIST reply: one line per client, by ID
+    pub fn list(&self) -> String {
+        let clients: Vec<_> = self.clients.lock().unwrap().values().cloned().collect();
+        clients.iter().map(|info| info.lock().unwrap().line() + "\n").collect()
+    }
+}
+
+impl Default for ClientRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmd(parts: &[&[u8]]) -> RespValue {
+        RespValue::array(parts.iter().map(|part| RespValue::bulk_string(part.to_vec())).collect())
+    }
+
+    #[test]
+    fn test_registry_lines() {
+        let registry = ClientRegistry::new();
+        let first = registry.register("127.0.0.1:4000".parse().ok());
+        let second = registry.register(None);
+        assert_eq!(registry.len(), 2);
+
+        first.lock().unwrap().record_command(&cmd(&[b"CLIENT", b"SETNAME"]));
+        first.lock().unwrap().name = "worker".to_string();
+        second.lock().unwrap().record_command(&cmd(&[b"GET", b"k"]));
+        assert_eq!(
+            registry.list(),
+            "id=1 addr=127.0.0.1:4000 name=worker age=0 idle=0 db=0 cmd=client|setname tot-cmds=1\n\
+             id=2 addr= name= age=0 idle=0 db=0 cmd=get tot-cmds=1\n"
+        );
+
+        registry.unregister(1);
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn test_client_names() {
+        assert!(is_valid_client_name(b"worker-1"));
+        assert!(is_valid_client_name(b""));
+        assert!(!is_valid_client_name(b"two words"));
+        assert!(!is_valid_client_name(b"new\nline"));
+    }
+}
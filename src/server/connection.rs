@@ -1,99 +1,221 @@
 //! Connection handling
 //!
 //! Manages individual client connections, parsing RESP commands
-//! and sending responses.
+//! and sending responses. Commands are read through a `FramedRead` with
+//! the `RespCodec`, which resumes parsing where it stopped as data arrives.
 
 use crate::dispatch::Dispatcher;
 use crate::cluster::ClusterManager;
-use crate::protocol::{RespParser, RespEncoder, RespValue, RespError};
-use bytes::BytesMut;
+use crate::commands::{self, ClientOptions};
+use crate::protocol::{ProtocolVersion, RespCodec, RespEncoder, RespError, RespFrame, RespValue};
+use crate::pubsub::{MessageSender, OutputBuffer, PubSubBroker, PubSubMessage};
+use super::rate_limit::RateLimiter;
+use super::{is_valid_client_name, ClientInfo, ConnectionConfig, CLIENTS};
+use bytes::{Bytes, BytesMut};
+use std::collections::BTreeSet;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{Decoder, FramedRead};
 use tracing::{debug, warn};
 
+/// Cached reply to an inline PING
+const PONG_REPLY: &[u8] = b"+PONG\r\n";
+
+/// Size of the encoded responses of a batch written together
+const BATCH_WRITE_SIZE: usize = 64 * 1024;
+
 /// Connection handler
 pub struct Connection {
-    /// TCP stream
-    stream: TcpStream,
+    /// Frames read from the client
+    reader: FramedRead<OwnedReadHalf, RespCodec>,
 
-    /// Read buffer
-    read_buffer: BytesMut,
+    /// Write half of the TCP stream
+    writer: OwnedWriteHalf,
 
     /// Write buffer
     write_buffer: BytesMut,
+
+    /// Protocol version the replies are encoded in
+    protocol: ProtocolVersion,
+
+    /// Output pending for this client, shared with the broker
+    output: Arc<OutputBuffer>,
+
+    /// Options set by this client (e.g. CLIENT NO-TOUCH)
+    client: ClientOptions,
+
+    /// Entry of this connection in the client registry
+    info: Arc<std::sync::Mutex<ClientInfo>>,
+
+    /// Global pub/sub broker (None when pub/sub is not available)
+    pubsub: Option<Arc<PubSubBroker>>,
+
+    /// Subscriber ID of this connection in the broker
+    subscriber_id: u64,
+
+    /// Channels this connection is subscribed to
+    subscriptions: BTreeSet<Bytes>,
+
+    /// Sharded channels this connection is subscribed to (SSUBSCRIBE)
+    shard_subscriptions: BTreeSet<Bytes>,
+
+    /// Sender given to the broker for this connection's subscriptions
+    message_tx: MessageSender,
+
+    /// Messages published on subscribed channels
+    message_rx: mpsc::Receiver<PubSubMessage>,
+
+    /// Rate limit of the commands sent by this client
+    limiter: RateLimiter,
+
+    /// Whether the client closed the connection while a reply was written
+    peer_closed: bool,
+}
+
+/// Something that happened while waiting on a cluster connection
+enum ConnectionEvent {
+    /// A frame read from the socket, None once the client disconnected
+    Frame(Option<Result<RespFrame, RespError>>),
+
+    /// A message published on a subscribed channel
+    Message(PubSubMessage),
+
+    /// The output buffer limit was crossed
+    Overflow,
+}
+
+/// What to do with a frame read from the client
+enum Incoming {
+    /// Execute this command
+    Command(RespValue),
+
+    /// Nothing, the frame was answered already
+    Handled,
+
+    /// Nothing more, the client disconnected
+    Closed,
 }
 
 impl Connection {
     /// Create a new connection handler
     pub fn new(stream: TcpStream) -> Self {
+        Self::with_config(stream, ConnectionConfig::default())
+    }
+
+    /// Create a new connection handler with the given configuration
+    pub fn with_config(stream: TcpStream, config: ConnectionConfig) -> Self {
+        let (message_tx, message_rx) = mpsc::channel(config.subscriber_queue_capacity.max(1));
+        let output = Arc::new(OutputBuffer::new(config.output_buffer_limit));
+        let client = ClientOptions {
+            addr: stream.peer_addr().ok(),
+            ..ClientOptions::default()
+        };
+        let info = CLIENTS.register(client.addr);
+        let (reader, writer) = stream.into_split();
         Connection {
-            stream,
-            read_buffer: BytesMut::with_capacity(4096),
+            reader: FramedRead::new(reader, RespCodec::new()),
+            writer,
             write_buffer: BytesMut::with_capacity(4096),
+            protocol: ProtocolVersion::default(),
+            message_tx: MessageSender::new(message_tx, output.clone()),
+            output,
+            client,
+            info,
+            pubsub: None,
+            subscriber_id: 0,
+            subscriptions: BTreeSet::new(),
+            shard_subscriptions: BTreeSet::new(),
+            message_rx,
+            limiter: RateLimiter::new(config.max_commands_per_second),
+            peer_closed: false,
         }
     }
 
+    /// Create a connection handler with access to the global pub/sub broker
+    pub fn with_pubsub(stream: TcpStream, pubsub: Arc<PubSubBroker>, config: ConnectionConfig) -> Self {
+        let mut connection = Self::with_config(stream, config);
+        connection.subscriber_id = pubsub.next_subscriber_id();
+        connection.pubsub = Some(pubsub);
+        connection
+    }
+
     /// Handle the connection
     ///
     /// Reads commands from the client, dispatches them, and sends responses.
+    /// The commands of a pipeline already received are dispatched together,
+    /// as one batch under one lock of the dispatcher.
     pub async fn handle(
         &mut self,
         dispatcher: Arc<Mutex<Dispatcher>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        loop {
-            // Read data from the socket
-            let n = self.stream.read_buf(&mut self.read_buffer).await?;
-
-            // Connection closed
-            if n == 0 {
-                if self.read_buffer.is_empty() {
-                    return Ok(());
-                } else {
-                    return Err("connection reset by peer".into());
-                }
-            }
+        let mut pending = None;
+        while !self.peer_closed {
+            let frame = match pending.take() {
+                Some(frame) => Some(frame),
+                None => self.reader.next().await,
+            };
+            let value = match self.take_command(frame).await? {
+                Incoming::Command(value) => value,
+                Incoming::Handled => continue,
+                Incoming::Closed => return Ok(()),
+            };
 
-            debug!("Read {} bytes", n);
-
-            // Try to parse commands from the buffer
-            loop {
-                match RespParser::parse(&mut self.read_buffer) {
-                    Ok(Some(value)) => {
-                        debug!("Parsed command: {}", value);
-
-                        // Dispatch the command
-                        let response = {
-                            let mut disp = dispatcher.lock().await;
-                            disp.dispatch(value)
-                        };
-
-                        debug!("Response: {}", response);
+            // Client options are connection state, not store state
+            if let Some(response) = self.handle_client_options(&value) {
+                self.send_response(response).await?;
+                continue;
+            }
+            if let Some(response) = self.handle_client_info(&value) {
+                self.send_response(response).await?;
+                continue;
+            }
+            if let Some(response) = self.handle_hello(&value) {
+                self.send_response(response).await?;
+                continue;
+            }
 
-                        // Encode and send the response
-                        self.send_response(response).await?;
-                    }
-                    Ok(None) => {
-                        // Need more data
-                        debug!("Need more data to complete command");
-                        break;
-                    }
-                    Err(RespError::Incomplete) => {
-                        // Need more data
-                        debug!("Incomplete command");
-                        break;
+            // Along with the following commands already buffered, up to one
+            // the connection handles itself or over the rate limit
+            let mut batch = vec![value];
+            while let Some(frame) = self.buffered_frame() {
+                match frame {
+                    Ok(RespFrame::Value(value)) if !is_connection_command(&value) && self.limiter.try_acquire(Instant::now()) => {
+                        self.info.lock().unwrap().record_command(&value);
+                        batch.push(value)
                     }
-                    Err(e) => {
-                        // Protocol error
-                        warn!("Protocol error: {}", e);
-                        let error_response = RespValue::error(format!("ERR protocol error: {}", e));
-                        self.send_response(error_response).await?;
+                    frame => {
+                        pending = Some(frame);
                         break;
                     }
                 }
             }
+
+            // Dispatch the commands
+            let responses = {
+                let mut disp = dispatcher.lock().await;
+                disp.context_mut().apply_client_options(self.client);
+                disp.dispatch_batch(batch)
+            };
+
+            // Encode and send the responses
+            self.send_batch(responses).await?;
         }
+        Ok(())
+    }
+
+    /// Decode the next frame already in the read buffer, without reading
+    /// from the socket
+    fn buffered_frame(&mut self) -> Option<Result<RespFrame, RespError>> {
+        // The decoder keeps its progress through a frame still incomplete
+        let mut codec = std::mem::take(self.reader.decoder_mut());
+        let frame = codec.decode(self.reader.read_buffer_mut()).transpose();
+        *self.reader.decoder_mut() = codec;
+        frame
     }
 
     /// Handle the connection with cluster manager
@@ -103,67 +225,783 @@ impl Connection {
         &mut self,
         cluster: Arc<ClusterManager>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        loop {
-            // Read data from the socket
-            let n = self.stream.read_buf(&mut self.read_buffer).await?;
-
-            // Connection closed
-            if n == 0 {
-                if self.read_buffer.is_empty() {
-                    return Ok(());
-                } else {
-                    return Err("connection reset by peer".into());
+        while !self.peer_closed {
+            // Read a frame from the socket, or forward a published message
+            let event = tokio::select! {
+                frame = self.reader.next() => ConnectionEvent::Frame(frame),
+                Some(message) = self.message_rx.recv() => ConnectionEvent::Message(message),
+                _ = self.output.overflowed() => ConnectionEvent::Overflow,
+            };
+
+            let frame = match event {
+                ConnectionEvent::Frame(frame) => frame,
+                ConnectionEvent::Message(message) => {
+                    // The message is accounted again, encoded, while written
+                    self.output.release(message.size());
+                    self.send_response(message.to_resp()).await?;
+                    continue;
                 }
+                ConnectionEvent::Overflow => return Err(self.overflow_error()),
+            };
+
+            let value = match self.take_command(frame).await? {
+                Incoming::Command(value) => value,
+                Incoming::Handled => continue,
+                Incoming::Closed => return Ok(()),
+            };
+
+            // Pub/sub commands are handled by the connection itself
+            if let Some(responses) = self.handle_pubsub(&value) {
+                for response in responses {
+                    self.send_response(response).await?;
+                }
+                continue;
             }
 
-            debug!("Read {} bytes", n);
+            // So are client options and the client registry
+            if let Some(response) = self.handle_client_options(&value) {
+                self.send_response(response).await?;
+                continue;
+            }
+            if let Some(response) = self.handle_client_info(&value) {
+                self.send_response(response).await?;
+                continue;
+            }
+            if let Some(response) = self.handle_hello(&value) {
+                self.send_response(response).await?;
+                continue;
+            }
 
-            // Try to parse commands from the buffer
-            loop {
-                match RespParser::parse(&mut self.read_buffer) {
-                    Ok(Some(value)) => {
-                        debug!("Parsed command: {}", value);
+            // Execute the command on the cluster
+            let response = cluster.execute_for_client(value, self.client).await;
 
-                        // Execute the command on the cluster
-                        let response = cluster.execute(value).await;
+            debug!("Response: {}", response);
 
-                        debug!("Response: {}", response);
+            // Encode and send the response
+            self.send_response(response).await?;
+        }
+        Ok(())
+    }
 
-                        // Encode and send the response
-                        self.send_response(response).await?;
-                    }
-                    Ok(None) => {
-                        // Need more data
-                        debug!("Need more data to complete command");
-                        break;
-                    }
-                    Err(RespError::Incomplete) => {
-                        // Need more data
-                        debug!("Incomplete command");
-                        break;
-                    }
-                    Err(e) => {
-                        // Protocol error
-                        warn!("Protocol error: {}", e);
-                        let error_response = RespValue::error(format!("ERR protocol error: {}", e));
-                        self.send_response(error_response).await?;
-                        break;
-                    }
+    /// Turn a frame read from the client into the command to execute
+    ///
+    /// An inline PING is answered here, with the cached `+PONG`, unless the
+    /// connection is subscribed: it is then a PING command like any other.
+    /// A protocol error is reported to the client, then the connection is
+    /// closed since the rest of its input can't be trusted. A client over
+    /// its rate limit waits here before its command is run.
+    async fn take_command(
+        &mut self,
+        frame: Option<Result<RespFrame, RespError>>,
+    ) -> Result<Incoming, Box<dyn std::error::Error>> {
+        if let Some(Ok(_)) = frame {
+            let delay = self.limiter.acquire(Instant::now());
+            if !delay.is_zero() {
+                debug!("Client over its rate limit, waiting {:?}", delay);
+                tokio::time::sleep(delay).await;
+            }
+        }
+        let value = match frame {
+            None => return Ok(Incoming::Closed),
+            Some(Ok(RespFrame::InlinePing)) if !self.is_subscribed() => {
+                self.info.lock().unwrap().record("ping".to_string());
+                self.send_raw(PONG_REPLY).await?;
+                return Ok(Incoming::Handled);
+            }
+            Some(Ok(RespFrame::InlinePing)) => RespValue::array(vec![RespValue::bulk_string("PING")]),
+            Some(Ok(RespFrame::Value(value))) => value,
+            Some(Err(RespError::IoError(e))) => return Err(e.into()),
+            Some(Err(e)) => {
+                warn!("Protocol error: {}", e);
+                self.send_response(RespValue::error(format!("ERR protocol error: {}", e))).await?;
+                return Err(e.into());
+            }
+        };
+
+        debug!("Parsed command: {}", value);
+        self.info.lock().unwrap().record_command(&value);
+        Ok(Incoming::Command(value))
+    }
+
+    /// Handle `CLIENT NO-TOUCH|DURABLE ON|OFF`, which only change this
+    /// connection
+    ///
+    /// Returns None for any other command. The options are sent along with
+    /// every following command so the store can honour them.
+    fn handle_client_options(&mut self, command: &RespValue) -> Option<RespValue> {
+        let parts = command.as_array()?;
+        if parts.len() < 2 || !parts[0].as_bulk_string()?.eq_ignore_ascii_case(b"CLIENT") {
+            return None;
+        }
+
+        let subcommand = parts[1].as_bulk_string()?;
+        let (option, name) = if subcommand.eq_ignore_ascii_case(b"NO-TOUCH") {
+            (&mut self.client.no_touch, "no-touch")
+        } else if subcommand.eq_ignore_ascii_case(b"DURABLE") {
+            (&mut self.client.durable, "durable")
+        } else {
+            return None;
+        };
+
+        if parts.len() != 3 {
+            return Some(RespValue::error(format!("ERR wrong number of arguments for 'client|{}' command", name)));
+        }
+
+        match parts[2].as_bulk_string() {
+            Some(mode) if mode.eq_ignore_ascii_case(b"ON") => *option = true,
+            Some(mode) if mode.eq_ignore_ascii_case(b"OFF") => *option = false,
+            _ => return Some(RespValue::error("ERR syntax error")),
+        }
+
+        Some(RespValue::simple_string("OK"))
+    }
+
+    /// Handle `CLIENT ID|INFO|LIST|SETNAME|GETNAME` from the client registry
+    ///
+    /// Returns None for any other command.
+    fn handle_client_info(&mut self, command: &RespValue) -> Option<RespValue> {
+        let parts = command.as_array()?;
+        if parts.len() < 2 || !parts[0].as_bulk_string()?.eq_ignore_ascii_case(b"CLIENT") {
+            return None;
+        }
+
+        let subcommand = String::from_utf8_lossy(parts[1].as_bulk_string()?).to_lowercase();
+        let arity = match subcommand.as_str() {
+            "id" | "info" | "list" | "getname" => 2,
+            "setname" => 3,
+            _ => return None,
+        };
+        if parts.len() != arity {
+            return Some(RespValue::error(format!(
+                "ERR wrong number of arguments for 'client|{}' command",
+                subcommand
+            )));
+        }
+
+        let mut info = self.info.lock().unwrap();
+        let reply = match subcommand.as_str() {
+            "id" => RespValue::integer(info.id as i64),
+            "info" => RespValue::bulk_string(info.line() + "\n"),
+            "list" => {
+                drop(info);
+                RespValue::bulk_string(CLIENTS.list())
+            }
+            "getname" if info.name.is_empty() => RespValue::Null,
+            "getname" => RespValue::bulk_string(info.name.clone()),
+            _ => {
+                let name = parts[2].as_bulk_string()?;
+                if !is_valid_client_name(name) {
+                    return Some(RespValue::error(
+                        "ERR Client names cannot contain spaces, newlines or special characters.",
+                    ));
+                }
+                info.name = String::from_utf8_lossy(name).into_owned();
+                RespValue::simple_string("OK")
+            }
+        };
+        Some(reply)
+    }
+
+    /// Handle `HELLO [protover [AUTH username password] [SETNAME name]]`,
+    /// switching the protocol of this connection's replies
+    ///
+    /// Returns None for any other command. Nothing changes unless every
+    /// option is valid; the reply, the server metadata, is already encoded
+    /// in the new protocol.
+    fn handle_hello(&mut self, command: &RespValue) -> Option<RespValue> {
+        let parts = command.as_array()?;
+        if !parts.first()?.as_bulk_string()?.eq_ignore_ascii_case(b"HELLO") {
+            return None;
+        }
+
+        let options = match commands::parse_hello(&parts[1..]) {
+            Ok(options) => options,
+            Err(error) => return Some(error),
+        };
+        if let Some((username, password)) = &options.auth {
+            if let Err(error) = commands::authenticate(username, password) {
+                return Some(error);
+            }
+        }
+        if options.name.as_ref().is_some_and(|name| !is_valid_client_name(name)) {
+            return Some(RespValue::error("ERR Client names cannot contain spaces, newlines or special characters."));
+        }
+
+        let mut info = self.info.lock().unwrap();
+        if let Some(name) = options.name {
+            info.name = String::from_utf8_lossy(&name).into_owned();
+        }
+        if let Some(protocol) = options.protocol {
+            self.protocol = protocol;
+        }
+        Some(commands::hello_reply(self.protocol, info.id))
+    }
+
+    /// Whether this connection is subscribed to any channel, classic or
+    /// sharded
+    fn is_subscribed(&self) -> bool {
+        !self.subscriptions.is_empty() || !self.shard_subscriptions.is_empty()
+    }
+
+    /// Handle SUBSCRIBE, UNSUBSCRIBE, PUBLISH and their sharded variants
+    /// (SSUBSCRIBE, SUNSUBSCRIBE, SPUBLISH) through the global broker
+    ///
+    /// Returns the replies to send, or None when the command must go to the
+    /// cluster. While subscribed, only pub/sub commands and PING are allowed.
+    /// The count of each confirmation is the number of channels of the same
+    /// kind the connection is subscribed to.
+    fn handle_pubsub(&mut self, command: &RespValue) -> Option<Vec<RespValue>> {
+        let broker = self.pubsub.clone()?;
+        let parts = command.as_array()?;
+        let name = String::from_utf8_lossy(parts.first()?.as_bulk_string()?).to_uppercase();
+        let args = &parts[1..];
+
+        let channels: Option<Vec<Bytes>> = args.iter().map(|a| a.as_bulk_string().cloned()).collect();
+        let channels = match channels {
+            Some(channels) => channels,
+            None => return Some(vec![RespValue::error("ERR Expected bulk string")]),
+        };
+
+        match name.as_str() {
+            "SUBSCRIBE" | "SSUBSCRIBE" => {
+                let sharded = name == "SSUBSCRIBE";
+                let kind = name.to_lowercase();
+                if channels.is_empty() {
+                    return Some(vec![RespValue::error(format!("ERR wrong number of arguments for '{}' command", kind))]);
+                }
+                let replies = channels
+                    .into_iter()
+                    .map(|channel| {
+                        let subscriptions = if sharded {
+                            broker.ssubscribe(self.subscriber_id, channel.clone(), self.message_tx.clone());
+                            &mut self.shard_subscriptions
+                        } else {
+                            broker.subscribe(self.subscriber_id, channel.clone(), self.message_tx.clone());
+                            &mut self.subscriptions
+                        };
+                        subscriptions.insert(channel.clone());
+                        subscription_reply(&kind, Some(channel), subscriptions.len())
+                    })
+                    .collect();
+                Some(replies)
+            }
+            "UNSUBSCRIBE" | "SUNSUBSCRIBE" => {
+                let sharded = name == "SUNSUBSCRIBE";
+                let kind = name.to_lowercase();
+                let subscriptions = if sharded { &mut self.shard_subscriptions } else { &mut self.subscriptions };
+
+                // Without arguments, unsubscribe from every channel
+                let channels = if channels.is_empty() {
+                    subscriptions.iter().cloned().collect()
+                } else {
+                    channels
+                };
+                if channels.is_empty() {
+                    return Some(vec![subscription_reply(&kind, None, 0)]);
                 }
+                let replies = channels
+                    .into_iter()
+                    .map(|channel| {
+                        if sharded {
+                            broker.sunsubscribe(self.subscriber_id, &channel);
+                        } else {
+                            broker.unsubscribe(self.subscriber_id, &channel);
+                        }
+                        subscriptions.remove(&channel);
+                        subscription_reply(&kind, Some(channel), subscriptions.len())
+                    })
+                    .collect();
+                Some(replies)
             }
+            "PUBLISH" | "SPUBLISH" => broker.try_publish(command).map(|reply| vec![reply]),
+            "PING" if self.is_subscribed() => {
+                let payload = channels.into_iter().next().unwrap_or_default();
+                Some(vec![RespValue::array(vec![
+                    RespValue::bulk_string("pong"),
+                    RespValue::bulk_string(payload),
+                ])])
+            }
+            _ if self.is_subscribed() => Some(vec![RespValue::error(format!(
+                "ERR Can't execute '{}': only SUBSCRIBE / UNSUBSCRIBE / PING are allowed in this context",
+                name.to_lowercase()
+            ))]),
+            _ => None,
         }
     }
 
     /// Send a response to the client
+    ///
+    /// The encoded response counts towards the output buffer limit until it
+    /// is written. If the limit is crossed, by this response or by messages
+    /// queued while the client is not reading, the connection is closed.
+    /// A client that went away while the response was written is not an
+    /// error: the connection just ends.
     async fn send_response(&mut self, response: RespValue) -> Result<(), Box<dyn std::error::Error>> {
         // Encode the response
         self.write_buffer.clear();
-        RespEncoder::encode_to(&mut self.write_buffer, &response);
+        RespEncoder::encode_for(&mut self.write_buffer, &response, self.protocol);
+        self.write_output().await
+    }
 
-        // Write to the socket
-        self.stream.write_all(&self.write_buffer).await?;
-        self.stream.flush().await?;
+    /// Send the responses of a batch of commands, like `send_response`
+    ///
+    /// Small responses are written together, up to `BATCH_WRITE_SIZE` bytes
+    /// at a time, instead of one write each.
+    async fn send_batch(&mut self, responses: Vec<RespValue>) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_buffer.clear();
+        for response in &responses {
+            debug!("Response: {}", response);
+            RespEncoder::encode_for(&mut self.write_buffer, response, self.protocol);
+            if self.write_buffer.len() >= BATCH_WRITE_SIZE {
+                self.write_output().await?;
+                self.write_buffer.clear();
+            }
+        }
+        if self.write_buffer.is_empty() {
+            return Ok(());
+        }
+        self.write_output().await
+    }
 
-        Ok(())
+    /// Send an already encoded reply, without building a RespValue
+    async fn send_raw(&mut self, reply: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_buffer.clear();
+        self.write_buffer.extend_from_slice(reply);
+        self.write_output().await
+    }
+
+    /// Write the write buffer to the socket, within the output buffer limit
+    ///
+    /// A client that closed the connection is logged at debug level and
+    /// flags the connection as closed, any other write error is returned.
+    async fn write_output(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.peer_closed {
+            return Ok(());
+        }
+        let len = self.write_buffer.len();
+        if !self.output.reserve(len) {
+            return Err(self.overflow_error());
+        }
+
+        // Write to the socket, unless the client stalls for too long
+        let stream = &mut self.writer;
+        let buffer = &self.write_buffer;
+        let write = async {
+            stream.write_all(buffer).await?;
+            stream.flush().await
+        };
+        let result = tokio::select! {
+            result = write => result,
+            _ = self.output.overflowed() => return Err(self.overflow_error()),
+        };
+
+        self.output.release(len);
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) if is_peer_closed(&e) => {
+                debug!("Client closed the connection: {}", e);
+                self.peer_closed = true;
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Error writing to the client: {}", e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Log and build the error closing a connection that can't keep up
+    /// (output buffer limit crossed or subscriber queue full)
+    fn overflow_error(&self) -> Box<dyn std::error::Error> {
+        warn!(
+            "Client can't keep up with its output ({} bytes pending, limit {}), closing connection",
+            self.output.pending(),
+            self.output.limit()
+        );
+        "client output buffer limit reached".into()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        CLIENTS.unregister(self.info.lock().unwrap().id);
+
+        // Remove this connection's subscriptions from the global broker
+        if let Some(broker) = &self.pubsub {
+            for channel in &self.subscriptions {
+                broker.unsubscribe(self.subscriber_id, channel);
+            }
+            for channel in &self.shard_subscriptions {
+                broker.sunsubscribe(self.subscriber_id, channel);
+            }
+        }
+    }
+}
+
+/// Build a SUBSCRIBE/UNSUBSCRIBE confirmation: [kind, channel, count]
+fn subscription_reply(kind: &str, channel: Option<Bytes>, count: usize) -> RespValue {
+    RespValue::array(vec![
+        RespValue::bulk_string(kind.to_string()),
+        channel.map(RespValue::bulk_string).unwrap_or(RespValue::Null),
+        RespValue::integer(count as i64),
+    ])
+}
+
+/// Whether a command is a CLIENT or HELLO command, which the connection may
+/// handle itself instead of the dispatcher
+fn is_connection_command(command: &RespValue) -> bool {
+    let name = command.as_array().and_then(|parts| parts.first()).and_then(|name| name.as_bulk_string());
+    name.is_some_and(|name| name.eq_ignore_ascii_case(b"CLIENT") || name.eq_ignore_ascii_case(b"HELLO"))
+}
+
+/// Whether an I/O error means the client closed the connection, a normal
+/// disconnect rather than a failure
+fn is_peer_closed(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        error.kind(),
+        ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::NotConnected
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_large_pipeline() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new()));
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let _ = Connection::new(socket).handle(dispatcher).await;
+        });
+
+        // 10000 commands in one write, read by the server over many reads
+        const COMMANDS: usize = 10_000;
+        let mut pipeline = BytesMut::new();
+        for i in 0..COMMANDS {
+            let command = match i % 3 {
+                0 => vec![RespValue::bulk_string("INCR"), RespValue::bulk_string("counter")],
+                1 => vec![
+                    RespValue::bulk_string("SET"),
+                    RespValue::bulk_string(format!("key:{}", i)),
+                    RespValue::bulk_string("x".repeat(i % 100)),
+                ],
+                _ => vec![RespValue::bulk_string("GET"), RespValue::bulk_string(format!("key:{}", i - 1))],
+            };
+            RespEncoder::encode_to(&mut pipeline, &RespValue::array(command));
+        }
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (mut read_half, mut write_half) = client.split();
+        let write = write_half.write_all(&pipeline);
+
+        // Read the replies while writing, so neither side blocks on a full socket
+        let read = async {
+            let mut replies = Vec::new();
+            let mut buf = BytesMut::new();
+            let mut codec = RespCodec::new();
+            while replies.len() < COMMANDS {
+                let n = read_half.read_buf(&mut buf).await.unwrap();
+                assert!(n > 0, "connection closed after {} replies", replies.len());
+                while let Some(RespFrame::Value(reply)) = tokio_util::codec::Decoder::decode(&mut codec, &mut buf).unwrap() {
+                    replies.push(reply);
+                }
+            }
+            replies
+        };
+        let (written, replies) = tokio::time::timeout(Duration::from_secs(30), async { tokio::join!(write, read) })
+            .await
+            .expect("pipeline timed out");
+        written.unwrap();
+
+        for (i, reply) in replies.iter().enumerate() {
+            let expected = match i % 3 {
+                0 => RespValue::integer((i / 3 + 1) as i64),
+                1 => RespValue::simple_string("OK"),
+                _ => RespValue::bulk_string("x".repeat((i - 1) % 100)),
+            };
+            assert_eq!(reply, &expected, "reply {}", i);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_protocol_error_closes_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new()));
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let _ = Connection::new(socket).handle(dispatcher).await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let get = b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n";
+        client.write_all(&[&get[..], b"?oops\r\n", get].concat()).await.unwrap();
+        let mut reply = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut reply))
+            .await
+            .expect("connection was not closed")
+            .unwrap();
+        let reply = String::from_utf8(reply).unwrap();
+        assert!(reply.starts_with("$-1\r\n-ERR protocol error: "), "{}", reply);
+        assert_eq!(reply.matches("$-1").count(), 1, "{}", reply);
+    }
+
+    /// Levels of the events logged while it is installed
+    #[derive(Clone, Default)]
+    struct LevelCapture(Arc<std::sync::Mutex<Vec<tracing::Level>>>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for LevelCapture {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+            self.0.lock().unwrap().push(*event.metadata().level());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_peer_closed_is_not_an_error() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let capture = LevelCapture::default();
+        let _default = tracing::subscriber::set_default(tracing_subscriber::registry().with(capture.clone()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new()));
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            Connection::new(socket).handle(dispatcher).await.map_err(|e| e.to_string())
+        });
+
+        // A large value, then more replies to it than the socket buffers
+        // hold, so the client is gone while they are being written
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let value = "x".repeat(1024 * 1024);
+        let set = format!("*3\r\n$3\r\nSET\r\n$3\r\nbig\r\n${}\r\n{}\r\n", value.len(), value);
+        client.write_all(set.as_bytes()).await.unwrap();
+        let mut reply = [0u8; 5];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+OK\r\n");
+        client.write_all(&b"*2\r\n$3\r\nGET\r\n$3\r\nbig\r\n".repeat(64)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Reset the connection rather than closing it cleanly
+        client.set_zero_linger().unwrap();
+        drop(client);
+
+        let result = tokio::time::timeout(Duration::from_secs(5), server).await.expect("connection still open").unwrap();
+        assert_eq!(result, Ok(()));
+        let levels = capture.0.lock().unwrap();
+        assert!(levels.iter().all(|level| *level > tracing::Level::WARN), "{:?}", levels);
+    }
+
+    #[tokio::test]
+    async fn test_inline_ping_skips_the_store() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new()));
+
+        let server_dispatcher = dispatcher.clone();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let _ = Connection::new(socket).handle(server_dispatcher).await;
+        });
+
+        // The store stays locked: any command reaching it would hang
+        let _store = dispatcher.lock().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"PING\r\nping\r\n").await.unwrap();
+        let mut reply = [0u8; 14];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut reply))
+            .await
+            .expect("inline PING waited on the store")
+            .unwrap();
+        assert_eq!(&reply, b"+PONG\r\n+PONG\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_client_info_reflects_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new()));
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let _ = Connection::new(socket).handle(dispatcher).await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"*3\r\n$6\r\nCLIENT\r\n$7\r\nSETNAME\r\n$6\r\nworker\r\n*2\r\n$6\r\nCLIENT\r\n$4\r\nINFO\r\n")
+            .await
+            .unwrap();
+        let mut reply = Vec::new();
+        let mut buf = [0u8; 512];
+        while !reply.ends_with(b"\n\r\n") {
+            let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf)).await.unwrap().unwrap();
+            assert!(n > 0, "connection closed");
+            reply.extend_from_slice(&buf[..n]);
+        }
+
+        let reply = String::from_utf8(reply).unwrap();
+        let info = reply.strip_prefix("+OK\r\n").unwrap();
+        let line = info.split("\r\n").nth(1).unwrap();
+        let fields: Vec<_> = line.split_whitespace().collect();
+        assert!(fields.contains(&"name=worker"), "{}", line);
+        assert!(fields.contains(&"cmd=client|info"), "{}", line);
+        assert!(fields.contains(&format!("addr={}", client.local_addr().unwrap()).as_str()), "{}", line);
+        let commands: u64 = fields.iter().find_map(|f| f.strip_prefix("tot-cmds=")).unwrap().parse().unwrap();
+        assert_eq!(commands, 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_info_counts_pipelined_commands() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new()));
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let dispatcher = dispatcher.clone();
+                tokio::spawn(async move {
+                    let _ = Connection::new(socket).handle(dispatcher).await;
+                });
+            }
+        });
+        async fn request(client: &mut TcpStream, command: &[u8], end: &[u8]) -> String {
+            client.write_all(command).await.unwrap();
+            let mut reply = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !reply.ends_with(end) {
+                let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf)).await.unwrap().unwrap();
+                assert!(n > 0, "connection closed");
+                reply.extend_from_slice(&buf[..n]);
+            }
+            String::from_utf8(reply).unwrap()
+        }
+        let field = |line: &str, name: &str| -> String {
+            line.split_whitespace().find_map(|f| f.strip_prefix(name)).unwrap().to_string()
+        };
+
+        // SET and GET in a single write: dispatched as one batch
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let reply = request(&mut client, b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n", b"$1\r\nv\r\n").await;
+        assert_eq!(reply, "+OK\r\n$1\r\nv\r\n");
+
+        // Seen from another connection, the last command is the GET
+        let mut other = TcpStream::connect(addr).await.unwrap();
+        let list = request(&mut other, b"*2\r\n$6\r\nCLIENT\r\n$4\r\nLIST\r\n", b"\n\r\n").await;
+        let me = format!("addr={}", client.local_addr().unwrap());
+        let line = list.lines().find(|line| line.split_whitespace().any(|f| f == me)).unwrap();
+        assert_eq!(field(line, "tot-cmds="), "2", "{}", line);
+        assert_eq!(field(line, "cmd="), "get", "{}", line);
+
+        let info = request(&mut client, b"*2\r\n$6\r\nCLIENT\r\n$4\r\nINFO\r\n", b"\n\r\n").await;
+        assert_eq!(field(&info, "tot-cmds="), "3", "{}", info);
+        assert_eq!(field(&info, "cmd="), "client|info", "{}", info);
+    }
+
+    #[tokio::test]
+    async fn test_hello_switches_protocol() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new()));
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let _ = Connection::new(socket).handle(dispatcher).await;
+        });
+
+        // HELLO, then a command of the same pipeline replied in RESP3
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"*5\r\n$5\r\nHELLO\r\n$1\r\n3\r\n$4\r\nAUTH\r\n$7\r\ndefault\r\n$2\r\npw\r\n*2\r\n$7\r\nHGETALL\r\n$1\r\nh\r\n")
+            .await
+            .unwrap();
+        let mut reply = Vec::new();
+        let mut buf = [0u8; 512];
+        while !reply.ends_with(b"%0\r\n") {
+            let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf)).await.unwrap().unwrap();
+            assert!(n > 0, "connection closed: {:?}", String::from_utf8_lossy(&reply));
+            reply.extend_from_slice(&buf[..n]);
+        }
+
+        let reply = String::from_utf8(reply).unwrap();
+        assert!(reply.starts_with("%7\r\n$6\r\nserver\r\n$8\r\nferrumdb\r\n"), "{}", reply);
+        for key in ["version", "proto", "id", "mode", "role", "modules"] {
+            assert!(reply.contains(&format!("${}\r\n{}\r\n", key.len(), key)), "{} missing in {}", key, reply);
+        }
+        assert!(reply.contains("$5\r\nproto\r\n:3\r\n"), "{}", reply);
+        let id = CLIENTS.list().lines().find_map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let addr = format!("addr={}", client.local_addr().unwrap());
+            fields.contains(&addr.as_str()).then(|| fields[0].to_string())
+        });
+        assert!(reply.contains(&format!("$2\r\nid\r\n:{}\r\n", id.unwrap().trim_start_matches("id="))), "{}", reply);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_delays_fast_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new()));
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let config = ConnectionConfig { max_commands_per_second: 100, ..ConnectionConfig::default() };
+            let _ = Connection::with_config(socket, config).handle(dispatcher).await;
+        });
+
+        // 150 commands at once: the first 100 run right away, the next 50
+        // at 100 per second
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let incr = b"*2\r\n$4\r\nINCR\r\n$7\r\ncounter\r\n";
+        let started = std::time::Instant::now();
+        client.write_all(&incr.repeat(150)).await.unwrap();
+        let mut reply = Vec::new();
+        let mut buf = [0u8; 4096];
+        while !reply.ends_with(b":150\r\n") {
+            let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf)).await.unwrap().unwrap();
+            assert!(n > 0, "connection closed");
+            reply.extend_from_slice(&buf[..n]);
+        }
+        assert!(started.elapsed() >= Duration::from_millis(450), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn test_output_buffer_limit_drops_stalled_subscriber() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let broker = Arc::new(PubSubBroker::new());
+        let cluster = Arc::new(ClusterManager::new(1, false).unwrap());
+
+        let server_broker = broker.clone();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let config = ConnectionConfig { output_buffer_limit: 64 * 1024, ..ConnectionConfig::default() };
+            let mut connection = Connection::with_pubsub(socket, server_broker, config);
+            connection.handle_with_cluster(cluster).await.is_err()
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$4\r\nfeed\r\n").await.unwrap();
+        let mut confirmation = [0u8; 33];
+        client.read_exact(&mut confirmation).await.unwrap();
+        assert!(confirmation.ends_with(b":1\r\n"));
+
+        // The client stops reading: once the socket buffers are full, the
+        // messages pile up until the limit is crossed and the client dropped
+        let payload = Bytes::from(vec![b'x'; 16 * 1024]);
+        let mut published = 0;
+        while broker.publish(Bytes::from("feed"), payload.clone()) == 1 {
+            published += 1;
+            assert!(published < 100_000, "subscriber was never dropped");
+            tokio::task::yield_now().await;
+        }
+
+        let closed_with_error = tokio::time::timeout(Duration::from_secs(5), server).await.unwrap().unwrap();
+        assert!(closed_with_error);
+        assert_eq!(broker.num_channels(), 0);
     }
 }
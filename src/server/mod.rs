@@ -4,18 +4,78 @@
 //! This module is responsible for accepting connections and delegating
 //! command processing to the dispatcher.
 
+mod clients;
 mod connection;
+mod rate_limit;
 
 use crate::dispatch::Dispatcher;
 use crate::cluster::ClusterManager;
 use crate::aof::AofConfig;
+use crate::pubsub::{PubSubBroker, DEFAULT_SUBSCRIBER_QUEUE_CAPACITY};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 use tracing::{info, error};
 
+pub use clients::{is_valid_client_name, ClientInfo, ClientRegistry, CLIENTS};
 pub use connection::Connection;
 
+/// Default `client-output-buffer-limit`, in bytes
+pub const DEFAULT_OUTPUT_BUFFER_LIMIT: usize = 32 * 1024 * 1024;
+
+/// `max-commands-per-second`, process-wide, read when the server starts
+/// accepting connections
+static MAX_COMMANDS_PER_SECOND: AtomicU32 = AtomicU32::new(0);
+
+/// Set `max-commands-per-second` (0 = no limit, effective at the next
+/// start)
+pub fn set_max_commands_per_second(rate: u32) {
+    MAX_COMMANDS_PER_SECOND.store(rate, Ordering::Relaxed);
+}
+
+/// Current `max-commands-per-second`
+pub fn max_commands_per_second() -> u32 {
+    MAX_COMMANDS_PER_SECOND.load(Ordering::Relaxed)
+}
+
+/// Per-connection configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionConfig {
+    /// Maximum output pending for a client, replies and published messages
+    /// included (0 means no limit). A client crossing it is disconnected.
+    pub output_buffer_limit: usize,
+
+    /// Messages queued for a subscriber before it is considered too slow
+    /// and disconnected
+    pub subscriber_queue_capacity: usize,
+
+    /// Commands a client may send per second (0 means no limit). A client
+    /// sending faster has its commands delayed down to the rate.
+    pub max_commands_per_second: u32,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            output_buffer_limit: DEFAULT_OUTPUT_BUFFER_LIMIT,
+            subscriber_queue_capacity: DEFAULT_SUBSCRIBER_QUEUE_CAPACITY,
+            max_commands_per_second: 0,
+        }
+    }
+}
+
+impl ConnectionConfig {
+    /// The defaults, with the settings of the config file
+    /// (`max-commands-per-second`)
+    pub fn from_server_config() -> Self {
+        ConnectionConfig {
+            max_commands_per_second: max_commands_per_second(),
+            ..ConnectionConfig::default()
+        }
+    }
+}
+
 /// Run the server
 ///
 /// Starts the TCP server on the given address and processes incoming connections.
@@ -67,9 +127,24 @@ pub async fn run_with_dispatcher(
 /// Run the server with a cluster manager
 ///
 /// Allows distributing keys across multiple shards for better parallelism.
+/// The pub/sub broker is global, shared by every connection (and the web
+/// server), independently of which shard handles the commands. The
+/// connections are configured from the config file
+/// (`ConnectionConfig::from_server_config`).
 pub async fn run_with_cluster(
     addr: &str,
     cluster: Arc<ClusterManager>,
+    pubsub: Arc<PubSubBroker>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    run_with_cluster_config(addr, cluster, pubsub, ConnectionConfig::from_server_config()).await
+}
+
+/// Run the server with a cluster manager and a connection configuration
+pub async fn run_with_cluster_config(
+    addr: &str,
+    cluster: Arc<ClusterManager>,
+    pubsub: Arc<PubSubBroker>,
+    config: ConnectionConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Bind the TCP listener
     let listener = TcpListener::bind(addr).await?;
@@ -80,12 +155,13 @@ pub async fn run_with_cluster(
         let (socket, addr) = listener.accept().await?;
         info!("New RESP connection from {}", addr);
 
-        // Clone the cluster Arc for this connection
+        // Clone the cluster and broker Arcs for this connection
         let cluster = cluster.clone();
+        let pubsub = pubsub.clone();
 
         // Spawn a new task to handle this connection
         tokio::spawn(async move {
-            let mut connection = Connection::new(socket);
+            let mut connection = Connection::with_pubsub(socket, pubsub, config);
 
             if let Err(e) = connection.handle_with_cluster(cluster).await {
                 error!("Connection error from {}: {}", addr, e);
@@ -0,0 +1,120 @@
+//! Per-connection command rate limit
+//!
+//! A token bucket holding up to one second of commands, refilled at the
+//! configured rate. A connection takes a token before each command; when the
+//! bucket is empty it still takes one, going into debt, and waits for the
+//! time the debt takes to be refilled before running the command. A client
+//! within the rate never waits, a faster one is slowed down to the rate.
+
+use std::time::{Duration, Instant};
+
+/// Token bucket limiting the commands of a connection
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Commands allowed per second (0 means no limit)
+    rate: u32,
+
+    /// Tokens available, negative when commands are waiting for them
+    tokens: f64,
+
+    /// When the tokens were last refilled
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing `rate` commands per second, with a full
+    /// bucket
+    pub fn new(rate: u32) -> Self {
+        RateLimiter {
+            rate,
+            tokens: f64::from(rate),
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Whether commands are limited at all
+    pub fn is_limited(&self) -> bool {
+        self.rate > 0
+    }
+
+    /// Take a token for a command at `now`, and return how long the command
+    /// must wait for it (zero when a token was available)
+    pub fn acquire(&mut self, now: Instant) -> Duration {
+        if !self.is_limited() {
+            return Duration::ZERO;
+        }
+        self.refill(now);
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / f64::from(self.rate))
+        }
+    }
+
+    /// Take a token for a command at `now` only if one is available
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        if !self.is_limited() {
+            return true;
+        }
+        self.refill(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Add the tokens earned since the last refill, up to a full bucket
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        let rate = f64::from(self.rate);
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.refilled = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fast_client_is_throttled() {
+        let mut limiter = RateLimiter::new(100);
+        let now = Instant::now();
+
+        // A second of commands right away, then each command waits for the
+        // one before it to be paid for
+        for _ in 0..100 {
+            assert_eq!(limiter.acquire(now), Duration::ZERO);
+        }
+        assert_eq!(limiter.acquire(now), Duration::from_millis(10));
+        assert_eq!(limiter.acquire(now), Duration::from_millis(20));
+        assert!(!limiter.try_acquire(now));
+
+        // Once the debt is paid, tokens come back at the rate
+        let later = now + Duration::from_millis(20);
+        assert!(!limiter.try_acquire(later));
+        assert!(limiter.try_acquire(later + Duration::from_millis(11)));
+    }
+
+    #[test]
+    fn test_slow_client_is_never_throttled() {
+        let mut limiter = RateLimiter::new(100);
+        let mut now = Instant::now();
+        for _ in 0..10_000 {
+            now += Duration::from_millis(11);
+            assert_eq!(limiter.acquire(now), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_no_limit() {
+        let mut limiter = RateLimiter::new(0);
+        let now = Instant::now();
+        for _ in 0..10_000 {
+            assert_eq!(limiter.acquire(now), Duration::ZERO);
+            assert!(limiter.try_acquire(now));
+        }
+    }
+}
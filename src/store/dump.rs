@@ -0,0 +1,248 @@
+//! DUMP serialization format
+//!
+//! Serializes a single value for DUMP, RESTORE and MIGRATE. The format is
+//! FerrumDB's own, not Redis' RDB encoding: a type byte, the length-prefixed
+//! contents, then a two-byte format version and an xxhash64 checksum of
+//! everything before it, so a payload from another version or a corrupted
+//! one is refused.
+//!
+//! Layout: [type(u8)] [contents...] [version(u16)] [checksum(u64)], all
+//! integers little-endian, and every byte string as [len(u32)] [bytes].
+
+use super::list::ListItem;
+use super::stream::{Stream, StreamId};
+use super::value::Value;
+use super::zset::SortedSet;
+use bytes::Bytes;
+
+/// Version of the DUMP format
+pub const DUMP_VERSION: u16 = 1;
+
+/// Bytes after the serialized contents: the version and the checksum
+pub const DUMP_FOOTER_LEN: usize = 2 + 8;
+
+/// Error returned for an unreadable payload, in Redis' words
+pub const BAD_DUMP_PAYLOAD: &str = "DUMP payload version or checksum are wrong";
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_HASH: u8 = 3;
+const TYPE_ZSET: u8 = 4;
+const TYPE_STREAM: u8 = 5;
+const TYPE_JSON: u8 = 6;
+
+/// Serialize a value
+pub fn dump_value(value: &Value) -> Vec<u8> {
+    let mut buf = Vec::new();
+    match value {
+        Value::String(bytes) => {
+            buf.push(TYPE_STRING);
+            put_bytes(&mut buf, bytes);
+        }
+        Value::Integer(i) => {
+            // Restored through Value::from_bytes, which gives back an Integer
+            buf.push(TYPE_STRING);
+            put_bytes(&mut buf, i.to_string().as_bytes());
+        }
+        Value::List(list) => {
+            buf.push(TYPE_LIST);
+            put_len(&mut buf, list.len());
+            list.iter().for_each(|item| put_bytes(&mut buf, &item.to_bytes()));
+        }
+        Value::Set(set) => {
+            buf.push(TYPE_SET);
+            put_len(&mut buf, set.len());
+            set.iter().for_each(|member| put_bytes(&mut buf, member));
+        }
+        Value::Hash(hash) => {
+            buf.push(TYPE_HASH);
+            put_len(&mut buf, hash.len());
+            for (field, value) in hash {
+                put_bytes(&mut buf, field);
+                put_bytes(&mut buf, value);
+            }
+        }
+        Value::ZSet(zset) => {
+            buf.push(TYPE_ZSET);
+            put_len(&mut buf, zset.len());
+            for (member, score) in zset.iter() {
+                buf.extend_from_slice(&score.to_le_bytes());
+                put_bytes(&mut buf, member);
+            }
+        }
+        Value::Stream(stream) => {
+            buf.push(TYPE_STREAM);
+            let entries = stream.range(StreamId::MIN, StreamId::MAX, None);
+            put_len(&mut buf, entries.len());
+            for (id, fields) in entries {
+                buf.extend_from_slice(&id.ms.to_le_bytes());
+                buf.extend_from_slice(&id.seq.to_le_bytes());
+                put_len(&mut buf, fields.len());
+                for (field, value) in fields {
+                    put_bytes(&mut buf, field);
+                    put_bytes(&mut buf, value);
+                }
+            }
+        }
+        Value::Json(doc) => {
+            buf.push(TYPE_JSON);
+            put_bytes(&mut buf, doc.to_string().as_bytes());
+        }
+    }
+
+    buf.extend_from_slice(&DUMP_VERSION.to_le_bytes());
+    let checksum = xxhash_rust::xxh64::xxh64(&buf, 0);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+/// Serialized size of a value, the DUMP payload without its footer
+///
+/// This is what DEBUG OBJECT reports as `serializedlength`, computed by the
+/// DUMP serializer itself so the two can't disagree.
+pub fn serialized_length(value: &Value) -> usize {
+    dump_value(value).len() - DUMP_FOOTER_LEN
+}
+
+/// Deserialize a value produced by `dump_value`
+///
+/// Fails with `BAD_DUMP_PAYLOAD` when the version, the checksum or the
+/// contents don't check out.
+pub fn restore_value(payload: &[u8]) -> Result<Value, &'static str> {
+    if payload.len() <= DUMP_FOOTER_LEN {
+        return Err(BAD_DUMP_PAYLOAD);
+    }
+    let (body, checksum) = payload.split_at(payload.len() - 8);
+    if xxhash_rust::xxh64::xxh64(body, 0).to_le_bytes() != checksum {
+        return Err(BAD_DUMP_PAYLOAD);
+    }
+    let (contents, version) = body.split_at(body.len() - 2);
+    if version != DUMP_VERSION.to_le_bytes() {
+        return Err(BAD_DUMP_PAYLOAD);
+    }
+
+    let mut reader = Reader { data: &contents[1..] };
+    let value = match contents[0] {
+        TYPE_STRING => Value::from_bytes(reader.bytes()?),
+        TYPE_LIST => {
+            let len = reader.len()?;
+            Value::List((0..len).map(|_| reader.bytes().map(ListItem::new)).collect::<Result<_, _>>()?)
+        }
+        TYPE_SET => {
+            let len = reader.len()?;
+            Value::Set((0..len).map(|_| reader.bytes()).collect::<Result<_, _>>()?)
+        }
+        TYPE_HASH => {
+            let len = reader.len()?;
+            Value::Hash(
+                (0..len)
+                    .map(|_| Ok((reader.bytes()?, reader.bytes()?)))
+                    .collect::<Result<_, &'static str>>()?,
+            )
+        }
+        TYPE_ZSET => {
+            let mut zset = SortedSet::new();
+            for _ in 0..reader.len()? {
+                let score = f64::from_le_bytes(reader.array()?);
+                zset.insert(reader.bytes()?, score);
+            }
+            Value::ZSet(zset)
+        }
+        TYPE_STREAM => {
+            let mut stream = Stream::new();
+            for _ in 0..reader.len()? {
+                let id = StreamId::new(u64::from_le_bytes(reader.array()?), u64::from_le_bytes(reader.array()?));
+                let fields = (0..reader.len()?)
+                    .map(|_| Ok((reader.bytes()?, reader.bytes()?)))
+                    .collect::<Result<_, &'static str>>()?;
+                stream.add(id, fields).map_err(|_| BAD_DUMP_PAYLOAD)?;
+            }
+            Value::Stream(stream)
+        }
+        TYPE_JSON => Value::Json(serde_json::from_slice(&reader.bytes()?).map_err(|_| BAD_DUMP_PAYLOAD)?),
+        _ => return Err(BAD_DUMP_PAYLOAD),
+    };
+
+    if !reader.data.is_empty() {
+        return Err(BAD_DUMP_PAYLOAD);
+    }
+    Ok(value)
+}
+
+fn put_len(buf: &mut Vec<u8>, len: usize) {
+    buf.extend_from_slice(&(len as u32).to_le_bytes());
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    put_len(buf, bytes.len());
+    buf.extend_from_slice(bytes);
+}
+
+/// Cursor over the contents of a payload
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], &'static str> {
+        if self.data.len() < N {
+            return Err(BAD_DUMP_PAYLOAD);
+        }
+        let (head, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(head.try_into().unwrap())
+    }
+
+    fn len(&mut self) -> Result<usize, &'static str> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn bytes(&mut self) -> Result<Bytes, &'static str> {
+        let len = self.len()?;
+        if self.data.len() < len {
+            return Err(BAD_DUMP_PAYLOAD);
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(Bytes::copy_from_slice(head))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_restore_round_trip() {
+        let mut zset = SortedSet::new();
+        zset.insert(Bytes::from("m"), -2.5);
+        let mut stream = Stream::new();
+        stream.add(StreamId::new(5, 1), vec![(Bytes::from("f"), Bytes::from("v"))]).unwrap();
+
+        let values = [
+            Value::string("hello"),
+            Value::integer(-7),
+            Value::list([Bytes::from("a"), Bytes::from("b")]),
+            Value::Set([Bytes::from("x"), Bytes::from("y")].into_iter().collect()),
+            Value::Hash([(Bytes::from("k"), Bytes::from("v"))].into_iter().collect()),
+            Value::ZSet(zset),
+            Value::Stream(stream),
+            Value::Json(serde_json::json!({"a": [1, "b", null]})),
+        ];
+        for value in values {
+            assert_eq!(restore_value(&dump_value(&value)), Ok(value));
+        }
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_payloads() {
+        let mut payload = dump_value(&Value::string("hello"));
+        assert_eq!(restore_value(&payload[..payload.len() - 1]), Err(BAD_DUMP_PAYLOAD));
+
+        let last = payload.len() - 1;
+        payload[last] ^= 0xFF;
+        assert_eq!(restore_value(&payload), Err(BAD_DUMP_PAYLOAD));
+        assert_eq!(restore_value(b"garbage"), Err(BAD_DUMP_PAYLOAD));
+    }
+}
@@ -0,0 +1,160 @@
+//! Encoding thresholds
+//!
+//! Most values have a single in-memory representation, but OBJECT ENCODING
+//! reports the compact Redis encoding a value of that size would use, based
+//! on the same thresholds as Redis' configuration. Sets really switch
+//! representation, at `set-max-listpack-entries`.
+
+use super::list::ListItem;
+use super::set::Set;
+use super::zset::SortedSet;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+
+/// Default `list-max-listpack-size` (-2: 8 KB per node)
+pub const DEFAULT_LIST_MAX_LISTPACK_SIZE: i64 = -2;
+
+/// `list-max-listpack-size`, process-wide
+static LIST_MAX_LISTPACK_SIZE: AtomicI64 = AtomicI64::new(DEFAULT_LIST_MAX_LISTPACK_SIZE);
+
+/// Set `list-max-listpack-size`
+///
+/// Like Redis, a positive value is a maximum number of elements, and -1 to
+/// -5 a maximum size of 4, 8, 16, 32 or 64 KB.
+pub fn set_list_max_listpack_size(size: i64) {
+    LIST_MAX_LISTPACK_SIZE.store(size, Ordering::Relaxed);
+}
+
+/// Current `list-max-listpack-size`
+pub fn list_max_listpack_size() -> i64 {
+    LIST_MAX_LISTPACK_SIZE.load(Ordering::Relaxed)
+}
+
+/// Default `set-max-intset-entries`
+pub const DEFAULT_SET_MAX_INTSET_ENTRIES: usize = 512;
+
+/// `set-max-intset-entries`, process-wide
+static SET_MAX_INTSET_ENTRIES: AtomicUsize = AtomicUsize::new(DEFAULT_SET_MAX_INTSET_ENTRIES);
+
+/// Set `set-max-intset-entries`, the largest set of integers kept as an
+/// intset
+pub fn set_set_max_intset_entries(entries: usize) {
+    SET_MAX_INTSET_ENTRIES.store(entries, Ordering::Relaxed);
+}
+
+/// Current `set-max-intset-entries`
+pub fn max_set_intset_entries() -> usize {
+    SET_MAX_INTSET_ENTRIES.load(Ordering::Relaxed)
+}
+
+/// Default `set-max-listpack-entries`
+pub const DEFAULT_SET_MAX_LISTPACK_ENTRIES: usize = 128;
+
+/// `set-max-listpack-entries`, process-wide
+static SET_MAX_LISTPACK_ENTRIES: AtomicUsize = AtomicUsize::new(DEFAULT_SET_MAX_LISTPACK_ENTRIES);
+
+/// Set `set-max-listpack-entries`, the largest set kept in the compact
+/// representation
+///
+/// Sets already promoted to a hash table stay one; the others are promoted
+/// on their next insertion past the limit.
+pub fn set_set_max_listpack_entries(entries: usize) {
+    SET_MAX_LISTPACK_ENTRIES.store(entries, Ordering::Relaxed);
+}
+
+/// Current `set-max-listpack-entries`
+pub fn max_set_listpack_entries() -> usize {
+    SET_MAX_LISTPACK_ENTRIES.load(Ordering::Relaxed)
+}
+
+/// Default `zset-max-listpack-entries`
+pub const DEFAULT_ZSET_MAX_LISTPACK_ENTRIES: usize = 128;
+
+/// `zset-max-listpack-entries`, process-wide
+static ZSET_MAX_LISTPACK_ENTRIES: AtomicUsize = AtomicUsize::new(DEFAULT_ZSET_MAX_LISTPACK_ENTRIES);
+
+/// Set `zset-max-listpack-entries`, the largest sorted set reported as a
+/// listpack
+pub fn set_zset_max_listpack_entries(entries: usize) {
+    ZSET_MAX_LISTPACK_ENTRIES.store(entries, Ordering::Relaxed);
+}
+
+/// Current `zset-max-listpack-entries`
+pub fn zset_max_listpack_entries() -> usize {
+    ZSET_MAX_LISTPACK_ENTRIES.load(Ordering::Relaxed)
+}
+
+/// Default `embstr-size-limit`, the longest string Redis embeds in its
+/// object header
+pub const DEFAULT_EMBSTR_SIZE_LIMIT: usize = 44;
+
+/// `embstr-size-limit`, process-wide
+static EMBSTR_SIZE_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_EMBSTR_SIZE_LIMIT);
+
+/// Set `embstr-size-limit`, the longest string reported as `embstr`
+pub fn set_embstr_size_limit(len: usize) {
+    EMBSTR_SIZE_LIMIT.store(len, Ordering::Relaxed);
+}
+
+/// Current `embstr-size-limit`
+pub fn embstr_size_limit() -> usize {
+    EMBSTR_SIZE_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Encoding reported for a string: `embstr` up to `embstr-size-limit`
+/// bytes, `raw` beyond
+pub(super) fn string_encoding(bytes: &Bytes) -> &'static str {
+    if bytes.len() <= embstr_size_limit() { "embstr" } else { "raw" }
+}
+
+/// Header and terminator of a listpack
+const LISTPACK_OVERHEAD: usize = 7;
+
+/// Approximate size of a listpack holding the elements
+///
+/// Each element costs its bytes plus an encoding byte and a back length.
+fn listpack_bytes(list: &VecDeque<ListItem>) -> usize {
+    LISTPACK_OVERHEAD + list.iter().map(|item| item.len() + 2).sum::<usize>()
+}
+
+/// Encoding reported for a list: `listpack` while it fits in a single
+/// listpack node, `quicklist` beyond
+pub(super) fn list_encoding(list: &VecDeque<ListItem>) -> &'static str {
+    let fits = match list_max_listpack_size() {
+        max if max > 0 => list.len() <= max as usize,
+        max => {
+            // -1 => 4 KB, -2 => 8 KB, ... -5 (or less) => 64 KB
+            let class = max.unsigned_abs().clamp(1, 5) as u32;
+            listpack_bytes(list) <= 4096 << (class - 1)
+        }
+    };
+    if fits { "listpack" } else { "quicklist" }
+}
+
+/// Whether a set member fits in an intset: a number within the i64 range
+///
+/// A numeric string overflowing i64 isn't an integer member, it makes the
+/// set a hashtable rather than an error.
+fn is_intset_member(member: &Bytes) -> bool {
+    std::str::from_utf8(member).is_ok_and(|member| member.parse::<i64>().is_ok())
+}
+
+/// Encoding reported for a set: `intset` while all its members are
+/// integers and it has at most `set-max-intset-entries` of them, otherwise
+/// `listpack` or `hashtable` by its representation
+pub(super) fn set_encoding(set: &Set) -> &'static str {
+    if set.len() <= max_set_intset_entries() && set.iter().all(is_intset_member) {
+        "intset"
+    } else if set.is_compact() {
+        "listpack"
+    } else {
+        "hashtable"
+    }
+}
+
+/// Encoding reported for a sorted set: `listpack` up to
+/// `zset-max-listpack-entries` members, `skiplist` beyond
+pub(super) fn zset_encoding(zset: &SortedSet) -> &'static str {
+    if zset.len() <= zset_max_listpack_entries() { "listpack" } else { "skiplist" }
+}
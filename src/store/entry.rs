@@ -1,8 +1,35 @@
 //! Entry structure for key-value pairs
 
+use super::maxmemory::{lfu_increment, maxmemory_policy, MaxmemoryPolicy, LFU_INIT_VAL};
 use super::value::Value;
 use bytes::Bytes;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Convert an absolute Unix time in milliseconds to an Instant
+///
+/// Times too far in the past to be represented are clamped to now, which
+/// is already expired as far as entries are concerned.
+pub fn unix_ms_to_instant(unix_ms: i64) -> Option<Instant> {
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_millis() as i64;
+    let now = Instant::now();
+    let delta = unix_ms.checked_sub(now_ms)?;
+    if delta >= 0 {
+        now.checked_add(Duration::from_millis(delta as u64))
+    } else {
+        Some(now.checked_sub(Duration::from_millis(delta.unsigned_abs())).unwrap_or(now))
+    }
+}
+
+/// Convert an Instant to an absolute Unix time in milliseconds
+pub fn instant_to_unix_ms(instant: Instant) -> i64 {
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+    let now = Instant::now();
+    if instant >= now {
+        now_ms.saturating_add(instant.duration_since(now).as_millis() as i64)
+    } else {
+        now_ms.saturating_sub(now.duration_since(instant).as_millis() as i64)
+    }
+}
 
 /// Represents a single entry in the store
 #[derive(Debug, Clone)]
@@ -18,6 +45,23 @@ pub struct Entry {
 
     /// Version number for optimistic concurrency control (future use)
     pub version: u64,
+
+    /// Last time the key was accessed (for OBJECT IDLETIME and LRU)
+    pub last_access: Instant,
+
+    /// Logarithmic access counter (for LFU), as of the last access; see
+    /// `access_frequency`
+    pub frequency: u8,
+
+    /// Whether the string value was modified in place (APPEND, SETRANGE,
+    /// SETBIT), which Redis only does once it is `raw`, whatever its length
+    pub raw_string: bool,
+
+    /// Memory usage last added to the store's counters
+    pub(super) accounted_memory: usize,
+
+    /// Expiration time the store's counters last saw for this entry
+    pub(super) accounted_expire_at: Option<Instant>,
 }
 
 impl Entry {
@@ -28,6 +72,11 @@ impl Entry {
             value,
             expire_at: None,
             version: 0,
+            last_access: Instant::now(),
+            frequency: LFU_INIT_VAL,
+            raw_string: false,
+            accounted_memory: 0,
+            accounted_expire_at: None,
         }
     }
 
@@ -42,6 +91,20 @@ impl Entry {
             value,
             expire_at: Some(Instant::now() + ttl),
             version: 0,
+            last_access: Instant::now(),
+            frequency: LFU_INIT_VAL,
+            raw_string: false,
+            accounted_memory: 0,
+            accounted_expire_at: None,
+        }
+    }
+
+    /// Encoding reported by OBJECT ENCODING: the value's, except for a
+    /// string modified in place, which is `raw`
+    pub fn encoding(&self) -> &'static str {
+        match self.value {
+            Value::String(_) if self.raw_string => "raw",
+            _ => self.value.encoding(),
         }
     }
 
@@ -83,6 +146,43 @@ impl Entry {
         }
     }
 
+    /// Get remaining TTL in milliseconds, with the same -1/-2 conventions
+    pub fn ttl_millis(&self) -> i64 {
+        match self.expire_at {
+            Some(expire_at) => {
+                let now = Instant::now();
+                if expire_at > now {
+                    expire_at.duration_since(now).as_millis() as i64
+                } else {
+                    -2 // Expired
+                }
+            }
+            None => -1, // No expiration
+        }
+    }
+
+    /// Record an access to the key
+    ///
+    /// The LFU counter is only maintained under an LFU policy, as in Redis.
+    pub fn touch(&mut self) {
+        if maxmemory_policy() == MaxmemoryPolicy::AllKeysLfu {
+            self.frequency = lfu_increment(self.access_frequency());
+        }
+        self.last_access = Instant::now();
+    }
+
+    /// The LFU counter, less one per minute since the last access (Redis'
+    /// default `lfu-decay-time`)
+    pub fn access_frequency(&self) -> u8 {
+        let idle_minutes = self.idle_time().as_secs() / 60;
+        self.frequency.saturating_sub(idle_minutes.min(u8::MAX as u64) as u8)
+    }
+
+    /// Time elapsed since the last access
+    pub fn idle_time(&self) -> Duration {
+        self.last_access.elapsed()
+    }
+
     /// Increment version (for future multi-node synchronization)
     pub fn increment_version(&mut self) {
         self.version = self.version.wrapping_add(1);
@@ -92,7 +192,7 @@ impl Entry {
     pub fn memory_usage(&self) -> usize {
         let key_size = self.key.len();
         let value_size = self.value.memory_usage();
-        let metadata_size = std::mem::size_of::<Option<Instant>>() + std::mem::size_of::<u64>();
+        let metadata_size = std::mem::size_of::<Option<Instant>>() + std::mem::size_of::<u64>() + std::mem::size_of::<Instant>();
         key_size + value_size + metadata_size
     }
 }
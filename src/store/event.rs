@@ -0,0 +1,37 @@
+//! Key events reported by a store to its observer
+//!
+//! A feature that needs to know when keys change (keyspace notifications,
+//! client-side cache invalidation, auditing...) registers an observer on
+//! the store with `MemoryStore::set_observer` instead of hooking every
+//! command that writes.
+
+use bytes::Bytes;
+
+/// What happened to a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEventKind {
+    /// The key was written with a new value, or its value was modified
+    Set,
+    /// The key was deleted
+    Del,
+    /// The key was given an expiration time
+    Expire,
+    /// The key expired and was removed, lazily or by the active sweep
+    Expired,
+    /// The key was evicted to free memory (see `MemoryStore::evict_one`)
+    Evicted,
+}
+
+/// Event passed to the observer of a store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent<'a> {
+    pub kind: KeyEventKind,
+    pub key: &'a Bytes,
+}
+
+/// Observer called synchronously on every key event of a store
+///
+/// It runs inside the store operation, so it should only record the event
+/// (e.g. push it to a channel) and return. It is `Sync` so that a store can
+/// be shared behind a lock (see `cluster::StripedStore`).
+pub type KeyObserver = Box<dyn Fn(KeyEvent<'_>) + Send + Sync>;
@@ -0,0 +1,152 @@
+//! Active expiration tuning (`hz`, `active-expire-effort`)
+//!
+//! Keys are expired lazily on access, and by a periodic sweep run `hz`
+//! times per second. Each sweep samples keys with a TTL and reaps the
+//! expired ones, looping while a large fraction of the samples had expired
+//! (Redis' adaptive algorithm). A higher effort samples more keys per loop,
+//! tolerates fewer stale keys and allows more time per sweep.
+//!
+//! With `expire-jitter-percent`, a relative TTL (EXPIRE, SET EX...) is
+//! randomized by up to that percentage either way when it is applied, so
+//! keys given the same TTL at once don't all expire at the same moment.
+//! The jittered time is the one stored, and reported by TTL and PTTL.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Default `hz`
+pub const DEFAULT_HZ: u32 = 10;
+
+/// Bounds of `hz`, values outside are clamped (like Redis)
+pub const MIN_HZ: u32 = 1;
+pub const MAX_HZ: u32 = 500;
+
+/// Default `active-expire-effort`
+pub const DEFAULT_ACTIVE_EXPIRE_EFFORT: u32 = 1;
+
+/// Highest `active-expire-effort`
+pub const MAX_ACTIVE_EXPIRE_EFFORT: u32 = 10;
+
+/// Keys sampled per loop at effort 1
+const KEYS_PER_LOOP: usize = 20;
+
+/// Percentage of expired samples tolerated at effort 1
+const ACCEPTABLE_STALE: usize = 10;
+
+/// Percentage of the time between two sweeps a sweep may use at effort 1
+const CYCLE_TIME_PERCENT: u64 = 25;
+
+/// Highest `expire-jitter-percent`
+pub const MAX_EXPIRE_JITTER_PERCENT: u32 = 100;
+
+/// `hz`, process-wide
+static HZ: AtomicU32 = AtomicU32::new(DEFAULT_HZ);
+
+/// `active-expire-effort`, process-wide
+static ACTIVE_EXPIRE_EFFORT: AtomicU32 = AtomicU32::new(DEFAULT_ACTIVE_EXPIRE_EFFORT);
+
+/// `expire-jitter-percent`, process-wide (0, the default, disables it)
+static EXPIRE_JITTER_PERCENT: AtomicU32 = AtomicU32::new(0);
+
+/// Set `hz`, clamped to 1..=500
+pub fn set_hz(hz: u32) {
+    HZ.store(hz.clamp(MIN_HZ, MAX_HZ), Ordering::Relaxed);
+}
+
+/// Current `hz`
+pub fn hz() -> u32 {
+    HZ.load(Ordering::Relaxed)
+}
+
+/// Set `active-expire-effort` (1 to 10)
+pub fn set_active_expire_effort(effort: u32) {
+    ACTIVE_EXPIRE_EFFORT.store(effort.clamp(1, MAX_ACTIVE_EXPIRE_EFFORT), Ordering::Relaxed);
+}
+
+/// Current `active-expire-effort`
+pub fn active_expire_effort() -> u32 {
+    ACTIVE_EXPIRE_EFFORT.load(Ordering::Relaxed)
+}
+
+/// Set `expire-jitter-percent` (0 to 100)
+pub fn set_expire_jitter_percent(percent: u32) {
+    EXPIRE_JITTER_PERCENT.store(percent.min(MAX_EXPIRE_JITTER_PERCENT), Ordering::Relaxed);
+}
+
+/// Current `expire-jitter-percent`
+pub fn expire_jitter_percent() -> u32 {
+    EXPIRE_JITTER_PERCENT.load(Ordering::Relaxed)
+}
+
+/// A TTL randomized by up to `percent` either way, at least a millisecond
+///
+/// The randomness comes from the process' random hasher keys and a
+/// counter, so keys jittered one after the other get spread times.
+pub fn jitter_ttl(ttl: Duration, percent: u32) -> Duration {
+    static JITTERED: AtomicU64 = AtomicU64::new(0);
+    let ttl_ms = ttl.as_millis().min(u64::MAX as u128) as u64;
+    let spread = ttl_ms / 100 * percent.min(MAX_EXPIRE_JITTER_PERCENT) as u64;
+    if spread == 0 {
+        return ttl;
+    }
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(JITTERED.fetch_add(1, Ordering::Relaxed));
+    let offset = hasher.finish() % (2 * spread + 1);
+    Duration::from_millis((ttl_ms - spread).saturating_add(offset).max(1))
+}
+
+/// Time between two sweeps at a given `hz`
+pub fn sweep_period(hz: u32) -> Duration {
+    Duration::from_micros(1_000_000 / hz.clamp(MIN_HZ, MAX_HZ) as u64)
+}
+
+/// Keys sampled per loop: 20, plus 25% per effort level
+pub(super) fn keys_per_loop(effort: u32) -> usize {
+    let extra = effort.clamp(1, MAX_ACTIVE_EXPIRE_EFFORT) as usize - 1;
+    KEYS_PER_LOOP + KEYS_PER_LOOP / 4 * extra
+}
+
+/// Percentage of expired samples below which a sweep stops: 10%, minus
+/// one per effort level
+pub(super) fn acceptable_stale_percent(effort: u32) -> usize {
+    let extra = effort.clamp(1, MAX_ACTIVE_EXPIRE_EFFORT) as usize - 1;
+    ACCEPTABLE_STALE - extra
+}
+
+/// Time a sweep may run: 25% of the period at `hz`, plus 2% per effort
+/// level
+pub fn sweep_time_limit(hz: u32, effort: u32) -> Duration {
+    let extra = effort.clamp(1, MAX_ACTIVE_EXPIRE_EFFORT) as u64 - 1;
+    sweep_period(hz) * (CYCLE_TIME_PERCENT + 2 * extra) as u32 / 100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effort_scaling() {
+        assert_eq!(keys_per_loop(1), 20);
+        assert_eq!(keys_per_loop(10), 65);
+        assert_eq!(acceptable_stale_percent(1), 10);
+        assert_eq!(acceptable_stale_percent(10), 1);
+        assert_eq!(sweep_period(10), Duration::from_millis(100));
+        assert_eq!(sweep_time_limit(10, 1), Duration::from_millis(25));
+        assert_eq!(sweep_time_limit(10, 10), Duration::from_millis(43));
+        assert_eq!(sweep_period(0), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_jitter_ttl_bounds() {
+        let ttl = Duration::from_secs(100);
+        assert_eq!(jitter_ttl(ttl, 0), ttl);
+        for _ in 0..1000 {
+            let jittered = jitter_ttl(ttl, 20);
+            assert!(jittered >= Duration::from_secs(80) && jittered <= Duration::from_secs(120), "{:?}", jittered);
+        }
+        assert!(jitter_ttl(Duration::from_millis(1), 100) >= Duration::from_millis(1));
+    }
+}
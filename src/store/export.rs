@@ -0,0 +1,267 @@
+//! NDJSON export format
+//!
+//! Each key is exported as a single JSON object on its own line:
+//! `{"key":...,"type":...,"ttl":...,"value":...}`. `type` is the name TYPE
+//! reports and `ttl` the remaining time to live in milliseconds (-1
+//! without one). Byte strings are JSON strings when they are valid UTF-8,
+//! otherwise `{"base64":"..."}` objects, so binary keys and values survive.
+//!
+//! Values by type:
+//! - string: a byte string
+//! - list, set: an array of byte strings
+//! - hash: an array of `[field, value]` pairs
+//! - zset: an array of `[member, score]` pairs, by increasing score
+//! - stream: an array of `[id, [[field, value], ...]]` entries, by ID
+//!
+//! `import_line` parses a line back, so an export can be re-imported.
+
+use super::entry::Entry;
+use super::hash::Hash;
+use super::stream::{Stream, StreamId};
+use super::value::Value;
+use super::zset::SortedSet;
+use base64::Engine;
+use bytes::Bytes;
+use serde_json::{json, Value as Json};
+use std::time::Instant;
+
+/// JSON form of a byte string
+pub fn bytes_to_json(bytes: &[u8]) -> Json {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Json::String(text.to_string()),
+        Err(_) => json!({ "base64": base64::engine::general_purpose::STANDARD.encode(bytes) }),
+    }
+}
+
+/// JSON form of a score: a number, or "inf"/"-inf" which JSON can't hold
+fn score_to_json(score: f64) -> Json {
+    if score.is_finite() {
+        json!(score)
+    } else if score > 0.0 {
+        json!("inf")
+    } else {
+        json!("-inf")
+    }
+}
+
+/// JSON form of a value
+pub fn value_to_json(value: &Value) -> Json {
+    let pair = |a: &[u8], b: &[u8]| Json::Array(vec![bytes_to_json(a), bytes_to_json(b)]);
+    match value {
+        Value::String(bytes) => bytes_to_json(bytes),
+        Value::Integer(i) => Json::String(i.to_string()),
+        Value::List(list) => list.iter().map(|item| bytes_to_json(&item.to_bytes())).collect(),
+        Value::Set(set) => set.iter().map(|member| bytes_to_json(member)).collect(),
+        Value::Hash(hash) => hash.iter().map(|(field, value)| pair(field, value)).collect(),
+        Value::ZSet(zset) => zset
+            .iter()
+            .map(|(member, score)| Json::Array(vec![bytes_to_json(member), score_to_json(score)]))
+            .collect(),
+        Value::Stream(stream) => stream
+            .range(StreamId::MIN, StreamId::MAX, None)
+            .into_iter()
+            .map(|(id, fields)| {
+                let fields: Json = fields.iter().map(|(field, value)| pair(field, value)).collect();
+                Json::Array(vec![Json::String(id.to_string()), fields])
+            })
+            .collect(),
+        Value::Json(doc) => doc.clone(),
+    }
+}
+
+/// Export line of an entry, without the trailing newline
+pub fn export_line(entry: &Entry) -> String {
+    let ttl = match entry.expire_at {
+        Some(expire_at) => expire_at.saturating_duration_since(Instant::now()).as_millis() as i64,
+        None => -1,
+    };
+    let type_name = match entry.value {
+        Value::Integer(_) => "string",
+        ref value => value.type_name(),
+    };
+
+    json!({
+        "key": bytes_to_json(&entry.key),
+        "type": type_name,
+        "ttl": ttl,
+        "value": value_to_json(&entry.value),
+    })
+    .to_string()
+}
+
+/// Key parsed from an export line
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedKey {
+    pub key: Bytes,
+    pub value: Value,
+
+    /// Remaining time to live in milliseconds, None without one
+    pub ttl_ms: Option<u64>,
+}
+
+/// Byte string of its JSON form (see `bytes_to_json`)
+fn json_to_bytes(json: &Json) -> Result<Bytes, String> {
+    match json {
+        Json::String(text) => Ok(Bytes::from(text.clone())),
+        Json::Object(object) => {
+            let encoded = object
+                .get("base64")
+                .and_then(Json::as_str)
+                .filter(|_| object.len() == 1)
+                .ok_or("invalid byte string")?;
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|e| format!("invalid base64: {}", e))?;
+            Ok(Bytes::from(decoded))
+        }
+        _ => Err("invalid byte string".to_string()),
+    }
+}
+
+/// Score of its JSON form (see `score_to_json`)
+fn json_to_score(json: &Json) -> Result<f64, String> {
+    match json {
+        Json::Number(number) => number.as_f64().ok_or_else(|| "invalid score".to_string()),
+        Json::String(text) if text == "inf" => Ok(f64::INFINITY),
+        Json::String(text) if text == "-inf" => Ok(f64::NEG_INFINITY),
+        _ => Err("invalid score".to_string()),
+    }
+}
+
+/// Items of a JSON array
+fn json_items(json: &Json) -> Result<&[Json], String> {
+    json.as_array().map(Vec::as_slice).ok_or_else(|| "value is not an array".to_string())
+}
+
+/// Two items of a `[a, b]` JSON pair
+fn json_pair(json: &Json) -> Result<(&Json, &Json), String> {
+    match json_items(json)? {
+        [a, b] => Ok((a, b)),
+        _ => Err("invalid pair".to_string()),
+    }
+}
+
+/// Value of the given type from its JSON form (see `value_to_json`)
+pub fn json_to_value(type_name: &str, json: &Json) -> Result<Value, String> {
+    let value = match type_name {
+        "string" => Value::from_bytes(json_to_bytes(json)?),
+        "list" => Value::list(json_items(json)?.iter().map(json_to_bytes).collect::<Result<Vec<_>, _>>()?),
+        "set" => Value::Set(json_items(json)?.iter().map(json_to_bytes).collect::<Result<_, _>>()?),
+        "hash" => {
+            let mut hash = Hash::new();
+            for item in json_items(json)? {
+                let (field, value) = json_pair(item)?;
+                hash.insert(json_to_bytes(field)?, json_to_bytes(value)?);
+            }
+            Value::Hash(hash)
+        }
+        "zset" => {
+            let mut zset = SortedSet::new();
+            for item in json_items(json)? {
+                let (member, score) = json_pair(item)?;
+                zset.insert(json_to_bytes(member)?, json_to_score(score)?);
+            }
+            Value::ZSet(zset)
+        }
+        "stream" => {
+            let mut stream = Stream::new();
+            for item in json_items(json)? {
+                let (id, fields) = json_pair(item)?;
+                let id = id
+                    .as_str()
+                    .and_then(|id| StreamId::parse(id.as_bytes(), 0))
+                    .ok_or("invalid stream ID")?;
+                let mut entry = Vec::new();
+                for field in json_items(fields)? {
+                    let (field, value) = json_pair(field)?;
+                    entry.push((json_to_bytes(field)?, json_to_bytes(value)?));
+                }
+                stream.add(id, entry)?;
+            }
+            Value::Stream(stream)
+        }
+        "ReJSON-RL" => Value::Json(json.clone()),
+        other => return Err(format!("unknown type '{}'", other)),
+    };
+    Ok(value)
+}
+
+/// Parse an export line (see `export_line`)
+pub fn import_line(line: &str) -> Result<ImportedKey, String> {
+    let line: Json = serde_json::from_str(line).map_err(|e| format!("invalid JSON: {}", e))?;
+    let key = json_to_bytes(line.get("key").ok_or("missing key")?)?;
+    let type_name = line.get("type").and_then(Json::as_str).ok_or("missing type")?;
+    let value = json_to_value(type_name, line.get("value").ok_or("missing value")?)?;
+    let ttl_ms = match line.get("ttl") {
+        None | Some(Json::Null) => None,
+        Some(ttl) => match ttl.as_i64().ok_or("invalid ttl")? {
+            -1 => None,
+            ms if ms >= 0 => Some(ms as u64),
+            _ => return Err("invalid ttl".to_string()),
+        },
+    };
+    Ok(ImportedKey { key, value, ttl_ms })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_export_line() {
+        let entry = Entry::new("name", Value::string("Alice"));
+        assert_eq!(export_line(&entry), r#"{"key":"name","ttl":-1,"type":"string","value":"Alice"}"#);
+
+        // Binary strings are base64-encoded, integers exported as strings
+        let entry = Entry::new(Bytes::from_static(b"\xff\x00"), Value::Integer(42));
+        let line: Json = serde_json::from_str(&export_line(&entry)).unwrap();
+        assert_eq!(line["key"], json!({ "base64": "/wA=" }));
+        assert_eq!(line["value"], json!("42"));
+
+        let mut zset = crate::store::SortedSet::new();
+        zset.insert(Bytes::from("low"), f64::NEG_INFINITY);
+        zset.insert(Bytes::from("high"), 1.5);
+        let mut entry = Entry::new("ranks", Value::ZSet(zset));
+        entry.expire_at = Some(Instant::now() + Duration::from_secs(100));
+        let line: Json = serde_json::from_str(&export_line(&entry)).unwrap();
+        assert_eq!(line["type"], json!("zset"));
+        assert_eq!(line["value"], json!([["low", "-inf"], ["high", 1.5]]));
+        let ttl = line["ttl"].as_i64().unwrap();
+        assert!(ttl > 99_000 && ttl <= 100_000, "{}", ttl);
+    }
+
+    #[test]
+    fn test_import_line_round_trip() {
+        let mut hash = Hash::new();
+        hash.insert(Bytes::from("f"), Bytes::from_static(b"\xfe"));
+        let mut stream = Stream::new();
+        stream.add(StreamId::new(1, 1), vec![(Bytes::from("a"), Bytes::from("1"))]).unwrap();
+        let mut zset = SortedSet::new();
+        zset.insert(Bytes::from("m"), f64::INFINITY);
+
+        for value in [
+            Value::string("text"),
+            Value::Integer(-7),
+            Value::list(["a", "b"].into_iter().map(Bytes::from)),
+            Value::Set(["x"].into_iter().map(Bytes::from).collect()),
+            Value::Hash(hash),
+            Value::ZSet(zset),
+            Value::Stream(stream),
+            Value::Json(serde_json::json!({"a": {"b": [true, 1.5]}})),
+        ] {
+            let entry = Entry::new(Bytes::from_static(b"\xffkey"), value.clone());
+            let imported = import_line(&export_line(&entry)).unwrap();
+            assert_eq!(imported.key, entry.key);
+            assert_eq!(imported.value, value);
+            assert_eq!(imported.ttl_ms, None);
+        }
+
+        let imported = import_line(r#"{"key":"k","type":"string","ttl":5000,"value":"v"}"#).unwrap();
+        assert_eq!(imported.ttl_ms, Some(5000));
+
+        assert!(import_line("not json").is_err());
+        assert!(import_line(r#"{"key":"k","type":"list","ttl":-1,"value":"v"}"#).is_err());
+        assert!(import_line(r#"{"key":"k","type":"nope","ttl":-1,"value":"v"}"#).is_err());
+    }
+}
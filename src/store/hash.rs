@@ -0,0 +1,226 @@
+//! Hash structure
+//!
+//! The fields of a hash live in a hash table. Next to it, an index orders
+//! the field names by the reversed bits of a fixed hash of their name, the
+//! virtual buckets HSCAN visits: a call takes the range of its COUNT
+//! buckets in the index instead of a pass over the whole table.
+
+use bytes::Bytes;
+use siphasher::sip::SipHasher13;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, BuildHasherDefault};
+
+/// Smallest number of bits of the virtual buckets HSCAN iterates over
+const MIN_SCAN_BUCKET_BITS: u32 = 4;
+
+/// A map of fields to their values
+#[derive(Debug, Clone, Default)]
+pub struct Hash {
+    fields: HashMap<Bytes, Bytes>,
+
+    /// Field names by reversed hash: the fields of a bucket share the
+    /// leading bits of their slot
+    scan_index: BTreeMap<u64, Vec<Bytes>>,
+}
+
+/// Slot of a field in the scan index
+fn scan_slot(field: &[u8]) -> u64 {
+    BuildHasherDefault::<SipHasher13>::default().hash_one(field).reverse_bits()
+}
+
+impl Hash {
+    /// Create an empty hash
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of fields
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Whether the hash has no fields
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Value of a field
+    pub fn get(&self, field: &[u8]) -> Option<&Bytes> {
+        self.fields.get(field)
+    }
+
+    /// Whether `field` is in the hash
+    pub fn contains_key(&self, field: &[u8]) -> bool {
+        self.fields.contains_key(field)
+    }
+
+    /// Set a field, returning its previous value
+    pub fn insert(&mut self, field: Bytes, value: Bytes) -> Option<Bytes> {
+        if let Some(current) = self.fields.get_mut(&field) {
+            return Some(std::mem::replace(current, value));
+        }
+        self.scan_index.entry(scan_slot(&field)).or_default().push(field.clone());
+        self.fields.insert(field, value)
+    }
+
+    /// Remove a field, returning its value
+    pub fn remove(&mut self, field: &[u8]) -> Option<Bytes> {
+        let value = self.fields.remove(field)?;
+        let slot = scan_slot(field);
+        if let Some(fields) = self.scan_index.get_mut(&slot) {
+            fields.retain(|f| f.as_ref() != field);
+            if fields.is_empty() {
+                self.scan_index.remove(&slot);
+            }
+        }
+        Some(value)
+    }
+
+    /// Iterate over the fields and their values, in any order
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &Bytes)> {
+        self.fields.iter()
+    }
+
+    /// Iterate over the field names, in any order
+    pub fn keys(&self) -> impl Iterator<Item = &Bytes> {
+        self.fields.keys()
+    }
+
+    /// One HSCAN step: the fields of `count` virtual buckets from `cursor`,
+    /// and the next cursor (0 when the iteration is complete)
+    ///
+    /// The number of buckets follows the number of fields, and the cursor
+    /// is advanced with reverse-binary iteration, like SCAN: every field
+    /// present for a whole iteration is returned at least once, even if the
+    /// hash grows or shrinks between calls.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, &Bytes)>) {
+        // The `count` buckets from the cursor are one range of the slots
+        let bits = self.len().max(1).next_power_of_two().trailing_zeros().max(MIN_SCAN_BUCKET_BITS);
+        let width = 1u64 << (64 - bits);
+        let start = cursor.reverse_bits() & !(width - 1);
+        let end = start.checked_add((count.max(1) as u64).saturating_mul(width));
+
+        let slots = match end {
+            Some(end) => self.scan_index.range(start..end),
+            None => self.scan_index.range(start..),
+        };
+        let fields = slots
+            .flat_map(|(_, fields)| fields)
+            .filter_map(|field| self.fields.get_key_value(field))
+            .collect();
+        (end.map_or(0, u64::reverse_bits), fields)
+    }
+
+    /// Calculate approximate memory usage in bytes: the fields' and values'
+    /// bytes, the slots of the table holding them and their scan index
+    pub fn memory_usage(&self) -> usize {
+        let items_size: usize = self.iter().map(|(field, value)| field.len() + value.len()).sum();
+        let table = super::value::hash_table_size::<(Bytes, Bytes)>(self.fields.capacity());
+        let index = self.scan_index.len() * (std::mem::size_of::<u64>() + std::mem::size_of::<Vec<Bytes>>())
+            + self.len() * std::mem::size_of::<Bytes>();
+        items_size + table + index + std::mem::size_of::<Self>()
+    }
+}
+
+impl PartialEq for Hash {
+    fn eq(&self, other: &Self) -> bool {
+        self.fields == other.fields
+    }
+}
+
+impl Extend<(Bytes, Bytes)> for Hash {
+    fn extend<I: IntoIterator<Item = (Bytes, Bytes)>>(&mut self, iter: I) {
+        for (field, value) in iter {
+            self.insert(field, value);
+        }
+    }
+}
+
+impl FromIterator<(Bytes, Bytes)> for Hash {
+    fn from_iter<I: IntoIterator<Item = (Bytes, Bytes)>>(iter: I) -> Self {
+        let mut hash = Hash::new();
+        hash.extend(iter);
+        hash
+    }
+}
+
+impl<'a> IntoIterator for &'a Hash {
+    type Item = (&'a Bytes, &'a Bytes);
+    type IntoIter = std::collections::hash_map::Iter<'a, Bytes, Bytes>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fields.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(i: usize) -> Bytes {
+        Bytes::from(format!("field:{}", i))
+    }
+
+    /// Every field of a full iteration, `count` buckets per call
+    fn full_scan(hash: &Hash, count: usize) -> Vec<Bytes> {
+        let (mut cursor, mut fields) = (0, Vec::new());
+        loop {
+            let (next, step) = hash.scan(cursor, count);
+            fields.extend(step.into_iter().map(|(field, _)| field.clone()));
+            if next == 0 {
+                return fields;
+            }
+            cursor = next;
+        }
+    }
+
+    #[test]
+    fn test_insert_remove_keep_index() {
+        let mut hash: Hash = (0..100).map(|i| (field(i), Bytes::from("v"))).collect();
+        assert_eq!(hash.insert(field(0), Bytes::from("w")), Some(Bytes::from("v")));
+        for i in 50..100 {
+            assert_eq!(hash.remove(&field(i)), Some(Bytes::from("v")));
+        }
+        assert_eq!(hash.remove(b"nope"), None);
+
+        assert_eq!(hash.len(), 50);
+        assert_eq!(hash.get(b"field:0"), Some(&Bytes::from("w")));
+        let mut fields = full_scan(&hash, 10);
+        fields.sort();
+        let mut expected: Vec<Bytes> = (0..50).map(field).collect();
+        expected.sort();
+        assert_eq!(fields, expected);
+    }
+
+    #[test]
+    fn test_scan_returns_count_buckets() {
+        let hash: Hash = (0..1000).map(|i| (field(i), Bytes::from("v"))).collect();
+
+        // 1024 buckets for 1000 fields: a call returns about COUNT of them
+        let (cursor, fields) = hash.scan(0, 10);
+        assert_ne!(cursor, 0);
+        assert!(fields.len() < 50, "{} fields", fields.len());
+        assert_eq!(full_scan(&hash, 10).len(), 1000);
+    }
+
+    #[test]
+    fn test_scan_survives_growth() {
+        let mut hash: Hash = (0..20).map(|i| (field(i), Bytes::from("v"))).collect();
+
+        // Fields present from start to end are all returned, even though
+        // the hash grows to many more buckets between the first calls
+        let (mut cursor, mut seen) = (0, Vec::new());
+        for call in 0.. {
+            let (next, step) = hash.scan(cursor, 2);
+            seen.extend(step.into_iter().map(|(field, _)| field.clone()));
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+            if call < 3 {
+                hash.extend((0..200).map(|i| (Bytes::from(format!("new:{}:{}", call, i)), Bytes::from("v"))));
+            }
+        }
+        assert!((0..20).all(|i| seen.contains(&field(i))));
+    }
+}
@@ -0,0 +1,206 @@
+//! JSON documents (a subset of RedisJSON)
+//!
+//! A JSON value is stored as a parsed `serde_json::Value` and navigated
+//! with simple paths: the root (`$` or `.`), object members (`.name` or
+//! `['name']`) and array elements (`[0]`, negative indices counting from
+//! the end). Paths starting with `$` are JSONPath, the others are the legacy
+//! RedisJSON syntax (`.a.b`, or `a.b` without the leading dot); the two only
+//! differ in how JSON.GET replies. Wildcards, recursive descent, array
+//! slices and filters are rejected.
+
+use serde_json::Value as Json;
+
+/// One step of a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    /// Member of an object
+    Key(String),
+    /// Element of an array, negative from the end
+    Index(i64),
+}
+
+/// A parsed path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    /// Steps from the root, empty for the root itself
+    pub segments: Vec<PathSegment>,
+    /// Whether the path uses the legacy syntax rather than JSONPath
+    pub legacy: bool,
+}
+
+impl JsonPath {
+    /// Parse a path
+    ///
+    /// The error describes what is not supported, for a
+    /// `ERR JSON Path error: ...` reply.
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let (legacy, mut rest) = match path.strip_prefix('$') {
+            Some(rest) => (false, rest),
+            None if path == "." => (true, ""),
+            None => (true, path),
+        };
+
+        // A legacy path like `a.b` starts with a bare member name
+        let mut segments = Vec::new();
+        if legacy && !rest.is_empty() && !rest.starts_with(['.', '[']) {
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            segments.push(PathSegment::Key(member_name(&rest[..end])?));
+            rest = &rest[end..];
+        }
+
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                if after.starts_with('.') {
+                    return Err("recursive descent is not supported".to_string());
+                }
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                segments.push(PathSegment::Key(member_name(&after[..end])?));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after.find(']').ok_or_else(|| format!("missing ']' in '{}'", path))?;
+                segments.push(bracket_segment(&after[..end])?);
+                rest = &after[end + 1..];
+            } else {
+                return Err(format!("unexpected '{}' in '{}'", rest, path));
+            }
+        }
+
+        Ok(JsonPath { segments, legacy })
+    }
+
+    /// Whether the path is the root of the document
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// The value at this path in `doc`, None if it does not exist
+    pub fn get<'a>(&self, doc: &'a Json) -> Option<&'a Json> {
+        self.segments.iter().try_fold(doc, |value, segment| match (segment, value) {
+            (PathSegment::Key(name), Json::Object(map)) => map.get(name),
+            (PathSegment::Index(index), Json::Array(array)) => array.get(array_position(*index, array.len())?),
+            _ => None,
+        })
+    }
+
+    /// Set the value at this path in `doc`
+    ///
+    /// The parent of the path must exist: a missing object member is added, a
+    /// missing array element is not. Returns false when nothing was set.
+    pub fn set(&self, doc: &mut Json, value: Json) -> bool {
+        let Some((last, parents)) = self.segments.split_last() else {
+            *doc = value;
+            return true;
+        };
+        let parent = parents.iter().try_fold(doc, |value, segment| match (segment, value) {
+            (PathSegment::Key(name), Json::Object(map)) => map.get_mut(name),
+            (PathSegment::Index(index), Json::Array(array)) => {
+                let position = array_position(*index, array.len())?;
+                array.get_mut(position)
+            }
+            _ => None,
+        });
+
+        match (last, parent) {
+            (PathSegment::Key(name), Some(Json::Object(map))) => {
+                map.insert(name.clone(), value);
+                true
+            }
+            (PathSegment::Index(index), Some(Json::Array(array))) => match array_position(*index, array.len()) {
+                Some(position) => {
+                    array[position] = value;
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Validate a member name written after a dot
+fn member_name(name: &str) -> Result<String, String> {
+    match name {
+        "" => Err("empty member name".to_string()),
+        "*" => Err("wildcards are not supported".to_string()),
+        _ if name.contains(['?', '(', ')', '@']) => Err("filters are not supported".to_string()),
+        _ => Ok(name.to_string()),
+    }
+}
+
+/// Parse the inside of `[...]`: a quoted member name or an array index
+fn bracket_segment(inner: &str) -> Result<PathSegment, String> {
+    let inner = inner.trim();
+    for quote in ['\'', '"'] {
+        if let Some(name) = inner.strip_prefix(quote).and_then(|rest| rest.strip_suffix(quote)) {
+            return Ok(PathSegment::Key(name.to_string()));
+        }
+    }
+    if inner == "*" {
+        return Err("wildcards are not supported".to_string());
+    }
+    if inner.contains(':') {
+        return Err("array slices are not supported".to_string());
+    }
+    if inner.starts_with('?') {
+        return Err("filters are not supported".to_string());
+    }
+    inner
+        .parse()
+        .map(PathSegment::Index)
+        .map_err(|_| format!("invalid array index '{}'", inner))
+}
+
+/// Position of `index` in an array of `len` elements, None if out of range
+fn array_position(index: i64, len: usize) -> Option<usize> {
+    let position = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&position).then_some(position as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn key(name: &str) -> PathSegment {
+        PathSegment::Key(name.to_string())
+    }
+
+    #[test]
+    fn test_parse_paths() {
+        let root = JsonPath { segments: vec![], legacy: false };
+        assert_eq!(JsonPath::parse("$"), Ok(root));
+        assert!(JsonPath::parse(".").unwrap().is_root());
+
+        let expected = vec![key("a"), key("b c"), PathSegment::Index(-1)];
+        assert_eq!(JsonPath::parse("$.a['b c'][-1]").unwrap().segments, expected);
+        assert_eq!(JsonPath::parse(".a[\"b c\"][-1]").unwrap().segments, expected);
+        assert_eq!(JsonPath::parse("a['b c'][-1]").unwrap().segments, expected);
+        assert!(JsonPath::parse("a.b").unwrap().legacy);
+        assert!(!JsonPath::parse("$.a").unwrap().legacy);
+
+        for invalid in ["$..a", "$.a[*]", "$.*", "$.a[1:3]", "$.a[?(@.b>1)]", "$.a[", "$a", "$.a."] {
+            assert!(JsonPath::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_get_and_set() {
+        let mut doc = json!({"a": {"b": [1, 2, 3]}});
+        let path = |p: &str| JsonPath::parse(p).unwrap();
+
+        assert_eq!(path("$.a.b[0]").get(&doc), Some(&json!(1)));
+        assert_eq!(path("$.a.b[-1]").get(&doc), Some(&json!(3)));
+        assert_eq!(path("$.a.b[3]").get(&doc), None);
+        assert_eq!(path("$.a.c").get(&doc), None);
+        assert_eq!(path("$.a.b.c").get(&doc), None);
+
+        assert!(path("$.a.c").set(&mut doc, json!("new")));
+        assert!(path("$.a.b[-1]").set(&mut doc, json!(30)));
+        assert!(!path("$.a.b[5]").set(&mut doc, json!(0)));
+        assert!(!path("$.x.y").set(&mut doc, json!(0)));
+        assert_eq!(doc, json!({"a": {"b": [1, 2, 30], "c": "new"}}));
+
+        assert!(path("$").set(&mut doc, json!([])));
+        assert_eq!(doc, json!([]));
+    }
+}
@@ -0,0 +1,107 @@
+//! List elements
+//!
+//! Like the integers of a Redis listpack, a list element that is the
+//! canonical form of an i64 is stored as the number itself, without a
+//! `Bytes` handle or a heap allocation of its own; any other element keeps
+//! its bytes. Reading an element back always gives the original bytes.
+
+use super::value::parse_canonical_int;
+use bytes::Bytes;
+
+/// An element of a list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListItem {
+    /// Any element that is not a canonical integer
+    Bytes(Bytes),
+
+    /// An element holding the canonical form of this integer
+    Int(i64),
+}
+
+impl ListItem {
+    /// Create an element, stored as an integer when the bytes are the
+    /// canonical form of one
+    pub fn new(bytes: Bytes) -> Self {
+        match parse_canonical_int(&bytes) {
+            Some(i) => ListItem::Int(i),
+            None => ListItem::Bytes(bytes),
+        }
+    }
+
+    /// The bytes of the element
+    pub fn to_bytes(&self) -> Bytes {
+        match self {
+            ListItem::Bytes(bytes) => bytes.clone(),
+            ListItem::Int(i) => Bytes::from(i.to_string()),
+        }
+    }
+
+    /// Length of the element's bytes
+    pub fn len(&self) -> usize {
+        match self {
+            ListItem::Bytes(bytes) => bytes.len(),
+            ListItem::Int(i) => {
+                let digits = i.unsigned_abs().checked_ilog10().map_or(1, |log| log as usize + 1);
+                digits + usize::from(*i < 0)
+            }
+        }
+    }
+
+    /// Whether the element is the empty string
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Approximate memory used by the element: its slot in the list, plus
+    /// the bytes it points to
+    pub fn memory_usage(&self) -> usize {
+        let slot = std::mem::size_of::<ListItem>();
+        match self {
+            ListItem::Bytes(bytes) => slot + bytes.len(),
+            ListItem::Int(_) => slot,
+        }
+    }
+}
+
+impl From<Bytes> for ListItem {
+    fn from(bytes: Bytes) -> Self {
+        ListItem::new(bytes)
+    }
+}
+
+impl From<ListItem> for Bytes {
+    fn from(item: ListItem) -> Self {
+        match item {
+            ListItem::Bytes(bytes) => bytes,
+            ListItem::Int(i) => Bytes::from(i.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_items_round_trip() {
+        for input in ["0", "12345", "-7", "9223372036854775807", "-9223372036854775808"] {
+            let item = ListItem::new(Bytes::from(input));
+            assert!(matches!(item, ListItem::Int(_)), "{}", input);
+            assert_eq!(item.to_bytes(), input);
+            assert_eq!(item.len(), input.len(), "{}", input);
+        }
+        for input in ["", "007", "+1", "-0", "1.5", "12345x", "9223372036854775808", "value"] {
+            let item = ListItem::new(Bytes::from(input));
+            assert_eq!(item, ListItem::Bytes(Bytes::from(input)));
+            assert_eq!(item.len(), input.len());
+        }
+        assert!(ListItem::new(Bytes::new()).is_empty());
+    }
+
+    #[test]
+    fn test_integer_items_use_less_memory() {
+        let int = ListItem::new(Bytes::from("1234567"));
+        let bytes = ListItem::Bytes(Bytes::from("1234567"));
+        assert!(int.memory_usage() < bytes.memory_usage());
+    }
+}
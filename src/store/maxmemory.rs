@@ -0,0 +1,174 @@
+//! Memory limit (`maxmemory`)
+//!
+//! Once the data of all the stores reaches `maxmemory`, a command that may
+//! grow memory first makes its store evict keys under `allkeys-lru` or
+//! `allkeys-lfu`. Under `noeviction` (the default), or when its store has
+//! nothing left to evict, the command is refused with an OOM error, while
+//! reads and deletions keep working.
+
+use super::memory::total_used_memory;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+/// `maxmemory` in bytes, process-wide (0 = no limit)
+static MAXMEMORY: AtomicU64 = AtomicU64::new(0);
+
+/// Keys compared by each eviction, like Redis' default `maxmemory-samples`
+pub const EVICTION_SAMPLES: usize = 5;
+
+/// LFU counter of a new key, so it isn't evicted before it is used again
+pub const LFU_INIT_VAL: u8 = 5;
+
+/// How slowly the LFU counter grows (Redis' default `lfu-log-factor`)
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// What to do when `maxmemory` is reached (`maxmemory-policy`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MaxmemoryPolicy {
+    /// Refuse the commands that may grow memory
+    #[default]
+    NoEviction,
+
+    /// Evict the keys idle the longest
+    AllKeysLru,
+
+    /// Evict the keys accessed the least often
+    AllKeysLfu,
+}
+
+impl MaxmemoryPolicy {
+    /// Every policy, by its `maxmemory-policy` name
+    pub const NAMES: &'static [(&'static str, MaxmemoryPolicy)] = &[
+        ("noeviction", MaxmemoryPolicy::NoEviction),
+        ("allkeys-lru", MaxmemoryPolicy::AllKeysLru),
+        ("allkeys-lfu", MaxmemoryPolicy::AllKeysLfu),
+    ];
+
+    /// Name of the policy in the configuration
+    pub fn name(self) -> &'static str {
+        Self::NAMES.iter().find(|(_, policy)| *policy == self).map_or("noeviction", |(name, _)| name)
+    }
+
+    /// Policy of a configuration name, in any case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, policy)| *policy)
+    }
+}
+
+/// `maxmemory-policy`, process-wide
+static MAXMEMORY_POLICY: AtomicU8 = AtomicU8::new(MaxmemoryPolicy::NoEviction as u8);
+
+/// Set `maxmemory` (0 = no limit)
+pub fn set_maxmemory(bytes: u64) {
+    MAXMEMORY.store(bytes, Ordering::Relaxed);
+}
+
+/// Current `maxmemory`
+pub fn maxmemory() -> u64 {
+    MAXMEMORY.load(Ordering::Relaxed)
+}
+
+/// Set `maxmemory-policy`
+pub fn set_maxmemory_policy(policy: MaxmemoryPolicy) {
+    MAXMEMORY_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Current `maxmemory-policy`
+pub fn maxmemory_policy() -> MaxmemoryPolicy {
+    match MAXMEMORY_POLICY.load(Ordering::Relaxed) {
+        policy if policy == MaxmemoryPolicy::AllKeysLru as u8 => MaxmemoryPolicy::AllKeysLru,
+        policy if policy == MaxmemoryPolicy::AllKeysLfu as u8 => MaxmemoryPolicy::AllKeysLfu,
+        _ => MaxmemoryPolicy::NoEviction,
+    }
+}
+
+/// The LFU counter after one more access
+///
+/// Redis' logarithmic counter: the higher it is, the less likely an access
+/// increments it, so 255 takes about a million accesses. The randomness
+/// comes from the process' random hasher keys and a counter, as for the
+/// TTL jitter.
+pub fn lfu_increment(counter: u8) -> u8 {
+    static INCREMENTS: AtomicU64 = AtomicU64::new(0);
+    if counter == u8::MAX {
+        return counter;
+    }
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(INCREMENTS.fetch_add(1, Ordering::Relaxed));
+    let draw = (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64;
+    let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    if draw < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+        counter + 1
+    } else {
+        counter
+    }
+}
+
+/// Whether writing `extra` more bytes would go over `maxmemory`
+pub fn exceeds_maxmemory(extra: usize) -> bool {
+    let limit = maxmemory();
+    limit > 0 && (total_used_memory() + extra) as u64 > limit
+}
+
+/// Parse a memory size like Redis' config: bytes, or with a `k`, `kb`,
+/// `m`, `mb`, `g` or `gb` suffix (`k` is 1000, `kb` 1024, ...)
+pub fn parse_memory(value: &str) -> Option<u64> {
+    let lower = value.to_ascii_lowercase();
+    let digits_end = lower.find(|c: char| !c.is_ascii_digit()).unwrap_or(lower.len());
+    let (digits, unit) = lower.split_at(digits_end);
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_names() {
+        assert_eq!(MaxmemoryPolicy::from_name("ALLKEYS-LRU"), Some(MaxmemoryPolicy::AllKeysLru));
+        assert_eq!(MaxmemoryPolicy::from_name("volatile-lru"), None);
+        for (name, policy) in MaxmemoryPolicy::NAMES {
+            assert_eq!(policy.name(), *name);
+        }
+    }
+
+    #[test]
+    fn test_lfu_increment_is_logarithmic() {
+        // Below the initial value every access counts
+        assert_eq!(lfu_increment(0), 1);
+        assert_eq!(lfu_increment(LFU_INIT_VAL), LFU_INIT_VAL + 1);
+        assert_eq!(lfu_increment(u8::MAX), u8::MAX);
+
+        // A thousand accesses take a new key well short of the maximum
+        let mut counter = LFU_INIT_VAL;
+        for _ in 0..1000 {
+            counter = lfu_increment(counter);
+        }
+        assert!((LFU_INIT_VAL + 5..100).contains(&counter), "{}", counter);
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(parse_memory("0"), Some(0));
+        assert_eq!(parse_memory("100"), Some(100));
+        assert_eq!(parse_memory("1k"), Some(1000));
+        assert_eq!(parse_memory("1KB"), Some(1024));
+        assert_eq!(parse_memory("64mb"), Some(64 * 1024 * 1024));
+        assert_eq!(parse_memory("2g"), Some(2_000_000_000));
+        assert_eq!(parse_memory("-1"), None);
+        assert_eq!(parse_memory("mb"), None);
+        assert_eq!(parse_memory("12tb"), None);
+    }
+}
@@ -1,10 +1,18 @@
 //! In-memory storage implementation
 
 use super::entry::Entry;
+use super::event::{KeyEvent, KeyEventKind, KeyObserver};
+use super::expire;
+use super::maxmemory::{MaxmemoryPolicy, EVICTION_SAMPLES};
+use super::stats::KEYSPACE_STATS;
 use super::value::Value;
 use bytes::Bytes;
-use std::collections::HashMap;
-use std::hash::BuildHasherDefault;
+use std::collections::hash_map::Entry as MapEntry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{BuildHasher, BuildHasherDefault};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 use siphasher::sip::SipHasher13;
 
 /// Type alias for our hash map with SipHasher
@@ -23,8 +31,77 @@ pub struct MemoryStore {
 
     /// Number of expired keys that haven't been cleaned up yet
     expired_keys: usize,
+
+    /// When set, reads don't update the keys' access time (CLIENT NO-TOUCH)
+    no_touch: bool,
+
+    /// Keys indexed by their bit-reversed hash, for SCAN
+    ///
+    /// A bucket of any power-of-two table size is a contiguous range of this
+    /// index, which lets SCAN use Redis' reverse-binary cursor regardless of
+    /// how the HashMap itself is resized.
+    scan_index: BTreeMap<u64, Vec<Bytes>>,
+
+    /// Approximate memory of the stored entries, kept up to date on writes
+    used_memory: usize,
+
+    /// Number of entries with a TTL, kept up to date on writes
+    volatile_keys: usize,
+
+    /// Number of entries expiring at each time, kept up to date with
+    /// `volatile_keys`: the expired ones not reaped yet are a range of it
+    expiry_counts: BTreeMap<Instant, usize>,
+
+    /// Keys handed out mutably whose new size isn't accounted yet
+    ///
+    /// Collections are mutated in place through `get_mut`, so their size is
+    /// only measured again when the counters are settled (on `stats`, or
+    /// once enough keys are pending), not on every push.
+    dirty_keys: HashSet<Bytes>,
+
+    /// Position of the active expiration sweep in the scan index
+    expire_cursor: u64,
+
+    /// Position of the eviction samples in the scan index
+    evict_cursor: u64,
+
+    /// Called on every key event, when set (see `set_observer`)
+    observer: Option<KeyObserver>,
+
+    /// Keys detached by `detach_all` and not freed yet
+    detached: Arc<PendingFrees>,
+
+    /// Capacity the store was created with, which `maybe_shrink` keeps
+    min_capacity: usize,
+}
+
+/// Count of detached key sets still to be freed, signalled when it drops
+#[derive(Default)]
+struct PendingFrees {
+    count: Mutex<usize>,
+    freed: Condvar,
 }
 
+/// Memory used by the entries of every store of the process (all shards)
+static TOTAL_USED_MEMORY: AtomicUsize = AtomicUsize::new(0);
+
+/// Approximate memory of the data held by every store of the process
+///
+/// Compared with `maxmemory`. Collections modified in place count with
+/// their old size until their store settles its counters.
+pub fn total_used_memory() -> usize {
+    TOTAL_USED_MEMORY.load(Ordering::Relaxed)
+}
+
+/// Smallest number of virtual buckets SCAN iterates over (as a power of two)
+const MIN_SCAN_BUCKET_BITS: u32 = 4;
+
+/// Number of pending keys after which the counters are settled eagerly
+const MAX_DIRTY_KEYS: usize = 1024;
+
+/// The map is shrunk once fewer than 1 / SHRINK_RATIO of its slots are used
+const SHRINK_RATIO: usize = 8;
+
 impl MemoryStore {
     /// Create a new memory store with default capacity
     pub fn new() -> Self {
@@ -40,24 +117,291 @@ impl MemoryStore {
             ),
             total_keys: 0,
             expired_keys: 0,
+            no_touch: false,
+            scan_index: BTreeMap::new(),
+            used_memory: 0,
+            volatile_keys: 0,
+            expiry_counts: BTreeMap::new(),
+            dirty_keys: HashSet::new(),
+            expire_cursor: 0,
+            evict_cursor: 0,
+            observer: None,
+            detached: Arc::default(),
+            min_capacity: capacity,
+        }
+    }
+
+    /// Enable or disable access time updates on reads
+    ///
+    /// Set before each command from the calling client's NO-TOUCH mode.
+    pub fn set_no_touch(&mut self, no_touch: bool) {
+        self.no_touch = no_touch;
+    }
+
+    /// Register the observer called on key events, or remove it with None
+    ///
+    /// The observer sees values written with `set` or modified in place
+    /// (see `notify_modified`), deletions, including every key of a `clear`,
+    /// expiration times set with `expire`/`set_expire_at` and expired keys
+    /// removed.
+    pub fn set_observer(&mut self, observer: Option<KeyObserver>) {
+        self.observer = observer;
+    }
+
+    /// Report a key event to the observer, if any
+    fn notify(&self, kind: KeyEventKind, key: &Bytes) {
+        if let Some(observer) = &self.observer {
+            observer(KeyEvent { kind, key });
         }
     }
 
+    /// Report a value modified in place through `get_mut` as a `Set` event
+    ///
+    /// `get_mut` can't tell a read from a write, so its callers report
+    /// their changes once done.
+    pub fn notify_modified(&self, key: &Bytes) {
+        self.notify(KeyEventKind::Set, key);
+    }
+
     /// Set a key-value pair
     pub fn set(&mut self, key: impl Into<Bytes>, value: Value) -> bool {
         let key = key.into();
-        let entry = Entry::new(key.clone(), value);
+        let mut entry = Entry::new(key.clone(), value);
         let is_new = !self.store.contains_key(&key);
 
-        self.store.insert(key, entry);
-
         if is_new {
             self.total_keys += 1;
+            let slot = self.scan_slot(&key);
+            self.scan_index.entry(slot).or_default().push(key.clone());
+        }
+
+        self.account_insert(&mut entry);
+        if let Some(old) = self.store.insert(key.clone(), entry) {
+            self.account_remove(&key, &old);
         }
+        self.notify(KeyEventKind::Set, &key);
 
         is_new
     }
 
+    /// Set a key only if it doesn't exist (or has expired), returns true if
+    /// the value was inserted
+    ///
+    /// A single lookup decides and inserts, instead of a check followed by
+    /// a `set`; only a key found expired takes a second one.
+    pub fn set_if_absent(&mut self, key: impl Into<Bytes>, value: Value) -> bool {
+        let key = key.into();
+        let mut entry = Entry::new(key.clone(), value);
+        entry.accounted_memory = entry.memory_usage();
+        let memory = entry.accounted_memory;
+
+        let pending = match self.store.entry(key.clone()) {
+            MapEntry::Occupied(occupied) if !occupied.get().is_expired() => return false,
+            MapEntry::Occupied(_) => Some(entry),
+            MapEntry::Vacant(vacant) => {
+                vacant.insert(entry);
+                None
+            }
+        };
+        if let Some(entry) = pending {
+            self.expire_lazily(&key);
+            return self.set(key, entry.value);
+        }
+
+        self.total_keys += 1;
+        let slot = self.scan_slot(&key);
+        self.scan_index.entry(slot).or_default().push(key.clone());
+        self.set_used_memory(self.used_memory + memory);
+        self.notify(KeyEventKind::Set, &key);
+        true
+    }
+
+    /// Replace the string of a key only if it is currently `expected`, or
+    /// set a missing (or expired) key only if `expected` is None; returns
+    /// true if the value was written
+    ///
+    /// A single lookup compares and replaces, keeping the TTL of the key;
+    /// only a missing key takes a second one, to be inserted. A value that
+    /// isn't a string is an error carrying its type name, unless `expected`
+    /// is None: any existing key then leaves the value alone.
+    pub fn compare_and_set(
+        &mut self,
+        key: impl Into<Bytes>,
+        expected: Option<&[u8]>,
+        value: Value,
+    ) -> Result<bool, &'static str> {
+        let key = key.into();
+        match self.store.entry(key.clone()) {
+            MapEntry::Occupied(mut occupied) if !occupied.get().is_expired() => {
+                let entry = occupied.get_mut();
+                if expected.is_none() {
+                    return Ok(false);
+                }
+                let matches = match entry.value.as_string_bytes() {
+                    Some(current) => expected == Some(&*current),
+                    None => return Err(entry.value.type_name()),
+                };
+                if !matches {
+                    return Ok(false);
+                }
+                entry.value = value;
+                entry.raw_string = false;
+                let (old, new) = (entry.accounted_memory, entry.memory_usage());
+                entry.accounted_memory = new;
+                self.set_used_memory(self.used_memory - old + new);
+                self.notify(KeyEventKind::Set, &key);
+                Ok(true)
+            }
+            _ if expected.is_none() => Ok(self.set_if_absent(key, value)),
+            _ => Ok(false),
+        }
+    }
+
+    /// Delete a key only if its string is currently `expected`, returns true
+    /// if it was deleted
+    ///
+    /// The safe release of a lock: a single lookup compares and removes, so
+    /// a lock taken over by another owner is left alone. A value that isn't
+    /// a string is an error carrying its type name.
+    pub fn compare_and_delete(&mut self, key: &Bytes, expected: &[u8]) -> Result<bool, &'static str> {
+        let MapEntry::Occupied(occupied) = self.store.entry(key.clone()) else {
+            return Ok(false);
+        };
+        if occupied.get().is_expired() {
+            return Ok(false);
+        }
+        match occupied.get().value.as_string_bytes() {
+            Some(current) if *current == *expected => {}
+            Some(_) => return Ok(false),
+            None => return Err(occupied.get().value.type_name()),
+        }
+
+        let entry = occupied.remove();
+        self.account_remove(key, &entry);
+        self.unindex_key(key);
+        self.total_keys -= 1;
+        self.notify(KeyEventKind::Del, key);
+        Ok(true)
+    }
+
+    /// Add a new entry to the memory and TTL counters
+    fn account_insert(&mut self, entry: &mut Entry) {
+        entry.accounted_memory = entry.memory_usage();
+        entry.accounted_expire_at = entry.expire_at;
+        self.set_used_memory(self.used_memory + entry.accounted_memory);
+        self.count_expiry(entry.accounted_expire_at);
+    }
+
+    /// Take an entry leaving the store out of the counters
+    fn account_remove(&mut self, key: &Bytes, entry: &Entry) {
+        self.set_used_memory(self.used_memory - entry.accounted_memory);
+        self.uncount_expiry(entry.accounted_expire_at);
+        self.dirty_keys.remove(key);
+    }
+
+    /// Count an entry expiring at `expire_at` in the TTL counters
+    fn count_expiry(&mut self, expire_at: Option<Instant>) {
+        if let Some(at) = expire_at {
+            self.volatile_keys += 1;
+            *self.expiry_counts.entry(at).or_default() += 1;
+        }
+    }
+
+    /// Take an entry expiring at `expire_at` out of the TTL counters
+    fn uncount_expiry(&mut self, expire_at: Option<Instant>) {
+        let Some(at) = expire_at else {
+            return;
+        };
+        self.volatile_keys -= 1;
+        if let Some(count) = self.expiry_counts.get_mut(&at) {
+            *count -= 1;
+            if *count == 0 {
+                self.expiry_counts.remove(&at);
+            }
+        }
+    }
+
+    /// Remember that an entry may change through a mutable reference
+    fn mark_dirty(&mut self, key: &Bytes) {
+        if self.dirty_keys.len() >= MAX_DIRTY_KEYS {
+            self.settle_counters();
+        }
+        if !self.dirty_keys.contains(key) {
+            self.dirty_keys.insert(key.clone());
+        }
+    }
+
+    /// Update the memory counter of the store, and the process-wide one
+    fn set_used_memory(&mut self, used_memory: usize) {
+        if used_memory >= self.used_memory {
+            TOTAL_USED_MEMORY.fetch_add(used_memory - self.used_memory, Ordering::Relaxed);
+        } else {
+            TOTAL_USED_MEMORY.fetch_sub(self.used_memory - used_memory, Ordering::Relaxed);
+        }
+        self.used_memory = used_memory;
+    }
+
+    /// Measure the pending entries again and update the counters
+    fn settle_counters(&mut self) {
+        let mut used_memory = self.used_memory;
+        for key in std::mem::take(&mut self.dirty_keys) {
+            let Some(entry) = self.store.get_mut(&key) else {
+                continue;
+            };
+            let memory = entry.memory_usage();
+            used_memory = used_memory - entry.accounted_memory + memory;
+            entry.accounted_memory = memory;
+            let (old, new) = (entry.accounted_expire_at, entry.expire_at);
+            entry.accounted_expire_at = new;
+            if old != new {
+                self.uncount_expiry(old);
+                self.count_expiry(new);
+            }
+        }
+        self.set_used_memory(used_memory);
+    }
+
+    /// Position of a key in the scan index (its bit-reversed hash)
+    fn scan_slot(&self, key: &Bytes) -> u64 {
+        self.store.hasher().hash_one(key).reverse_bits()
+    }
+
+    /// Remove a key from the map and the scan index
+    fn remove_key(&mut self, key: &Bytes) -> Option<Entry> {
+        let entry = self.store.remove(key)?;
+        self.account_remove(key, &entry);
+        self.unindex_key(key);
+        Some(entry)
+    }
+
+    /// Remove a key from the scan index
+    fn unindex_key(&mut self, key: &Bytes) {
+        let slot = self.scan_slot(key);
+        if let Some(keys) = self.scan_index.get_mut(&slot) {
+            keys.retain(|k| k != key);
+            if keys.is_empty() {
+                self.scan_index.remove(&slot);
+            }
+        }
+    }
+
+    /// Remove a key found expired on access (lazy expiration)
+    fn expire_lazily(&mut self, key: &Bytes) {
+        self.expired_keys += 1;
+        self.remove_key(key);
+        KEYSPACE_STATS.record_expired(1);
+        self.notify(KeyEventKind::Expired, key);
+    }
+
+    /// Get a value by key for a read command, counting a keyspace hit or miss
+    ///
+    /// Same as `get`, but reported in INFO (`keyspace_hits`/`keyspace_misses`).
+    pub fn lookup_read(&mut self, key: &Bytes) -> Option<&Value> {
+        let value = self.get(key);
+        KEYSPACE_STATS.record_lookup(value.is_some());
+        value
+    }
+
     /// Get a value by key, returns None if not found or expired
     pub fn get(&mut self, key: &Bytes) -> Option<&Value> {
         // First check if key exists and if it's expired
@@ -66,13 +410,18 @@ impl MemoryStore {
             .unwrap_or(false);
 
         if is_expired {
-            self.expired_keys += 1;
-            self.store.remove(key);
+            self.expire_lazily(key);
             return None;
         }
 
-        // Now get the value reference
-        self.store.get(key).map(|entry| &entry.value)
+        // Now get the value reference, recording the access
+        let no_touch = self.no_touch;
+        self.store.get_mut(key).map(|entry| {
+            if !no_touch {
+                entry.touch();
+            }
+            &entry.value
+        })
     }
 
     /// Get a mutable reference to a value by key
@@ -80,24 +429,36 @@ impl MemoryStore {
         // Check if key exists and not expired
         if let Some(entry) = self.store.get(key) {
             if entry.is_expired() {
-                self.expired_keys += 1;
-                self.store.remove(key);
+                self.expire_lazily(key);
                 return None;
             }
         }
 
-        // Now get mutable reference
-        self.store.get_mut(key).map(|entry| &mut entry.value)
+        // Now get mutable reference, recording the access
+        if self.store.contains_key(key) {
+            self.mark_dirty(key);
+        }
+        let no_touch = self.no_touch;
+        self.store.get_mut(key).map(|entry| {
+            if !no_touch {
+                entry.touch();
+            }
+            &mut entry.value
+        })
     }
 
     /// Delete a key, returns true if the key existed
     pub fn delete(&mut self, key: &Bytes) -> bool {
-        if let Some(entry) = self.store.remove(key) {
+        if let Some(entry) = self.remove_key(key) {
             if !entry.is_expired() {
                 self.total_keys -= 1;
+                self.notify(KeyEventKind::Del, key);
                 true
             } else {
-                self.expired_keys -= 1;
+                // Still counted in total_keys, as it hadn't been reaped
+                self.total_keys -= 1;
+                KEYSPACE_STATS.record_expired(1);
+                self.notify(KeyEventKind::Expired, key);
                 false
             }
         } else {
@@ -105,12 +466,21 @@ impl MemoryStore {
         }
     }
 
+    /// Delete a key holding an emptied collection, returns true if it did
+    ///
+    /// Like Redis, a list, set, hash or sorted set never exists empty: every
+    /// command removing elements calls this once it is done, so the key stops
+    /// showing up in EXISTS, TYPE or SCAN. Streams and strings are left alone.
+    pub fn remove_if_empty(&mut self, key: &Bytes) -> bool {
+        let is_empty = self.store.get(key).is_some_and(|entry| entry.value.element_count() == Some(0));
+        is_empty && self.delete(key)
+    }
+
     /// Check if a key exists (and is not expired)
     pub fn exists(&mut self, key: &Bytes) -> bool {
         if let Some(entry) = self.store.get(key) {
             if entry.is_expired() {
-                self.expired_keys += 1;
-                self.store.remove(key);
+                self.expire_lazily(key);
                 return false;
             }
             return true;
@@ -119,19 +489,50 @@ impl MemoryStore {
     }
 
     /// Set expiration on a key (TTL in seconds)
+    ///
+    /// Like Redis, a TTL of 0 or less deletes the key at once. Returns false
+    /// if the key is missing. The TTL is jittered by `expire-jitter-percent`.
     pub fn expire(&mut self, key: &Bytes, ttl_seconds: i64) -> bool {
+        self.expire_with_jitter(key, ttl_seconds, super::expire_jitter_percent())
+    }
+
+    /// Set expiration on a key, with the TTL randomized by up to
+    /// `jitter_percent` either way (0 for the exact TTL, as when replaying)
+    pub fn expire_with_jitter(&mut self, key: &Bytes, ttl_seconds: i64, jitter_percent: u32) -> bool {
+        if ttl_seconds <= 0 {
+            return self.delete(key);
+        }
         if let Some(entry) = self.store.get_mut(key) {
             if entry.is_expired() {
-                self.expired_keys += 1;
-                self.store.remove(key);
+                self.expire_lazily(key);
                 return false;
             }
-            entry.set_expiration(ttl_seconds);
+            let ttl = super::jitter_ttl(Duration::from_secs(ttl_seconds as u64), jitter_percent);
+            entry.expire_at = Some(Instant::now() + ttl);
+            self.mark_dirty(key);
+            self.notify(KeyEventKind::Expire, key);
             return true;
         }
         false
     }
 
+    /// Set the absolute expiration time of a key, in the past or the future
+    ///
+    /// Writes the entry directly, without the checks of `expire`: a key given
+    /// a past time stays in the store, expired but not reaped, until it is
+    /// accessed or actively cleaned up. Returns false if the key is missing.
+    pub fn set_expire_at(&mut self, key: &Bytes, expire_at: Instant) -> bool {
+        match self.store.get_mut(key) {
+            Some(entry) => {
+                entry.expire_at = Some(expire_at);
+                self.mark_dirty(key);
+                self.notify(KeyEventKind::Expire, key);
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Get TTL for a key in seconds
     /// Returns:
     /// - Some(n) where n >= 0: remaining TTL in seconds
@@ -140,8 +541,7 @@ impl MemoryStore {
     pub fn ttl(&mut self, key: &Bytes) -> i64 {
         if let Some(entry) = self.store.get(key) {
             if entry.is_expired() {
-                self.expired_keys += 1;
-                self.store.remove(key);
+                self.expire_lazily(key);
                 return -2;
             }
             return entry.ttl_seconds();
@@ -149,6 +549,18 @@ impl MemoryStore {
         -2 // Key not found
     }
 
+    /// Get TTL for a key in milliseconds, like `ttl`
+    pub fn pttl(&mut self, key: &Bytes) -> i64 {
+        if let Some(entry) = self.store.get(key) {
+            if entry.is_expired() {
+                self.expire_lazily(key);
+                return -2;
+            }
+            return entry.ttl_millis();
+        }
+        -2 // Key not found
+    }
+
     /// Get the entry for a key (including expiration metadata)
     pub fn get_entry(&self, key: &Bytes) -> Option<&Entry> {
         self.store.get(key)
@@ -156,14 +568,83 @@ impl MemoryStore {
 
     /// Get a mutable entry reference
     pub fn get_entry_mut(&mut self, key: &Bytes) -> Option<&mut Entry> {
+        if self.store.contains_key(key) {
+            self.mark_dirty(key);
+        }
         self.store.get_mut(key)
     }
 
     /// Remove all keys
     pub fn clear(&mut self) {
+        if self.observer.is_some() {
+            for key in self.store.keys() {
+                self.notify(KeyEventKind::Del, key);
+            }
+        }
         self.store.clear();
+        self.scan_index.clear();
+        self.reset_counters();
+        self.maybe_shrink();
+    }
+
+    /// Number of keys the map can hold without reallocating
+    pub fn capacity(&self) -> usize {
+        self.store.capacity()
+    }
+
+    /// Give memory back after mass deletions, returns true if it did
+    ///
+    /// A `HashMap` keeps its capacity as keys are removed: once the map is
+    /// less than an eighth full, it is rebuilt at twice its number of keys
+    /// (never below the capacity the store was created with). Called by
+    /// the shard's maintenance, and after FLUSHDB.
+    pub fn maybe_shrink(&mut self) -> bool {
+        let target = (self.store.len() * 2).max(self.min_capacity);
+        if self.store.len() >= self.store.capacity() / SHRINK_RATIO || self.store.capacity() <= target {
+            return false;
+        }
+        self.store.shrink_to(target);
+        self.dirty_keys.shrink_to(MAX_DIRTY_KEYS);
+        true
+    }
+
+    /// Remove all keys without freeing them, for FLUSHDB ASYNC
+    ///
+    /// The store is empty when this returns; the keys are only freed when
+    /// the returned value is dropped, which can be done on another thread.
+    /// Their memory is no longer counted from now on.
+    pub fn detach_all(&mut self) -> DetachedKeys {
+        let store = std::mem::replace(
+            &mut self.store,
+            HashMap::with_hasher(BuildHasherDefault::<SipHasher13>::default()),
+        );
+        let scan_index = std::mem::take(&mut self.scan_index);
+        self.reset_counters();
+        *self.detached.count.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+        DetachedKeys { store: Some((store, scan_index)), pending: self.detached.clone() }
+    }
+
+    /// Wait until the keys detached by `detach_all` have all been freed
+    ///
+    /// Returns false if some are still being freed after `timeout`.
+    pub fn wait_detached_freed(&self, timeout: Duration) -> bool {
+        let count = self.detached.count.lock().unwrap_or_else(PoisonError::into_inner);
+        let (count, _) = self
+            .detached
+            .freed
+            .wait_timeout_while(count, timeout, |count| *count > 0)
+            .unwrap_or_else(PoisonError::into_inner);
+        *count == 0
+    }
+
+    /// Reset the counters of a store just emptied
+    fn reset_counters(&mut self) {
         self.total_keys = 0;
         self.expired_keys = 0;
+        self.set_used_memory(0);
+        self.volatile_keys = 0;
+        self.expiry_counts.clear();
+        self.dirty_keys.clear();
     }
 
     /// Get the number of active keys (excluding expired)
@@ -178,73 +659,255 @@ impl MemoryStore {
 
     /// Get all keys (expensive operation, for debugging/admin)
     pub fn keys(&self) -> Vec<Bytes> {
-        self.store
-            .values()
-            .filter(|entry| !entry.is_expired())
-            .map(|entry| entry.key.clone())
-            .collect()
+        self.iter_keys().cloned().collect()
+    }
+
+    /// Iterate over the keys that haven't expired, without cloning them
+    ///
+    /// Lets KEYS filter lazily and only copy the keys it returns.
+    pub fn iter_keys(&self) -> impl Iterator<Item = &Bytes> {
+        self.entries().map(|entry| &entry.key)
+    }
+
+    /// Iterate over the entries that haven't expired (for AOF rewrites)
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.store.values().filter(|entry| !entry.is_expired())
+    }
+
+    /// Incrementally iterate over the keys with a Redis-style cursor
+    ///
+    /// Visits up to `count` virtual buckets starting at `cursor` and returns
+    /// the next cursor (0 when the iteration is complete) with the keys found.
+    /// The number of buckets follows the number of keys, and the cursor is
+    /// advanced with reverse-binary iteration, so a full iteration returns
+    /// every key present for its whole duration at least once, even when
+    /// keys are added or removed between calls. Keys may be returned twice.
+    pub fn scan_cursor(&self, cursor: u64, count: usize) -> (u64, Vec<Bytes>) {
+        let bits = self.len().max(1).next_power_of_two().trailing_zeros().max(MIN_SCAN_BUCKET_BITS);
+        let mask = (1u64 << bits) - 1;
+
+        let mut keys = Vec::new();
+        let mut cursor = cursor;
+        for _ in 0..count.max(1) {
+            // The keys whose hash ends with the bucket bits share the same
+            // leading bits once reversed
+            let start = (cursor & mask).reverse_bits();
+            let end = start | (u64::MAX >> bits);
+            for bucket_keys in self.scan_index.range(start..=end).map(|(_, keys)| keys) {
+                keys.extend(
+                    bucket_keys
+                        .iter()
+                        .filter(|key| self.store.get(*key).is_some_and(|entry| !entry.is_expired()))
+                        .cloned(),
+                );
+            }
+
+            // Increment the reversed cursor
+            cursor |= !mask;
+            cursor = cursor.reverse_bits().wrapping_add(1).reverse_bits();
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        (cursor, keys)
     }
 
     /// Cleanup expired keys (proactive expiration)
     /// Returns the number of keys removed
     pub fn cleanup_expired(&mut self) -> usize {
-        let mut removed = 0;
         let keys_to_remove: Vec<Bytes> = self.store
             .values()
             .filter(|entry| entry.is_expired())
             .map(|entry| entry.key.clone())
             .collect();
 
-        for key in keys_to_remove {
-            self.store.remove(&key);
+        self.reap_expired(keys_to_remove)
+    }
+
+    /// Remove keys found expired by a sweep, returning how many there were
+    fn reap_expired(&mut self, keys: Vec<Bytes>) -> usize {
+        let mut removed = 0;
+        for key in keys {
+            self.remove_key(&key);
+            self.notify(KeyEventKind::Expired, &key);
             removed += 1;
         }
 
         self.expired_keys = self.expired_keys.saturating_sub(removed);
         self.total_keys = self.total_keys.saturating_sub(removed);
+        KEYSPACE_STATS.record_expired(removed as u64);
         removed
     }
 
-    /// Calculate approximate memory usage of stored data in bytes
-    pub fn memory_usage(&self) -> usize {
-        self.store
-            .values()
-            .filter(|entry| !entry.is_expired())
-            .map(|entry| entry.memory_usage())
-            .sum()
+    /// Run one active expiration sweep, returning the number of keys reaped
+    ///
+    /// Redis' adaptive algorithm: sample keys with a TTL, reap the expired
+    /// ones, and sample again while more than the acceptable fraction of the
+    /// samples had expired, until `time_limit` is spent. The samples are
+    /// taken in scan index order from where the previous sweep stopped, so
+    /// successive sweeps cover the whole keyspace.
+    pub fn active_expire_cycle(&mut self, effort: u32, time_limit: Duration) -> usize {
+        let keys_per_loop = expire::keys_per_loop(effort);
+        let acceptable_stale = expire::acceptable_stale_percent(effort);
+        let start = Instant::now();
+
+        let mut reaped = 0;
+        while self.volatile_keys > 0 {
+            let (sampled, expired) = self.sample_expired(keys_per_loop);
+            let removed = self.reap_expired(expired);
+            reaped += removed;
+
+            if sampled == 0 || removed * 100 <= sampled * acceptable_stale || start.elapsed() >= time_limit {
+                break;
+            }
+        }
+        reaped
     }
 
-    /// Get statistics about the store
-    pub fn stats(&self) -> StoreStats {
-        // Count actual non-expired keys (not relying on lazy deletion counter)
-        let active_count = self.store
-            .values()
-            .filter(|entry| !entry.is_expired())
-            .count();
+    /// Sample up to `count` keys with a TTL from the sweep cursor
+    ///
+    /// Returns the number of keys sampled and those found expired. Keys
+    /// without a TTL are skipped, up to a bound so a mostly persistent
+    /// keyspace doesn't make a sample walk all of it.
+    fn sample_expired(&mut self, count: usize) -> (usize, Vec<Bytes>) {
+        let max_visited = count * 20;
+        let (mut sampled, mut visited) = (0, 0);
+        let mut expired = Vec::new();
+        let mut next_cursor = 0;
 
-        // Count expired keys that haven't been cleaned up yet
-        let expired_count = self.store.len() - active_count;
+        for (&slot, keys) in self.scan_index.range(self.expire_cursor..) {
+            for key in keys {
+                visited += 1;
+                if let Some(entry) = self.store.get(key).filter(|entry| entry.expire_at.is_some()) {
+                    sampled += 1;
+                    if entry.is_expired() {
+                        expired.push(key.clone());
+                    }
+                }
+            }
+            if sampled >= count || visited >= max_visited {
+                // Resume after this slot (or from the start past the last one)
+                next_cursor = slot.wrapping_add(1);
+                break;
+            }
+        }
 
+        self.expire_cursor = next_cursor;
+        (sampled, expired)
+    }
+
+    /// Evict a key to free memory, returning it if there was one to evict
+    ///
+    /// Like Redis, the key is the best of `EVICTION_SAMPLES` candidates: the
+    /// one idle the longest under `allkeys-lru`, or accessed the least often
+    /// under `allkeys-lfu` (the longest idle among equals). The candidates
+    /// are taken in scan index order from where the previous eviction
+    /// stopped, so successive evictions cover the whole keyspace. Nothing is
+    /// evicted under `noeviction`.
+    pub fn evict_one(&mut self, policy: MaxmemoryPolicy) -> Option<Bytes> {
+        if policy == MaxmemoryPolicy::NoEviction {
+            return None;
+        }
+
+        let rank = |entry: &Entry| match policy {
+            MaxmemoryPolicy::AllKeysLfu => (u8::MAX - entry.access_frequency(), entry.idle_time()),
+            _ => (0, entry.idle_time()),
+        };
+        let mut best: Option<(&Bytes, (u8, Duration))> = None;
+        let (mut sampled, mut next_cursor) = (0, 0);
+        let after = self.scan_index.range(self.evict_cursor..);
+        let before = self.scan_index.range(..self.evict_cursor);
+        for (&slot, keys) in after.chain(before) {
+            for key in keys {
+                if let Some(entry) = self.store.get(key) {
+                    let candidate = rank(entry);
+                    if best.is_none_or(|(_, best_rank)| candidate > best_rank) {
+                        best = Some((key, candidate));
+                    }
+                    sampled += 1;
+                }
+            }
+            if sampled >= EVICTION_SAMPLES {
+                next_cursor = slot.wrapping_add(1);
+                break;
+            }
+        }
+        let key = best?.0.clone();
+
+        self.evict_cursor = next_cursor;
+        self.remove_key(&key)?;
+        self.total_keys -= 1;
+        KEYSPACE_STATS.record_evicted(1);
+        self.notify(KeyEventKind::Evicted, &key);
+        Some(key)
+    }
+
+    /// Approximate memory usage of stored data in bytes
+    ///
+    /// O(1) apart from the entries modified since the last settlement.
+    /// Expired entries count until they are reaped.
+    pub fn memory_usage(&mut self) -> usize {
+        self.settle_counters();
+        self.used_memory
+    }
+
+    /// Get statistics about the store
+    ///
+    /// Maintained incrementally on writes rather than walking the keyspace,
+    /// so polling it is cheap. The keys already expired but not reaped yet
+    /// are counted from the index of the expiration times, apart from the
+    /// active ones.
+    pub fn stats(&mut self) -> StoreStats {
+        self.settle_counters();
+
+        let expired_keys = self.expiry_counts.range(..=Instant::now()).map(|(_, count)| count).sum();
         StoreStats {
             total_keys: self.store.len(),
-            expired_keys: expired_count,
-            active_keys: active_count,
-            used_memory_bytes: self.memory_usage(),
+            expired_keys,
+            volatile_keys: self.volatile_keys,
+            active_keys: self.store.len() - expired_keys,
+            used_memory_bytes: self.used_memory,
         }
     }
 }
 
+impl Drop for MemoryStore {
+    fn drop(&mut self) {
+        self.set_used_memory(0);
+    }
+}
+
 impl Default for MemoryStore {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Keys taken out of a store with `MemoryStore::detach_all`, freed on drop
+pub struct DetachedKeys {
+    store: Option<(StoreMap, BTreeMap<u64, Vec<Bytes>>)>,
+    pending: Arc<PendingFrees>,
+}
+
+impl Drop for DetachedKeys {
+    /// Free the keys, then tell the store they are gone
+    fn drop(&mut self) {
+        drop(self.store.take());
+        let mut count = self.pending.count.lock().unwrap_or_else(PoisonError::into_inner);
+        *count = count.saturating_sub(1);
+        self.pending.freed.notify_all();
+    }
+}
+
 /// Statistics about the memory store
 #[derive(Debug, Clone)]
 pub struct StoreStats {
     pub total_keys: usize,
+    /// Keys expired but not reaped yet
     pub expired_keys: usize,
+    /// Keys with a TTL, expired ones included
+    pub volatile_keys: usize,
     pub active_keys: usize,
     pub used_memory_bytes: usize,
 }
@@ -252,6 +915,138 @@ pub struct StoreStats {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::ListItem;
+
+    #[test]
+    fn test_expire_jitter_spreads_expiry_times() {
+        let mut store = MemoryStore::new();
+        let mut ttls = Vec::new();
+        for i in 0..100 {
+            let key = Bytes::from(format!("key:{}", i));
+            store.set(key.clone(), Value::integer(i));
+            assert!(store.expire_with_jitter(&key, 100, 20));
+            ttls.push(store.pttl(&key));
+        }
+
+        // Within 20% of the TTL either way, and spread over the range
+        assert!(ttls.iter().all(|ttl| (79_000..=120_000).contains(ttl)), "{:?}", ttls);
+        let (min, max) = (ttls.iter().min().unwrap(), ttls.iter().max().unwrap());
+        assert!(max - min > 20_000, "{} to {}", min, max);
+
+        // Without jitter, the TTL is exact
+        store.set("exact", Value::integer(0));
+        store.expire_with_jitter(&Bytes::from("exact"), 100, 0);
+        assert!((99_000..=100_000).contains(&store.pttl(&Bytes::from("exact"))));
+    }
+
+    #[test]
+    fn test_maybe_shrink_after_mass_deletion() {
+        let mut store = MemoryStore::new();
+        for i in 0..200_000 {
+            store.set(format!("key:{}", i), Value::integer(i));
+        }
+        let full_capacity = store.capacity();
+        assert!(!store.maybe_shrink());
+
+        for i in 1000..200_000 {
+            store.delete(&Bytes::from(format!("key:{}", i)));
+        }
+        assert!(store.capacity() > full_capacity / 2);
+        assert!(store.maybe_shrink());
+        assert!(store.capacity() < full_capacity / 50, "{} of {}", store.capacity(), full_capacity);
+        assert!(!store.maybe_shrink());
+
+        // The remaining keys are intact, and still scanned
+        assert_eq!(store.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(store.get(&Bytes::from(format!("key:{}", i))), Some(&Value::integer(i)));
+        }
+        assert_eq!(store.scan_cursor(0, 2000).1.len(), 1000);
+
+        // Never below the capacity the store was created with
+        store.clear();
+        assert!(store.capacity() >= 1024);
+    }
+
+    #[test]
+    fn test_set_if_absent() {
+        let mut store = MemoryStore::new();
+        assert!(store.set_if_absent("key", Value::string("first")));
+        assert!(!store.set_if_absent("key", Value::string("second")));
+        assert_eq!(store.get(&Bytes::from("key")), Some(&Value::string("first")));
+        assert_eq!(store.stats().total_keys, 1);
+
+        // An expired key is replaced
+        store.set_expire_at(&Bytes::from("key"), Instant::now());
+        assert!(store.set_if_absent("key", Value::string("third")));
+        assert_eq!(store.get(&Bytes::from("key")), Some(&Value::string("third")));
+        assert_eq!(store.stats().total_keys, 1);
+        assert_eq!(store.scan_cursor(0, 10).1, vec![Bytes::from("key")]);
+    }
+
+    #[test]
+    fn test_compare_and_set() {
+        let mut store = MemoryStore::new();
+        let key = Bytes::from("key");
+        assert_eq!(store.compare_and_set("key", Some(b"a"), Value::string("b")), Ok(false));
+        assert_eq!(store.compare_and_set("key", None, Value::string("a")), Ok(true));
+        assert_eq!(store.compare_and_set("key", None, Value::string("b")), Ok(false));
+
+        // The TTL is kept, the memory counter follows the new value
+        store.expire(&key, 100);
+        assert_eq!(store.compare_and_set("key", Some(b"a"), Value::string("x".repeat(1000))), Ok(true));
+        assert_eq!(store.get(&key), Some(&Value::string("x".repeat(1000))));
+        assert!(store.ttl(&key) > 0);
+        assert_eq!(store.memory_usage(), store.get_entry(&key).unwrap().memory_usage());
+
+        // Integers compare by their bytes
+        store.set("n", Value::Integer(42));
+        assert_eq!(store.compare_and_set("n", Some(b"42"), Value::Integer(43)), Ok(true));
+        store.set("list", Value::list([Bytes::from("a")]));
+        assert_eq!(store.compare_and_set("list", Some(b"a"), Value::string("b")), Err("list"));
+        assert_eq!(store.compare_and_set("list", None, Value::string("b")), Ok(false));
+        assert_eq!(store.get(&Bytes::from("list")), Some(&Value::list([Bytes::from("a")])));
+    }
+
+    #[test]
+    fn test_compare_and_delete() {
+        let mut store = MemoryStore::new();
+        let key = Bytes::from("lock");
+        assert_eq!(store.compare_and_delete(&key, b"token"), Ok(false));
+        store.set("lock", Value::string("token"));
+        assert_eq!(store.compare_and_delete(&key, b"other"), Ok(false));
+        assert_eq!(store.compare_and_delete(&key, b"token"), Ok(true));
+        assert_eq!(store.get(&key), None);
+        assert_eq!(store.stats().total_keys, 0);
+        assert_eq!(store.memory_usage(), 0);
+        assert!(store.scan_cursor(0, 10).1.is_empty());
+
+        // An expired lock is no longer held
+        store.set("lock", Value::string("token"));
+        store.set_expire_at(&key, Instant::now());
+        assert_eq!(store.compare_and_delete(&key, b"token"), Ok(false));
+    }
+
+    #[test]
+    fn test_concurrent_set_if_absent_has_one_winner() {
+        let store = std::sync::Arc::new(std::sync::RwLock::new(MemoryStore::new()));
+        for round in 0..100 {
+            let key = Bytes::from(format!("key:{}", round));
+            let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+            let threads: Vec<_> = (0..2)
+                .map(|thread| {
+                    let (store, barrier, key) = (store.clone(), barrier.clone(), key.clone());
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        store.write().unwrap().set_if_absent(key, Value::integer(thread))
+                    })
+                })
+                .collect();
+            let wins = threads.into_iter().map(|t| t.join().unwrap()).filter(|won| *won).count();
+            assert_eq!(wins, 1, "round {}", round);
+        }
+        assert_eq!(store.write().unwrap().stats().total_keys, 100);
+    }
 
     #[test]
     fn test_basic_set_get() {
@@ -280,6 +1075,291 @@ mod tests {
         assert!(!store.exists(&Bytes::from("key2")));
     }
 
+    #[test]
+    fn test_incremental_stats_match_full_recomputation() {
+        let mut store = MemoryStore::new();
+        for i in 0..2000 {
+            store.set(format!("key:{}", i), Value::string("x".repeat(i % 50)));
+        }
+
+        // Collections grown in place, overwrites, deletes and TTLs
+        for i in 0..200 {
+            let key = Bytes::from(format!("list:{}", i % 20));
+            if store.get(&key).is_none() {
+                store.set(key.clone(), Value::empty_list());
+            }
+            store.get_mut(&key).unwrap().as_list_mut().unwrap().push_back(ListItem::new(Bytes::from(vec![b'v'; i])));
+        }
+        for i in (0..2000).step_by(3) {
+            store.set(format!("key:{}", i), Value::Integer(i as i64));
+        }
+        for i in (0..2000).step_by(7) {
+            store.delete(&Bytes::from(format!("key:{}", i)));
+        }
+        for i in (1..2000).step_by(11) {
+            store.expire(&Bytes::from(format!("key:{}", i)), 100);
+        }
+
+        let memory: usize = store.store.values().map(|entry| entry.memory_usage()).sum();
+        let volatile = store.store.values().filter(|entry| entry.expire_at.is_some()).count();
+
+        let stats = store.stats();
+        assert_eq!(stats.active_keys, store.store.len());
+        assert_eq!(stats.expired_keys, 0);
+        assert_eq!(stats.used_memory_bytes, memory);
+        assert_eq!(stats.volatile_keys, volatile);
+
+        // Keys past their time but not reaped yet aren't active
+        let past = Instant::now() - Duration::from_secs(1);
+        for i in (1..2000).step_by(11).take(5) {
+            store.get_entry_mut(&Bytes::from(format!("key:{}", i))).unwrap().expire_at = Some(past);
+        }
+        store.set_expire_at(&Bytes::from("key:3"), past);
+        let stats = store.stats();
+        assert_eq!(stats.expired_keys, 6);
+        assert_eq!(stats.active_keys, store.store.len() - 6);
+        assert_eq!(stats.volatile_keys, volatile + 1);
+        store.active_expire_cycle(super::expire::MAX_ACTIVE_EXPIRE_EFFORT, Duration::from_secs(1));
+        let stats = store.stats();
+        assert_eq!(stats.expired_keys, 0);
+        assert_eq!((stats.total_keys, stats.active_keys), (store.store.len(), store.store.len()));
+
+        store.clear();
+        assert_eq!(store.memory_usage(), 0);
+    }
+
+    #[test]
+    fn test_evict_one_by_policy() {
+        let mut store = MemoryStore::new();
+        for (key, idle, frequency) in [("stale", 300, 20), ("idle", 200, 1), ("fresh", 10, 50)] {
+            store.set(key, Value::string("v"));
+            let entry = store.get_entry_mut(&Bytes::from(key)).unwrap();
+            entry.last_access = Instant::now() - Duration::from_secs(idle);
+            entry.frequency = frequency;
+        }
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        store.set_observer(Some(Box::new(move |event: KeyEvent<'_>| {
+            recorded.lock().unwrap().push((event.kind, event.key.clone()));
+        })));
+
+        assert_eq!(store.evict_one(MaxmemoryPolicy::NoEviction), None);
+
+        // The one accessed the least often, even if not the stalest
+        assert_eq!(store.evict_one(MaxmemoryPolicy::AllKeysLfu), Some(Bytes::from("idle")));
+        assert_eq!(store.evict_one(MaxmemoryPolicy::AllKeysLru), Some(Bytes::from("stale")));
+        assert_eq!(store.evict_one(MaxmemoryPolicy::AllKeysLru), Some(Bytes::from("fresh")));
+        assert_eq!(store.evict_one(MaxmemoryPolicy::AllKeysLru), None);
+
+        assert_eq!(store.len(), 0);
+        assert_eq!(store.memory_usage(), 0);
+        assert_eq!(
+            *events.lock().unwrap(),
+            ["idle", "stale", "fresh"].map(|key| (KeyEventKind::Evicted, Bytes::from(key)))
+        );
+    }
+
+    #[test]
+    fn test_memory_usage_scales_with_element_count() {
+        fn hash(fields: usize, size: usize) -> Value {
+            let hash = (0..fields)
+                .map(|i| {
+                    let mut field = i.to_string().into_bytes();
+                    field.resize(size, b'f');
+                    (Bytes::from(field), Bytes::from(vec![b'v'; size]))
+                })
+                .collect();
+            Value::Hash(hash)
+        }
+
+        // The same bytes, in 100 times more fields
+        let mut store = MemoryStore::new();
+        store.set("few", hash(100, 500));
+        let few = store.memory_usage();
+        store.set("many", hash(10_000, 5));
+        let many = store.memory_usage() - few;
+
+        let per_field = 2 * std::mem::size_of::<Bytes>();
+        assert!(many >= 10_000 * per_field, "{}", many);
+        assert!(many > 2 * few, "{} vs {}", many, few);
+    }
+
+    #[test]
+    fn test_no_touch_keeps_access_time() {
+        let mut store = MemoryStore::new();
+        store.set("key1", Value::string("value1"));
+        let key = Bytes::from("key1");
+
+        let backdated = std::time::Instant::now() - std::time::Duration::from_secs(60);
+        store.get_entry_mut(&key).unwrap().last_access = backdated;
+
+        store.set_no_touch(true);
+        store.get(&key);
+        store.get_mut(&key);
+        assert_eq!(store.get_entry(&key).unwrap().last_access, backdated);
+
+        store.set_no_touch(false);
+        store.get(&key);
+        assert!(store.get_entry(&key).unwrap().idle_time().as_secs() < 1);
+    }
+
+    #[test]
+    fn test_scan_cursor_full_coverage_under_writes() {
+        let mut store = MemoryStore::new();
+        for i in 0..1000 {
+            store.set(format!("persist:{}", i), Value::string("v"));
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        let mut cursor = 0;
+        let mut round = 0;
+        loop {
+            let (next, keys) = store.scan_cursor(cursor, 10);
+            seen.extend(keys);
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+
+            // Grow the keyspace for a while, then shrink it, so the number
+            // of buckets changes in both directions during the scan
+            round += 1;
+            if round < 40 {
+                for i in 0..200 {
+                    store.set(format!("tmp:{}:{}", round, i), Value::string("v"));
+                }
+            } else if round < 80 {
+                for i in 0..200 {
+                    store.delete(&Bytes::from(format!("tmp:{}:{}", round - 39, i)));
+                }
+            }
+        }
+
+        for i in 0..1000 {
+            assert!(seen.contains(&Bytes::from(format!("persist:{}", i))), "persist:{} not returned", i);
+        }
+    }
+
+    #[test]
+    fn test_scan_cursor_survives_map_rehash() {
+        // Small initial map, so the growth below really rehashes it
+        let mut store = MemoryStore::with_capacity(16);
+        for i in 0..100 {
+            store.set(format!("persist:{}", i), Value::string("v"));
+        }
+        let initial_capacity = store.store.capacity();
+
+        let mut seen = std::collections::HashSet::new();
+        let (mut cursor, keys) = store.scan_cursor(0, 4);
+        seen.extend(keys);
+        let mut capacities = vec![initial_capacity];
+        let mut round = 0;
+        while cursor != 0 {
+            round += 1;
+            match round {
+                // Grow by a factor of 100, then shrink back and release the memory
+                1..=5 => {
+                    for i in 0..2000 {
+                        store.set(format!("tmp:{}:{}", round, i), Value::string("v"));
+                    }
+                }
+                6..=10 => {
+                    for i in 0..2000 {
+                        store.delete(&Bytes::from(format!("tmp:{}:{}", round - 5, i)));
+                    }
+                    store.store.shrink_to_fit();
+                }
+                _ => {}
+            }
+            capacities.push(store.store.capacity());
+
+            let (next, keys) = store.scan_cursor(cursor, 4);
+            seen.extend(keys);
+            cursor = next;
+        }
+
+        // The map was rehashed both ways while the scan was running
+        assert!(capacities.iter().any(|&c| c > initial_capacity * 10), "{:?}", capacities);
+        assert!(*capacities.last().unwrap() < capacities.iter().copied().max().unwrap());
+        for i in 0..100 {
+            assert!(seen.contains(&Bytes::from(format!("persist:{}", i))), "persist:{} not returned", i);
+        }
+    }
+
+    #[test]
+    fn test_iter_keys_skips_expired() {
+        let mut store = MemoryStore::new();
+        store.set("live", Value::string("v"));
+        store.set("volatile", Value::string("v"));
+        store.set("expired", Value::string("v"));
+        store.expire(&Bytes::from("volatile"), 100);
+        store.set_expire_at(&Bytes::from("expired"), Instant::now() - std::time::Duration::from_secs(1));
+
+        // The expired key is still stored, but not visited
+        assert_eq!(store.len(), 3);
+        let mut keys: Vec<&Bytes> = store.iter_keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec![&Bytes::from("live"), &Bytes::from("volatile")]);
+        assert_eq!(store.keys().len(), 2);
+    }
+
+    #[test]
+    fn test_observer_sees_set_del_and_lazy_expiration() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut store = MemoryStore::new();
+        let seen = events.clone();
+        store.set_observer(Some(Box::new(move |event: KeyEvent<'_>| {
+            seen.lock().unwrap().push((event.kind, event.key.clone()));
+        })));
+
+        store.set("a", Value::string("1"));
+        assert!(store.delete(&Bytes::from("a")));
+        assert!(!store.delete(&Bytes::from("a")));
+        store.set("b", Value::string("2"));
+        store.set_expire_at(&Bytes::from("b"), Instant::now() - Duration::from_secs(1));
+        assert!(store.get(&Bytes::from("b")).is_none());
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (KeyEventKind::Set, Bytes::from("a")),
+                (KeyEventKind::Del, Bytes::from("a")),
+                (KeyEventKind::Set, Bytes::from("b")),
+                (KeyEventKind::Expire, Bytes::from("b")),
+                (KeyEventKind::Expired, Bytes::from("b")),
+            ]
+        );
+
+        // Every key removed by a clear is a deletion
+        store.set("c", Value::string("3"));
+        events.lock().unwrap().clear();
+        store.clear();
+        assert_eq!(*events.lock().unwrap(), vec![(KeyEventKind::Del, Bytes::from("c"))]);
+
+        // Without an observer nothing is reported
+        store.set_observer(None);
+        store.set("d", Value::string("4"));
+        assert_eq!(events.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_remove_if_empty() {
+        let mut store = MemoryStore::new();
+        store.set("list", Value::empty_list());
+        store.set("stream", Value::empty_stream());
+        store.set("string", Value::string(""));
+        store.set("hash", Value::Hash([(Bytes::from("f"), Bytes::from("v"))].into_iter().collect()));
+
+        assert!(store.remove_if_empty(&Bytes::from("list")));
+        assert!(!store.exists(&Bytes::from("list")));
+        for key in ["stream", "string", "hash", "missing"] {
+            assert!(!store.remove_if_empty(&Bytes::from(key)), "{}", key);
+        }
+        assert_eq!(store.len(), 3);
+    }
+
     #[test]
     fn test_expiration() {
         let mut store = MemoryStore::new();
@@ -293,4 +1373,47 @@ mod tests {
 
         assert!(!store.exists(&Bytes::from("key1")));
     }
+
+    #[test]
+    fn test_non_positive_expire_deletes() {
+        let mut store = MemoryStore::new();
+        for (key, ttl) in [("zero", 0), ("negative", -1)] {
+            store.set(key, Value::string("v"));
+            assert!(store.expire(&Bytes::from(key), ttl));
+            assert!(!store.exists(&Bytes::from(key)));
+            assert!(!store.expire(&Bytes::from(key), ttl));
+        }
+        assert_eq!(store.len(), 0);
+    }
+
+    #[test]
+    fn test_active_expire_reclaims_expired_batch() {
+        let mut store = MemoryStore::new();
+        let past = Instant::now();
+        for i in 0..5000 {
+            let key = Bytes::from(format!("expired:{}", i));
+            store.set(key.clone(), Value::string("v"));
+            store.set_expire_at(&key, past);
+        }
+        for i in 0..500 {
+            store.set(format!("kept:{}", i), Value::string("v"));
+        }
+        store.set("ttl", Value::string("v"));
+        store.set_expire_at(&Bytes::from("ttl"), Instant::now() + Duration::from_secs(100));
+
+        // At the highest effort a sweep keeps looping while most samples
+        // are stale, so the batch goes in a bounded number of sweeps
+        let mut sweeps = 0;
+        let mut reaped = 0;
+        while reaped < 5000 {
+            reaped += store.active_expire_cycle(10, Duration::from_secs(1));
+            sweeps += 1;
+            assert!(sweeps <= 5, "only {} keys reaped after {} sweeps", reaped, sweeps);
+        }
+        assert_eq!(reaped, 5000);
+        assert_eq!(store.len(), 501);
+        assert_eq!(store.active_expire_cycle(10, Duration::from_secs(1)), 0);
+        assert!(store.exists(&Bytes::from("ttl")));
+    }
+
 }
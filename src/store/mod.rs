@@ -6,7 +6,46 @@
 mod entry;
 mod value;
 mod memory;
+mod zset;
+mod stream;
+mod stats;
+mod encoding;
+mod dump;
+mod maxmemory;
+mod expire;
+mod export;
+mod event;
+mod json;
+mod list;
+mod set;
+mod hash;
 
-pub use entry::Entry;
-pub use value::Value;
-pub use memory::{MemoryStore, StoreStats};
+pub use entry::{instant_to_unix_ms, unix_ms_to_instant, Entry};
+pub use value::{hash_table_size, Value};
+pub(crate) use value::parse_canonical_int;
+pub use memory::{total_used_memory, DetachedKeys, MemoryStore, StoreStats};
+pub use list::ListItem;
+pub use set::Set;
+pub use hash::Hash;
+pub use zset::SortedSet;
+pub use stream::{Stream, StreamId, StreamFields};
+pub use stats::{KeyspaceStats, KEYSPACE_STATS};
+pub use dump::{dump_value, restore_value, serialized_length, BAD_DUMP_PAYLOAD, DUMP_FOOTER_LEN, DUMP_VERSION};
+pub use event::{KeyEvent, KeyEventKind, KeyObserver};
+pub use json::{JsonPath, PathSegment};
+pub use export::{bytes_to_json, export_line, import_line, json_to_value, value_to_json, ImportedKey};
+pub use maxmemory::{
+    exceeds_maxmemory, lfu_increment, maxmemory, maxmemory_policy, parse_memory, set_maxmemory, set_maxmemory_policy,
+    MaxmemoryPolicy, EVICTION_SAMPLES, LFU_INIT_VAL,
+};
+pub use expire::{
+    active_expire_effort, expire_jitter_percent, hz, jitter_ttl, set_active_expire_effort, set_expire_jitter_percent,
+    set_hz, sweep_period, sweep_time_limit, DEFAULT_ACTIVE_EXPIRE_EFFORT, DEFAULT_HZ, MAX_ACTIVE_EXPIRE_EFFORT,
+    MAX_EXPIRE_JITTER_PERCENT, MAX_HZ, MIN_HZ,
+};
+pub use encoding::{
+    embstr_size_limit, list_max_listpack_size, max_set_intset_entries, max_set_listpack_entries, set_embstr_size_limit,
+    set_list_max_listpack_size, set_set_max_intset_entries, set_set_max_listpack_entries, set_zset_max_listpack_entries,
+    zset_max_listpack_entries, DEFAULT_EMBSTR_SIZE_LIMIT, DEFAULT_LIST_MAX_LISTPACK_SIZE, DEFAULT_SET_MAX_INTSET_ENTRIES,
+    DEFAULT_SET_MAX_LISTPACK_ENTRIES, DEFAULT_ZSET_MAX_LISTPACK_ENTRIES,
+};
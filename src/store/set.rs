@@ -0,0 +1,211 @@
+//! Set structure
+//!
+//! Like a Redis listpack, a small set keeps its members in a sorted vector,
+//! found by binary search, without the buckets of a hash table. Once it has
+//! more than `set-max-listpack-entries` members it is promoted to a
+//! `HashSet`, and stays one even if it shrinks back, as in Redis.
+
+use super::encoding::max_set_listpack_entries;
+use bytes::Bytes;
+use std::collections::HashSet;
+
+/// The members of a set, in one of its two representations
+#[derive(Debug, Clone)]
+enum Members {
+    /// Sorted and without duplicates
+    Compact(Vec<Bytes>),
+
+    /// Any order
+    Table(HashSet<Bytes>),
+}
+
+/// A set of unique members
+#[derive(Debug, Clone)]
+pub struct Set {
+    members: Members,
+}
+
+impl Set {
+    /// Create an empty set, in the compact representation
+    pub fn new() -> Self {
+        Set { members: Members::Compact(Vec::new()) }
+    }
+
+    /// Number of members
+    pub fn len(&self) -> usize {
+        match &self.members {
+            Members::Compact(members) => members.len(),
+            Members::Table(members) => members.len(),
+        }
+    }
+
+    /// Whether the set has no members
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the set is still in the compact representation
+    pub fn is_compact(&self) -> bool {
+        matches!(self.members, Members::Compact(_))
+    }
+
+    /// Whether `member` is in the set
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match &self.members {
+            Members::Compact(members) => members.binary_search_by(|m| m.as_ref().cmp(member)).is_ok(),
+            Members::Table(members) => members.contains(member),
+        }
+    }
+
+    /// Add a member, promoting the set to a hash table when it outgrows the
+    /// compact representation
+    ///
+    /// Returns true if the member was not in the set.
+    pub fn insert(&mut self, member: Bytes) -> bool {
+        match &mut self.members {
+            Members::Compact(members) => match members.binary_search(&member) {
+                Ok(_) => false,
+                Err(_) if members.len() >= max_set_listpack_entries() => {
+                    let mut table: HashSet<Bytes> = std::mem::take(members).into_iter().collect();
+                    table.insert(member);
+                    self.members = Members::Table(table);
+                    true
+                }
+                Err(pos) => {
+                    members.insert(pos, member);
+                    true
+                }
+            },
+            Members::Table(members) => members.insert(member),
+        }
+    }
+
+    /// Remove a member
+    ///
+    /// Returns true if the member was in the set.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match &mut self.members {
+            Members::Compact(members) => match members.binary_search_by(|m| m.as_ref().cmp(member)) {
+                Ok(pos) => {
+                    members.remove(pos);
+                    true
+                }
+                Err(_) => false,
+            },
+            Members::Table(members) => members.remove(member),
+        }
+    }
+
+    /// Iterate over the members, in order while the set is compact
+    pub fn iter(&self) -> impl Iterator<Item = &Bytes> {
+        let (compact, table) = match &self.members {
+            Members::Compact(members) => (Some(members.iter()), None),
+            Members::Table(members) => (None, Some(members.iter())),
+        };
+        compact.into_iter().flatten().chain(table.into_iter().flatten())
+    }
+
+    /// Calculate approximate memory usage in bytes: the members' bytes and
+    /// the slots holding them
+    pub fn memory_usage(&self) -> usize {
+        let items_size: usize = self.iter().map(|member| member.len()).sum();
+        let slots = match &self.members {
+            Members::Compact(members) => members.capacity() * std::mem::size_of::<Bytes>(),
+            Members::Table(members) => super::value::hash_table_size::<Bytes>(members.capacity()),
+        };
+        items_size + slots + std::mem::size_of::<Self>()
+    }
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for Set {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|member| other.contains(member))
+    }
+}
+
+impl Extend<Bytes> for Set {
+    fn extend<I: IntoIterator<Item = Bytes>>(&mut self, iter: I) {
+        for member in iter {
+            self.insert(member);
+        }
+    }
+}
+
+impl FromIterator<Bytes> for Set {
+    /// Collect members, compact if there are few enough of them once
+    /// duplicates are removed
+    fn from_iter<I: IntoIterator<Item = Bytes>>(iter: I) -> Self {
+        let mut members: Vec<Bytes> = iter.into_iter().collect();
+        members.sort_unstable();
+        members.dedup();
+        if members.len() > max_set_listpack_entries() {
+            Set { members: Members::Table(members.into_iter().collect()) }
+        } else {
+            Set { members: Members::Compact(members) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{set_set_max_listpack_entries, DEFAULT_SET_MAX_LISTPACK_ENTRIES};
+
+    #[test]
+    fn test_small_set_is_compact() {
+        let mut set = Set::new();
+        for member in ["c", "a", "b", "a"] {
+            set.insert(Bytes::from(member));
+        }
+        assert!(set.is_compact());
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.iter().map(|m| m.as_ref()).collect::<Vec<_>>(), [b"a", b"b", b"c"]);
+
+        assert!(set.remove(b"b"));
+        assert!(!set.remove(b"b"));
+        assert!(!set.contains(b"b"));
+        assert!(set.contains(b"c"));
+
+        let collected: Set = ["x", "y", "x"].into_iter().map(Bytes::from).collect();
+        assert!(collected.is_compact());
+        assert_eq!(collected.len(), 2);
+    }
+
+    #[test]
+    fn test_membership_across_promotion() {
+        let _guard = crate::config::TEST_PARAMS_LOCK.lock().unwrap();
+        set_set_max_listpack_entries(16);
+
+        let member = |i: usize| Bytes::from(format!("member:{}", i));
+        let mut set = Set::new();
+        for i in 0..40 {
+            assert!(set.insert(member(i)));
+            assert!(!set.insert(member(i)));
+            assert_eq!(set.is_compact(), i < 16, "{} members", i + 1);
+            assert!((0..=i).all(|j| set.contains(&member(j))));
+            assert!(!set.contains(&member(i + 1)));
+        }
+
+        // The promoted set equals a compact one with the same members
+        let compact: Set = {
+            set_set_max_listpack_entries(DEFAULT_SET_MAX_LISTPACK_ENTRIES);
+            (0..40).map(member).collect()
+        };
+        assert!(compact.is_compact());
+        assert_eq!(set, compact);
+
+        // Removing members doesn't make it compact again
+        for i in 0..30 {
+            assert!(set.remove(&member(i)));
+        }
+        assert!(!set.is_compact());
+        assert_eq!(set.len(), 10);
+        assert!((30..40).all(|i| set.contains(&member(i))));
+    }
+}
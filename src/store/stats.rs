@@ -0,0 +1,80 @@
+//! Process-wide keyspace statistics
+//!
+//! Counters reported in the `# Stats` section of INFO. They are atomics
+//! shared by every store (one per shard), so they are aggregated across
+//! shards as they are updated.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Keyspace counters
+#[derive(Debug)]
+pub struct KeyspaceStats {
+    /// Lookups of read commands that found the key
+    keyspace_hits: AtomicU64,
+
+    /// Lookups of read commands that didn't find the key
+    keyspace_misses: AtomicU64,
+
+    /// Keys removed because their TTL elapsed (lazily or actively)
+    expired_keys: AtomicU64,
+
+    /// Keys removed to comply with maxmemory
+    evicted_keys: AtomicU64,
+}
+
+/// Counters of the whole process
+pub static KEYSPACE_STATS: KeyspaceStats = KeyspaceStats::new();
+
+impl KeyspaceStats {
+    /// Create zeroed counters
+    pub const fn new() -> Self {
+        KeyspaceStats {
+            keyspace_hits: AtomicU64::new(0),
+            keyspace_misses: AtomicU64::new(0),
+            expired_keys: AtomicU64::new(0),
+            evicted_keys: AtomicU64::new(0),
+        }
+    }
+
+    /// Record the outcome of a read lookup
+    pub fn record_lookup(&self, hit: bool) {
+        let counter = if hit { &self.keyspace_hits } else { &self.keyspace_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record keys removed because they expired
+    pub fn record_expired(&self, count: u64) {
+        self.expired_keys.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record keys evicted to free memory
+    pub fn record_evicted(&self, count: u64) {
+        self.evicted_keys.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Number of read lookups that found the key
+    pub fn keyspace_hits(&self) -> u64 {
+        self.keyspace_hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of read lookups that didn't find the key
+    pub fn keyspace_misses(&self) -> u64 {
+        self.keyspace_misses.load(Ordering::Relaxed)
+    }
+
+    /// Number of keys removed because they expired
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys.load(Ordering::Relaxed)
+    }
+
+    /// Number of keys evicted to free memory
+    pub fn evicted_keys(&self) -> u64 {
+        self.evicted_keys.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for KeyspaceStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,180 @@
+//! Stream structure
+//!
+//! An append-only log of field/value entries keyed by monotonically
+//! increasing `<ms>-<seq>` IDs.
+
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+
+/// Stream entry ID (`<milliseconds>-<sequence>`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    /// Smallest possible ID
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+
+    /// Largest possible ID
+    pub const MAX: StreamId = StreamId { ms: u64::MAX, seq: u64::MAX };
+
+    /// Create a new ID
+    pub fn new(ms: u64, seq: u64) -> Self {
+        StreamId { ms, seq }
+    }
+
+    /// Parse an ID of the form `<ms>-<seq>` or `<ms>`
+    ///
+    /// When the sequence part is omitted, `default_seq` is used
+    /// (0 for range starts, u64::MAX for range ends).
+    pub fn parse(s: &[u8], default_seq: u64) -> Option<Self> {
+        let s = std::str::from_utf8(s).ok()?;
+        match s.split_once('-') {
+            Some((ms, seq)) => Some(StreamId {
+                ms: ms.parse().ok()?,
+                seq: seq.parse().ok()?,
+            }),
+            None => Some(StreamId {
+                ms: s.parse().ok()?,
+                seq: default_seq,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Field/value pairs of a single stream entry
+pub type StreamFields = Vec<(Bytes, Bytes)>;
+
+/// Append-only stream of entries ordered by ID
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Stream {
+    /// Entries ordered by ID
+    entries: BTreeMap<StreamId, StreamFields>,
+
+    /// Last ID ever added (kept even if entries are trimmed in the future)
+    last_id: StreamId,
+}
+
+impl Stream {
+    /// Create an empty stream
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the stream is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Last ID added to the stream
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Generate the next ID for the given wall-clock time in milliseconds
+    ///
+    /// Uses the current time when it is ahead of the last ID, otherwise
+    /// keeps the last millisecond and bumps the sequence, so IDs stay
+    /// strictly increasing even if the clock goes backwards.
+    pub fn next_id(&self, now_ms: u64) -> StreamId {
+        if now_ms > self.last_id.ms {
+            StreamId::new(now_ms, 0)
+        } else if self.last_id.seq < u64::MAX {
+            StreamId::new(self.last_id.ms, self.last_id.seq + 1)
+        } else {
+            StreamId::new(self.last_id.ms + 1, 0)
+        }
+    }
+
+    /// Append an entry with an explicit ID
+    ///
+    /// Fails if the ID is not strictly greater than the last one.
+    pub fn add(&mut self, id: StreamId, fields: StreamFields) -> Result<(), &'static str> {
+        if id == StreamId::MIN {
+            return Err("The ID specified in XADD must be greater than 0-0");
+        }
+        if id <= self.last_id {
+            return Err("The ID specified in XADD is equal or smaller than the target stream top item");
+        }
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        Ok(())
+    }
+
+    /// Get entries with IDs in `[start, end]`, limited to `count` entries
+    pub fn range(&self, start: StreamId, end: StreamId, count: Option<usize>) -> Vec<(StreamId, &StreamFields)> {
+        if start > end {
+            return Vec::new();
+        }
+        self.entries
+            .range((Bound::Included(start), Bound::Included(end)))
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| (*id, fields))
+            .collect()
+    }
+
+    /// Calculate approximate memory usage in bytes
+    pub fn memory_usage(&self) -> usize {
+        let items_size: usize = self.entries
+            .values()
+            .map(|fields| {
+                let fields_size: usize = fields.iter().map(|(f, v)| f.len() + v.len()).sum();
+                let slots = fields.capacity() * std::mem::size_of::<(Bytes, Bytes)>();
+                fields_size + slots + std::mem::size_of::<(StreamId, StreamFields)>()
+            })
+            .sum();
+        items_size + std::mem::size_of::<Self>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_id_monotonic() {
+        let mut stream = Stream::new();
+        stream.add(StreamId::new(1000, 0), vec![]).unwrap();
+
+        // Same millisecond bumps the sequence
+        assert_eq!(stream.next_id(1000), StreamId::new(1000, 1));
+
+        // Clock going backwards still yields a larger ID
+        assert_eq!(stream.next_id(500), StreamId::new(1000, 1));
+
+        // Clock moving forward resets the sequence
+        assert_eq!(stream.next_id(2000), StreamId::new(2000, 0));
+    }
+
+    #[test]
+    fn test_add_rejects_smaller_id() {
+        let mut stream = Stream::new();
+        assert!(stream.add(StreamId::MIN, vec![]).is_err());
+        stream.add(StreamId::new(5, 1), vec![]).unwrap();
+        assert!(stream.add(StreamId::new(5, 1), vec![]).is_err());
+        assert!(stream.add(StreamId::new(4, 9), vec![]).is_err());
+        assert_eq!(stream.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_id() {
+        assert_eq!(StreamId::parse(b"12-3", 0), Some(StreamId::new(12, 3)));
+        assert_eq!(StreamId::parse(b"12", u64::MAX), Some(StreamId::new(12, u64::MAX)));
+        assert_eq!(StreamId::parse(b"abc", 0), None);
+    }
+}
@@ -1,7 +1,12 @@
 //! Value types for the key-value store
 
+use super::hash::Hash;
+use super::list::ListItem;
+use super::set::Set;
+use super::stream::Stream;
+use super::zset::SortedSet;
 use bytes::Bytes;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::VecDeque;
 
 /// Represents the different types of values that can be stored
 #[derive(Debug, Clone, PartialEq)]
@@ -12,16 +17,25 @@ pub enum Value {
     /// Integer value (used for counters)
     Integer(i64),
 
-    /// List of values (ordered)
-    List(VecDeque<Bytes>),
+    /// List of values (ordered), integers stored compactly
+    List(VecDeque<ListItem>),
 
-    /// Set of unique values (unordered)
-    Set(HashSet<Bytes>),
+    /// Set of unique values, compact while small
+    Set(Set),
 
     /// Hash map (field -> value)
-    Hash(HashMap<Bytes, Bytes>),
+    Hash(Hash),
 
-    // TODO Phase 2+: ZSet (sorted set), Bitmap, etc.
+    /// Sorted set (member -> score, ordered by score)
+    ZSet(SortedSet),
+
+    /// Append-only stream of entries keyed by `<ms>-<seq>` IDs
+    Stream(Stream),
+
+    /// JSON document (JSON.SET / JSON.GET)
+    Json(serde_json::Value),
+
+    // TODO Phase 2+: Bitmap, etc.
 }
 
 impl Value {
@@ -35,19 +49,47 @@ impl Value {
         Value::Integer(i)
     }
 
+    /// Create a string value, stored as an integer when it is the canonical
+    /// form of an i64 (like Redis' `int` encoding)
+    ///
+    /// Canonical means the integer formats back to exactly the same bytes,
+    /// so "12345" and "-7" become integers while "007", "+1" or "12345x"
+    /// stay strings. Reading the value back always gives the original bytes.
+    pub fn from_bytes(bytes: Bytes) -> Self {
+        match parse_canonical_int(&bytes) {
+            Some(i) => Value::Integer(i),
+            None => Value::String(bytes),
+        }
+    }
+
     /// Create an empty list
     pub fn empty_list() -> Self {
         Value::List(VecDeque::new())
     }
 
+    /// Create a list of the given elements
+    pub fn list(items: impl IntoIterator<Item = Bytes>) -> Self {
+        Value::List(items.into_iter().map(ListItem::new).collect())
+    }
+
     /// Create an empty set
     pub fn empty_set() -> Self {
-        Value::Set(HashSet::new())
+        Value::Set(Set::new())
     }
 
     /// Create an empty hash
     pub fn empty_hash() -> Self {
-        Value::Hash(HashMap::new())
+        Value::Hash(Hash::new())
+    }
+
+    /// Create an empty sorted set
+    pub fn empty_zset() -> Self {
+        Value::ZSet(SortedSet::new())
+    }
+
+    /// Create an empty stream
+    pub fn empty_stream() -> Self {
+        Value::Stream(Stream::new())
     }
 
     /// Get the type name as a string
@@ -58,6 +100,35 @@ impl Value {
             Value::List(_) => "list",
             Value::Set(_) => "set",
             Value::Hash(_) => "hash",
+            Value::ZSet(_) => "zset",
+            Value::Stream(_) => "stream",
+            Value::Json(_) => "ReJSON-RL",
+        }
+    }
+
+    /// Get the internal encoding name reported by OBJECT ENCODING
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Value::String(bytes) => super::encoding::string_encoding(bytes),
+            Value::Integer(_) => "int",
+            Value::List(list) => super::encoding::list_encoding(list),
+            Value::Set(set) => super::encoding::set_encoding(set),
+            Value::Hash(_) => "hashtable",
+            Value::ZSet(zset) => super::encoding::zset_encoding(zset),
+            Value::Stream(_) => "stream",
+            Value::Json(_) => "json",
+        }
+    }
+
+    /// Number of elements of a list, set, hash or sorted set, as used for
+    /// its encoding (None for strings, streams and JSON documents)
+    pub fn element_count(&self) -> Option<usize> {
+        match self {
+            Value::List(list) => Some(list.len()),
+            Value::Set(set) => Some(set.len()),
+            Value::Hash(hash) => Some(hash.len()),
+            Value::ZSet(zset) => Some(zset.len()),
+            Value::String(_) | Value::Integer(_) | Value::Stream(_) | Value::Json(_) => None,
         }
     }
 
@@ -79,6 +150,19 @@ impl Value {
         }
     }
 
+    /// Try to get the bytes of a string, integers being strings too
+    ///
+    /// INCR stores `Integer` where SET stores `String`, but to the string
+    /// commands both are strings: an integer is seen as its decimal form.
+    /// A string is returned as a handle sharing the stored bytes.
+    pub fn as_string_bytes(&self) -> Option<Bytes> {
+        match self {
+            Value::String(b) => Some(b.clone()),
+            Value::Integer(i) => Some(Bytes::from(i.to_string())),
+            _ => None,
+        }
+    }
+
     /// Try to get as integer
     pub fn as_integer(&self) -> Option<i64> {
         match self {
@@ -88,7 +172,7 @@ impl Value {
     }
 
     /// Try to get as mutable list
-    pub fn as_list_mut(&mut self) -> Option<&mut VecDeque<Bytes>> {
+    pub fn as_list_mut(&mut self) -> Option<&mut VecDeque<ListItem>> {
         match self {
             Value::List(list) => Some(list),
             _ => None,
@@ -96,7 +180,7 @@ impl Value {
     }
 
     /// Try to get as mutable set
-    pub fn as_set_mut(&mut self) -> Option<&mut HashSet<Bytes>> {
+    pub fn as_set_mut(&mut self) -> Option<&mut Set> {
         match self {
             Value::Set(set) => Some(set),
             _ => None,
@@ -104,7 +188,7 @@ impl Value {
     }
 
     /// Try to get as mutable hash
-    pub fn as_hash_mut(&mut self) -> Option<&mut HashMap<Bytes, Bytes>> {
+    pub fn as_hash_mut(&mut self) -> Option<&mut Hash> {
         match self {
             Value::Hash(hash) => Some(hash),
             _ => None,
@@ -112,7 +196,7 @@ impl Value {
     }
 
     /// Try to get as hash reference
-    pub fn as_hash(&self) -> Option<&HashMap<Bytes, Bytes>> {
+    pub fn as_hash(&self) -> Option<&Hash> {
         match self {
             Value::Hash(hash) => Some(hash),
             _ => None,
@@ -120,7 +204,7 @@ impl Value {
     }
 
     /// Try to get as list reference
-    pub fn as_list(&self) -> Option<&VecDeque<Bytes>> {
+    pub fn as_list(&self) -> Option<&VecDeque<ListItem>> {
         match self {
             Value::List(list) => Some(list),
             _ => None,
@@ -128,37 +212,101 @@ impl Value {
     }
 
     /// Try to get as set reference
-    pub fn as_set(&self) -> Option<&HashSet<Bytes>> {
+    pub fn as_set(&self) -> Option<&Set> {
         match self {
             Value::Set(set) => Some(set),
             _ => None,
         }
     }
 
+    /// Try to get as mutable sorted set
+    pub fn as_zset_mut(&mut self) -> Option<&mut SortedSet> {
+        match self {
+            Value::ZSet(zset) => Some(zset),
+            _ => None,
+        }
+    }
+
+    /// Try to get as sorted set reference
+    pub fn as_zset(&self) -> Option<&SortedSet> {
+        match self {
+            Value::ZSet(zset) => Some(zset),
+            _ => None,
+        }
+    }
+
+    /// Try to get as mutable stream
+    pub fn as_stream_mut(&mut self) -> Option<&mut Stream> {
+        match self {
+            Value::Stream(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    /// Try to get as stream reference
+    pub fn as_stream(&self) -> Option<&Stream> {
+        match self {
+            Value::Stream(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
     /// Calculate approximate memory usage in bytes
+    ///
+    /// Collections count their allocations, not only the bytes of their
+    /// elements: every slot of a list's buffer or of a hash table's
+    /// capacity (a `Bytes` handle per element, for instance), used or not,
+    /// on top of the bytes the elements point to.
     pub fn memory_usage(&self) -> usize {
         match self {
             Value::String(bytes) => bytes.len(),
             Value::Integer(_) => std::mem::size_of::<i64>(),
             Value::List(list) => {
-                let items_size: usize = list.iter().map(|b| b.len()).sum();
-                let overhead = std::mem::size_of::<VecDeque<Bytes>>();
-                items_size + overhead
-            }
-            Value::Set(set) => {
-                let items_size: usize = set.iter().map(|b| b.len()).sum();
-                let overhead = std::mem::size_of::<HashSet<Bytes>>();
-                items_size + overhead
-            }
-            Value::Hash(hash) => {
-                let items_size: usize = hash.iter()
-                    .map(|(k, v)| k.len() + v.len())
-                    .sum();
-                let overhead = std::mem::size_of::<HashMap<Bytes, Bytes>>();
-                items_size + overhead
+                let items_size: usize = list.iter().map(ListItem::memory_usage).sum();
+                let slack = (list.capacity() - list.len()) * std::mem::size_of::<ListItem>();
+                items_size + slack + std::mem::size_of::<VecDeque<ListItem>>()
             }
+            Value::Set(set) => set.memory_usage(),
+            Value::Hash(hash) => hash.memory_usage(),
+            Value::ZSet(zset) => zset.memory_usage(),
+            Value::Stream(stream) => stream.memory_usage(),
+            Value::Json(doc) => doc.to_string().len() + std::mem::size_of::<serde_json::Value>(),
+        }
+    }
+}
+
+/// Size of the buckets of a hash table of `capacity` elements of type `T`:
+/// the element itself and its control byte
+pub fn hash_table_size<T>(capacity: usize) -> usize {
+    capacity * (std::mem::size_of::<T>() + 1)
+}
+
+/// The integer whose canonical form is `bytes`, if any
+///
+/// Canonical means the integer formats back to exactly the same bytes, so
+/// "12345" and "-7" are integers while "007", "+1", "-0" or "12345x" are
+/// not. Parsed from the bytes directly, without allocating.
+pub(crate) fn parse_canonical_int(bytes: &[u8]) -> Option<i64> {
+    let (negative, digits) = match bytes {
+        [b'-', digits @ ..] => (true, digits),
+        digits => (false, digits),
+    };
+    match digits {
+        [] => return None,
+        [b'0'] => return (!negative).then_some(0),
+        [b'0', ..] => return None,
+        _ => {}
+    }
+
+    // Accumulated as a negative number, which reaches i64::MIN
+    let mut value: i64 = 0;
+    for &digit in digits {
+        if !digit.is_ascii_digit() {
+            return None;
         }
+        value = value.checked_mul(10)?.checked_sub(i64::from(digit - b'0'))?;
     }
+    if negative { Some(value) } else { value.checked_neg() }
 }
 
 // Implement Eq and Hash for Bytes to allow it in HashSet
@@ -186,6 +334,18 @@ impl std::hash::Hash for Value {
                 4u8.hash(state);
                 h.len().hash(state);
             }
+            Value::ZSet(z) => {
+                5u8.hash(state);
+                z.len().hash(state);
+            }
+            Value::Stream(s) => {
+                6u8.hash(state);
+                s.len().hash(state);
+            }
+            Value::Json(doc) => {
+                7u8.hash(state);
+                doc.to_string().hash(state);
+            }
         }
     }
 }
@@ -0,0 +1,164 @@
+//! Sorted set structure
+//!
+//! Members are unique and ordered by (score, member), like Redis ZSets.
+
+use bytes::Bytes;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+/// A member with its score, ordered by score then by member bytes
+#[derive(Debug, Clone, PartialEq)]
+struct ScoredMember {
+    score: f64,
+    member: Bytes,
+}
+
+impl Eq for ScoredMember {}
+
+impl PartialOrd for ScoredMember {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScoredMember {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| self.member.cmp(&other.member))
+    }
+}
+
+/// Sorted set: a member -> score map plus an ordered index
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SortedSet {
+    /// Score lookup by member
+    scores: HashMap<Bytes, f64>,
+
+    /// Members ordered by (score, member)
+    ordered: BTreeSet<ScoredMember>,
+}
+
+impl SortedSet {
+    /// Create an empty sorted set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of members
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    /// Check if the sorted set is empty
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Insert or update a member, returns true if the member is new
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        match self.scores.insert(member.clone(), score) {
+            Some(old_score) => {
+                self.ordered.remove(&ScoredMember { score: old_score, member: member.clone() });
+                self.ordered.insert(ScoredMember { score, member });
+                false
+            }
+            None => {
+                self.ordered.insert(ScoredMember { score, member });
+                true
+            }
+        }
+    }
+
+    /// Remove a member, returns true if it existed
+    pub fn remove(&mut self, member: &Bytes) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.ordered.remove(&ScoredMember { score, member: member.clone() });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get the score of a member
+    pub fn score(&self, member: &Bytes) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Remove and return up to `count` members with the lowest scores
+    pub fn pop_min(&mut self, count: usize) -> Vec<(Bytes, f64)> {
+        let mut popped = Vec::with_capacity(count.min(self.len()));
+        while popped.len() < count {
+            match self.ordered.pop_first() {
+                Some(item) => {
+                    self.scores.remove(&item.member);
+                    popped.push((item.member, item.score));
+                }
+                None => break,
+            }
+        }
+        popped
+    }
+
+    /// Remove and return up to `count` members with the highest scores
+    pub fn pop_max(&mut self, count: usize) -> Vec<(Bytes, f64)> {
+        let mut popped = Vec::with_capacity(count.min(self.len()));
+        while popped.len() < count {
+            match self.ordered.pop_last() {
+                Some(item) => {
+                    self.scores.remove(&item.member);
+                    popped.push((item.member, item.score));
+                }
+                None => break,
+            }
+        }
+        popped
+    }
+
+    /// Iterate over members in ascending (score, member) order
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> {
+        self.ordered.iter().map(|item| (&item.member, item.score))
+    }
+
+    /// Calculate approximate memory usage in bytes
+    pub fn memory_usage(&self) -> usize {
+        // The index holds a second handle on each member's bytes
+        let items_size: usize = self.scores.keys().map(|m| m.len()).sum();
+        let table = super::value::hash_table_size::<(Bytes, f64)>(self.scores.capacity());
+        let index = self.ordered.len() * std::mem::size_of::<ScoredMember>();
+        items_size + table + index + std::mem::size_of::<Self>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_order() {
+        let mut zset = SortedSet::new();
+        assert!(zset.insert(Bytes::from("b"), 2.0));
+        assert!(zset.insert(Bytes::from("a"), 1.0));
+        assert!(zset.insert(Bytes::from("c"), 1.0));
+
+        // Updating a score is not a new insertion
+        assert!(!zset.insert(Bytes::from("b"), 0.5));
+
+        let members: Vec<&Bytes> = zset.iter().map(|(m, _)| m).collect();
+        assert_eq!(members, vec![&Bytes::from("b"), &Bytes::from("a"), &Bytes::from("c")]);
+        assert_eq!(zset.score(&Bytes::from("b")), Some(0.5));
+    }
+
+    #[test]
+    fn test_pop_both_ends() {
+        let mut zset = SortedSet::new();
+        zset.insert(Bytes::from("low"), 1.0);
+        zset.insert(Bytes::from("mid"), 5.0);
+        zset.insert(Bytes::from("high"), 10.0);
+
+        assert_eq!(zset.pop_min(1), vec![(Bytes::from("low"), 1.0)]);
+        assert_eq!(zset.pop_max(5), vec![(Bytes::from("high"), 10.0), (Bytes::from("mid"), 5.0)]);
+        assert!(zset.is_empty());
+    }
+}
@@ -1,8 +1,9 @@
 //! HTTP handlers for the web interface
 
 use axum::{
-    extract::State,
-    http::StatusCode,
+    body::Body,
+    extract::{FromRef, State},
+    http::{header, StatusCode},
     response::{Html, IntoResponse, Json},
 };
 use serde::{Deserialize, Serialize};
@@ -13,12 +14,38 @@ use tracing::debug;
 use crate::dispatch::Dispatcher;
 use crate::cluster::ClusterManager;
 use crate::protocol::RespValue;
+use crate::pubsub::PubSubBroker;
+use crate::store;
 use bytes::Bytes;
 use sysinfo::System;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
 
 /// Shared application state
 pub type AppState = Arc<Mutex<Dispatcher>>;
 
+/// Shared application state in cluster mode
+#[derive(Clone)]
+pub struct ClusterAppState {
+    /// Cluster manager executing the commands
+    pub cluster: Arc<ClusterManager>,
+
+    /// Global pub/sub broker shared with the RESP server
+    pub pubsub: Arc<PubSubBroker>,
+}
+
+impl FromRef<ClusterAppState> for Arc<ClusterManager> {
+    fn from_ref(state: &ClusterAppState) -> Self {
+        state.cluster.clone()
+    }
+}
+
+impl FromRef<ClusterAppState> for Arc<PubSubBroker> {
+    fn from_ref(state: &ClusterAppState) -> Self {
+        state.pubsub.clone()
+    }
+}
+
 /// Request body for command execution
 #[derive(Debug, Deserialize)]
 pub struct CommandRequest {
@@ -62,25 +89,11 @@ pub async fn execute_command(
 ) -> impl IntoResponse {
     debug!("Executing command: {}", req.command);
 
-    // Parse command string into parts and convert to RESP values
-    let parts: Vec<RespValue> = req.command
-        .trim()
-        .split_whitespace()
-        .map(|s| RespValue::BulkString(Bytes::from(s.to_string())))
-        .collect();
-
-    if parts.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(CommandResponse {
-                success: false,
-                result: "Empty command".to_string(),
-            }),
-        );
-    }
-
-    // Build RESP array command
-    let command = RespValue::Array(parts);
+    // Parse command string into a RESP array command
+    let command = match parse_command_line(&req.command) {
+        Some(command) => command,
+        None => return empty_command_response(),
+    };
 
     // Execute command
     let mut dispatcher = dispatcher.lock().await;
@@ -98,6 +111,35 @@ pub async fn execute_command(
     )
 }
 
+/// Split a command line into a RESP array command
+///
+/// Tokens are separated by any run of whitespace; leading, trailing and
+/// repeated whitespace never produce empty arguments. Returns None when the
+/// line holds no token at all.
+fn parse_command_line(line: &str) -> Option<RespValue> {
+    let parts: Vec<RespValue> = line
+        .split_whitespace()
+        .map(|token| RespValue::BulkString(Bytes::from(token.to_string())))
+        .collect();
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(RespValue::Array(parts))
+    }
+}
+
+/// 400 response for an empty or whitespace-only command
+fn empty_command_response() -> (StatusCode, Json<CommandResponse>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(CommandResponse {
+            success: false,
+            result: "Empty command".to_string(),
+        }),
+    )
+}
+
 /// Format a RESP value for display
 fn format_resp_value(value: &RespValue) -> String {
     match value {
@@ -119,6 +161,19 @@ fn format_resp_value(value: &RespValue) -> String {
                 items.join("\n")
             }
         }
+        RespValue::Map(pairs) => {
+            if pairs.is_empty() {
+                "(empty map)".to_string()
+            } else {
+                let items: Vec<String> = pairs
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (k, v))| format!("{}# {} => {}", i + 1, format_resp_value(k), format_resp_value(v)))
+                    .collect();
+                items.join("\n")
+            }
+        }
+        RespValue::Attribute(_, value) => format_resp_value(value),
         RespValue::Null => "(nil)".to_string(),
     }
 }
@@ -142,8 +197,8 @@ pub async fn stats_handler(State(dispatcher): State<AppState>) -> impl IntoRespo
     let cpu_usage = sys.global_cpu_usage() as f64;
 
     // Get database memory usage
-    let dispatcher = dispatcher.lock().await;
-    let store_stats = dispatcher.context().store.stats();
+    let mut dispatcher = dispatcher.lock().await;
+    let store_stats = dispatcher.context_mut().store.stats();
     let db_memory_mb = store_stats.used_memory_bytes as f64 / 1024.0 / 1024.0;
 
     let stats = SystemStats {
@@ -160,28 +215,21 @@ pub async fn stats_handler(State(dispatcher): State<AppState>) -> impl IntoRespo
 /// Execute command with cluster
 pub async fn execute_command_cluster(
     State(cluster): State<Arc<ClusterManager>>,
+    State(pubsub): State<Arc<PubSubBroker>>,
     Json(req): Json<CommandRequest>,
 ) -> impl IntoResponse {
     debug!("Executing command on cluster: {}", req.command);
 
-    let parts: Vec<RespValue> = req.command
-        .trim()
-        .split_whitespace()
-        .map(|s| RespValue::BulkString(Bytes::from(s.to_string())))
-        .collect();
-
-    if parts.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(CommandResponse {
-                success: false,
-                result: "Empty command".to_string(),
-            }),
-        );
-    }
+    let command = match parse_command_line(&req.command) {
+        Some(command) => command,
+        None => return empty_command_response(),
+    };
 
-    let command = RespValue::Array(parts);
-    let response = cluster.execute(command).await;
+    // PUBLISH goes through the global broker, not the shards
+    let response = match pubsub.try_publish(&command) {
+        Some(response) => response,
+        None => cluster.execute(command).await,
+    };
     let result = format_resp_value(&response);
 
     (
@@ -221,8 +269,347 @@ pub async fn stats_handler_cluster(State(cluster): State<Arc<ClusterManager>>) -
     (StatusCode::OK, Json(stats))
 }
 
+/// Readiness of the server
+#[derive(Debug, Serialize)]
+pub struct ReadyResponse {
+    /// False while the shards replay their AOF
+    pub ready: bool,
+
+    /// AOF entries replayed by the shards so far (all of them once ready)
+    pub entries_loaded: u64,
+}
+
+/// Readiness probe: 503 while the shards are loading their AOF
+pub async fn ready_handler(State(cluster): State<Arc<ClusterManager>>) -> impl IntoResponse {
+    let ready = !cluster.is_loading();
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(ReadyResponse { ready, entries_loaded: cluster.replayed_entries() }))
+}
+
 /// Get detailed shard statistics
 pub async fn shard_stats_handler(State(cluster): State<Arc<ClusterManager>>) -> impl IntoResponse {
     let shard_details = cluster.get_shard_details().await;
     (StatusCode::OK, Json(shard_details))
 }
+
+/// SCAN count of each export batch requested from a shard
+const EXPORT_BATCH_COUNT: usize = 100;
+
+/// Batches buffered between the shards and the HTTP response
+const EXPORT_BUFFERED_BATCHES: usize = 4;
+
+/// Export the whole keyspace as NDJSON, one line per key
+/// (`store::export_line`)
+///
+/// The response is streamed: the shards are scanned one after the other, a
+/// batch at a time, and each batch is only requested once the previous one
+/// has been taken by the response, so the dataset is never held in memory.
+/// Like a SCAN, a key written during the export may or may not be in it.
+/// A shard found down ends the response with an error.
+pub async fn export_handler(State(cluster): State<Arc<ClusterManager>>) -> impl IntoResponse {
+    let (tx, rx) = tokio::sync::mpsc::channel::<std::io::Result<Bytes>>(EXPORT_BUFFERED_BATCHES);
+    tokio::spawn(async move {
+        for shard_id in 0..cluster.num_shards() {
+            let mut cursor = 0;
+            loop {
+                let Some(batch) = cluster.export_batch(shard_id, cursor, EXPORT_BATCH_COUNT).await else {
+                    let error = std::io::Error::other(format!("shard {} is down", shard_id));
+                    let _ = tx.send(Err(error)).await;
+                    return;
+                };
+                if !batch.lines.is_empty() {
+                    let mut chunk = batch.lines.join("\n");
+                    chunk.push('\n');
+                    // The client went away
+                    if tx.send(Ok(Bytes::from(chunk))).await.is_err() {
+                        return;
+                    }
+                }
+                cursor = batch.cursor;
+                if cursor == 0 {
+                    break;
+                }
+            }
+        }
+    });
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+}
+
+/// Outcome of an NDJSON import
+#[derive(Debug, Serialize)]
+pub struct ImportResponse {
+    /// Keys written
+    pub imported: usize,
+    /// Lines that could not be parsed or written
+    pub failed: usize,
+}
+
+/// Lines of an import parsed before their RESTOREs are sent to the shards
+const IMPORT_BATCH_LINES: usize = 1000;
+
+/// Longest line of an import: a longer one is counted as failed and
+/// skipped up to its newline, rather than buffered
+const IMPORT_MAX_LINE_LEN: usize = 64 * 1024 * 1024;
+
+/// Import a keyspace from NDJSON, in the format of `GET /export`
+///
+/// The body is read as it arrives and each line becomes a RESTORE ... REPLACE
+/// on the shard owning the key, so imported keys overwrite existing ones,
+/// keep their TTL and reach the AOF. The RESTOREs are sent to the shards
+/// `IMPORT_BATCH_LINES` at a time (`ClusterManager::execute_batch`). A
+/// malformed line, one over `IMPORT_MAX_LINE_LEN` bytes, or one the shard
+/// refuses (e.g. over maxmemory), is counted as failed and the import goes
+/// on with the next one. Blank lines are skipped.
+pub async fn import_handler(State(cluster): State<Arc<ClusterManager>>, body: Body) -> impl IntoResponse {
+    let mut response = ImportResponse { imported: 0, failed: 0 };
+    let mut stream = body.into_data_stream();
+    let mut pending: Vec<u8> = Vec::new();
+    let mut batch = Vec::new();
+    // Whether the rest of a line over the length limit is being dropped
+    let mut skipping = false;
+
+    loop {
+        let chunk = match stream.next().await {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(e)) => {
+                debug!("Import body aborted: {}", e);
+                break;
+            }
+            None => break,
+        };
+
+        // The lines are cut from a moving offset, the consumed bytes
+        // dropped once per chunk, and only the new bytes searched
+        let mut start = 0;
+        let mut scanned = pending.len();
+        pending.extend_from_slice(&chunk);
+        while let Some(offset) = pending[scanned..].iter().position(|&b| b == b'\n') {
+            let end = scanned + offset;
+            if !std::mem::take(&mut skipping) {
+                queue_import_line(&pending[start..end], &mut batch, &mut response);
+            }
+            start = end + 1;
+            scanned = start;
+            if batch.len() >= IMPORT_BATCH_LINES {
+                restore_batch(&cluster, &mut batch, &mut response).await;
+            }
+        }
+        pending.drain(..start);
+
+        if skipping {
+            pending.clear();
+        } else if pending.len() > IMPORT_MAX_LINE_LEN {
+            debug!("Import line over {} bytes rejected", IMPORT_MAX_LINE_LEN);
+            response.failed += 1;
+            pending.clear();
+            skipping = true;
+        }
+    }
+    // The last line may lack its newline
+    if !skipping {
+        queue_import_line(&pending, &mut batch, &mut response);
+    }
+    restore_batch(&cluster, &mut batch, &mut response).await;
+
+    (StatusCode::OK, Json(response))
+}
+
+/// Send the RESTOREs of a batch of import lines, and count their outcome
+async fn restore_batch(cluster: &ClusterManager, batch: &mut Vec<RespValue>, response: &mut ImportResponse) {
+    if batch.is_empty() {
+        return;
+    }
+    for reply in cluster.execute_batch(std::mem::take(batch)).await {
+        match reply {
+            RespValue::Error(e) => {
+                debug!("Import of a key failed: {}", e);
+                response.failed += 1;
+            }
+            _ => response.imported += 1,
+        }
+    }
+}
+
+/// Parse a single NDJSON line into the RESTORE of its key, added to `batch`
+fn queue_import_line(line: &[u8], batch: &mut Vec<RespValue>, response: &mut ImportResponse) {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return;
+    }
+    let imported = std::str::from_utf8(line)
+        .map_err(|e| e.to_string())
+        .and_then(store::import_line);
+    let imported = match imported {
+        Ok(imported) => imported,
+        Err(e) => {
+            debug!("Import line rejected: {}", e);
+            response.failed += 1;
+            return;
+        }
+    };
+
+    // RESTORE reads a TTL of 0 as no expiration
+    let ttl_ms = imported.ttl_ms.map_or(0, |ms| ms.max(1));
+    batch.push(RespValue::array(vec![
+        RespValue::bulk_string("RESTORE"),
+        RespValue::bulk_string(imported.key.to_vec()),
+        RespValue::bulk_string(ttl_ms.to_string()),
+        RespValue::bulk_string(store::dump_value(&imported.value)),
+        RespValue::bulk_string("REPLACE"),
+    ]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn run_command(command: &str) -> (StatusCode, String) {
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new()));
+        run_on(dispatcher, command).await
+    }
+
+    async fn run_on(dispatcher: AppState, command: &str) -> (StatusCode, String) {
+        let request = CommandRequest { command: command.to_string() };
+        let response = execute_command(State(dispatcher), Json(request)).await.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_whitespace_only_command() {
+        for command in ["", "   ", " \t \n "] {
+            let (status, body) = run_command(command).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(body.contains("Empty command"), "unexpected body: {}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_embedded_double_spaces() {
+        let dispatcher = Arc::new(Mutex::new(Dispatcher::new()));
+
+        let (status, body) = run_on(dispatcher.clone(), "  SET  key   value  ").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("\"success\":true"), "unexpected body: {}", body);
+
+        let (_, body) = run_on(dispatcher, "GET\t\tkey").await;
+        assert!(body.contains("\"result\":\"value\""), "unexpected body: {}", body);
+    }
+
+    #[tokio::test]
+    async fn test_cluster_whitespace_handling() {
+        let cluster = Arc::new(ClusterManager::new(2, false).unwrap());
+        let pubsub = Arc::new(PubSubBroker::new());
+
+        let request = CommandRequest { command: "    ".to_string() };
+        let response = execute_command_cluster(State(cluster.clone()), State(pubsub.clone()), Json(request))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let request = CommandRequest { command: " SET  key  value ".to_string() };
+        let response = execute_command_cluster(State(cluster), State(pubsub), Json(request))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_export_streams_every_key() {
+        let cluster = Arc::new(ClusterManager::new(3, false).unwrap());
+        let command = |parts: &[&[u8]]| RespValue::array(parts.iter().map(|p| RespValue::bulk_string(p.to_vec())).collect());
+        for i in 0..20 {
+            cluster.execute(command(&[b"SET", format!("key:{}", i).as_bytes(), b"v"])).await;
+        }
+        cluster.execute(command(&[b"RPUSH", b"list", b"a", b"b"])).await;
+        cluster.execute(command(&[b"SET", b"\xffbin", b"\xfe\x00"])).await;
+        cluster.execute(command(&[b"EXPIRE", b"list", b"100"])).await;
+
+        let response = export_handler(State(cluster)).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/x-ndjson");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("every line is a JSON object"))
+            .collect();
+        assert_eq!(lines.len(), 22);
+
+        let find = |key: serde_json::Value| lines.iter().find(|line| line["key"] == key).unwrap().clone();
+        for i in 0..20 {
+            let line = find(serde_json::json!(format!("key:{}", i)));
+            assert_eq!(line["type"], "string");
+            assert_eq!(line["ttl"], -1);
+            assert_eq!(line["value"], "v");
+        }
+        let list = find(serde_json::json!("list"));
+        assert_eq!(list["value"], serde_json::json!(["a", "b"]));
+        assert!(list["ttl"].as_i64().unwrap() > 0);
+        let binary = find(serde_json::json!({ "base64": "/2Jpbg==" }));
+        assert_eq!(binary["value"], serde_json::json!({ "base64": "/gA=" }));
+    }
+
+    #[tokio::test]
+    async fn test_import_skips_malformed_lines() {
+        let cluster = Arc::new(ClusterManager::new(3, false).unwrap());
+        let body = concat!(
+            r#"{"key":"name","type":"string","ttl":-1,"value":"Alice"}"#, "\n",
+            r#"{"key":"broken","type":"list""#, "\n",
+            "\n",
+            r#"{"key":"list","type":"list","ttl":100000,"value":["a","b"]}"#, "\n",
+            r#"{"key":{"base64":"/2Jpbg=="},"type":"hash","ttl":-1,"value":[["f","v"]]}"#,
+        );
+
+        let response = import_handler(State(cluster.clone()), Body::from(body)).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let counts: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(counts, serde_json::json!({ "imported": 3, "failed": 1 }));
+
+        let command = |parts: &[&[u8]]| RespValue::array(parts.iter().map(|p| RespValue::bulk_string(p.to_vec())).collect());
+        assert_eq!(cluster.execute(command(&[b"GET", b"name"])).await, RespValue::bulk_string("Alice"));
+        assert_eq!(
+            cluster.execute(command(&[b"LRANGE", b"list", b"0", b"-1"])).await,
+            RespValue::array(vec![RespValue::bulk_string("a"), RespValue::bulk_string("b")])
+        );
+        match cluster.execute(command(&[b"TTL", b"list"])).await {
+            RespValue::Integer(secs) => assert!(secs > 0 && secs <= 100, "{}", secs),
+            other => panic!("unexpected TTL reply: {:?}", other),
+        }
+        assert_eq!(cluster.execute(command(&[b"HGET", b"\xffbin", b"f"])).await, RespValue::bulk_string("v"));
+        assert_eq!(cluster.execute(command(&[b"EXISTS", b"broken"])).await, RespValue::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn test_import_batches_split_and_overlong_lines() {
+        let cluster = Arc::new(ClusterManager::new(3, false).unwrap());
+        let mut lines = String::new();
+        for i in 0..IMPORT_BATCH_LINES + 500 {
+            lines.push_str(&format!(r#"{{"key":"key:{}","type":"string","ttl":-1,"value":"v{}"}}"#, i, i));
+            lines.push('\n');
+        }
+
+        // Lines cut across chunks, then one over the limit spanning several
+        let (head, tail) = lines.split_at(lines.len() / 2 + 7);
+        let mut chunks = vec![Bytes::from(head.to_string()), Bytes::from(tail.to_string())];
+        let overlong = IMPORT_MAX_LINE_LEN / 4 + 1;
+        chunks.extend((0..4).map(|_| Bytes::from(vec![b'x'; overlong])));
+        chunks.push(Bytes::from(concat!("xx\n", r#"{"key":"last","type":"string","ttl":-1,"value":"end"}"#)));
+        let body = Body::from_stream(tokio_stream::iter(chunks.into_iter().map(Ok::<_, std::io::Error>)));
+
+        let response = import_handler(State(cluster.clone()), body).await.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let counts: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(counts, serde_json::json!({ "imported": IMPORT_BATCH_LINES + 501, "failed": 1 }));
+
+        let command = |parts: &[&[u8]]| RespValue::array(parts.iter().map(|p| RespValue::bulk_string(p.to_vec())).collect());
+        assert_eq!(cluster.execute(command(&[b"GET", b"key:1234"])).await, RespValue::bulk_string("v1234"));
+        assert_eq!(cluster.execute(command(&[b"GET", b"last"])).await, RespValue::bulk_string("end"));
+    }
+}
@@ -11,7 +11,8 @@ use tracing::info;
 
 use crate::dispatch::Dispatcher;
 use crate::cluster::ClusterManager;
-use super::handlers::{index_handler, execute_command, execute_command_cluster, stats_handler, stats_handler_cluster, shard_stats_handler};
+use crate::pubsub::PubSubBroker;
+use super::handlers::{index_handler, execute_command, execute_command_cluster, stats_handler, stats_handler_cluster, shard_stats_handler, ready_handler, export_handler, import_handler, ClusterAppState};
 
 /// Run the web server
 pub async fn run_web_server(
@@ -36,9 +37,13 @@ pub async fn run_web_server(
 }
 
 /// Run the web server with cluster manager
+///
+/// The pub/sub broker is the one shared with the RESP server, so PUBLISH
+/// from the web API reaches RESP subscribers.
 pub async fn run_web_with_cluster(
     addr: &str,
     cluster: Arc<ClusterManager>,
+    pubsub: Arc<PubSubBroker>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Build the application router
     let app = Router::new()
@@ -46,8 +51,11 @@ pub async fn run_web_with_cluster(
         .route("/command", post(execute_command_cluster))
         .route("/stats", get(stats_handler_cluster))
         .route("/shards", get(shard_stats_handler))
+        .route("/ready", get(ready_handler))
+        .route("/export", get(export_handler))
+        .route("/import", post(import_handler))
         .layer(CorsLayer::permissive())
-        .with_state(cluster);
+        .with_state(ClusterAppState { cluster, pubsub });
 
     // Start the server
     let listener = tokio::net::TcpListener::bind(addr).await?;
@@ -0,0 +1,85 @@
+//! MIGRATE integration test: moving keys between two servers
+
+use ferrumdb::{server, ClusterManager, PubSubBroker};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Find a free local port
+fn free_addr() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// Start a RESP server with its own cluster and return a connection to it
+async fn start_server(addr: &str) -> TcpStream {
+    let cluster = Arc::new(ClusterManager::new(2, false).unwrap());
+    let pubsub = Arc::new(PubSubBroker::new());
+    let listen_addr = addr.to_string();
+    tokio::spawn(async move {
+        let _ = server::run_with_cluster(&listen_addr, cluster, pubsub).await;
+    });
+
+    for _ in 0..100 {
+        if let Ok(stream) = TcpStream::connect(addr).await {
+            return stream;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Server at {} did not start", addr);
+}
+
+/// Send a command and return its raw reply
+async fn command(stream: &mut TcpStream, parts: &[&str]) -> String {
+    let mut request = format!("*{}\r\n", parts.len());
+    for part in parts {
+        request.push_str(&format!("${}\r\n{}\r\n", part.len(), part));
+    }
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    // Every reply used here fits in a single read and ends with CRLF
+    let mut buf = vec![0u8; 1024];
+    let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+        .await
+        .expect("timed out waiting for a reply")
+        .unwrap();
+    String::from_utf8_lossy(&buf[..n]).to_string()
+}
+
+#[tokio::test]
+async fn test_migrate_between_servers() {
+    let source_addr = free_addr();
+    let target_addr = free_addr();
+    let mut source = start_server(&source_addr).await;
+    let mut target = start_server(&target_addr).await;
+    let target_port = target_addr.rsplit(':').next().unwrap();
+
+    // The key moves with its TTL and is gone from the source
+    assert_eq!(command(&mut source, &["SET", "k", "v1"]).await, "+OK\r\n");
+    assert_eq!(command(&mut source, &["EXPIRE", "k", "100"]).await, ":1\r\n");
+    assert_eq!(command(&mut source, &["MIGRATE", "127.0.0.1", target_port, "k", "0", "1000"]).await, "+OK\r\n");
+    assert_eq!(command(&mut source, &["GET", "k"]).await, "$-1\r\n");
+    assert_eq!(command(&mut target, &["GET", "k"]).await, "$2\r\nv1\r\n");
+    let ttl = command(&mut target, &["TTL", "k"]).await;
+    assert!(ttl == ":100\r\n" || ttl == ":99\r\n", "unexpected TTL: {}", ttl);
+
+    // Without REPLACE the existing target key wins and the source keeps its own
+    assert_eq!(command(&mut source, &["SET", "k", "v2"]).await, "+OK\r\n");
+    assert_eq!(
+        command(&mut source, &["MIGRATE", "127.0.0.1", target_port, "k", "0", "1000"]).await,
+        "-ERR Target instance replied with error: BUSYKEY Target key name already exists.\r\n"
+    );
+    assert_eq!(command(&mut source, &["GET", "k"]).await, "$2\r\nv2\r\n");
+
+    // COPY REPLACE overwrites the target and keeps the source
+    assert_eq!(
+        command(&mut source, &["MIGRATE", "127.0.0.1", target_port, "k", "0", "1000", "COPY", "REPLACE"]).await,
+        "+OK\r\n"
+    );
+    assert_eq!(command(&mut source, &["GET", "k"]).await, "$2\r\nv2\r\n");
+    assert_eq!(command(&mut target, &["GET", "k"]).await, "$2\r\nv2\r\n");
+    assert_eq!(command(&mut target, &["TTL", "k"]).await, ":-1\r\n");
+
+    assert_eq!(command(&mut source, &["MIGRATE", "127.0.0.1", target_port, "nope", "0", "1000"]).await, "+NOKEY\r\n");
+}
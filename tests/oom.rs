@@ -0,0 +1,116 @@
+//! maxmemory integration test: writes refused under `noeviction`, keys
+//! evicted under `allkeys-lru` and `allkeys-lfu`
+//!
+//! In its own test binary since `maxmemory` is process-wide.
+
+use bytes::Bytes;
+use ferrumdb::config::SERVER_CONFIG;
+use ferrumdb::dispatch::Dispatcher;
+use ferrumdb::RespValue;
+use std::sync::Mutex;
+
+/// Serializes the tests, which share the process-wide limit
+static MAXMEMORY_LOCK: Mutex<()> = Mutex::new(());
+
+fn cmd(parts: &[&str]) -> RespValue {
+    RespValue::array(parts.iter().map(|part| RespValue::bulk_string(Bytes::copy_from_slice(part.as_bytes()))).collect())
+}
+
+#[test]
+fn test_noeviction_refuses_writes_over_maxmemory() {
+    let _guard = MAXMEMORY_LOCK.lock().unwrap();
+    let mut dispatcher = Dispatcher::new();
+    SERVER_CONFIG.set("maxmemory-policy", "noeviction").unwrap();
+    SERVER_CONFIG.set("maxmemory", "64kb").unwrap();
+
+    // Fill until the limit is reached
+    let value = "x".repeat(1000);
+    let mut written = 0;
+    let oom = loop {
+        let reply = dispatcher.dispatch(cmd(&["SET", &format!("key:{}", written), &value]));
+        if reply != RespValue::simple_string("OK") {
+            break reply;
+        }
+        written += 1;
+        assert!(written < 1000, "maxmemory was never reached");
+    };
+    assert!(written > 0);
+    assert_eq!(oom, RespValue::error("OOM command not allowed when used memory > 'maxmemory'."));
+
+    // The next write is refused too, reads and deletions still work
+    let reply = dispatcher.dispatch(cmd(&["SET", "another", &value]));
+    assert_eq!(reply, RespValue::error("OOM command not allowed when used memory > 'maxmemory'."));
+    let reply = dispatcher.dispatch(cmd(&["GET", "key:0"]));
+    assert_eq!(reply, RespValue::bulk_string(Bytes::from(value)));
+    assert_eq!(dispatcher.dispatch(cmd(&["DEL", "key:0", "key:1"])), RespValue::integer(2));
+
+    // Lifting the limit lets writes through again
+    SERVER_CONFIG.set("maxmemory", "0").unwrap();
+    assert_eq!(dispatcher.dispatch(cmd(&["SET", "another", "value"])), RespValue::simple_string("OK"));
+}
+
+#[test]
+fn test_set_store_refused_before_computing_result() {
+    let _guard = MAXMEMORY_LOCK.lock().unwrap();
+    let mut dispatcher = Dispatcher::new();
+
+    // Two sets of about 90KB each, under a 300KB limit
+    for key in ["left", "right"] {
+        let members: Vec<String> = (0..1000).map(|i| format!("{}:{:024}", key, i)).collect();
+        let mut parts = vec!["SADD", key];
+        parts.extend(members.iter().map(String::as_str));
+        assert_eq!(dispatcher.dispatch(cmd(&parts)), RespValue::integer(1000));
+    }
+    SERVER_CONFIG.set("maxmemory", "300kb").unwrap();
+
+    // Their union would take the store past the limit
+    let reply = dispatcher.dispatch(cmd(&["SUNIONSTORE", "dest", "left", "right"]));
+    assert_eq!(reply, RespValue::error("OOM command not allowed when used memory > 'maxmemory'."));
+    assert_eq!(dispatcher.dispatch(cmd(&["EXISTS", "dest"])), RespValue::integer(0));
+
+    // A result estimated to fit is stored
+    assert_eq!(dispatcher.dispatch(cmd(&["SINTERSTORE", "dest", "left", "right"])), RespValue::integer(0));
+    assert_eq!(dispatcher.dispatch(cmd(&["SDIFFSTORE", "dest", "left", "right"])), RespValue::integer(1000));
+
+    SERVER_CONFIG.set("maxmemory", "0").unwrap();
+    assert_eq!(dispatcher.dispatch(cmd(&["SUNIONSTORE", "dest", "left", "right"])), RespValue::integer(2000));
+}
+
+/// Write a 20KB value at each key, then evict under `policy` with a 50KB
+/// limit: the third write must make room for itself
+fn evict_with(policy: &str, preset: &[&[&str]]) -> Vec<RespValue> {
+    let mut dispatcher = Dispatcher::new();
+    SERVER_CONFIG.set("maxmemory-policy", policy).unwrap();
+    let value = "x".repeat(20 * 1024);
+    for key in ["first", "second"] {
+        assert_eq!(dispatcher.dispatch(cmd(&["SET", key, &value])), RespValue::simple_string("OK"));
+    }
+    for parts in preset {
+        assert_eq!(dispatcher.dispatch(cmd(parts)), RespValue::simple_string("OK"));
+    }
+
+    SERVER_CONFIG.set("maxmemory", "50kb").unwrap();
+    let reply = dispatcher.dispatch(cmd(&["SET", "third", &value]));
+    SERVER_CONFIG.set("maxmemory", "0").unwrap();
+    SERVER_CONFIG.set("maxmemory-policy", "noeviction").unwrap();
+    assert_eq!(reply, RespValue::simple_string("OK"));
+
+    ["first", "second", "third"].iter().map(|key| dispatcher.dispatch(cmd(&["EXISTS", key]))).collect()
+}
+
+#[test]
+fn test_allkeys_lru_evicts_stalest_key_first() {
+    let _guard = MAXMEMORY_LOCK.lock().unwrap();
+
+    // The second key was written last but has been idle the longest
+    let exists = evict_with("allkeys-lru", &[&["DEBUG", "SET-LRU", "first", "10"], &["DEBUG", "SET-LRU", "second", "100"]]);
+    assert_eq!(exists, [1, 0, 1].map(RespValue::integer));
+}
+
+#[test]
+fn test_allkeys_lfu_evicts_least_used_key_first() {
+    let _guard = MAXMEMORY_LOCK.lock().unwrap();
+
+    let exists = evict_with("allkeys-lfu", &[&["DEBUG", "SET-FREQ", "first", "1"], &["DEBUG", "SET-FREQ", "second", "100"]]);
+    assert_eq!(exists, [0, 1, 1].map(RespValue::integer));
+}
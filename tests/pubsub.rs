@@ -0,0 +1,137 @@
+//! Pub/sub integration tests: PUBLISH on the web API, SUBSCRIBE over RESP,
+//! and sharded channels (SSUBSCRIBE, SPUBLISH)
+
+use ferrumdb::{server, web, ClusterManager, PubSubBroker};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Find a free local port
+fn free_addr() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// Connect to a server, retrying while it starts up
+async fn connect(addr: &str) -> TcpStream {
+    for _ in 0..100 {
+        if let Ok(stream) = TcpStream::connect(addr).await {
+            return stream;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Server at {} did not start", addr);
+}
+
+/// Read from the stream until `expected` has been received
+async fn read_until(stream: &mut TcpStream, expected: &[u8]) -> Vec<u8> {
+    let mut received = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !received.windows(expected.len()).any(|w| w == expected) {
+        let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .expect("timed out waiting for data")
+            .unwrap();
+        assert!(n > 0, "connection closed, received so far: {:?}", String::from_utf8_lossy(&received));
+        received.extend_from_slice(&buf[..n]);
+    }
+    received
+}
+
+/// POST a command to the web `/command` endpoint and return the response body
+async fn web_command(addr: &str, command: &str) -> String {
+    let body = format!("{{\"command\":\"{}\"}}", command);
+    let request = format!(
+        "POST /command HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        addr,
+        body.len(),
+        body
+    );
+
+    let mut stream = connect(addr).await;
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    String::from_utf8_lossy(&response).to_string()
+}
+
+#[tokio::test]
+async fn test_web_publish_reaches_resp_subscriber() {
+    let cluster = Arc::new(ClusterManager::new(2, false).unwrap());
+    let pubsub = Arc::new(PubSubBroker::new());
+
+    let resp_addr = free_addr();
+    let web_addr = free_addr();
+
+    let (addr, c, p) = (resp_addr.clone(), cluster.clone(), pubsub.clone());
+    tokio::spawn(async move {
+        let _ = server::run_with_cluster(&addr, c, p).await;
+    });
+    let (addr, c, p) = (web_addr.clone(), cluster.clone(), pubsub.clone());
+    tokio::spawn(async move {
+        let _ = web::run_web_with_cluster(&addr, c, p).await;
+    });
+
+    // SUBSCRIBE news over RESP
+    let mut subscriber = connect(&resp_addr).await;
+    subscriber.write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$4\r\nnews\r\n").await.unwrap();
+    read_until(&mut subscriber, b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n").await;
+
+    // PUBLISH news hello through the web API
+    let response = web_command(&web_addr, "PUBLISH news hello").await;
+    assert!(response.contains("\"result\":\"1\""), "unexpected response: {}", response);
+
+    read_until(&mut subscriber, b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n").await;
+
+    // And the other way around: PUBLISH over RESP reaches the same subscriber
+    let mut publisher = connect(&resp_addr).await;
+    publisher.write_all(b"*3\r\n$7\r\nPUBLISH\r\n$4\r\nnews\r\n$3\r\nbye\r\n").await.unwrap();
+    read_until(&mut publisher, b":1\r\n").await;
+    read_until(&mut subscriber, b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$3\r\nbye\r\n").await;
+}
+
+#[tokio::test]
+async fn test_spublish_reaches_shard_subscribers() {
+    let cluster = Arc::new(ClusterManager::new(4, false).unwrap());
+    let pubsub = Arc::new(PubSubBroker::with_shards(4));
+    let resp_addr = free_addr();
+    let (addr, c, p) = (resp_addr.clone(), cluster.clone(), pubsub.clone());
+    tokio::spawn(async move {
+        let _ = server::run_with_cluster(&addr, c, p).await;
+    });
+
+    // Two sharded subscribers and a classic one on the same channel
+    let mut first = connect(&resp_addr).await;
+    first.write_all(b"*3\r\n$10\r\nSSUBSCRIBE\r\n$6\r\norders\r\n$5\r\nstock\r\n").await.unwrap();
+    read_until(&mut first, b"*3\r\n$10\r\nssubscribe\r\n$5\r\nstock\r\n:2\r\n").await;
+    let mut second = connect(&resp_addr).await;
+    second.write_all(b"*2\r\n$10\r\nSSUBSCRIBE\r\n$6\r\norders\r\n").await.unwrap();
+    read_until(&mut second, b"*3\r\n$10\r\nssubscribe\r\n$6\r\norders\r\n:1\r\n").await;
+    let mut classic = connect(&resp_addr).await;
+    classic.write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$6\r\norders\r\n").await.unwrap();
+    read_until(&mut classic, b"*3\r\n$9\r\nsubscribe\r\n$6\r\norders\r\n:1\r\n").await;
+    let shard = pubsub.shard_of(&bytes::Bytes::from("orders"));
+    assert!(pubsub.num_shard_channels(shard) >= 1);
+
+    // SPUBLISH counts the subscribers of the channel's shard only
+    let mut publisher = connect(&resp_addr).await;
+    publisher.write_all(b"*3\r\n$8\r\nSPUBLISH\r\n$6\r\norders\r\n$3\r\nnew\r\n").await.unwrap();
+    read_until(&mut publisher, b":2\r\n").await;
+    let smessage = b"*3\r\n$8\r\nsmessage\r\n$6\r\norders\r\n$3\r\nnew\r\n";
+    read_until(&mut first, smessage).await;
+    read_until(&mut second, smessage).await;
+
+    // Once the second one leaves, only the first one is counted
+    second.write_all(b"*1\r\n$12\r\nSUNSUBSCRIBE\r\n").await.unwrap();
+    read_until(&mut second, b"*3\r\n$12\r\nsunsubscribe\r\n$6\r\norders\r\n:0\r\n").await;
+    publisher.write_all(b"*3\r\n$8\r\nSPUBLISH\r\n$6\r\norders\r\n$4\r\nmore\r\n").await.unwrap();
+    read_until(&mut publisher, b":1\r\n").await;
+    read_until(&mut first, b"$4\r\nmore\r\n").await;
+
+    // The classic subscriber got neither, but does get a PUBLISH
+    publisher.write_all(b"*3\r\n$7\r\nPUBLISH\r\n$6\r\norders\r\n$3\r\nold\r\n").await.unwrap();
+    read_until(&mut publisher, b":1\r\n").await;
+    let received = read_until(&mut classic, b"$3\r\nold\r\n").await;
+    assert!(!received.windows(8).any(|w| w == b"smessage"));
+}